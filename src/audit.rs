@@ -0,0 +1,752 @@
+//! Audit log: who created, changed or deleted what, in the administration or automatically.
+//!
+//! Entities are recorded as JSON snapshots; an entry only keeps the fields which changed. Secrets
+//! (password hashes, tokens, webhook secrets) are never part of the snapshots.
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::AnyConnection;
+use std::fmt::Write as _;
+
+use crate::{
+    controllers::auth::CurrentUser,
+    db::models::{
+        alert_rules::AlertRule,
+        api_tokens::ApiToken,
+        audit::{AuditAction, AuditEntity, AuditEntry, AuditFilter},
+        comments::Comment,
+        heartbeats::Heartbeat,
+        interventions::Intervention,
+        probes::{Probe, ProbeCheck},
+        services::Service,
+        teams::Team,
+        users::User,
+        webhooks::Webhook,
+    },
+    reports::csv_field,
+};
+
+/// Format of the dates of the export's parameters.
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Who made a change.
+pub(crate) struct Actor {
+    user_id: Option<i64>,
+    username: String,
+}
+
+impl Actor {
+    /// The changes made automatically: monitoring, scheduled maintenances, etc.
+    pub fn system() -> Self {
+        Self {
+            user_id: None,
+            username: "system".to_owned(),
+        }
+    }
+
+    /// The user who made the change, if it wasn't made automatically.
+    pub fn user_id(&self) -> Option<i64> {
+        self.user_id
+    }
+}
+
+impl From<&CurrentUser> for Actor {
+    fn from(user: &CurrentUser) -> Self {
+        Self {
+            user_id: Some(user.id),
+            username: user.username.clone(),
+        }
+    }
+}
+
+/// An entity which can be recorded in the audit log.
+pub(crate) trait Audited {
+    const ENTITY: AuditEntity;
+
+    /// The fields of the entity worth recording, without any secret.
+    fn snapshot(&self) -> Value;
+}
+
+impl Audited for Service {
+    const ENTITY: AuditEntity = AuditEntity::Service;
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "name": self.name,
+            "url": self.url,
+            "team_id": self.team_id,
+        })
+    }
+}
+
+impl Audited for Intervention {
+    const ENTITY: AuditEntity = AuditEntity::Intervention;
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "title": self.title,
+            "description": self.description,
+            "status": self.status,
+            "severity": self.severity,
+            "start_date": self.start_date,
+            "estimated_duration": self.estimated_duration,
+            "end_date": self.end_date,
+            "is_planned": self.is_planned,
+            "auto_resolve": self.auto_resolve,
+        })
+    }
+}
+
+/// An intervention along with its services.
+pub(crate) struct InterventionWithServiceIds<'a>(pub &'a Intervention, pub Vec<i64>);
+
+impl Audited for InterventionWithServiceIds<'_> {
+    const ENTITY: AuditEntity = AuditEntity::Intervention;
+
+    fn snapshot(&self) -> Value {
+        let mut snapshot = self.0.snapshot();
+        let mut service_ids = self.1.clone();
+        service_ids.sort_unstable();
+        snapshot["services"] = json!(service_ids);
+        snapshot
+    }
+}
+
+/// An update, along with the intervention it was posted on.
+pub(crate) struct InterventionUpdate<'a>(pub i64, pub &'a Comment);
+
+impl Audited for InterventionUpdate<'_> {
+    const ENTITY: AuditEntity = AuditEntity::Update;
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "intervention_id": self.0,
+            "date": self.1.date,
+            "description": self.1.description,
+        })
+    }
+}
+
+impl Audited for User {
+    const ENTITY: AuditEntity = AuditEntity::User;
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "username": self.username,
+            "role": self.role.to_db_str(),
+            "team_id": self.team_id,
+        })
+    }
+}
+
+impl Audited for Team {
+    const ENTITY: AuditEntity = AuditEntity::Team;
+
+    fn snapshot(&self) -> Value {
+        json!({ "name": self.name })
+    }
+}
+
+impl Audited for ApiToken {
+    const ENTITY: AuditEntity = AuditEntity::ApiToken;
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "name": self.name,
+            "scopes": self.scopes.iter().map(|scope| scope.to_db_str()).collect::<Vec<_>>(),
+            "user_id": self.user_id,
+            "expires_at": self.expires_at,
+        })
+    }
+}
+
+impl Audited for Webhook {
+    const ENTITY: AuditEntity = AuditEntity::Webhook;
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "url": self.url,
+            "events": self.events,
+            "enabled": self.enabled,
+        })
+    }
+}
+
+impl Audited for AlertRule {
+    const ENTITY: AuditEntity = AuditEntity::AlertRule;
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "source": self.source,
+            "label": self.label,
+            "pattern": self.pattern,
+            "service_id": self.service_id,
+            "severity": self.severity,
+        })
+    }
+}
+
+impl Audited for Probe {
+    const ENTITY: AuditEntity = AuditEntity::Probe;
+
+    fn snapshot(&self) -> Value {
+        let settings = match &self.check {
+            ProbeCheck::Http(check) => json!(check),
+            ProbeCheck::Tcp(check) => json!(check),
+            ProbeCheck::Tls(check) => json!(check),
+            ProbeCheck::Dns(check) => json!(check),
+        };
+        json!({
+            "service_id": self.service_id,
+            "enabled": self.enabled,
+            "interval_secs": self.interval_secs,
+            "timeout_secs": self.timeout_secs,
+            "failure_threshold": self.failure_threshold,
+            "success_threshold": self.success_threshold,
+            "min_incident_secs": self.min_incident_secs,
+            "flap_window_secs": self.flap_window_secs,
+            "flap_threshold": self.flap_threshold,
+            "kind": self.check.kind(),
+            "settings": settings,
+        })
+    }
+}
+
+impl Audited for Heartbeat {
+    const ENTITY: AuditEntity = AuditEntity::Heartbeat;
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "service_id": self.service_id,
+            "enabled": self.enabled,
+            "period_secs": self.period_secs,
+            "grace_secs": self.grace_secs,
+        })
+    }
+}
+
+/// Compute the diff between two snapshots, keeping only the fields which changed.
+///
+/// Returns `None` if nothing changed.
+fn diff(before: Option<Value>, after: Option<Value>) -> Option<Value> {
+    match (before, after) {
+        (Some(Value::Object(before)), Some(Value::Object(after))) => {
+            let mut changed_before = Map::new();
+            let mut changed_after = Map::new();
+            for (key, value) in &after {
+                let previous = before.get(key).unwrap_or(&Value::Null);
+                if previous != value {
+                    changed_before.insert(key.clone(), previous.clone());
+                    changed_after.insert(key.clone(), value.clone());
+                }
+            }
+            for (key, value) in &before {
+                if !after.contains_key(key) {
+                    changed_before.insert(key.clone(), value.clone());
+                    changed_after.insert(key.clone(), Value::Null);
+                }
+            }
+            if changed_after.is_empty() {
+                return None;
+            }
+            Some(json!({ "before": changed_before, "after": changed_after }))
+        }
+        (before, after) => Some(json!({ "before": before, "after": after })),
+    }
+}
+
+/// Record a change in the audit log.
+///
+/// Without a previous state, the entity was created; without a new one, it was deleted. Updates
+/// which didn't change anything aren't recorded.
+pub(crate) async fn record<T: Audited>(
+    conn: &mut AnyConnection,
+    actor: &Actor,
+    entity_id: i64,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> anyhow::Result<()> {
+    let action = match (before, after) {
+        (None, Some(_)) => AuditAction::Create,
+        (Some(_), Some(_)) => AuditAction::Update,
+        (Some(_), None) => AuditAction::Delete,
+        (None, None) => return Ok(()),
+    };
+
+    let Some(diff) = diff(before.map(T::snapshot), after.map(T::snapshot)) else {
+        return Ok(());
+    };
+
+    insert(conn, actor, action, T::ENTITY, entity_id, diff).await
+}
+
+async fn insert(
+    conn: &mut AnyConnection,
+    actor: &Actor,
+    action: AuditAction,
+    entity: AuditEntity,
+    entity_id: i64,
+    diff: Value,
+) -> anyhow::Result<()> {
+    AuditEntry::insert(
+        conn,
+        &AuditEntry {
+            id: None,
+            date: chrono::Utc::now().naive_utc(),
+            user_id: actor.user_id,
+            username: actor.username.clone(),
+            action,
+            entity,
+            entity_id: Some(entity_id),
+            diff: diff.to_string(),
+        },
+    )
+    .await
+    .context("recording a change in the audit log")?;
+    Ok(())
+}
+
+/// Record the creation of an entity.
+pub(crate) async fn created<T: Audited>(
+    conn: &mut AnyConnection,
+    actor: &Actor,
+    entity_id: i64,
+    entity: &T,
+) -> anyhow::Result<()> {
+    record(conn, actor, entity_id, None, Some(entity)).await
+}
+
+/// Record the update of an entity.
+pub(crate) async fn updated<T: Audited>(
+    conn: &mut AnyConnection,
+    actor: &Actor,
+    entity_id: i64,
+    before: &T,
+    after: &T,
+) -> anyhow::Result<()> {
+    record(conn, actor, entity_id, Some(before), Some(after)).await
+}
+
+/// Record the deletion of an entity.
+pub(crate) async fn deleted<T: Audited>(
+    conn: &mut AnyConnection,
+    actor: &Actor,
+    entity_id: i64,
+    entity: &T,
+) -> anyhow::Result<()> {
+    record(conn, actor, entity_id, Some(entity), None).await
+}
+
+/// Record that the password of a user was changed, without recording anything about it.
+pub(crate) async fn password_changed(
+    conn: &mut AnyConnection,
+    actor: &Actor,
+    user_id: i64,
+) -> anyhow::Result<()> {
+    let diff = json!({
+        "before": { "password": "(hidden)" },
+        "after": { "password": "(changed)" },
+    });
+    insert(
+        conn,
+        actor,
+        AuditAction::Update,
+        AuditEntity::User,
+        user_id,
+        diff,
+    )
+    .await
+}
+
+/// Parameters of an export of the audit log, as given in the query string of the admin page or
+/// on the command line.
+///
+/// All of them are optional: by default, the whole log is exported.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub(crate) struct AuditParams {
+    /// Kind of entity, e.g. `service` or `api-token`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity: Option<String>,
+    #[serde(rename = "entity-id", skip_serializing_if = "Option::is_none")]
+    pub entity_id: Option<String>,
+    /// `create`, `update` or `delete`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    /// Name of the user who made the changes, or `system`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// First day, as `YYYY-MM-DD`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Last day, included, as `YYYY-MM-DD`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// Format of the export: `html` (in the admin only), `csv` or `json`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+impl AuditParams {
+    /// Read the parameters from command line arguments, like `--entity service --from 2024-05-01`.
+    pub fn from_args(args: &[String]) -> anyhow::Result<Self> {
+        let mut params = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let name = arg
+                .strip_prefix("--")
+                .with_context(|| format!("unexpected argument: {arg}"))?;
+            let value = args
+                .next()
+                .with_context(|| format!("missing value for --{name}"))?
+                .clone();
+            let field = match name {
+                "entity" => &mut params.entity,
+                "entity-id" => &mut params.entity_id,
+                "action" => &mut params.action,
+                "user" => &mut params.user,
+                "from" => &mut params.from,
+                "to" => &mut params.to,
+                "format" => &mut params.format,
+                _ => anyhow::bail!("unknown option: --{name}"),
+            };
+            *field = Some(value);
+        }
+        Ok(params)
+    }
+
+    /// The parameters as a query string, without the format; used by the export links.
+    pub fn to_query(&self) -> anyhow::Result<String> {
+        let params = Self {
+            format: None,
+            ..self.clone()
+        };
+        Ok(serde_html_form::to_string(params)?)
+    }
+
+    /// Turn the parameters into a filter; empty parameters are ignored.
+    pub fn to_filter(&self) -> anyhow::Result<AuditFilter> {
+        fn non_empty(value: &Option<String>) -> Option<&str> {
+            value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+        }
+
+        let parse_date = |value: &str| {
+            chrono::NaiveDate::parse_from_str(value, DATE_FORMAT)
+                .with_context(|| format!("invalid date, expected YYYY-MM-DD: {value}"))
+        };
+
+        Ok(AuditFilter {
+            entity: non_empty(&self.entity)
+                .map(AuditEntity::from_db_str)
+                .transpose()?,
+            entity_id: non_empty(&self.entity_id)
+                .map(|id| {
+                    id.parse()
+                        .with_context(|| format!("invalid entity id: {id}"))
+                })
+                .transpose()?,
+            action: non_empty(&self.action)
+                .map(AuditAction::from_db_str)
+                .transpose()?,
+            username: non_empty(&self.user).map(ToOwned::to_owned),
+            from: non_empty(&self.from)
+                .map(|date| parse_date(date).map(|date| date.and_hms_opt(0, 0, 0).unwrap()))
+                .transpose()?,
+            to: non_empty(&self.to)
+                .map(|date| {
+                    parse_date(date)
+                        .map(|date| date.succ_opt().unwrap().and_hms_opt(0, 0, 0).unwrap())
+                })
+                .transpose()?,
+            limit: None,
+        })
+    }
+}
+
+/// Export entries of the audit log as CSV, one line per entry.
+pub(crate) fn to_csv(entries: &[AuditEntry]) -> String {
+    let mut csv = String::from("id,date,user_id,username,action,entity,entity_id,diff\n");
+    for entry in entries {
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{}",
+            entry.id.unwrap_or_default(),
+            entry.date.format("%Y-%m-%dT%H:%M:%SZ"),
+            entry.user_id.map(|id| id.to_string()).unwrap_or_default(),
+            csv_field(&entry.username),
+            entry.action.to_db_str(),
+            entry.entity.to_db_str(),
+            entry.entity_id.map(|id| id.to_string()).unwrap_or_default(),
+            csv_field(&entry.diff),
+        );
+    }
+    csv
+}
+
+/// Export entries of the audit log as a JSON array, the diffs being nested objects.
+pub(crate) fn to_json(entries: &[AuditEntry]) -> anyhow::Result<String> {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            Ok(json!({
+                "id": entry.id,
+                "date": entry.date,
+                "user_id": entry.user_id,
+                "username": entry.username,
+                "action": entry.action.to_db_str(),
+                "entity": entry.entity.to_db_str(),
+                "entity_id": entry.entity_id,
+                "diff": serde_json::from_str::<Value>(&entry.diff)
+                    .context("reading the diff of an audit entry")?,
+            }))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(serde_json::to_string_pretty(&entries)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::models::users::Role, testing};
+    use chrono::NaiveDateTime;
+    // The client's HTTP types.
+    use reqwest::{header, StatusCode};
+
+    fn date(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    /// Read a CSV export, with quoted fields as written by [`csv_field`].
+    fn parse_csv(csv: &str) -> Vec<Vec<String>> {
+        let mut rows = Vec::new();
+        let mut row = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = csv.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (quoted, c) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (true, '"') => quoted = false,
+                (true, c) => field.push(c),
+                (false, '"') => quoted = true,
+                (false, ',') => row.push(std::mem::take(&mut field)),
+                (false, '\n') => {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                (false, c) => field.push(c),
+            }
+        }
+        assert!(!quoted && field.is_empty() && row.is_empty());
+        rows
+    }
+
+    #[tokio::test]
+    async fn the_log_is_append_only() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let mut conn = ctx.db_connection.lock().await;
+        let team = Team {
+            id: None,
+            name: "Infra".to_owned(),
+        };
+        created(&mut conn, &Actor::system(), 1, &team)
+            .await
+            .unwrap();
+
+        for statement in [
+            "UPDATE audit_log SET username = 'nobody'",
+            "DELETE FROM audit_log",
+        ] {
+            let err = sqlx::query(statement)
+                .execute(&mut *conn)
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("append-only"), "{err}");
+        }
+
+        let entries = AuditEntry::get_filtered(&AuditFilter::default(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].username, "system");
+    }
+
+    /// Entries matching the query string of the admin page, most recent first, as indexes in the
+    /// inserted ids.
+    async fn filtered(conn: &mut AnyConnection, ids: &[i64], query: &str) -> Vec<usize> {
+        let params: AuditParams = serde_html_form::from_str(query).unwrap();
+        let filter = params.to_filter().unwrap();
+        let entries = AuditEntry::get_filtered(&filter, conn).await.unwrap();
+        entries
+            .into_iter()
+            .map(|e| ids.iter().position(|&id| Some(id) == e.id).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn entries_are_filtered() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let mut conn = ctx.db_connection.lock().await;
+        let mut ids = Vec::new();
+        for (day, username, action, entity, entity_id) in [
+            (
+                "2024-05-01 10:00:00",
+                "alice",
+                AuditAction::Create,
+                AuditEntity::Intervention,
+                1,
+            ),
+            (
+                "2024-05-02 23:59:59",
+                "bob",
+                AuditAction::Update,
+                AuditEntity::Intervention,
+                1,
+            ),
+            (
+                "2024-05-03 00:00:00",
+                "system",
+                AuditAction::Update,
+                AuditEntity::Intervention,
+                2,
+            ),
+            (
+                "2024-05-03 12:00:00",
+                "alice",
+                AuditAction::Create,
+                AuditEntity::Service,
+                1,
+            ),
+        ] {
+            let entry = AuditEntry {
+                id: None,
+                date: date(day),
+                user_id: None,
+                username: username.to_owned(),
+                action,
+                entity,
+                entity_id: Some(entity_id),
+                diff: "{}".to_owned(),
+            };
+            ids.push(AuditEntry::insert(&mut conn, &entry).await.unwrap());
+        }
+
+        assert_eq!(filtered(&mut conn, &ids, "").await, [3, 2, 1, 0]);
+        assert_eq!(filtered(&mut conn, &ids, "user=alice").await, [3, 0]);
+        assert_eq!(filtered(&mut conn, &ids, "user=system").await, [2]);
+        assert_eq!(
+            filtered(&mut conn, &ids, "entity=intervention").await,
+            [2, 1, 0]
+        );
+        assert_eq!(
+            filtered(&mut conn, &ids, "entity=intervention&entity-id=1").await,
+            [1, 0]
+        );
+        assert_eq!(filtered(&mut conn, &ids, "action=create").await, [3, 0]);
+        // Both days are included.
+        assert_eq!(
+            filtered(&mut conn, &ids, "from=2024-05-02&to=2024-05-02").await,
+            [1]
+        );
+        assert_eq!(
+            filtered(&mut conn, &ids, "from=2024-05-02").await,
+            [3, 2, 1]
+        );
+        assert_eq!(filtered(&mut conn, &ids, "to=2024-05-02").await, [1, 0]);
+        // Empty parameters, as sent by the form, are ignored.
+        assert_eq!(
+            filtered(&mut conn, &ids, "entity=&user=alice&from=&to=2024-05-01").await,
+            [0]
+        );
+
+        for invalid in [
+            "entity=nope",
+            "entity-id=one",
+            "from=05/01/2024",
+            "action=read",
+        ] {
+            let params: AuditParams = serde_html_form::from_str(invalid).unwrap();
+            assert!(params.to_filter().is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn exports_keep_the_titles_intact() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let url = testing::serve_admin(ctx);
+        let client = testing::http_client();
+        let service_id = testing::insert_service(ctx, "Forge", "https://forge.example.org").await;
+        let user_id = testing::insert_user(ctx, "alice", Role::Admin, None).await;
+        let session = testing::open_session(ctx, user_id).await;
+
+        let title = "Forge, \"the\" forge,\nis down";
+        let mut form = testing::intervention_form(service_id);
+        form.retain(|(name, _)| *name != "title");
+        form.push(("title", title.to_owned()));
+        form.push(("csrf-token", session.csrf_token.clone()));
+        let response = client
+            .post(format!("{url}/api/intervention"))
+            .header(header::COOKIE, session.cookie())
+            .form(&form)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+
+        let export = |format: &str| {
+            client
+                .get(format!("{url}/audit?entity=intervention&format={format}"))
+                .header(header::COOKIE, session.cookie())
+                .send()
+        };
+
+        let response = export("csv").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/csv; charset=utf-8"
+        );
+        let rows = parse_csv(&response.text().await.unwrap());
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            rows[0],
+            [
+                "id",
+                "date",
+                "user_id",
+                "username",
+                "action",
+                "entity",
+                "entity_id",
+                "diff"
+            ]
+        );
+        assert_eq!(rows[1].len(), 8);
+        assert_eq!(rows[1][2], user_id.to_string());
+        assert_eq!(rows[1][3], "alice");
+        assert_eq!(rows[1][4], "create");
+        assert_eq!(rows[1][5], "intervention");
+        let diff: Value = serde_json::from_str(&rows[1][7]).unwrap();
+        assert_eq!(diff["before"], Value::Null);
+        assert_eq!(diff["after"]["title"], title);
+
+        let response = export("json").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let entries: Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(entries.as_array().unwrap().len(), 1);
+        assert_eq!(entries[0]["username"], "alice");
+        assert_eq!(entries[0]["diff"]["after"]["title"], title);
+
+        // The fields themselves are quoted whenever they need to.
+        for field in ["a,b", "a\"b", "a\nb", "a\r\nb"] {
+            assert_eq!(parse_csv(&format!("{}\n", csv_field(field))), [[field]]);
+        }
+        assert_eq!(csv_field("plain"), "plain");
+    }
+}
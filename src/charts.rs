@@ -0,0 +1,202 @@
+//! Static charts of the history of the services, computed from the probe aggregates.
+//!
+//! Only the geometry is computed here; the templates draw it as inline SVG, in a viewBox of
+//! [`WIDTH`] x [`HEIGHT`], so the pages don't need any JavaScript.
+
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::db::models::probes::{ProbeAggregate, Resolution};
+
+/// Width of the viewBox of the charts.
+const WIDTH: f64 = 100.0;
+
+/// Height of the viewBox of the charts.
+const HEIGHT: f64 = 30.0;
+
+/// Part of the height left above the highest point of the response time charts.
+const TOP_MARGIN: f64 = 0.1;
+
+/// Average response times over a period, as a line broken where there's no data.
+#[derive(Clone, Serialize)]
+pub(crate) struct ResponseTimeChart {
+    /// Points of each part of the line, in the format of the `points` attribute of a
+    /// `<polyline>`.
+    segments: Vec<String>,
+    /// Average response time over the whole period, in milliseconds.
+    avg_ms: i64,
+    /// Highest average response time over a bucket, in milliseconds.
+    max_ms: i64,
+}
+
+/// Availability over a bucket of a period.
+#[derive(Clone, Serialize)]
+struct AvailabilityBar {
+    x: f64,
+    width: f64,
+    css_class: &'static str,
+    /// Describes the bar, for tooltips and screen readers.
+    label: String,
+}
+
+/// Availability over a period, as a bar per bucket.
+#[derive(Clone, Serialize)]
+pub(crate) struct AvailabilityChart {
+    bars: Vec<AvailabilityBar>,
+    /// Availability over the whole period, as a percentage.
+    availability: String,
+}
+
+/// Round a coordinate, to keep the pages small.
+fn round(coordinate: f64) -> f64 {
+    (coordinate * 1000.0).round() / 1000.0
+}
+
+/// Index of the bucket of an aggregate within the period, if it belongs to it.
+fn bucket_index(
+    aggregate: &ProbeAggregate,
+    resolution: Resolution,
+    from: NaiveDateTime,
+    num_buckets: usize,
+) -> Option<usize> {
+    let offset = aggregate.bucket_start - from;
+    if offset < chrono::Duration::zero() {
+        return None;
+    }
+    let index = (offset.num_seconds() / resolution.duration().num_seconds()) as usize;
+    (index < num_buckets).then_some(index)
+}
+
+/// Format a percentage, without pretending to be more precise than it is.
+fn format_percentage(ratio: f64) -> String {
+    // TODO i18n
+    let percentage = format!("{:.2}", ratio * 100.0).replace('.', ",");
+    percentage
+        .trim_end_matches('0')
+        .trim_end_matches(',')
+        .to_owned()
+}
+
+/// Chart of the average response times of the given aggregates, over `num_buckets` buckets
+/// starting at `from`; returns nothing if no response time was measured in that period.
+pub(crate) fn response_time(
+    aggregates: &[&ProbeAggregate],
+    resolution: Resolution,
+    from: NaiveDateTime,
+    num_buckets: usize,
+) -> Option<ResponseTimeChart> {
+    let mut averages = vec![None; num_buckets];
+    let mut sum_ms = 0;
+    let mut num_response_times = 0;
+
+    for aggregate in aggregates {
+        let Some(index) = bucket_index(aggregate, resolution, from, num_buckets) else {
+            continue;
+        };
+        averages[index] = aggregate.avg_response_time_ms();
+        sum_ms += aggregate.sum_response_time_ms;
+        num_response_times += aggregate.num_response_times;
+    }
+
+    if num_response_times == 0 {
+        return None;
+    }
+
+    let max_ms = averages.iter().flatten().copied().fold(1.0, f64::max);
+    let scale = HEIGHT * (1.0 - TOP_MARGIN) / max_ms;
+    let bucket_width = WIDTH / num_buckets as f64;
+
+    let mut segments = Vec::new();
+    let mut current: Vec<String> = Vec::new();
+    for (index, average) in averages.iter().enumerate() {
+        match average {
+            Some(average) => {
+                let x = (index as f64 + 0.5) * bucket_width;
+                let y = HEIGHT - average * scale;
+                current.push(format!("{x:.2},{y:.2}"));
+            }
+            None => {
+                if !current.is_empty() {
+                    segments.push(current.join(" "));
+                    current.clear();
+                }
+            }
+        }
+    }
+    if !current.is_empty() {
+        segments.push(current.join(" "));
+    }
+
+    Some(ResponseTimeChart {
+        segments,
+        avg_ms: sum_ms / num_response_times,
+        max_ms: max_ms.round() as i64,
+    })
+}
+
+/// Chart of the availability of the given aggregates, over `num_buckets` buckets starting at
+/// `from`; returns nothing if there was no check in that period.
+pub(crate) fn availability(
+    aggregates: &[&ProbeAggregate],
+    resolution: Resolution,
+    from: NaiveDateTime,
+    num_buckets: usize,
+) -> Option<AvailabilityChart> {
+    let mut buckets: Vec<Option<&ProbeAggregate>> = vec![None; num_buckets];
+    for aggregate in aggregates {
+        if let Some(index) = bucket_index(aggregate, resolution, from, num_buckets) {
+            buckets[index] = Some(aggregate);
+        }
+    }
+
+    let num_checks: i64 = buckets.iter().flatten().map(|a| a.num_checks).sum();
+    if num_checks == 0 {
+        return None;
+    }
+    let num_successes: i64 = buckets.iter().flatten().map(|a| a.num_successes).sum();
+
+    let date_format = match resolution {
+        Resolution::Hour => "%d/%m/%Y %H:%M",
+        Resolution::Day => "%d/%m/%Y",
+    };
+
+    let bucket_width = WIDTH / num_buckets as f64;
+    let bars = buckets
+        .iter()
+        .enumerate()
+        .map(|(index, aggregate)| {
+            let date = (from + resolution.duration() * index as i32).format(date_format);
+            let (css_class, label) = match aggregate.filter(|a| a.num_checks > 0) {
+                Some(aggregate) => {
+                    let ratio = aggregate.num_successes as f64 / aggregate.num_checks as f64;
+                    let css_class = if ratio >= 0.999 {
+                        "operational"
+                    } else if ratio >= 0.99 {
+                        "performance-issue"
+                    } else if ratio >= 0.9 {
+                        "partial-outage"
+                    } else {
+                        "full-outage"
+                    };
+                    // TODO i18n
+                    (
+                        css_class,
+                        format!("{date} : {} %", format_percentage(ratio)),
+                    )
+                }
+                None => ("no-data", format!("{date} : pas de données")),
+            };
+            AvailabilityBar {
+                x: round(index as f64 * bucket_width),
+                width: round(bucket_width),
+                css_class,
+                label,
+            }
+        })
+        .collect();
+
+    Some(AvailabilityChart {
+        bars,
+        availability: format_percentage(num_successes as f64 / num_checks as f64),
+    })
+}
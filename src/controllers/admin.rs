@@ -1,50 +1,49 @@
-use axum::extract::RawForm;
-use axum::response::Response;
+use axum::extract::{Path, Query, RawForm};
 use axum::{
-    http::{header, HeaderValue, StatusCode},
-    response::{Html, IntoResponse},
-    Extension, Form,
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Extension, Form, Json,
 };
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use sqlx::Connection as _;
 
 use std::sync::Arc;
 use tracing as log;
 
+use super::{auth::CurrentUser, forbidden, not_found, redirect};
 use crate::{
+    audit::{self, Actor, AuditParams},
     db::{
+        models::alert_rules::AlertRule,
+        models::api_tokens::{ApiToken, Scope},
+        models::audit::{AuditAction, AuditEntity, AuditEntry},
+        models::heartbeats::Heartbeat,
         models::interventions::{Intervention, Severity, Status},
+        models::monitors::{MonitorSource, MonitorState},
+        models::outbox::{OutboxEntry, OutboxStatus},
+        models::preferences::{Preferences, SubscriptionKind},
+        models::probes::{DnsCheck, HttpCheck, Probe, ProbeCheck, ProbeResult, TcpCheck, TlsCheck},
         models::services::{Service, ServiceWithNumInterventions},
+        models::sessions::Session,
+        models::teams::Team,
+        models::users::{Role, User},
+        models::webhooks::{Webhook, WebhookDelivery},
     },
+    interventions,
+    monitoring::{probes, receivers},
+    notifications::Event,
+    reports::{self, ReportOptions, ReportParams},
+    status::InterventionWithServices,
+    tokens::{self, random_token},
     AppContext,
 };
 
-macro_rules! try500 {
-    ($val:expr, $ctx:literal) => {
-        match $val {
-            Ok(r) => r,
-            Err(err) => {
-                log::error!("error when {}: {:?}", $ctx, err,);
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Html("Ohnoes, something went wrong!").into_response(),
-                );
-            }
-        }
-    };
-}
+/// Format of the dates in the forms, as used by `datetime-local` inputs.
+const FORM_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M";
 
-fn not_found(text: impl Into<String>) -> (StatusCode, Response) {
-    (StatusCode::NOT_FOUND, Html(text.into()).into_response())
-}
-
-fn redirect(to_url: &'static str) -> (StatusCode, Response) {
-    let location = HeaderValue::from_static(to_url);
-    (
-        StatusCode::FOUND,
-        [(header::LOCATION, location)].into_response(),
-    )
-}
+/// Prefix of the API tokens, so they're easy to recognize (e.g. by secret scanners).
+const API_TOKEN_PREFIX: &str = "rst_";
 
 /// Status read from the form, using the "value" HTML fields.
 #[derive(Deserialize)]
@@ -83,6 +82,8 @@ struct AdminRenderIntervention {
     pub description: Option<String>,
     pub status: String,
     pub is_planned: String,
+    /// Can the current user edit the intervention?
+    pub editable: bool,
 }
 
 impl From<&Intervention> for AdminRenderIntervention {
@@ -98,17 +99,31 @@ impl From<&Intervention> for AdminRenderIntervention {
             description: value.description.clone(),
             status: value.status.label().to_owned(),
             is_planned: value.is_planned.to_string(),
+            editable: false,
         }
     }
 }
 
+#[derive(Serialize)]
+struct AdminRenderService {
+    #[serde(flatten)]
+    service: ServiceWithNumInterventions,
+    /// Can the current user manage the interventions of the service?
+    manageable: bool,
+}
+
 #[derive(Serialize)]
 struct AdminTemplateCtx {
     interventions: Vec<AdminRenderIntervention>,
-    services: Vec<ServiceWithNumInterventions>,
+    services: Vec<AdminRenderService>,
+    can_edit: bool,
+    is_admin: bool,
 }
 
-pub(crate) async fn index(Extension(ctx): Extension<Arc<AppContext>>) -> impl IntoResponse {
+pub(crate) async fn index(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+) -> impl IntoResponse {
     let (services, interventions) = {
         let mut conn = ctx.db_connection.lock().await;
         let services = try500!(
@@ -117,21 +132,45 @@ pub(crate) async fn index(Extension(ctx): Extension<Arc<AppContext>>) -> impl In
         );
 
         let interventions = try500!(
-            Intervention::get_all(&mut conn).await,
+            InterventionWithServices::get_all(&mut conn).await,
             "retrieving list of interventions for admin index"
         );
 
         (services, interventions)
     };
 
+    let manages = |service_id: i64| {
+        services
+            .iter()
+            .find(|s| s.id == service_id)
+            .is_some_and(|s| user.can_manage(s.team_id))
+    };
+    let interventions = interventions
+        .iter()
+        .map(|int| AdminRenderIntervention {
+            editable: user.can(Role::Editor) && int.service_ids.iter().all(|sid| manages(sid.0)),
+            ..AdminRenderIntervention::from(&int.intervention)
+        })
+        .collect();
+    let services = services
+        .into_iter()
+        .map(|service| AdminRenderService {
+            manageable: user.can_manage(service.team_id),
+            service,
+        })
+        .collect();
+
     // TODO: render intervention.description as Markdown
     let mut render_ctx = try500!(
         tera::Context::from_serialize(AdminTemplateCtx {
-            interventions: interventions.iter().map(From::from).collect(),
+            interventions,
             services,
+            can_edit: user.can(Role::Editor),
+            is_admin: user.can(Role::Admin),
         }),
         "preparing context for admin template"
     );
+    render_ctx.insert("username", &user.username);
 
     {
         let toast = ctx.toast.write().unwrap().take();
@@ -140,6 +179,8 @@ pub(crate) async fn index(Extension(ctx): Extension<Arc<AppContext>>) -> impl In
         }
     }
 
+    render_ctx.insert("csrf_token", &user.csrf_token);
+
     let page = try500!(
         ctx.templates
             .read()
@@ -153,12 +194,26 @@ pub(crate) async fn index(Extension(ctx): Extension<Arc<AppContext>>) -> impl In
 
 pub(crate) async fn create_service_form(
     Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
 ) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let teams = {
+        let mut conn = ctx.db_connection.lock().await;
+        try500!(Team::get_all(&mut conn).await, "retrieving list of teams")
+    };
+
+    let mut render_ctx = tera::Context::new();
+    render_ctx.insert("teams", &teams);
+    render_ctx.insert("csrf_token", &user.csrf_token);
+
     let page = try500!(
         ctx.templates
             .read()
             .unwrap()
-            .render("new-service.html", &tera::Context::new()),
+            .render("new-service.html", &render_ctx),
         "rendering new-intervention template"
     );
 
@@ -169,25 +224,70 @@ pub(crate) async fn create_service_form(
 pub struct CreateService {
     name: String,
     url: String,
+    #[serde(default)]
+    team: Option<i64>,
+}
+
+/// Check the team chosen in a form exists, if any.
+async fn check_team(
+    team_id: Option<i64>,
+    conn: &mut sqlx::AnyConnection,
+) -> Result<(), (StatusCode, Response)> {
+    let Some(team_id) = team_id else {
+        return Ok(());
+    };
+    match Team::by_id(team_id, conn).await {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(not_found(format!("Team with id {team_id} doesn't exist!"))),
+        Err(err) => {
+            log::error!("error when retrieving a team by id: {err:#}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html("Ohnoes, something went wrong!").into_response(),
+            ))
+        }
+    }
 }
 
 pub(crate) async fn create_service(
-    // this argument tells axum to parse the request body
-    // as JSON into a `CreateService` type
     Extension(ctx): Extension<Arc<AppContext>>,
-    Form(payload): Form<CreateService>,
+    Extension(user): Extension<CurrentUser>,
+    RawForm(request_bytes): RawForm,
 ) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let payload: CreateService = match serde_html_form::from_bytes(&request_bytes) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("error when parsing new-service request: {err:#}");
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("invalid request").into_response(),
+            );
+        }
+    };
+
     let service = Service {
         id: None,
         name: payload.name,
         url: payload.url,
+        team_id: payload.team,
     };
 
     {
         let mut conn = ctx.db_connection.lock().await;
+        if let Err(response) = check_team(service.team_id, &mut conn).await {
+            return response;
+        }
         let s_id = Service::insert(&mut conn, &service).await;
         let id = try500!(s_id, "inserting a new service");
         log::trace!("service {} created with id {}", service.name, id);
+        try500!(
+            audit::created(&mut conn, &Actor::from(&user), id, &service).await,
+            "recording a new service in the audit log"
+        );
     }
 
     if let Err(err) = ctx.regenerate_pages.send(()).await {
@@ -209,12 +309,29 @@ pub struct FormIntervention {
     estimated_duration: Option<i64>,
     severity: Severity,
     status: Status,
+    #[serde(rename = "auto-resolve", default)]
+    auto_resolve: Option<String>,
     services: Vec<u64>,
+    /// Only when editing: a new update to post on the intervention.
+    #[serde(default)]
+    update: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ServiceRenderCtx {
+    id: i64,
+    name: String,
+    selected: bool,
 }
 
 pub(crate) async fn create_intervention_form(
     Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
 ) -> impl IntoResponse {
+    if !user.can(Role::Editor) {
+        return forbidden();
+    }
+
     let services = {
         let mut conn = ctx.db_connection.lock().await;
         try500!(
@@ -223,30 +340,28 @@ pub(crate) async fn create_intervention_form(
         )
     };
 
-    #[derive(Serialize)]
-    struct ServiceRenderCtx {
-        id: i64,
-        name: String,
-    }
-
     #[derive(Serialize)]
     struct CreateInterventionFormRenderCtx {
         services: Vec<ServiceRenderCtx>,
     }
 
-    let render_ctx = try500!(
+    let mut render_ctx = try500!(
         tera::Context::from_serialize(CreateInterventionFormRenderCtx {
             services: services
                 .into_iter()
+                .filter(|s| user.can_manage(s.team_id))
                 .map(|s| ServiceRenderCtx {
                     id: s.id.unwrap(),
                     name: s.name,
+                    selected: false,
                 })
                 .collect(),
         }),
         "preparing context for new-intervention template"
     );
 
+    render_ctx.insert("csrf_token", &user.csrf_token);
+
     let page = try500!(
         ctx.templates
             .read()
@@ -260,8 +375,13 @@ pub(crate) async fn create_intervention_form(
 
 pub(crate) async fn create_intervention(
     Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
     RawForm(request_bytes): RawForm,
 ) -> impl IntoResponse {
+    if !user.can(Role::Editor) {
+        return forbidden();
+    }
+
     let payload: FormIntervention = match serde_html_form::from_bytes(&request_bytes) {
         Ok(payload) => payload,
         Err(err) => {
@@ -274,7 +394,7 @@ pub(crate) async fn create_intervention(
     };
 
     let start_date = try500!(
-        NaiveDateTime::parse_from_str(&payload.start_date, "%Y-%m-%dT%H:%M"),
+        NaiveDateTime::parse_from_str(&payload.start_date, FORM_DATE_FORMAT),
         "converting start date to NaiveDateTime"
     );
 
@@ -288,41 +408,3054 @@ pub(crate) async fn create_intervention(
         end_date: None,
         severity: payload.severity,
         is_planned: payload.status == Status::Planned,
+        auto_resolve: payload.status == Status::Planned && payload.auto_resolve.is_some(),
     };
 
     {
         let mut conn = ctx.db_connection.lock().await;
+        let mut tx = try500!(conn.begin().await, "starting a transaction");
 
-        // Check all the services exist before doing any write.
+        // Check all the services exist, and may be managed, before doing any write.
         for sid in &payload.services {
             let service = try500!(
-                Service::by_id(*sid as i64, &mut conn).await,
+                Service::by_id(*sid as i64, &mut tx).await,
                 "retrieving a service by id"
             );
-            if service.is_none() {
+            let Some(service) = service else {
                 return not_found(format!("Service with id {sid} doesn't exist!"));
+            };
+            if !user.can_manage(service.team_id) {
+                return forbidden();
             }
         }
 
         // All the services exists; confirm write.
-        let int_id = try500!(
-            Intervention::insert(&mut conn, &intervention).await,
+        let mut service_ids: Vec<_> = payload.services.iter().map(|sid| *sid as i64).collect();
+        service_ids.sort_unstable();
+        service_ids.dedup();
+        try500!(
+            interventions::create(
+                &ctx,
+                &mut tx,
+                &Actor::from(&user),
+                &intervention,
+                &service_ids
+            )
+            .await,
             "creating a new intervention"
         );
 
-        for sid in payload.services {
-            if let Err(err) = Intervention::add_service(int_id, sid as i64, &mut conn).await {
-                log::error!("when adding a service to an intervention: {err}");
+        try500!(tx.commit().await, "committing a new intervention");
+    }
+
+    // TODO i18n
+    *ctx.toast.write().unwrap() = Some(format!("Intervention {} created!", intervention.title));
+
+    if let Err(err) = ctx.regenerate_pages.send(()).await {
+        log::error!("unable to regenerate page: {err:#}");
+    }
+
+    if let Err(err) = ctx.wake_outbox.send(()).await {
+        log::error!("unable to send notifications: {err:#}");
+    }
+
+    if let Err(err) = ctx.wake_scheduler.send(()).await {
+        log::error!("unable to wake up the scheduler: {err:#}");
+    }
+
+    redirect("/admin")
+}
+
+pub(crate) async fn edit_intervention_form(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if !user.can(Role::Editor) {
+        return forbidden();
+    }
+
+    let (intervention, service_ids, services, changes) = {
+        let mut conn = ctx.db_connection.lock().await;
+
+        let intervention = try500!(
+            Intervention::by_id(id, &mut conn).await,
+            "retrieving an intervention by id"
+        );
+        let Some(intervention) = intervention else {
+            return not_found(format!("Intervention with id {id} doesn't exist!"));
+        };
+
+        let changes = try500!(
+            Intervention::get_changes(id, &mut conn).await,
+            "retrieving the changes of an intervention"
+        );
+
+        let service_ids = try500!(
+            Intervention::get_service_ids(id, &mut conn).await,
+            "retrieving the services of an intervention"
+        );
+
+        let services = try500!(
+            Service::get_all(&mut conn).await,
+            "retrieving services when editing an intervention"
+        );
+
+        // The intervention can only be edited by those who manage all its services.
+        let manages_all = services
+            .iter()
+            .filter(|s| service_ids.iter().any(|sid| Some(sid.0) == s.id))
+            .all(|s| user.can_manage(s.team_id));
+        if !manages_all {
+            return forbidden();
+        }
+
+        (intervention, service_ids, services, changes)
+    };
+
+    #[derive(Serialize)]
+    struct InterventionChangeRenderCtx {
+        date: NaiveDateTime,
+        action: String,
+        username: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct EditInterventionRenderCtx {
+        id: i64,
+        title: String,
+        description: Option<String>,
+        start_date: String,
+        estimated_duration: Option<i64>,
+        severity: Severity,
+        status: Status,
+        auto_resolve: bool,
+    }
+
+    #[derive(Serialize)]
+    struct EditInterventionFormRenderCtx {
+        intervention: EditInterventionRenderCtx,
+        services: Vec<ServiceRenderCtx>,
+        /// Most recent first.
+        changes: Vec<InterventionChangeRenderCtx>,
+    }
+
+    let mut render_ctx = try500!(
+        tera::Context::from_serialize(EditInterventionFormRenderCtx {
+            intervention: EditInterventionRenderCtx {
+                id,
+                title: intervention.title,
+                description: intervention.description,
+                start_date: intervention.start_date.format(FORM_DATE_FORMAT).to_string(),
+                estimated_duration: intervention.estimated_duration,
+                severity: intervention.severity,
+                status: intervention.status,
+                auto_resolve: intervention.auto_resolve,
+            },
+            services: services
+                .into_iter()
+                .filter(|s| user.can_manage(s.team_id))
+                .map(|s| {
+                    let id = s.id.unwrap();
+                    ServiceRenderCtx {
+                        id,
+                        name: s.name,
+                        selected: service_ids.iter().any(|sid| sid.0 == id),
+                    }
+                })
+                .collect(),
+            changes: changes
+                .into_iter()
+                .map(|c| InterventionChangeRenderCtx {
+                    date: c.date,
+                    action: c.action.label().to_owned(),
+                    username: c.username,
+                })
+                .collect(),
+        }),
+        "preparing context for edit-intervention template"
+    );
+
+    render_ctx.insert("csrf_token", &user.csrf_token);
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("edit-intervention.html", &render_ctx),
+        "rendering edit-intervention template"
+    );
+
+    (StatusCode::OK, Html(page).into_response())
+}
+
+pub(crate) async fn update_intervention(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(id): Path<i64>,
+    RawForm(request_bytes): RawForm,
+) -> impl IntoResponse {
+    if !user.can(Role::Editor) {
+        return forbidden();
+    }
+
+    let payload: FormIntervention = match serde_html_form::from_bytes(&request_bytes) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("error when parsing update-intervention request: {err:#}");
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("invalid request").into_response(),
+            );
+        }
+    };
+
+    let start_date = try500!(
+        NaiveDateTime::parse_from_str(&payload.start_date, FORM_DATE_FORMAT),
+        "converting start date to NaiveDateTime"
+    );
+
+    let now = chrono::Utc::now().naive_utc();
+
+    let intervention = {
+        let mut conn = ctx.db_connection.lock().await;
+        let mut tx = try500!(conn.begin().await, "starting a transaction");
+
+        let previous = try500!(
+            Intervention::by_id(id, &mut tx).await,
+            "retrieving an intervention by id"
+        );
+        let Some(previous) = previous else {
+            return not_found(format!("Intervention with id {id} doesn't exist!"));
+        };
+
+        // The intervention can only be edited by those who manage all its services.
+        let previous_service_ids = try500!(
+            Intervention::get_service_ids(id, &mut tx).await,
+            "retrieving the services of an intervention"
+        );
+        for sid in &previous_service_ids {
+            let service = try500!(
+                Service::by_id(sid.0, &mut tx).await,
+                "retrieving a service by id"
+            );
+            if service.is_some_and(|service| !user.can_manage(service.team_id)) {
+                return forbidden();
+            }
+        }
+
+        // Check all the services exist, and may be managed, before doing any write.
+        for sid in &payload.services {
+            let service = try500!(
+                Service::by_id(*sid as i64, &mut tx).await,
+                "retrieving a service by id"
+            );
+            let Some(service) = service else {
+                return not_found(format!("Service with id {sid} doesn't exist!"));
+            };
+            if !user.can_manage(service.team_id) {
+                return forbidden();
             }
         }
+
+        let is_resolved = payload.status == Status::Resolved;
+        let is_planned = previous.is_planned || payload.status == Status::Planned;
+
+        let intervention = Intervention {
+            id: Some(id),
+            title: payload.title,
+            description: Some(payload.description),
+            status: payload.status,
+            start_date,
+            estimated_duration: payload.estimated_duration,
+            // Keep the original end date, if the intervention had already been resolved.
+            end_date: is_resolved.then(|| previous.end_date.unwrap_or(now)),
+            severity: payload.severity,
+            is_planned,
+            auto_resolve: is_planned && payload.auto_resolve.is_some(),
+        };
+
+        let mut service_ids: Vec<_> = payload.services.iter().map(|sid| *sid as i64).collect();
+        service_ids.sort_unstable();
+        service_ids.dedup();
+        try500!(
+            interventions::update(
+                &ctx,
+                &mut tx,
+                &Actor::from(&user),
+                id,
+                &intervention,
+                Some(&service_ids),
+                payload.update,
+            )
+            .await,
+            "updating an intervention"
+        );
+
+        try500!(tx.commit().await, "committing an updated intervention");
+
+        intervention
     };
 
     // TODO i18n
-    *ctx.toast.write().unwrap() = Some(format!("Intervention {} created!", intervention.title));
+    *ctx.toast.write().unwrap() = Some(format!("Intervention {} updated!", intervention.title));
 
     if let Err(err) = ctx.regenerate_pages.send(()).await {
         log::error!("unable to regenerate page: {err:#}");
     }
 
+    if let Err(err) = ctx.wake_outbox.send(()).await {
+        log::error!("unable to send notifications: {err:#}");
+    }
+
+    if let Err(err) = ctx.wake_scheduler.send(()).await {
+        log::error!("unable to wake up the scheduler: {err:#}");
+    }
+
     redirect("/admin")
 }
+
+/// Number of webhook deliveries displayed in the admin.
+const NUM_DISPLAYED_DELIVERIES: i64 = 50;
+
+pub(crate) async fn webhooks(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let (webhooks, deliveries, services) = {
+        let mut conn = ctx.db_connection.lock().await;
+        let mut webhooks = Vec::new();
+        for webhook in try500!(
+            Webhook::get_all(&mut conn).await,
+            "retrieving list of webhooks"
+        ) {
+            let prefs = try500!(
+                Preferences::read(SubscriptionKind::Webhook, webhook.id.unwrap(), &mut conn).await,
+                "retrieving the preferences of a webhook"
+            );
+            webhooks.push((webhook, prefs));
+        }
+        let deliveries = try500!(
+            WebhookDelivery::get_recent(NUM_DISPLAYED_DELIVERIES, &mut conn).await,
+            "retrieving list of webhook deliveries"
+        );
+        let services = try500!(
+            Service::get_all(&mut conn).await,
+            "retrieving services for the webhooks page"
+        );
+        (webhooks, deliveries, services)
+    };
+
+    #[derive(Serialize)]
+    struct WebhookRenderCtx {
+        id: i64,
+        url: String,
+        secret: String,
+        events: Vec<String>,
+        enabled: bool,
+        /// Names of the services of interest; empty means all of them.
+        services: Vec<String>,
+        min_severity: Option<String>,
+        include_planned: bool,
+    }
+
+    #[derive(Serialize)]
+    struct WebhookDeliveryRenderCtx {
+        url: String,
+        event: String,
+        attempt: i64,
+        date: NaiveDateTime,
+        status_code: Option<i64>,
+        error: Option<String>,
+        payload: String,
+    }
+
+    #[derive(Serialize)]
+    struct WebhooksTemplateCtx {
+        webhooks: Vec<WebhookRenderCtx>,
+        deliveries: Vec<WebhookDeliveryRenderCtx>,
+        event_names: &'static [&'static str],
+        services: Vec<ServiceRenderCtx>,
+    }
+
+    let deliveries = deliveries
+        .into_iter()
+        .map(|d| WebhookDeliveryRenderCtx {
+            url: webhooks
+                .iter()
+                .map(|(w, _)| w)
+                .find(|w| w.id == Some(d.webhook_id))
+                .map(|w| w.url.clone())
+                .unwrap_or_default(),
+            event: d.event,
+            attempt: d.attempt,
+            date: d.date,
+            status_code: d.status_code,
+            error: d.error,
+            payload: d.payload,
+        })
+        .collect();
+
+    let mut render_ctx = try500!(
+        tera::Context::from_serialize(WebhooksTemplateCtx {
+            webhooks: webhooks
+                .into_iter()
+                .map(|(w, prefs)| WebhookRenderCtx {
+                    id: w.id.unwrap(),
+                    url: w.url,
+                    secret: w.secret,
+                    events: w.events,
+                    enabled: w.enabled,
+                    services: services
+                        .iter()
+                        .filter(|s| prefs.service_ids.contains(&s.id.unwrap()))
+                        .map(|s| s.name.clone())
+                        .collect(),
+                    min_severity: prefs.min_severity.map(|s| s.label().to_owned()),
+                    include_planned: prefs.include_planned,
+                })
+                .collect(),
+            deliveries,
+            event_names: Event::NAMES,
+            services: services
+                .into_iter()
+                .map(|s| ServiceRenderCtx {
+                    id: s.id.unwrap(),
+                    name: s.name,
+                    selected: false,
+                })
+                .collect(),
+        }),
+        "preparing context for webhooks template"
+    );
+
+    {
+        let toast = ctx.toast.write().unwrap().take();
+        if let Some(t) = toast {
+            render_ctx.insert("toast_success", &t);
+        }
+    }
+
+    render_ctx.insert("csrf_token", &user.csrf_token);
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("webhooks.html", &render_ctx),
+        "rendering webhooks template"
+    );
+
+    (StatusCode::OK, Html(page).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct FormWebhook {
+    url: String,
+    /// If empty, a random secret is generated.
+    #[serde(default)]
+    secret: String,
+    #[serde(default)]
+    events: Vec<String>,
+    /// No service at all means all of them.
+    #[serde(default)]
+    services: Vec<i64>,
+    #[serde(rename = "min-severity", default)]
+    min_severity: Option<Severity>,
+    #[serde(rename = "include-planned", default)]
+    include_planned: Option<String>,
+}
+
+pub(crate) async fn create_webhook(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    RawForm(request_bytes): RawForm,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let payload: FormWebhook = match serde_html_form::from_bytes(&request_bytes) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("error when parsing new-webhook request: {err:#}");
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("invalid request").into_response(),
+            );
+        }
+    };
+
+    if reqwest::Url::parse(&payload.url).is_err() {
+        return (StatusCode::BAD_REQUEST, Html("invalid URL").into_response());
+    }
+
+    if let Some(unknown) = payload
+        .events
+        .iter()
+        .find(|e| !Event::NAMES.contains(&e.as_str()))
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html(format!("unknown event {unknown}")).into_response(),
+        );
+    }
+
+    let secret = if payload.secret.trim().is_empty() {
+        random_token()
+    } else {
+        payload.secret.trim().to_owned()
+    };
+
+    let webhook = Webhook {
+        id: None,
+        url: payload.url,
+        secret,
+        events: payload.events,
+        enabled: true,
+    };
+
+    let prefs = Preferences {
+        service_ids: payload.services,
+        min_severity: payload.min_severity,
+        include_planned: payload.include_planned.is_some(),
+    };
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+
+        // Check all the services exist before doing any write.
+        for sid in &prefs.service_ids {
+            let service = try500!(
+                Service::by_id(*sid, &mut conn).await,
+                "looking for a service when creating a webhook"
+            );
+            if service.is_none() {
+                return not_found(format!("service {sid} not found"));
+            }
+        }
+
+        let id = try500!(
+            Webhook::insert(&mut conn, &webhook).await,
+            "inserting a new webhook"
+        );
+        try500!(
+            prefs.save(SubscriptionKind::Webhook, id, &mut conn).await,
+            "saving the preferences of a new webhook"
+        );
+        log::trace!("webhook {} created with id {}", webhook.url, id);
+        try500!(
+            audit::created(&mut conn, &Actor::from(&user), id, &webhook).await,
+            "recording a new webhook in the audit log"
+        );
+    }
+
+    *ctx.toast.write().unwrap() = Some(format!("Webhook {} created!", webhook.url));
+
+    redirect("/admin/webhooks")
+}
+
+pub(crate) async fn delete_webhook(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        let webhook = try500!(
+            Webhook::by_id(id, &mut conn).await,
+            "retrieving a webhook by id"
+        );
+        try500!(Webhook::delete(id, &mut conn).await, "deleting a webhook");
+        if let Some(webhook) = webhook {
+            try500!(
+                audit::deleted(&mut conn, &Actor::from(&user), id, &webhook).await,
+                "recording a deleted webhook in the audit log"
+            );
+        }
+    }
+
+    *ctx.toast.write().unwrap() = Some("Webhook deleted!".to_owned());
+
+    redirect("/admin/webhooks")
+}
+
+pub(crate) async fn alert_rules(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let (rules, services) = {
+        let mut conn = ctx.db_connection.lock().await;
+        let rules = try500!(
+            AlertRule::get_all(&mut conn).await,
+            "retrieving list of alert rules"
+        );
+        let services = try500!(
+            Service::get_all(&mut conn).await,
+            "retrieving services for the alert rules page"
+        );
+        (rules, services)
+    };
+
+    #[derive(Serialize)]
+    struct AlertRuleRenderCtx {
+        id: i64,
+        source: Option<String>,
+        label: String,
+        pattern: String,
+        service: String,
+        severity: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct AlertRulesTemplateCtx {
+        enabled: bool,
+        public_url: String,
+        sources: Vec<&'static str>,
+        rules: Vec<AlertRuleRenderCtx>,
+        services: Vec<ServiceRenderCtx>,
+    }
+
+    let mut render_ctx = try500!(
+        tera::Context::from_serialize(AlertRulesTemplateCtx {
+            enabled: ctx.config.alerts_token.is_some(),
+            public_url: ctx.config.public_url.clone(),
+            sources: receivers::sources().collect(),
+            rules: rules
+                .into_iter()
+                .map(|r| AlertRuleRenderCtx {
+                    id: r.id.unwrap(),
+                    source: r.source,
+                    label: r.label,
+                    pattern: r.pattern,
+                    service: services
+                        .iter()
+                        .find(|s| s.id == Some(r.service_id))
+                        .map(|s| s.name.clone())
+                        .unwrap_or_default(),
+                    severity: r.severity.map(|s| s.label().to_owned()),
+                })
+                .collect(),
+            services: services
+                .into_iter()
+                .map(|s| ServiceRenderCtx {
+                    id: s.id.unwrap(),
+                    name: s.name,
+                    selected: false,
+                })
+                .collect(),
+        }),
+        "preparing context for alert rules template"
+    );
+
+    {
+        let toast = ctx.toast.write().unwrap().take();
+        if let Some(t) = toast {
+            render_ctx.insert("toast_success", &t);
+        }
+    }
+
+    render_ctx.insert("csrf_token", &user.csrf_token);
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("alert-rules.html", &render_ctx),
+        "rendering alert rules template"
+    );
+
+    (StatusCode::OK, Html(page).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct FormAlertRule {
+    /// Empty means any source.
+    #[serde(default)]
+    source: String,
+    label: String,
+    pattern: String,
+    service: i64,
+    /// Missing means the severity of the alert.
+    #[serde(default)]
+    severity: Option<Severity>,
+}
+
+pub(crate) async fn create_alert_rule(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    RawForm(request_bytes): RawForm,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let payload: FormAlertRule = match serde_html_form::from_bytes(&request_bytes) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("error when parsing new-alert-rule request: {err:#}");
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("invalid request").into_response(),
+            );
+        }
+    };
+
+    let source = Some(payload.source.trim().to_owned()).filter(|source| !source.is_empty());
+    if let Some(source) = &source {
+        if receivers::parser(source).is_none() {
+            return (
+                StatusCode::BAD_REQUEST,
+                Html(format!("unknown alert source {source}")).into_response(),
+            );
+        }
+    }
+
+    let rule = AlertRule {
+        id: None,
+        source,
+        label: payload.label.trim().to_owned(),
+        pattern: payload.pattern.trim().to_owned(),
+        service_id: payload.service,
+        severity: payload.severity,
+    };
+    if rule.label.is_empty() || rule.pattern.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html("the label and the pattern are required").into_response(),
+        );
+    }
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+
+        let service = try500!(
+            Service::by_id(rule.service_id, &mut conn).await,
+            "looking for a service when creating an alert rule"
+        );
+        if service.is_none() {
+            return not_found(format!("service {} not found", rule.service_id));
+        }
+
+        let id = try500!(
+            AlertRule::insert(&mut conn, &rule).await,
+            "inserting a new alert rule"
+        );
+        log::trace!("alert rule created with id {}", id);
+        try500!(
+            audit::created(&mut conn, &Actor::from(&user), id, &rule).await,
+            "recording a new alert rule in the audit log"
+        );
+    }
+
+    *ctx.toast.write().unwrap() = Some("Alert rule created!".to_owned());
+
+    redirect("/admin/alert-rules")
+}
+
+pub(crate) async fn delete_alert_rule(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        let rule = try500!(
+            AlertRule::by_id(id, &mut conn).await,
+            "retrieving an alert rule by id"
+        );
+        try500!(
+            AlertRule::delete(id, &mut conn).await,
+            "deleting an alert rule"
+        );
+        if let Some(rule) = rule {
+            try500!(
+                audit::deleted(&mut conn, &Actor::from(&user), id, &rule).await,
+                "recording a deleted alert rule in the audit log"
+            );
+        }
+    }
+
+    *ctx.toast.write().unwrap() = Some("Alert rule deleted!".to_owned());
+
+    redirect("/admin/alert-rules")
+}
+
+pub(crate) async fn outbox(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    let (pending, failed) = {
+        let mut conn = ctx.db_connection.lock().await;
+        let pending = try500!(
+            OutboxEntry::get_by_status(OutboxStatus::Pending, &mut conn).await,
+            "retrieving pending notifications"
+        );
+        let failed = try500!(
+            OutboxEntry::get_by_status(OutboxStatus::Failed, &mut conn).await,
+            "retrieving failed notifications"
+        );
+        (pending, failed)
+    };
+
+    #[derive(Serialize)]
+    struct OutboxEntryRenderCtx {
+        id: i64,
+        channel: String,
+        target: String,
+        event: String,
+        attempts: i64,
+        created_at: NaiveDateTime,
+        next_attempt_at: NaiveDateTime,
+        last_error: Option<String>,
+    }
+
+    impl From<OutboxEntry> for OutboxEntryRenderCtx {
+        fn from(e: OutboxEntry) -> Self {
+            Self {
+                id: e.id.unwrap(),
+                channel: e.channel,
+                target: e.target,
+                event: e.event,
+                attempts: e.attempts,
+                created_at: e.created_at,
+                next_attempt_at: e.next_attempt_at,
+                last_error: e.last_error,
+            }
+        }
+    }
+
+    #[derive(Serialize)]
+    struct OutboxTemplateCtx {
+        pending: Vec<OutboxEntryRenderCtx>,
+        failed: Vec<OutboxEntryRenderCtx>,
+    }
+
+    let mut render_ctx = try500!(
+        tera::Context::from_serialize(OutboxTemplateCtx {
+            pending: pending.into_iter().map(From::from).collect(),
+            failed: failed.into_iter().map(From::from).collect(),
+        }),
+        "preparing context for outbox template"
+    );
+
+    {
+        let toast = ctx.toast.write().unwrap().take();
+        if let Some(t) = toast {
+            render_ctx.insert("toast_success", &t);
+        }
+    }
+
+    render_ctx.insert("is_admin", &user.can(Role::Admin));
+    render_ctx.insert("csrf_token", &user.csrf_token);
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("outbox.html", &render_ctx),
+        "rendering outbox template"
+    );
+
+    (StatusCode::OK, Html(page).into_response())
+}
+
+pub(crate) async fn reports(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Query(params): Query<ReportParams>,
+) -> impl IntoResponse {
+    let now = chrono::Utc::now().naive_utc();
+
+    let options = match ReportOptions::new(&params, now) {
+        Ok(options) => options,
+        Err(err) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Html(format!("{err:#}")).into_response(),
+            )
+        }
+    };
+
+    let report = {
+        let mut conn = ctx.db_connection.lock().await;
+        try500!(
+            reports::generate(&options, now, &mut conn).await,
+            "computing the report"
+        )
+    };
+
+    let file_name = format!("report-{}-{}", options.from, options.to);
+    match params.format.as_deref().unwrap_or("html") {
+        "html" => {}
+        "csv" => {
+            let disposition = format!("attachment; filename=\"{file_name}.csv\"");
+            return (
+                StatusCode::OK,
+                (
+                    [
+                        (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_owned()),
+                        (header::CONTENT_DISPOSITION, disposition),
+                    ],
+                    report.to_csv(),
+                )
+                    .into_response(),
+            );
+        }
+        "json" => {
+            let json = try500!(report.to_json(), "serializing the report");
+            let disposition = format!("attachment; filename=\"{file_name}.json\"");
+            return (
+                StatusCode::OK,
+                (
+                    [
+                        (header::CONTENT_TYPE, "application/json".to_owned()),
+                        (header::CONTENT_DISPOSITION, disposition),
+                    ],
+                    json,
+                )
+                    .into_response(),
+            );
+        }
+        format => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Html(format!("unknown report format: {format}")).into_response(),
+            )
+        }
+    }
+
+    #[derive(Serialize)]
+    struct ServiceReportRenderCtx {
+        id: i64,
+        name: String,
+        uptime: Option<String>,
+        downtime: String,
+        incidents: usize,
+        mttr: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct ReportsTemplateCtx {
+        month: Option<String>,
+        from: String,
+        to: String,
+        weight_full_outage: f64,
+        weight_partial_outage: f64,
+        weight_performance_issue: f64,
+        exclude_planned: bool,
+        export_query: String,
+        services: Vec<ServiceReportRenderCtx>,
+    }
+
+    let render_ctx = try500!(
+        tera::Context::from_serialize(ReportsTemplateCtx {
+            month: options.month(),
+            from: options.from.to_string(),
+            to: options.to.to_string(),
+            weight_full_outage: options.weights.full_outage,
+            weight_partial_outage: options.weights.partial_outage,
+            weight_performance_issue: options.weights.performance_issue,
+            exclude_planned: options.exclude_planned,
+            export_query: options.to_query(),
+            services: report
+                .services
+                .into_iter()
+                .map(|s| ServiceReportRenderCtx {
+                    id: s.service_id,
+                    name: s.service,
+                    uptime: s.uptime_percent.map(|uptime| format!("{uptime:.3} %")),
+                    downtime: format_minutes(s.downtime_minutes),
+                    incidents: s.incidents,
+                    mttr: s.mttr_minutes.map(format_minutes),
+                })
+                .collect(),
+        }),
+        "preparing context for reports template"
+    );
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("reports.html", &render_ctx),
+        "rendering reports template"
+    );
+
+    (StatusCode::OK, Html(page).into_response())
+}
+
+/// Format a duration given in minutes, e.g. "2 h 05 min".
+fn format_minutes(minutes: f64) -> String {
+    let minutes = minutes.round() as i64;
+    if minutes < 60 {
+        format!("{minutes} min")
+    } else {
+        format!("{} h {:02} min", minutes / 60, minutes % 60)
+    }
+}
+
+/// Number of entries of the audit log displayed in the admin; the exports have all of them.
+const NUM_DISPLAYED_AUDIT_ENTRIES: i64 = 500;
+
+pub(crate) async fn audit_log(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Query(params): Query<AuditParams>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let mut filter = match params.to_filter() {
+        Ok(filter) => filter,
+        Err(err) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Html(format!("{err:#}")).into_response(),
+            )
+        }
+    };
+
+    let format = params.format.as_deref().unwrap_or("html");
+    if format == "html" {
+        filter.limit = Some(NUM_DISPLAYED_AUDIT_ENTRIES);
+    }
+
+    let (entries, usernames) = {
+        let mut conn = ctx.db_connection.lock().await;
+        let entries = try500!(
+            AuditEntry::get_filtered(&filter, &mut conn).await,
+            "retrieving the audit log"
+        );
+        let usernames = try500!(
+            AuditEntry::get_usernames(&mut conn).await,
+            "retrieving the users of the audit log"
+        );
+        (entries, usernames)
+    };
+
+    let file_name = format!("audit-{}", chrono::Utc::now().format("%Y-%m-%d"));
+    match format {
+        "html" => {}
+        "csv" => {
+            let disposition = format!("attachment; filename=\"{file_name}.csv\"");
+            return (
+                StatusCode::OK,
+                (
+                    [
+                        (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_owned()),
+                        (header::CONTENT_DISPOSITION, disposition),
+                    ],
+                    audit::to_csv(&entries),
+                )
+                    .into_response(),
+            );
+        }
+        "json" => {
+            let json = try500!(audit::to_json(&entries), "serializing the audit log");
+            let disposition = format!("attachment; filename=\"{file_name}.json\"");
+            return (
+                StatusCode::OK,
+                (
+                    [
+                        (header::CONTENT_TYPE, "application/json".to_owned()),
+                        (header::CONTENT_DISPOSITION, disposition),
+                    ],
+                    json,
+                )
+                    .into_response(),
+            );
+        }
+        format => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Html(format!("unknown audit export format: {format}")).into_response(),
+            )
+        }
+    }
+
+    #[derive(Serialize)]
+    struct ChangeRenderCtx {
+        field: String,
+        before: Option<String>,
+        after: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct AuditEntryRenderCtx {
+        date: NaiveDateTime,
+        username: String,
+        /// Was the change made automatically?
+        system: bool,
+        action: String,
+        entity: String,
+        entity_id: Option<i64>,
+        changes: Vec<ChangeRenderCtx>,
+    }
+
+    #[derive(Serialize)]
+    struct OptionRenderCtx {
+        value: String,
+        label: String,
+    }
+
+    #[derive(Serialize)]
+    struct AuditTemplateCtx {
+        entries: Vec<AuditEntryRenderCtx>,
+        truncated: bool,
+        entities: Vec<OptionRenderCtx>,
+        actions: Vec<OptionRenderCtx>,
+        usernames: Vec<String>,
+        params: AuditParams,
+        export_query: String,
+    }
+
+    /// A value of a snapshot, as displayed; strings aren't quoted.
+    fn display_value(value: &serde_json::Value) -> Option<String> {
+        match value {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(s) => Some(s.clone()),
+            value => Some(value.to_string()),
+        }
+    }
+
+    let truncated = entries.len() as i64 >= NUM_DISPLAYED_AUDIT_ENTRIES;
+    let entries = entries
+        .into_iter()
+        .map(|entry| {
+            let diff: serde_json::Value = serde_json::from_str(&entry.diff).unwrap_or_default();
+            let before = diff["before"].as_object();
+            let after = diff["after"].as_object();
+            let mut fields: Vec<&String> = before
+                .into_iter()
+                .chain(after)
+                .flat_map(|values| values.keys())
+                .collect();
+            fields.sort();
+            fields.dedup();
+            let changes = fields
+                .into_iter()
+                .map(|field| ChangeRenderCtx {
+                    field: field.clone(),
+                    before: before.and_then(|b| b.get(field)).and_then(display_value),
+                    after: after.and_then(|a| a.get(field)).and_then(display_value),
+                })
+                .collect();
+            AuditEntryRenderCtx {
+                date: entry.date,
+                system: entry.user_id.is_none(),
+                username: entry.username,
+                action: entry.action.label().to_owned(),
+                entity: entry.entity.label().to_owned(),
+                entity_id: entry.entity_id,
+                changes,
+            }
+        })
+        .collect();
+
+    let export_query = try500!(params.to_query(), "preparing the export links");
+
+    let render_ctx = try500!(
+        tera::Context::from_serialize(AuditTemplateCtx {
+            entries,
+            truncated,
+            entities: AuditEntity::ALL
+                .iter()
+                .map(|entity| OptionRenderCtx {
+                    value: entity.to_db_str().to_owned(),
+                    label: entity.label().to_owned(),
+                })
+                .collect(),
+            actions: AuditAction::ALL
+                .iter()
+                .map(|action| OptionRenderCtx {
+                    value: action.to_db_str().to_owned(),
+                    label: action.label().to_owned(),
+                })
+                .collect(),
+            usernames,
+            params,
+            export_query,
+        }),
+        "preparing context for audit template"
+    );
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("audit.html", &render_ctx),
+        "rendering audit template"
+    );
+
+    (StatusCode::OK, Html(page).into_response())
+}
+
+pub(crate) async fn retry_outbox_entry(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let found = {
+        let mut conn = ctx.db_connection.lock().await;
+        try500!(
+            OutboxEntry::retry(id, &mut conn).await,
+            "retrying a failed notification"
+        )
+    };
+
+    if !found {
+        return not_found(format!("No failed notification with id {id}!"));
+    }
+
+    if let Err(err) = ctx.wake_outbox.send(()).await {
+        log::error!("unable to send notifications: {err:#}");
+    }
+
+    *ctx.toast.write().unwrap() = Some("Notification queued for delivery!".to_owned());
+
+    redirect("/admin/outbox")
+}
+
+/// Number of probe results displayed in the admin.
+const NUM_DISPLAYED_PROBE_RESULTS: i64 = 50;
+
+/// Number of heartbeat pings displayed in the admin.
+const NUM_DISPLAYED_PINGS: i64 = 50;
+
+pub(crate) async fn probe(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(service_id): Path<i64>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let (service, probe, results, state) = {
+        let mut conn = ctx.db_connection.lock().await;
+        let service = try500!(
+            Service::by_id(service_id, &mut conn).await,
+            "retrieving a service by id"
+        );
+        let Some(service) = service else {
+            return not_found(format!("Service with id {service_id} doesn't exist!"));
+        };
+        let probe = try500!(
+            Probe::by_service(service_id, &mut conn).await,
+            "retrieving the probe of a service"
+        );
+        let results = try500!(
+            ProbeResult::get_latest(service_id, NUM_DISPLAYED_PROBE_RESULTS, &mut conn).await,
+            "retrieving the latest probe results"
+        );
+        let state = try500!(
+            MonitorState::read(service_id, MonitorSource::Probe, &mut conn).await,
+            "retrieving the state of a probe"
+        );
+        (service, probe, results, state)
+    };
+
+    /// Settings of the probe, along with defaults for all the kinds of checks, so that the
+    /// kind can be changed in the form.
+    #[derive(Serialize)]
+    struct ProbeRenderCtx {
+        kind: &'static str,
+        enabled: bool,
+        interval_secs: i64,
+        timeout_secs: i64,
+        failure_threshold: i64,
+        success_threshold: i64,
+        min_incident_minutes: i64,
+        flap_window_minutes: i64,
+        flap_threshold: i64,
+        http: HttpCheck,
+        tcp: TcpCheck,
+        tls: TlsCheck,
+        dns: DnsCheck,
+    }
+
+    #[derive(Serialize)]
+    struct ProbeTemplateCtx {
+        service: Service,
+        /// Whether a probe has been configured for the service at all.
+        configured: bool,
+        kinds: &'static [&'static str],
+        probe: ProbeRenderCtx,
+        results: Vec<ProbeResult>,
+        consecutive_failures: i64,
+        intervention_id: Option<i64>,
+    }
+
+    // Sensible defaults for a new probe, derived from the service's URL.
+    let service_url = reqwest::Url::parse(&service.url).ok();
+    let host = service_url
+        .as_ref()
+        .and_then(|url| url.host_str())
+        .unwrap_or_default()
+        .to_owned();
+    let port = service_url
+        .as_ref()
+        .and_then(|url| url.port_or_known_default())
+        .unwrap_or(443);
+
+    let mut render_probe = ProbeRenderCtx {
+        kind: "http",
+        enabled: true,
+        interval_secs: 60,
+        timeout_secs: 10,
+        failure_threshold: 3,
+        success_threshold: 1,
+        min_incident_minutes: 0,
+        flap_window_minutes: 60,
+        flap_threshold: 0,
+        http: HttpCheck {
+            url: None,
+            expected_status: 200,
+            body_match: None,
+        },
+        tcp: TcpCheck {
+            host: host.clone(),
+            port,
+        },
+        tls: TlsCheck {
+            host: host.clone(),
+            port,
+            expiry_warning_days: 14,
+        },
+        dns: DnsCheck {
+            name: host,
+            expected_address: None,
+        },
+    };
+
+    let configured = probe.is_some();
+    if let Some(probe) = probe {
+        render_probe.kind = probe.check.kind();
+        render_probe.enabled = probe.enabled;
+        render_probe.interval_secs = probe.interval_secs;
+        render_probe.timeout_secs = probe.timeout_secs;
+        render_probe.failure_threshold = probe.failure_threshold;
+        render_probe.success_threshold = probe.success_threshold;
+        render_probe.min_incident_minutes = probe.min_incident_secs / 60;
+        render_probe.flap_window_minutes = probe.flap_window_secs / 60;
+        render_probe.flap_threshold = probe.flap_threshold;
+        match probe.check {
+            ProbeCheck::Http(check) => render_probe.http = check,
+            ProbeCheck::Tcp(check) => render_probe.tcp = check,
+            ProbeCheck::Tls(check) => render_probe.tls = check,
+            ProbeCheck::Dns(check) => render_probe.dns = check,
+        }
+    }
+
+    let mut render_ctx = try500!(
+        tera::Context::from_serialize(ProbeTemplateCtx {
+            service,
+            configured,
+            kinds: ProbeCheck::KINDS,
+            probe: render_probe,
+            results,
+            consecutive_failures: state.consecutive_failures,
+            intervention_id: state.intervention_id,
+        }),
+        "preparing context for probe template"
+    );
+
+    {
+        let toast = ctx.toast.write().unwrap().take();
+        if let Some(t) = toast {
+            render_ctx.insert("toast_success", &t);
+        }
+    }
+
+    render_ctx.insert("csrf_token", &user.csrf_token);
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("probe.html", &render_ctx),
+        "rendering probe template"
+    );
+
+    (StatusCode::OK, Html(page).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct FormProbe {
+    kind: String,
+    #[serde(default)]
+    enabled: Option<String>,
+    #[serde(rename = "interval")]
+    interval_secs: i64,
+    #[serde(rename = "timeout")]
+    timeout_secs: i64,
+    #[serde(rename = "failure-threshold")]
+    failure_threshold: i64,
+    #[serde(rename = "success-threshold")]
+    success_threshold: i64,
+    #[serde(rename = "min-incident-duration")]
+    min_incident_minutes: i64,
+    #[serde(rename = "flap-window")]
+    flap_window_minutes: i64,
+    #[serde(rename = "flap-threshold")]
+    flap_threshold: i64,
+
+    /// If empty, the service's URL is probed.
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(rename = "expected-status", default)]
+    expected_status: Option<u16>,
+    #[serde(rename = "body-match", default)]
+    body_match: Option<String>,
+
+    #[serde(rename = "tcp-host", default)]
+    tcp_host: Option<String>,
+    #[serde(rename = "tcp-port", default)]
+    tcp_port: Option<u16>,
+
+    #[serde(rename = "tls-host", default)]
+    tls_host: Option<String>,
+    #[serde(rename = "tls-port", default)]
+    tls_port: Option<u16>,
+    #[serde(rename = "expiry-warning-days", default)]
+    expiry_warning_days: Option<i64>,
+
+    #[serde(rename = "dns-name", default)]
+    dns_name: Option<String>,
+    #[serde(rename = "expected-address", default)]
+    expected_address: Option<String>,
+}
+
+impl FormProbe {
+    /// Build the check of the selected kind, or explain what's wrong with its settings.
+    fn check(self) -> Result<ProbeCheck, String> {
+        /// Trimmed value of a text field, if it isn't empty.
+        fn text(value: Option<String>) -> Option<String> {
+            value
+                .map(|value| value.trim().to_owned())
+                .filter(|value| !value.is_empty())
+        }
+
+        match self.kind.as_str() {
+            "http" => {
+                let url = text(self.url);
+                if url
+                    .as_ref()
+                    .is_some_and(|url| reqwest::Url::parse(url).is_err())
+                {
+                    return Err("invalid URL".to_owned());
+                }
+                Ok(ProbeCheck::Http(HttpCheck {
+                    url,
+                    expected_status: self.expected_status.ok_or("missing expected status code")?,
+                    body_match: self.body_match.filter(|s| !s.is_empty()),
+                }))
+            }
+
+            "tcp" => Ok(ProbeCheck::Tcp(TcpCheck {
+                host: text(self.tcp_host).ok_or("missing host")?,
+                port: self.tcp_port.ok_or("missing port")?,
+            })),
+
+            "tls" => Ok(ProbeCheck::Tls(TlsCheck {
+                host: text(self.tls_host).ok_or("missing host")?,
+                port: self.tls_port.ok_or("missing port")?,
+                expiry_warning_days: self
+                    .expiry_warning_days
+                    .ok_or("missing number of days before the expiry")?,
+            })),
+
+            "dns" => {
+                let expected_address = text(self.expected_address);
+                if expected_address
+                    .as_ref()
+                    .is_some_and(|address| address.parse::<std::net::IpAddr>().is_err())
+                {
+                    return Err("invalid expected address".to_owned());
+                }
+                Ok(ProbeCheck::Dns(DnsCheck {
+                    name: text(self.dns_name).ok_or("missing name")?,
+                    expected_address,
+                }))
+            }
+
+            kind => Err(format!("unknown kind of probe {kind}")),
+        }
+    }
+}
+
+pub(crate) async fn save_probe(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(service_id): Path<i64>,
+    RawForm(request_bytes): RawForm,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let payload: FormProbe = match serde_html_form::from_bytes(&request_bytes) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("error when parsing probe request: {err:#}");
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("invalid request").into_response(),
+            );
+        }
+    };
+
+    if payload.interval_secs < 1
+        || payload.timeout_secs < 1
+        || payload.timeout_secs > payload.interval_secs
+        || payload.failure_threshold < 1
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html("the timeout must be positive and shorter than the interval, and the threshold positive")
+                .into_response(),
+        );
+    }
+
+    if payload.success_threshold < 1
+        || payload.min_incident_minutes < 0
+        || payload.flap_threshold < 0
+        || (payload.flap_threshold > 0 && payload.flap_window_minutes < 1)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html("the thresholds and the durations must be positive").into_response(),
+        );
+    }
+
+    let enabled = payload.enabled.is_some();
+    let interval_secs = payload.interval_secs;
+    let timeout_secs = payload.timeout_secs;
+    let failure_threshold = payload.failure_threshold;
+    let success_threshold = payload.success_threshold;
+    let min_incident_secs = payload.min_incident_minutes * 60;
+    let flap_window_secs = payload.flap_window_minutes * 60;
+    let flap_threshold = payload.flap_threshold;
+
+    let check = match payload.check() {
+        Ok(check) => check,
+        Err(err) => return (StatusCode::BAD_REQUEST, Html(err).into_response()),
+    };
+
+    let probe = Probe {
+        id: None,
+        service_id,
+        enabled,
+        interval_secs,
+        timeout_secs,
+        failure_threshold,
+        success_threshold,
+        min_incident_secs,
+        flap_window_secs,
+        flap_threshold,
+        check,
+    };
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        let service = try500!(
+            Service::by_id(service_id, &mut conn).await,
+            "retrieving a service by id"
+        );
+        if service.is_none() {
+            return not_found(format!("Service with id {service_id} doesn't exist!"));
+        }
+        let previous = try500!(
+            Probe::by_service(service_id, &mut conn).await,
+            "retrieving the probe of a service"
+        );
+        try500!(Probe::save(&mut conn, &probe).await, "saving a probe");
+        let saved = try500!(
+            Probe::by_service(service_id, &mut conn).await,
+            "retrieving the probe of a service"
+        );
+        if let Some(saved) = saved {
+            try500!(
+                audit::record(
+                    &mut conn,
+                    &Actor::from(&user),
+                    saved.id.unwrap(),
+                    previous.as_ref(),
+                    Some(&saved)
+                )
+                .await,
+                "recording a saved probe in the audit log"
+            );
+        }
+    }
+
+    if let Err(err) = ctx.wake_prober.send(()).await {
+        log::error!("unable to wake up the prober: {err:#}");
+    }
+
+    *ctx.toast.write().unwrap() = Some("Probe saved!".to_owned());
+
+    redirect(&format!("/admin/service/{service_id}/probe"))
+}
+
+/// Run the probe of a service once, and report the outcome without recording it.
+pub(crate) async fn test_probe(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(service_id): Path<i64>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let (service, probe) = {
+        let mut conn = ctx.db_connection.lock().await;
+        let service = try500!(
+            Service::by_id(service_id, &mut conn).await,
+            "retrieving a service by id"
+        );
+        let probe = try500!(
+            Probe::by_service(service_id, &mut conn).await,
+            "retrieving the probe of a service"
+        );
+        (service, probe)
+    };
+    let (Some(service), Some(probe)) = (service, probe) else {
+        return not_found(format!("No probe for the service with id {service_id}!"));
+    };
+
+    let outcome = probes::check(&ctx, &probe, &service).await;
+
+    let response_time = outcome
+        .response_time
+        .map(|d| format!(" in {} ms", d.as_millis()))
+        .unwrap_or_default();
+    *ctx.toast.write().unwrap() = Some(if outcome.success {
+        format!("Probe succeeded{response_time}: {}", outcome.message)
+    } else {
+        format!("Probe failed{response_time}: {}", outcome.message)
+    });
+
+    redirect(&format!("/admin/service/{service_id}/probe"))
+}
+
+pub(crate) async fn delete_probe(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(service_id): Path<i64>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        let probe = try500!(
+            Probe::by_service(service_id, &mut conn).await,
+            "retrieving the probe of a service"
+        );
+        try500!(
+            Probe::delete_for_service(service_id, &mut conn).await,
+            "deleting a probe"
+        );
+        if let Some(probe) = probe {
+            try500!(
+                audit::deleted(&mut conn, &Actor::from(&user), probe.id.unwrap(), &probe).await,
+                "recording a deleted probe in the audit log"
+            );
+        }
+    }
+
+    if let Err(err) = ctx.wake_prober.send(()).await {
+        log::error!("unable to wake up the prober: {err:#}");
+    }
+
+    *ctx.toast.write().unwrap() = Some("Probe deleted!".to_owned());
+
+    redirect(&format!("/admin/service/{service_id}/probe"))
+}
+
+pub(crate) async fn heartbeat(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(service_id): Path<i64>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let (service, heartbeat, pings, state) = {
+        let mut conn = ctx.db_connection.lock().await;
+        let service = try500!(
+            Service::by_id(service_id, &mut conn).await,
+            "retrieving a service by id"
+        );
+        let Some(service) = service else {
+            return not_found(format!("Service with id {service_id} doesn't exist!"));
+        };
+        let heartbeat = try500!(
+            Heartbeat::by_service(service_id, &mut conn).await,
+            "retrieving the heartbeat of a service"
+        );
+        // One more than displayed, to know the interval before the oldest displayed ping.
+        let pings = try500!(
+            Heartbeat::get_latest_pings(service_id, NUM_DISPLAYED_PINGS + 1, &mut conn).await,
+            "retrieving the latest pings of a heartbeat"
+        );
+        let state = try500!(
+            MonitorState::read(service_id, MonitorSource::Heartbeat, &mut conn).await,
+            "retrieving the state of a heartbeat"
+        );
+        (service, heartbeat, pings, state)
+    };
+
+    #[derive(Serialize)]
+    struct HeartbeatRenderCtx {
+        enabled: bool,
+        period_minutes: i64,
+        grace_minutes: i64,
+        ping_url: Option<String>,
+        last_ping_at: Option<NaiveDateTime>,
+        deadline: Option<NaiveDateTime>,
+    }
+
+    #[derive(Serialize)]
+    struct PingRenderCtx {
+        date: NaiveDateTime,
+        /// Minutes since the previous ping, if known.
+        minutes_since_previous: Option<i64>,
+        /// Whether the ping came after the deadline set by the previous one.
+        late: bool,
+    }
+
+    #[derive(Serialize)]
+    struct HeartbeatTemplateCtx {
+        service: Service,
+        /// Whether a heartbeat has been configured for the service at all.
+        configured: bool,
+        heartbeat: HeartbeatRenderCtx,
+        pings: Vec<PingRenderCtx>,
+        intervention_id: Option<i64>,
+    }
+
+    let configured = heartbeat.is_some();
+    let render_heartbeat = match &heartbeat {
+        Some(heartbeat) => HeartbeatRenderCtx {
+            enabled: heartbeat.enabled,
+            period_minutes: heartbeat.period_secs / 60,
+            grace_minutes: heartbeat.grace_secs / 60,
+            ping_url: Some(format!(
+                "{}/heartbeat/{}",
+                ctx.config.public_url, heartbeat.token
+            )),
+            last_ping_at: heartbeat.last_ping_at,
+            deadline: heartbeat.enabled.then(|| heartbeat.deadline()),
+        },
+        None => HeartbeatRenderCtx {
+            enabled: true,
+            period_minutes: 24 * 60,
+            grace_minutes: 60,
+            ping_url: None,
+            last_ping_at: None,
+            deadline: None,
+        },
+    };
+
+    let allowed_secs = heartbeat
+        .as_ref()
+        .map_or(i64::MAX, |h| h.period_secs + h.grace_secs);
+    let pings = pings
+        .iter()
+        .enumerate()
+        .take(NUM_DISPLAYED_PINGS as usize)
+        .map(|(i, date)| {
+            let since_previous = pings.get(i + 1).map(|previous| *date - *previous);
+            PingRenderCtx {
+                date: *date,
+                minutes_since_previous: since_previous.map(|d| d.num_minutes()),
+                late: since_previous.is_some_and(|d| d.num_seconds() > allowed_secs),
+            }
+        })
+        .collect();
+
+    let mut render_ctx = try500!(
+        tera::Context::from_serialize(HeartbeatTemplateCtx {
+            service,
+            configured,
+            heartbeat: render_heartbeat,
+            pings,
+            intervention_id: state.intervention_id,
+        }),
+        "preparing context for heartbeat template"
+    );
+
+    {
+        let toast = ctx.toast.write().unwrap().take();
+        if let Some(t) = toast {
+            render_ctx.insert("toast_success", &t);
+        }
+    }
+
+    render_ctx.insert("csrf_token", &user.csrf_token);
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("heartbeat.html", &render_ctx),
+        "rendering heartbeat template"
+    );
+
+    (StatusCode::OK, Html(page).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct FormHeartbeat {
+    #[serde(default)]
+    enabled: Option<String>,
+    #[serde(rename = "period")]
+    period_minutes: i64,
+    #[serde(rename = "grace")]
+    grace_minutes: i64,
+}
+
+pub(crate) async fn save_heartbeat(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(service_id): Path<i64>,
+    RawForm(request_bytes): RawForm,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let payload: FormHeartbeat = match serde_html_form::from_bytes(&request_bytes) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("error when parsing heartbeat request: {err:#}");
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("invalid request").into_response(),
+            );
+        }
+    };
+
+    if payload.period_minutes < 1 || payload.grace_minutes < 0 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html("the period must be positive, and the grace time can't be negative")
+                .into_response(),
+        );
+    }
+
+    let heartbeat = Heartbeat {
+        id: None,
+        service_id,
+        // Only used when creating the heartbeat.
+        token: random_token(),
+        enabled: payload.enabled.is_some(),
+        period_secs: payload.period_minutes * 60,
+        grace_secs: payload.grace_minutes * 60,
+        created_at: chrono::Utc::now().naive_utc(),
+        last_ping_at: None,
+    };
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        let service = try500!(
+            Service::by_id(service_id, &mut conn).await,
+            "retrieving a service by id"
+        );
+        if service.is_none() {
+            return not_found(format!("Service with id {service_id} doesn't exist!"));
+        }
+        let previous = try500!(
+            Heartbeat::by_service(service_id, &mut conn).await,
+            "retrieving the heartbeat of a service"
+        );
+        try500!(
+            Heartbeat::save(&mut conn, &heartbeat).await,
+            "saving a heartbeat"
+        );
+        let saved = try500!(
+            Heartbeat::by_service(service_id, &mut conn).await,
+            "retrieving the heartbeat of a service"
+        );
+        if let Some(saved) = saved {
+            try500!(
+                audit::record(
+                    &mut conn,
+                    &Actor::from(&user),
+                    saved.id.unwrap(),
+                    previous.as_ref(),
+                    Some(&saved)
+                )
+                .await,
+                "recording a saved heartbeat in the audit log"
+            );
+        }
+    }
+
+    if let Err(err) = ctx.wake_heartbeats.send(()).await {
+        log::error!("unable to wake up the heartbeat watcher: {err:#}");
+    }
+
+    *ctx.toast.write().unwrap() = Some("Heartbeat saved!".to_owned());
+
+    redirect(&format!("/admin/service/{service_id}/heartbeat"))
+}
+
+pub(crate) async fn delete_heartbeat(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(service_id): Path<i64>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        let heartbeat = try500!(
+            Heartbeat::by_service(service_id, &mut conn).await,
+            "retrieving the heartbeat of a service"
+        );
+        try500!(
+            Heartbeat::delete_for_service(service_id, &mut conn).await,
+            "deleting a heartbeat"
+        );
+        if let Some(heartbeat) = heartbeat {
+            try500!(
+                audit::deleted(
+                    &mut conn,
+                    &Actor::from(&user),
+                    heartbeat.id.unwrap(),
+                    &heartbeat
+                )
+                .await,
+                "recording a deleted heartbeat in the audit log"
+            );
+        }
+    }
+
+    if let Err(err) = ctx.wake_heartbeats.send(()).await {
+        log::error!("unable to wake up the heartbeat watcher: {err:#}");
+    }
+
+    *ctx.toast.write().unwrap() = Some("Heartbeat deleted!".to_owned());
+
+    redirect(&format!("/admin/service/{service_id}/heartbeat"))
+}
+
+pub(crate) async fn users(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    if !current_user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let (users, teams) = {
+        let mut conn = ctx.db_connection.lock().await;
+        let users = try500!(User::get_all(&mut conn).await, "retrieving list of users");
+        let teams = try500!(Team::get_all(&mut conn).await, "retrieving list of teams");
+        (users, teams)
+    };
+
+    #[derive(Serialize)]
+    struct UserRenderCtx {
+        id: i64,
+        username: String,
+        created_at: NaiveDateTime,
+        is_current: bool,
+        role: &'static str,
+        team_id: Option<i64>,
+    }
+
+    #[derive(Serialize)]
+    struct RoleRenderCtx {
+        value: &'static str,
+        label: String,
+    }
+
+    #[derive(Serialize)]
+    struct UsersTemplateCtx {
+        users: Vec<UserRenderCtx>,
+        roles: Vec<RoleRenderCtx>,
+        teams: Vec<Team>,
+    }
+
+    let mut render_ctx = try500!(
+        tera::Context::from_serialize(UsersTemplateCtx {
+            users: users
+                .into_iter()
+                .map(|u| UserRenderCtx {
+                    id: u.id.unwrap(),
+                    username: u.username,
+                    created_at: u.created_at,
+                    is_current: u.id == Some(current_user.id),
+                    role: u.role.to_db_str(),
+                    team_id: u.team_id,
+                })
+                .collect(),
+            roles: Role::ALL
+                .iter()
+                .map(|role| RoleRenderCtx {
+                    value: role.to_db_str(),
+                    label: role.label().to_owned(),
+                })
+                .collect(),
+            teams,
+        }),
+        "preparing context for users template"
+    );
+
+    {
+        let toast = ctx.toast.write().unwrap().take();
+        if let Some(t) = toast {
+            render_ctx.insert("toast_success", &t);
+        }
+    }
+
+    render_ctx.insert("csrf_token", &current_user.csrf_token);
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("users.html", &render_ctx),
+        "rendering users template"
+    );
+
+    (StatusCode::OK, Html(page).into_response())
+}
+
+/// Minimum length of the passwords of the administrators.
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Hash a password entered in a form, if it's long enough.
+async fn hash_form_password(password: String) -> Result<String, (StatusCode, Response)> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Html(format!(
+                "the password must be at least {MIN_PASSWORD_LENGTH} characters long"
+            ))
+            .into_response(),
+        ));
+    }
+    match tokio::task::spawn_blocking(move || tokens::hash_password(&password)).await {
+        Ok(Ok(hash)) => Ok(hash),
+        Ok(Err(err)) => {
+            log::error!("error when hashing a password: {err:#}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html("Ohnoes, something went wrong!").into_response(),
+            ))
+        }
+        Err(err) => {
+            log::error!("error when hashing a password: {err:#}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html("Ohnoes, something went wrong!").into_response(),
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FormUser {
+    username: String,
+    password: String,
+    role: String,
+    #[serde(default)]
+    team: Option<i64>,
+}
+
+pub(crate) async fn create_user(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    RawForm(request_bytes): RawForm,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let payload: FormUser = match serde_html_form::from_bytes(&request_bytes) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("error when parsing new-user request: {err:#}");
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("invalid request").into_response(),
+            );
+        }
+    };
+
+    let username = payload.username.trim().to_owned();
+    if username.is_empty() || username.contains(':') {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html("the username must not be empty, nor contain a colon").into_response(),
+        );
+    }
+
+    let Ok(role) = Role::from_db_str(&payload.role) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html(format!("unknown role {}", payload.role)).into_response(),
+        );
+    };
+
+    let password_hash = match hash_form_password(payload.password).await {
+        Ok(hash) => hash,
+        Err(response) => return response,
+    };
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+
+        if let Err(response) = check_team(payload.team, &mut conn).await {
+            return response;
+        }
+
+        let existing = try500!(
+            User::by_username(&username, &mut conn).await,
+            "looking for a user by name"
+        );
+        if existing.is_some() {
+            return (
+                StatusCode::BAD_REQUEST,
+                Html(format!("the user {username} already exists")).into_response(),
+            );
+        }
+
+        let new_user = User {
+            id: None,
+            username: username.clone(),
+            password_hash,
+            created_at: chrono::Utc::now().naive_utc(),
+            role,
+            team_id: payload.team,
+        };
+        let id = try500!(
+            User::insert(&mut conn, &new_user).await,
+            "inserting a new user"
+        );
+        log::trace!("user {} created with id {}", username, id);
+        try500!(
+            audit::created(&mut conn, &Actor::from(&user), id, &new_user).await,
+            "recording a new user in the audit log"
+        );
+    }
+
+    *ctx.toast.write().unwrap() = Some(format!("User {username} created!"));
+
+    redirect("/admin/users")
+}
+
+#[derive(Deserialize)]
+pub struct FormPassword {
+    password: String,
+}
+
+pub(crate) async fn change_user_password(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(current_user): Extension<CurrentUser>,
+    Path(id): Path<i64>,
+    Form(payload): Form<FormPassword>,
+) -> impl IntoResponse {
+    if !current_user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let password_hash = match hash_form_password(payload.password).await {
+        Ok(hash) => hash,
+        Err(response) => return response,
+    };
+
+    let user = {
+        let mut conn = ctx.db_connection.lock().await;
+        let user = try500!(User::by_id(id, &mut conn).await, "retrieving a user by id");
+        let Some(user) = user else {
+            return not_found(format!("User with id {id} doesn't exist!"));
+        };
+        try500!(
+            User::update_password(id, &password_hash, &mut conn).await,
+            "changing the password of a user"
+        );
+        // Whoever knew the former password must log in again.
+        try500!(
+            Session::delete_for_user(id, current_user.session_id, &mut conn).await,
+            "closing the sessions of a user"
+        );
+        try500!(
+            audit::password_changed(&mut conn, &Actor::from(&current_user), id).await,
+            "recording a password change in the audit log"
+        );
+        user
+    };
+
+    *ctx.toast.write().unwrap() = Some(format!("Password of {} changed!", user.username));
+
+    redirect("/admin/users")
+}
+
+#[derive(Deserialize)]
+pub struct FormRole {
+    role: String,
+    #[serde(default)]
+    team: Option<i64>,
+}
+
+pub(crate) async fn change_user_role(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(current_user): Extension<CurrentUser>,
+    Path(id): Path<i64>,
+    RawForm(request_bytes): RawForm,
+) -> impl IntoResponse {
+    if !current_user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let payload: FormRole = match serde_html_form::from_bytes(&request_bytes) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("error when parsing user-role request: {err:#}");
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("invalid request").into_response(),
+            );
+        }
+    };
+
+    let Ok(role) = Role::from_db_str(&payload.role) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html(format!("unknown role {}", payload.role)).into_response(),
+        );
+    };
+    if id == current_user.id && role != Role::Admin {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html("you can't remove your own administrator role").into_response(),
+        );
+    }
+
+    let user = {
+        let mut conn = ctx.db_connection.lock().await;
+        let user = try500!(User::by_id(id, &mut conn).await, "retrieving a user by id");
+        let Some(user) = user else {
+            return not_found(format!("User with id {id} doesn't exist!"));
+        };
+        if let Err(response) = check_team(payload.team, &mut conn).await {
+            return response;
+        }
+        try500!(
+            User::update_role(id, role, payload.team, &mut conn).await,
+            "changing the role of a user"
+        );
+        let updated = User {
+            role,
+            team_id: payload.team,
+            ..user.clone()
+        };
+        try500!(
+            audit::updated(&mut conn, &Actor::from(&current_user), id, &user, &updated).await,
+            "recording a role change in the audit log"
+        );
+        user
+    };
+
+    *ctx.toast.write().unwrap() = Some(format!("Role of {} changed!", user.username));
+
+    redirect("/admin/users")
+}
+
+pub(crate) async fn delete_user(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(current_user): Extension<CurrentUser>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if !current_user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    if id == current_user.id {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html("you can't delete your own account").into_response(),
+        );
+    }
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        let user = try500!(User::by_id(id, &mut conn).await, "retrieving a user by id");
+        try500!(User::delete(id, &mut conn).await, "deleting a user");
+        if let Some(user) = user {
+            try500!(
+                audit::deleted(&mut conn, &Actor::from(&current_user), id, &user).await,
+                "recording a deleted user in the audit log"
+            );
+        }
+    }
+
+    *ctx.toast.write().unwrap() = Some("User deleted!".to_owned());
+
+    redirect("/admin/users")
+}
+
+pub(crate) async fn teams(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let (teams, services) = {
+        let mut conn = ctx.db_connection.lock().await;
+        let teams = try500!(Team::get_all(&mut conn).await, "retrieving list of teams");
+        let services = try500!(
+            Service::get_all(&mut conn).await,
+            "retrieving list of services"
+        );
+        (teams, services)
+    };
+
+    #[derive(Serialize)]
+    struct TeamsTemplateCtx {
+        teams: Vec<Team>,
+        services: Vec<Service>,
+    }
+
+    let mut render_ctx = try500!(
+        tera::Context::from_serialize(TeamsTemplateCtx { teams, services }),
+        "preparing context for teams template"
+    );
+
+    {
+        let toast = ctx.toast.write().unwrap().take();
+        if let Some(t) = toast {
+            render_ctx.insert("toast_success", &t);
+        }
+    }
+
+    render_ctx.insert("csrf_token", &user.csrf_token);
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("teams.html", &render_ctx),
+        "rendering teams template"
+    );
+
+    (StatusCode::OK, Html(page).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct FormTeam {
+    name: String,
+}
+
+pub(crate) async fn create_team(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Form(payload): Form<FormTeam>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let name = payload.name.trim().to_owned();
+    if name.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html("the name of the team must not be empty").into_response(),
+        );
+    }
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        let team = Team {
+            id: None,
+            name: name.clone(),
+        };
+        let id = try500!(Team::insert(&mut conn, &team).await, "inserting a new team");
+        log::trace!("team {} created with id {}", name, id);
+        try500!(
+            audit::created(&mut conn, &Actor::from(&user), id, &team).await,
+            "recording a new team in the audit log"
+        );
+    }
+
+    *ctx.toast.write().unwrap() = Some(format!("Team {name} created!"));
+
+    redirect("/admin/teams")
+}
+
+/// Delete a team; its members and services are left without a team.
+pub(crate) async fn delete_team(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        let team = try500!(Team::by_id(id, &mut conn).await, "retrieving a team by id");
+        try500!(Team::delete(id, &mut conn).await, "deleting a team");
+        if let Some(team) = team {
+            try500!(
+                audit::deleted(&mut conn, &Actor::from(&user), id, &team).await,
+                "recording a deleted team in the audit log"
+            );
+        }
+    }
+
+    *ctx.toast.write().unwrap() = Some("Team deleted!".to_owned());
+
+    redirect("/admin/teams")
+}
+
+#[derive(Deserialize)]
+pub struct FormServiceTeam {
+    #[serde(default)]
+    team: Option<i64>,
+}
+
+pub(crate) async fn set_service_team(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(id): Path<i64>,
+    RawForm(request_bytes): RawForm,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let payload: FormServiceTeam = match serde_html_form::from_bytes(&request_bytes) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("error when parsing service-team request: {err:#}");
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("invalid request").into_response(),
+            );
+        }
+    };
+
+    let service = {
+        let mut conn = ctx.db_connection.lock().await;
+        let service = try500!(
+            Service::by_id(id, &mut conn).await,
+            "retrieving a service by id"
+        );
+        let Some(service) = service else {
+            return not_found(format!("Service with id {id} doesn't exist!"));
+        };
+        if let Err(response) = check_team(payload.team, &mut conn).await {
+            return response;
+        }
+        try500!(
+            Service::set_team(id, payload.team, &mut conn).await,
+            "changing the team of a service"
+        );
+        let updated = Service {
+            id: service.id,
+            name: service.name.clone(),
+            url: service.url.clone(),
+            team_id: payload.team,
+        };
+        try500!(
+            audit::updated(&mut conn, &Actor::from(&user), id, &service, &updated).await,
+            "recording a team change in the audit log"
+        );
+        service
+    };
+
+    *ctx.toast.write().unwrap() = Some(format!("Team of {} changed!", service.name));
+
+    redirect("/admin/teams")
+}
+
+/// Render the page of the API tokens; a token which was just created is shown once.
+async fn render_api_tokens(
+    ctx: &AppContext,
+    current_user: &CurrentUser,
+    new_token: Option<String>,
+) -> (StatusCode, Response) {
+    let (api_tokens, users) = {
+        let mut conn = ctx.db_connection.lock().await;
+        let api_tokens = try500!(
+            ApiToken::get_all(&mut conn).await,
+            "retrieving list of API tokens"
+        );
+        let users = try500!(User::get_all(&mut conn).await, "retrieving list of users");
+        (api_tokens, users)
+    };
+
+    #[derive(Serialize)]
+    struct ApiTokenRenderCtx {
+        id: i64,
+        name: String,
+        scopes: Vec<&'static str>,
+        username: Option<String>,
+        created_at: NaiveDateTime,
+        expires_at: Option<NaiveDateTime>,
+        last_used_at: Option<NaiveDateTime>,
+        expired: bool,
+    }
+
+    #[derive(Serialize)]
+    struct ApiTokensTemplateCtx {
+        api_tokens: Vec<ApiTokenRenderCtx>,
+        scopes: Vec<&'static str>,
+        new_token: Option<String>,
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let mut render_ctx = try500!(
+        tera::Context::from_serialize(ApiTokensTemplateCtx {
+            api_tokens: api_tokens
+                .into_iter()
+                .map(|t| ApiTokenRenderCtx {
+                    id: t.id.unwrap(),
+                    expired: t.is_expired(now),
+                    username: users
+                        .iter()
+                        .find(|u| u.id == Some(t.user_id))
+                        .map(|u| u.username.clone()),
+                    scopes: t.scopes.iter().map(|scope| scope.to_db_str()).collect(),
+                    name: t.name,
+                    created_at: t.created_at,
+                    expires_at: t.expires_at,
+                    last_used_at: t.last_used_at,
+                })
+                .collect(),
+            scopes: Scope::ALL.iter().map(|scope| scope.to_db_str()).collect(),
+            new_token,
+        }),
+        "preparing context for api-tokens template"
+    );
+
+    {
+        let toast = ctx.toast.write().unwrap().take();
+        if let Some(t) = toast {
+            render_ctx.insert("toast_success", &t);
+        }
+    }
+
+    render_ctx.insert("csrf_token", &current_user.csrf_token);
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("api-tokens.html", &render_ctx),
+        "rendering api-tokens template"
+    );
+
+    (StatusCode::OK, Html(page).into_response())
+}
+
+pub(crate) async fn api_tokens(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    if !current_user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    render_api_tokens(&ctx, &current_user, None).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct FormApiToken {
+    name: String,
+    #[serde(default)]
+    scopes: Vec<String>,
+    #[serde(default)]
+    expires_at: Option<String>,
+}
+
+/// Create an API token acting on behalf of the current user.
+pub(crate) async fn create_api_token(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(current_user): Extension<CurrentUser>,
+    RawForm(request_bytes): RawForm,
+) -> impl IntoResponse {
+    if !current_user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    let payload: FormApiToken = match serde_html_form::from_bytes(&request_bytes) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("error when parsing new-api-token request: {err:#}");
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("invalid request").into_response(),
+            );
+        }
+    };
+
+    let name = payload.name.trim().to_owned();
+    if name.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html("the name of the token must not be empty").into_response(),
+        );
+    }
+
+    let scopes = match payload
+        .scopes
+        .iter()
+        .map(|scope| Scope::from_db_str(scope))
+        .collect::<anyhow::Result<Vec<_>>>()
+    {
+        Ok(scopes) if !scopes.is_empty() => scopes,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("the token must have at least one valid scope").into_response(),
+            );
+        }
+    };
+
+    let expires_at = match payload
+        .expires_at
+        .as_deref()
+        .filter(|date| !date.is_empty())
+    {
+        Some(date) => Some(try500!(
+            NaiveDateTime::parse_from_str(date, FORM_DATE_FORMAT),
+            "converting expiry date to NaiveDateTime"
+        )),
+        None => None,
+    };
+
+    let token = format!("{API_TOKEN_PREFIX}{}", random_token());
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        let api_token = ApiToken {
+            id: None,
+            name: name.clone(),
+            token_hash: tokens::hash_token(&token),
+            scopes,
+            user_id: current_user.id,
+            created_at: chrono::Utc::now().naive_utc(),
+            expires_at,
+            last_used_at: None,
+        };
+        let id = try500!(
+            ApiToken::insert(&mut conn, &api_token).await,
+            "inserting a new API token"
+        );
+        log::trace!("API token {} created with id {}", name, id);
+        try500!(
+            audit::created(&mut conn, &Actor::from(&current_user), id, &api_token).await,
+            "recording a new API token in the audit log"
+        );
+    }
+
+    *ctx.toast.write().unwrap() = Some(format!("API token {name} created!"));
+
+    // The token isn't stored: show it right away, instead of redirecting.
+    render_api_tokens(&ctx, &current_user, Some(token)).await
+}
+
+pub(crate) async fn delete_api_token(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if !user.can(Role::Admin) {
+        return forbidden();
+    }
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        let api_token = try500!(
+            ApiToken::by_id(id, &mut conn).await,
+            "retrieving an API token by id"
+        );
+        try500!(
+            ApiToken::delete(id, &mut conn).await,
+            "deleting an API token"
+        );
+        if let Some(api_token) = api_token {
+            try500!(
+                audit::deleted(&mut conn, &Actor::from(&user), id, &api_token).await,
+                "recording a revoked API token in the audit log"
+            );
+        }
+    }
+
+    *ctx.toast.write().unwrap() = Some("API token revoked!".to_owned());
+
+    redirect("/admin/api-tokens")
+}
+
+/// List the services, as JSON.
+pub(crate) async fn api_services(Extension(ctx): Extension<Arc<AppContext>>) -> impl IntoResponse {
+    let services = {
+        let mut conn = ctx.db_connection.lock().await;
+        try500!(
+            Service::get_all(&mut conn).await,
+            "retrieving list of services for the API"
+        )
+    };
+
+    (StatusCode::OK, Json(services).into_response())
+}
+
+/// List the interventions, with the ids of their services, as JSON.
+pub(crate) async fn api_interventions(
+    Extension(ctx): Extension<Arc<AppContext>>,
+) -> impl IntoResponse {
+    let interventions = {
+        let mut conn = ctx.db_connection.lock().await;
+        try500!(
+            InterventionWithServices::get_all(&mut conn).await,
+            "retrieving list of interventions for the API"
+        )
+    };
+
+    #[derive(Serialize)]
+    struct ApiIntervention {
+        #[serde(flatten)]
+        intervention: Intervention,
+        services: Vec<i64>,
+    }
+
+    let interventions: Vec<_> = interventions
+        .into_iter()
+        .map(|int| ApiIntervention {
+            intervention: int.intervention,
+            services: int.service_ids.iter().map(|id| id.0).collect(),
+        })
+        .collect();
+
+    (StatusCode::OK, Json(interventions).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, AdminSession};
+    // The client's HTTP types.
+    use reqwest::StatusCode;
+
+    /// An administrator of the given role, with an open session.
+    async fn log_in(
+        ctx: &AppContext,
+        username: &str,
+        role: Role,
+        team_id: Option<i64>,
+    ) -> AdminSession {
+        let user_id = testing::insert_user(ctx, username, role, team_id).await;
+        testing::open_session(ctx, user_id).await
+    }
+
+    async fn insert_team(ctx: &AppContext, name: &str) -> i64 {
+        let mut conn = ctx.db_connection.lock().await;
+        Team::insert(
+            &mut conn,
+            &Team {
+                id: None,
+                name: name.to_owned(),
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Insert an intervention about a new service of the team, and return the ids of both.
+    async fn insert_team_intervention(ctx: &AppContext, team_id: i64) -> (i64, i64) {
+        let intervention_id = testing::insert_intervention(ctx, "Forge down").await;
+        let mut conn = ctx.db_connection.lock().await;
+        let service_id = Intervention::get_service_ids(intervention_id, &mut conn)
+            .await
+            .unwrap()[0]
+            .0;
+        Service::set_team(service_id, Some(team_id), &mut conn)
+            .await
+            .unwrap();
+        (intervention_id, service_id)
+    }
+
+    /// Post the form to the administration as the administrator, and return the status.
+    async fn post(
+        url: &str,
+        session: &AdminSession,
+        path: &str,
+        form: &[(&str, String)],
+    ) -> StatusCode {
+        let mut form = form.to_vec();
+        form.push(("csrf-token", session.csrf_token.clone()));
+        testing::http_client()
+            .post(format!("{url}{path}"))
+            .header(reqwest::header::COOKIE, session.cookie())
+            .form(&form)
+            .send()
+            .await
+            .unwrap()
+            .status()
+    }
+
+    fn resolution_form(service_id: i64) -> Vec<(&'static str, String)> {
+        let mut form = testing::intervention_form(service_id);
+        for (name, value) in &mut form {
+            if *name == "status" {
+                *value = "resolved".to_owned();
+            }
+        }
+        form
+    }
+
+    #[tokio::test]
+    async fn viewers_cannot_change_anything() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let url = testing::serve_admin(ctx);
+        let team_id = insert_team(ctx, "Infra").await;
+        let (intervention_id, service_id) = insert_team_intervention(ctx, team_id).await;
+        let other_id = testing::insert_user(ctx, "bob", Role::Editor, Some(team_id)).await;
+        let viewer = log_in(ctx, "alice", Role::Viewer, Some(team_id)).await;
+
+        let requests = [
+            (
+                "/api/intervention".to_owned(),
+                testing::intervention_form(service_id),
+            ),
+            (
+                format!("/api/intervention/{intervention_id}"),
+                testing::intervention_form(service_id),
+            ),
+            (
+                format!("/api/intervention/{intervention_id}"),
+                resolution_form(service_id),
+            ),
+            (
+                "/api/user".to_owned(),
+                vec![
+                    ("username", "mallory".to_owned()),
+                    ("password", testing::PASSWORD.to_owned()),
+                    ("role", "admin".to_owned()),
+                ],
+            ),
+            (
+                format!("/api/user/{other_id}/password"),
+                vec![("password", "hunter2hunter2".to_owned())],
+            ),
+            (
+                format!("/api/user/{other_id}/role"),
+                vec![("role", "admin".to_owned())],
+            ),
+            (format!("/api/user/{other_id}/delete"), vec![]),
+            ("/api/team".to_owned(), vec![("name", "Web".to_owned())]),
+            (format!("/api/team/{team_id}/delete"), vec![]),
+            (format!("/api/service/{service_id}/team"), vec![]),
+        ];
+        for (path, form) in &requests {
+            assert_eq!(
+                post(&url, &viewer, path, form).await,
+                StatusCode::FORBIDDEN,
+                "{path}"
+            );
+        }
+
+        // Nothing changed.
+        let mut conn = ctx.db_connection.lock().await;
+        assert_eq!(Intervention::get_all(&mut conn).await.unwrap().len(), 1);
+        let intervention = Intervention::by_id(intervention_id, &mut conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(intervention.status, Status::Ongoing);
+        assert_eq!(intervention.title, "Forge down");
+        assert_eq!(User::get_all(&mut conn).await.unwrap().len(), 2);
+        let other = User::by_id(other_id, &mut conn).await.unwrap().unwrap();
+        assert_eq!(other.role, Role::Editor);
+        assert_eq!(Team::get_all(&mut conn).await.unwrap().len(), 1);
+        let service = Service::by_id(service_id, &mut conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(service.team_id, Some(team_id));
+    }
+
+    #[tokio::test]
+    async fn editors_only_manage_the_services_of_their_team() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let url = testing::serve_admin(ctx);
+        let team_id = insert_team(ctx, "Infra").await;
+        let other_team_id = insert_team(ctx, "Web").await;
+        let (intervention_id, service_id) = insert_team_intervention(ctx, team_id).await;
+        let (other_intervention_id, other_service_id) =
+            insert_team_intervention(ctx, other_team_id).await;
+        let editor = log_in(ctx, "alice", Role::Editor, Some(team_id)).await;
+
+        // Another team's services are out of reach…
+        let forbidden = [
+            (
+                "/api/intervention".to_owned(),
+                testing::intervention_form(other_service_id),
+            ),
+            (
+                format!("/api/intervention/{other_intervention_id}"),
+                resolution_form(other_service_id),
+            ),
+            // …even when moving an intervention of the team to them.
+            (
+                format!("/api/intervention/{intervention_id}"),
+                testing::intervention_form(other_service_id),
+            ),
+            // …or when adding them to a new intervention of the team.
+            (
+                "/api/intervention".to_owned(),
+                [
+                    testing::intervention_form(service_id),
+                    vec![("services", other_service_id.to_string())],
+                ]
+                .concat(),
+            ),
+            ("/api/team".to_owned(), vec![("name", "Mobile".to_owned())]),
+        ];
+        for (path, form) in &forbidden {
+            assert_eq!(
+                post(&url, &editor, path, form).await,
+                StatusCode::FORBIDDEN,
+                "{path}"
+            );
+        }
+        {
+            let mut conn = ctx.db_connection.lock().await;
+            assert_eq!(Intervention::get_all(&mut conn).await.unwrap().len(), 2);
+            let other = Intervention::by_id(other_intervention_id, &mut conn)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(other.status, Status::Ongoing);
+        }
+
+        // …but those of the team aren't.
+        let path = "/api/intervention";
+        let form = testing::intervention_form(service_id);
+        assert_eq!(post(&url, &editor, path, &form).await, StatusCode::FOUND);
+        let path = format!("/api/intervention/{intervention_id}");
+        let form = resolution_form(service_id);
+        assert_eq!(post(&url, &editor, &path, &form).await, StatusCode::FOUND);
+        let mut conn = ctx.db_connection.lock().await;
+        assert_eq!(Intervention::get_all(&mut conn).await.unwrap().len(), 3);
+        let intervention = Intervention::by_id(intervention_id, &mut conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(intervention.status, Status::Resolved);
+    }
+
+    #[tokio::test]
+    async fn admins_manage_everything() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let url = testing::serve_admin(ctx);
+        let team_id = insert_team(ctx, "Infra").await;
+        let (intervention_id, service_id) = insert_team_intervention(ctx, team_id).await;
+        let admin = log_in(ctx, "alice", Role::Admin, None).await;
+
+        let path = "/api/intervention";
+        let form = testing::intervention_form(service_id);
+        assert_eq!(post(&url, &admin, path, &form).await, StatusCode::FOUND);
+        let path = format!("/api/intervention/{intervention_id}");
+        let form = resolution_form(service_id);
+        assert_eq!(post(&url, &admin, &path, &form).await, StatusCode::FOUND);
+
+        let form = [
+            ("username", "bob".to_owned()),
+            ("password", testing::PASSWORD.to_owned()),
+            ("role", "viewer".to_owned()),
+        ];
+        assert_eq!(
+            post(&url, &admin, "/api/user", &form).await,
+            StatusCode::FOUND
+        );
+        let bob_id = {
+            let mut conn = ctx.db_connection.lock().await;
+            User::by_username("bob", &mut conn)
+                .await
+                .unwrap()
+                .unwrap()
+                .id
+                .unwrap()
+        };
+        let form = [("role", "editor".to_owned()), ("team", team_id.to_string())];
+        let path = format!("/api/user/{bob_id}/role");
+        assert_eq!(post(&url, &admin, &path, &form).await, StatusCode::FOUND);
+        let form = [("password", "hunter2hunter2".to_owned())];
+        let path = format!("/api/user/{bob_id}/password");
+        assert_eq!(post(&url, &admin, &path, &form).await, StatusCode::FOUND);
+        let form = [("name", "Web".to_owned())];
+        assert_eq!(
+            post(&url, &admin, "/api/team", &form).await,
+            StatusCode::FOUND
+        );
+        let path = format!("/api/service/{service_id}/team");
+        assert_eq!(post(&url, &admin, &path, &[]).await, StatusCode::FOUND);
+
+        {
+            let mut conn = ctx.db_connection.lock().await;
+            assert_eq!(Intervention::get_all(&mut conn).await.unwrap().len(), 2);
+            let intervention = Intervention::by_id(intervention_id, &mut conn)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(intervention.status, Status::Resolved);
+            let bob = User::by_id(bob_id, &mut conn).await.unwrap().unwrap();
+            assert_eq!((bob.role, bob.team_id), (Role::Editor, Some(team_id)));
+            assert_eq!(Team::get_all(&mut conn).await.unwrap().len(), 2);
+            let service = Service::by_id(service_id, &mut conn)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(service.team_id, None);
+        }
+
+        let path = format!("/api/team/{team_id}/delete");
+        assert_eq!(post(&url, &admin, &path, &[]).await, StatusCode::FOUND);
+        let path = format!("/api/user/{bob_id}/delete");
+        assert_eq!(post(&url, &admin, &path, &[]).await, StatusCode::FOUND);
+        let mut conn = ctx.db_connection.lock().await;
+        assert!(User::by_id(bob_id, &mut conn).await.unwrap().is_none());
+        assert_eq!(Team::get_all(&mut conn).await.unwrap().len(), 1);
+    }
+}
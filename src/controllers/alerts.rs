@@ -0,0 +1,117 @@
+use axum::{
+    body::Bytes,
+    extract::Path,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use std::sync::Arc;
+use tracing as log;
+
+use super::is_authorized;
+use crate::{
+    monitoring::{alertmanager, alerts, receivers},
+    AppContext,
+};
+
+/// Webhook receiver for Alertmanager.
+pub(crate) async fn alertmanager(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let Some(config) = &ctx.config.alertmanager else {
+        return (
+            StatusCode::NOT_FOUND,
+            "the Alertmanager receiver is disabled\n",
+        );
+    };
+
+    if !is_authorized(&headers, &config.token) {
+        return (StatusCode::UNAUTHORIZED, "invalid token\n");
+    }
+
+    let payload: alertmanager::Payload = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("error when parsing an Alertmanager payload: {err:#}");
+            return (StatusCode::BAD_REQUEST, "invalid payload\n");
+        }
+    };
+
+    let parsed = {
+        let mut conn = ctx.db_connection.lock().await;
+        alertmanager::parse(config, payload, &mut conn).await
+    };
+    let parsed = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            log::error!("error when mapping Alertmanager alerts to services: {err:#}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "internal error\n");
+        }
+    };
+
+    for alert in &parsed {
+        if let Err(err) = alerts::handle(&ctx, alertmanager::SOURCE, alert).await {
+            log::error!(
+                "error when handling Alertmanager alert {}: {err:#}",
+                alert.fingerprint
+            );
+            return (StatusCode::INTERNAL_SERVER_ERROR, "internal error\n");
+        }
+    }
+
+    (StatusCode::OK, "OK\n")
+}
+
+/// Generic webhook receiver, for the sources which have a payload parser.
+pub(crate) async fn receive(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Path(source): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let Some(token) = &ctx.config.alerts_token else {
+        return (StatusCode::NOT_FOUND, "the alert receivers are disabled\n");
+    };
+
+    let Some(parser) = receivers::parser(&source) else {
+        return (StatusCode::NOT_FOUND, "unknown alert source\n");
+    };
+
+    if !is_authorized(&headers, token) {
+        return (StatusCode::UNAUTHORIZED, "invalid token\n");
+    }
+
+    let received = match parser.parse(&body) {
+        Ok(received) => received,
+        Err(err) => {
+            log::error!("error when parsing a {source} payload: {err:#}");
+            return (StatusCode::BAD_REQUEST, "invalid payload\n");
+        }
+    };
+
+    let parsed = {
+        let mut conn = ctx.db_connection.lock().await;
+        receivers::map_all(&source, received, &mut conn).await
+    };
+    let parsed = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            log::error!("error when mapping {source} alerts to services: {err:#}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "internal error\n");
+        }
+    };
+
+    for alert in &parsed {
+        if let Err(err) = alerts::handle(&ctx, &source, alert).await {
+            log::error!(
+                "error when handling {source} alert {}: {err:#}",
+                alert.fingerprint
+            );
+            return (StatusCode::INTERNAL_SERVER_ERROR, "internal error\n");
+        }
+    }
+
+    (StatusCode::OK, "OK\n")
+}
@@ -0,0 +1,893 @@
+//! Authentication of the administrators: login page, cookie sessions and CSRF protection, and
+//! API tokens for the scripts.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{FromRequest as _, OriginalUri, Query},
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{AppendHeaders, Html, IntoResponse, Response},
+    Extension, Form,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing as log;
+
+use crate::{
+    db::models::{
+        api_tokens::{ApiToken, Scope},
+        sessions::Session,
+        users::{Role, User},
+    },
+    tokens, AppContext,
+};
+
+/// Name of the cookie holding the session token.
+const SESSION_COOKIE: &str = "rustatouille_session";
+
+/// Name of the cookie holding the CSRF token of the login form, before there's any session.
+const LOGIN_CSRF_COOKIE: &str = "rustatouille_login_csrf";
+
+/// Name of the form field carrying the CSRF token.
+const CSRF_FIELD: &str = "csrf-token";
+
+/// Header carrying the CSRF token, for the requests which aren't sent by a form.
+const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Don't update the last activity of a session more often than this, in seconds.
+const TOUCH_INTERVAL: i64 = 60;
+
+/// The administrator making the request.
+#[derive(Clone, Debug)]
+pub(crate) struct CurrentUser {
+    pub id: i64,
+    pub username: String,
+    pub role: Role,
+    pub team_id: Option<i64>,
+    /// Missing for the requests authenticated by an API token.
+    pub session_id: Option<i64>,
+    /// Token the forms must send back; see [`require_csrf`]. Empty without a session.
+    pub csrf_token: String,
+}
+
+impl CurrentUser {
+    fn new(user: User, session_id: Option<i64>, csrf_token: String) -> Self {
+        Self {
+            id: user.id.unwrap(),
+            username: user.username,
+            role: user.role,
+            team_id: user.team_id,
+            session_id,
+            csrf_token,
+        }
+    }
+
+    /// Does the user have at least the given role?
+    pub fn can(&self, role: Role) -> bool {
+        self.role >= role
+    }
+
+    /// Can the user manage the interventions of a service owned by the given team?
+    ///
+    /// Administrators manage all the services, and editors those of their team, or of no team.
+    pub fn can_manage(&self, service_team_id: Option<i64>) -> bool {
+        match self.role {
+            Role::Admin => true,
+            Role::Editor => service_team_id.is_none() || service_team_id == self.team_id,
+            Role::Viewer => false,
+        }
+    }
+}
+
+/// Read the token of an `Authorization: Bearer` header.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Read a non-empty cookie of the request.
+fn read_cookie<'a>(headers: &'a HeaderMap, cookie_name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == cookie_name)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+/// Read the session token from the cookies of the request.
+fn session_token(headers: &HeaderMap) -> Option<&str> {
+    read_cookie(headers, SESSION_COOKIE)
+}
+
+/// `Set-Cookie` header value storing the session token, or clearing it if there's none.
+fn session_cookie(ctx: &AppContext, token: Option<&str>) -> HeaderValue {
+    let max_age = match token {
+        Some(_) => ctx.config.session_lifetime.num_seconds(),
+        None => 0,
+    };
+    let mut cookie = format!(
+        "{SESSION_COOKIE}={}; Path=/admin; HttpOnly; SameSite=Lax; Max-Age={max_age}",
+        token.unwrap_or_default()
+    );
+    if ctx.config.public_url.starts_with("https://") {
+        cookie.push_str("; Secure");
+    }
+    HeaderValue::from_str(&cookie).expect("tokens are alphanumeric")
+}
+
+/// `Set-Cookie` header value storing the CSRF token of the login form, or clearing it if there's
+/// none; it only lasts as long as the browser session.
+fn login_csrf_cookie(ctx: &AppContext, token: Option<&str>) -> HeaderValue {
+    let mut cookie = format!(
+        "{LOGIN_CSRF_COOKIE}={}; Path=/admin/login; HttpOnly; SameSite=Strict",
+        token.unwrap_or_default()
+    );
+    if token.is_none() {
+        cookie.push_str("; Max-Age=0");
+    }
+    if ctx.config.public_url.starts_with("https://") {
+        cookie.push_str("; Secure");
+    }
+    HeaderValue::from_str(&cookie).expect("tokens are alphanumeric")
+}
+
+/// Only redirect to pages of the administration after logging in.
+fn is_safe_next(next: &str) -> bool {
+    next.starts_with("/admin") && !next.starts_with("/admin/login")
+}
+
+/// Send the visitor to the login page; pages they were trying to see are shown after logging in.
+fn login_redirect<B>(request: &Request<B>) -> Response {
+    let mut location = "/admin/login".to_owned();
+    if request.method() == Method::GET {
+        // The router of the administration is nested: its URIs are stripped of `/admin`.
+        let next = request
+            .extensions()
+            .get::<OriginalUri>()
+            .and_then(|uri| uri.path_and_query())
+            .map_or("/admin", |path| path.as_str());
+        if is_safe_next(next) {
+            if let Ok(query) = serde_html_form::to_string([("next", next)]) {
+                location = format!("{location}?{query}");
+            }
+        }
+    }
+    let location = HeaderValue::from_str(&location).expect("the query is URL-encoded");
+    (StatusCode::SEE_OTHER, [(header::LOCATION, location)]).into_response()
+}
+
+/// Look for the session of a token, and its user; sessions which timed out are closed.
+async fn find_session(
+    ctx: &AppContext,
+    token: &str,
+    now: chrono::NaiveDateTime,
+) -> anyhow::Result<Option<(Session, User)>> {
+    let mut conn = ctx.db_connection.lock().await;
+    let Some(session) = Session::by_token_hash(&tokens::hash_token(token), &mut conn).await? else {
+        return Ok(None);
+    };
+    let session_id = session.id.unwrap();
+    if !session.is_alive(
+        now,
+        ctx.config.session_idle_timeout,
+        ctx.config.session_lifetime,
+    ) {
+        Session::delete(session_id, &mut conn).await?;
+        return Ok(None);
+    }
+    let Some(user) = User::by_id(session.user_id, &mut conn).await? else {
+        return Ok(None);
+    };
+    if (now - session.last_seen_at).num_seconds() >= TOUCH_INTERVAL {
+        Session::touch(session_id, now, &mut conn).await?;
+    }
+    Ok(Some((session, user)))
+}
+
+/// Look for an API token which can be used at the given date, and its user.
+async fn find_api_token(
+    ctx: &AppContext,
+    token: &str,
+    now: chrono::NaiveDateTime,
+) -> anyhow::Result<Option<(ApiToken, User)>> {
+    let mut conn = ctx.db_connection.lock().await;
+    let Some(api_token) = ApiToken::by_token_hash(&tokens::hash_token(token), &mut conn).await?
+    else {
+        return Ok(None);
+    };
+    if api_token.is_expired(now) {
+        return Ok(None);
+    }
+    let Some(user) = User::by_id(api_token.user_id, &mut conn).await? else {
+        return Ok(None);
+    };
+    ApiToken::touch(api_token.id.unwrap(), now, &mut conn).await?;
+    Ok(Some((api_token, user)))
+}
+
+/// Scope an API token needs to make a request; tokens can't make the requests without any.
+///
+/// Tokens are only accepted on the API routes: reading anything, and writing the interventions
+/// and the services.
+fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    // The router of the administration is nested: its paths are stripped of `/admin`.
+    let path = path.strip_prefix("/api/")?;
+    if method == Method::GET {
+        Some(Scope::Read)
+    } else if method != Method::POST {
+        None
+    } else if path.starts_with("intervention") {
+        Some(Scope::InterventionsWrite)
+    } else if path.starts_with("service") {
+        Some(Scope::ServicesWrite)
+    } else {
+        None
+    }
+}
+
+fn invalid_api_token() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"))],
+        "Invalid or expired API token.",
+    )
+        .into_response()
+}
+
+/// Let a script in with an API token, if it has the scope of the request.
+async fn authenticate_api_token<B>(
+    ctx: &AppContext,
+    token: &str,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let now = chrono::Utc::now().naive_utc();
+    let (api_token, user) = match find_api_token(ctx, token, now).await {
+        Ok(Some(found)) => found,
+        Ok(None) => return invalid_api_token(),
+        Err(err) => {
+            log::error!("error when looking for an API token: {err:#}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let allowed = required_scope(request.method(), request.uri().path())
+        .is_some_and(|scope| api_token.scopes.contains(&scope));
+    if !allowed {
+        return (
+            StatusCode::FORBIDDEN,
+            "The API token doesn't allow this request.",
+        )
+            .into_response();
+    }
+
+    request
+        .extensions_mut()
+        .insert(CurrentUser::new(user, None, String::new()));
+    next.run(request).await
+}
+
+/// Middleware letting only the administrators in; the handlers get their account as a
+/// [`CurrentUser`] extension.
+///
+/// Scripts authenticate with an API token, as a bearer token; visitors without a valid session
+/// are sent to the login page.
+pub(crate) async fn require_user<B>(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(token) = bearer_token(request.headers()) {
+        let token = token.to_owned();
+        return authenticate_api_token(&ctx, &token, request, next).await;
+    }
+
+    let Some(token) = session_token(request.headers()) else {
+        return login_redirect(&request);
+    };
+    let now = chrono::Utc::now().naive_utc();
+
+    let found = find_session(&ctx, token, now).await;
+
+    let (session, user) = match found {
+        Ok(Some(found)) => found,
+        Ok(None) => return login_redirect(&request),
+        Err(err) => {
+            log::error!("error when looking for a session: {err:#}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    request
+        .extensions_mut()
+        .insert(CurrentUser::new(user, session.id, session.csrf_token));
+    next.run(request).await
+}
+
+fn csrf_rejection() -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        Html("Invalid or missing CSRF token; reload the page and try again."),
+    )
+        .into_response()
+}
+
+/// Middleware rejecting the requests which may change something, unless they carry the CSRF
+/// token of the session, in the `csrf-token` form field or the `X-CSRF-Token` header.
+///
+/// Must run after [`require_user`].
+pub(crate) async fn require_csrf(request: Request<Body>, next: Next<Body>) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+
+    let Some(user) = request.extensions().get::<CurrentUser>() else {
+        return csrf_rejection();
+    };
+    // Requests authenticated by an API token don't carry any cookie, so they can't be forged.
+    if user.session_id.is_none() {
+        return next.run(request).await;
+    }
+    let expected = user.csrf_token.clone();
+
+    if let Some(token) = request
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        if tokens::secure_eq(token, &expected) {
+            return next.run(request).await;
+        }
+        return csrf_rejection();
+    }
+
+    let is_form = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return csrf_rejection();
+    }
+
+    // Read the whole form to find the token, then hand it over to the handler.
+    let (parts, body) = request.into_parts();
+    let bytes = match Bytes::from_request(Request::new(body), &()).await {
+        Ok(bytes) => bytes,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let fields: Vec<(String, String)> = serde_html_form::from_bytes(&bytes).unwrap_or_default();
+    let valid = fields
+        .iter()
+        .any(|(name, value)| name == CSRF_FIELD && tokens::secure_eq(value, &expected));
+    if !valid {
+        return csrf_rejection();
+    }
+
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LoginQuery {
+    next: Option<String>,
+}
+
+/// Render the login form, carrying the given CSRF token, which is also stored in a cookie.
+fn render_login(
+    ctx: &AppContext,
+    status: StatusCode,
+    next: Option<&str>,
+    error: Option<&str>,
+    csrf_token: &str,
+) -> (StatusCode, Response) {
+    let mut render_ctx = tera::Context::new();
+    render_ctx.insert("next", &next.filter(|next| is_safe_next(next)));
+    render_ctx.insert("error", &error);
+    render_ctx.insert("csrf_token", csrf_token);
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("login.html", &render_ctx),
+        "rendering login template"
+    );
+
+    let mut response = Html(page).into_response();
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, login_csrf_cookie(ctx, Some(csrf_token)));
+    (status, response)
+}
+
+/// Show the login form; the CSRF token of a previous visit is kept, so that several tabs work.
+pub(crate) async fn login_form(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    headers: HeaderMap,
+    Query(query): Query<LoginQuery>,
+) -> impl IntoResponse {
+    let csrf_token = read_cookie(&headers, LOGIN_CSRF_COOKIE)
+        .map(str::to_owned)
+        .unwrap_or_else(tokens::random_token);
+    render_login(
+        &ctx,
+        StatusCode::OK,
+        query.next.as_deref(),
+        None,
+        &csrf_token,
+    )
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FormLogin {
+    username: String,
+    password: String,
+    next: Option<String>,
+    #[serde(default, rename = "csrf-token")]
+    csrf_token: String,
+}
+
+/// Check the credentials, and open a session if they're right.
+///
+/// The form must carry the CSRF token of its cookie, so that another site can't log a visitor in
+/// with its own account.
+pub(crate) async fn login(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    headers: HeaderMap,
+    Form(payload): Form<FormLogin>,
+) -> impl IntoResponse {
+    let Some(csrf_token) = read_cookie(&headers, LOGIN_CSRF_COOKIE)
+        .filter(|expected| tokens::secure_eq(&payload.csrf_token, expected))
+    else {
+        return (StatusCode::FORBIDDEN, csrf_rejection());
+    };
+
+    let user = {
+        let mut conn = ctx.db_connection.lock().await;
+        try500!(
+            User::by_username(&payload.username, &mut conn).await,
+            "looking for a user"
+        )
+    };
+
+    // Unknown users are checked against a dummy hash, so that they take as long as the others:
+    // the response time doesn't tell which usernames exist.
+    let password = payload.password;
+    let hash = user.as_ref().map_or_else(
+        || tokens::DUMMY_PASSWORD_HASH.to_owned(),
+        |user| user.password_hash.clone(),
+    );
+    let valid =
+        tokio::task::spawn_blocking(move || tokens::verify_password(&password, &hash)).await;
+    let valid = try500!(valid, "verifying a password");
+
+    let Some(user) = user.filter(|_| valid) else {
+        log::warn!("failed login attempt for user {}", payload.username);
+        return render_login(
+            &ctx,
+            StatusCode::UNAUTHORIZED,
+            payload.next.as_deref(),
+            Some("Invalid username or password."),
+            csrf_token,
+        );
+    };
+
+    let token = tokens::random_token();
+    let now = chrono::Utc::now().naive_utc();
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        try500!(
+            Session::delete_expired(
+                now,
+                ctx.config.session_idle_timeout,
+                ctx.config.session_lifetime,
+                &mut conn
+            )
+            .await,
+            "removing expired sessions"
+        );
+        try500!(
+            Session::insert(
+                &mut conn,
+                &Session {
+                    id: None,
+                    token_hash: tokens::hash_token(&token),
+                    user_id: user.id.unwrap(),
+                    csrf_token: tokens::random_token(),
+                    created_at: now,
+                    last_seen_at: now,
+                },
+            )
+            .await,
+            "opening a session"
+        );
+    }
+
+    log::info!("user {} logged in", user.username);
+
+    let next = payload
+        .next
+        .filter(|next| is_safe_next(next))
+        .unwrap_or_else(|| "/admin".to_owned());
+    let location = try500!(HeaderValue::from_str(&next), "reading the next page");
+    (
+        StatusCode::SEE_OTHER,
+        AppendHeaders([
+            (header::LOCATION, location),
+            (header::SET_COOKIE, session_cookie(&ctx, Some(&token))),
+            (header::SET_COOKIE, login_csrf_cookie(&ctx, None)),
+        ])
+        .into_response(),
+    )
+}
+
+/// Close the session of the current user.
+pub(crate) async fn logout(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Extension(user): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    if let Some(session_id) = user.session_id {
+        let mut conn = ctx.db_connection.lock().await;
+        try500!(
+            Session::delete(session_id, &mut conn).await,
+            "closing a session"
+        );
+    }
+
+    (
+        StatusCode::SEE_OTHER,
+        [
+            (header::LOCATION, HeaderValue::from_static("/admin/login")),
+            (header::SET_COOKIE, session_cookie(&ctx, None)),
+        ]
+        .into_response(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, AdminSession};
+    use chrono::Duration;
+    // The client's HTTP types.
+    use reqwest::{header, StatusCode};
+
+    /// Is the session still in the database?
+    async fn session_exists(ctx: &AppContext, session: &AdminSession) -> bool {
+        let mut conn = ctx.db_connection.lock().await;
+        Session::by_token_hash(&tokens::hash_token(&session.token), &mut conn)
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    async fn num_sessions(ctx: &AppContext) -> i64 {
+        let mut conn = ctx.db_connection.lock().await;
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM sessions")
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        count
+    }
+
+    /// Value of a cookie set by the response.
+    fn set_cookie(response: &reqwest::Response, cookie_name: &str) -> Option<String> {
+        response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| value.split(';').next()?.split_once('='))
+            .find(|(name, _)| *name == cookie_name)
+            .map(|(_, value)| value.to_owned())
+    }
+
+    fn location(response: &reqwest::Response) -> &str {
+        response.headers()[header::LOCATION].to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn csrf_token_is_required() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let url = testing::serve_admin(ctx);
+        let client = testing::http_client();
+        let user_id = testing::insert_user(ctx, "alice", Role::Admin, None).await;
+        let session = testing::open_session(ctx, user_id).await;
+
+        let logout = |form: Vec<(&'static str, String)>, header: Option<&str>| {
+            let mut request = client
+                .post(format!("{url}/logout"))
+                .header(header::COOKIE, session.cookie())
+                .form(&form);
+            if let Some(token) = header {
+                request = request.header(CSRF_HEADER, token);
+            }
+            request.send()
+        };
+
+        // Missing or wrong tokens are rejected, and the session stays open.
+        let response = logout(vec![], None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = logout(vec![(CSRF_FIELD, "wrong".to_owned())], None)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = logout(
+            vec![(CSRF_FIELD, session.csrf_token.clone())],
+            Some("wrong"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = client
+            .post(format!("{url}/logout"))
+            .header(header::COOKIE, session.cookie())
+            .json(&serde_json::json!({ CSRF_FIELD: session.csrf_token }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(session_exists(ctx, &session).await);
+
+        // Logging out with the right token closes the session.
+        let response = logout(vec![(CSRF_FIELD, session.csrf_token.clone())], None)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(location(&response), "/admin/login");
+        assert_eq!(set_cookie(&response, SESSION_COOKIE).as_deref(), Some(""));
+        assert!(!session_exists(ctx, &session).await);
+
+        // The token may come in a header as well.
+        let session = testing::open_session(ctx, user_id).await;
+        let response = client
+            .post(format!("{url}/logout"))
+            .header(header::COOKIE, session.cookie())
+            .header(CSRF_HEADER, &session.csrf_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert!(!session_exists(ctx, &session).await);
+
+        // A closed session doesn't let anyone in anymore.
+        let response = client
+            .get(&url)
+            .header(header::COOKIE, session.cookie())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    }
+
+    #[tokio::test]
+    async fn sessions_time_out() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let url = testing::serve_admin(ctx);
+        let client = testing::http_client();
+        let user_id = testing::insert_user(ctx, "alice", Role::Admin, None).await;
+        let now = chrono::Utc::now().naive_utc();
+
+        let get_index = |session: &AdminSession| {
+            client
+                .get(&url)
+                .header(header::COOKIE, session.cookie())
+                .send()
+        };
+
+        // Sessions last 12 hours, as long as they're used at least every hour.
+        let alive = testing::open_session_at(
+            ctx,
+            user_id,
+            now - Duration::hours(11),
+            now - Duration::minutes(59),
+        )
+        .await;
+        let response = get_index(&alive).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let idle = testing::open_session_at(
+            ctx,
+            user_id,
+            now - Duration::hours(2),
+            now - Duration::minutes(61),
+        )
+        .await;
+        let too_old = testing::open_session_at(ctx, user_id, now - Duration::hours(13), now).await;
+        for session in [idle, too_old] {
+            let response = get_index(&session).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SEE_OTHER);
+            assert_eq!(location(&response), "/admin/login?next=%2Fadmin");
+            assert!(!session_exists(ctx, &session).await);
+        }
+
+        // So are the visitors without any session.
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    }
+
+    #[tokio::test]
+    async fn login_requires_the_csrf_cookie() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let url = testing::serve_admin(ctx);
+        let client = testing::http_client();
+        testing::insert_user(ctx, "alice", Role::Admin, None).await;
+
+        let response = client.get(format!("{url}/login")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let csrf_token = set_cookie(&response, LOGIN_CSRF_COOKIE).unwrap();
+        assert!(response.text().await.unwrap().contains(&csrf_token));
+
+        let login = |password: &str, cookie: Option<&str>, field: &str| {
+            let mut request = client.post(format!("{url}/login")).form(&[
+                ("username", "alice"),
+                ("password", password),
+                (CSRF_FIELD, field),
+            ]);
+            if let Some(token) = cookie {
+                request = request.header(header::COOKIE, format!("{LOGIN_CSRF_COOKIE}={token}"));
+            }
+            request.send()
+        };
+
+        // Without the cookie, or with another token, the credentials aren't even checked.
+        let response = login(testing::PASSWORD, None, &csrf_token).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = login(testing::PASSWORD, Some(&csrf_token), "wrong")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = login("wrong", Some(&csrf_token), &csrf_token)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(num_sessions(ctx).await, 0);
+
+        // With both, a session is opened, and the login cookie is cleared.
+        let response = login(testing::PASSWORD, Some(&csrf_token), &csrf_token)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(location(&response), "/admin");
+        assert_eq!(
+            set_cookie(&response, LOGIN_CSRF_COOKIE).as_deref(),
+            Some("")
+        );
+        let token = set_cookie(&response, SESSION_COOKIE).unwrap();
+        assert_eq!(num_sessions(ctx).await, 1);
+
+        let response = client
+            .get(&url)
+            .header(header::COOKIE, format!("{SESSION_COOKIE}={token}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    async fn api_token(ctx: &AppContext, id: i64) -> Option<ApiToken> {
+        let mut conn = ctx.db_connection.lock().await;
+        ApiToken::by_id(id, &mut conn).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn api_tokens_are_scoped() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let url = testing::serve_admin(ctx);
+        let client = testing::http_client();
+        let user_id = testing::insert_user(ctx, "alice", Role::Admin, None).await;
+        let service_id = testing::insert_service(ctx, "Forge", "https://forge.example.org").await;
+        let (read_id, read_only) =
+            testing::insert_api_token(ctx, user_id, &[Scope::Read], None).await;
+        let (_, writer) = testing::insert_api_token(ctx, user_id, &Scope::ALL, None).await;
+
+        // Reading is allowed, and marks the token as used.
+        assert!(api_token(ctx, read_id)
+            .await
+            .unwrap()
+            .last_used_at
+            .is_none());
+        let response = client
+            .get(format!("{url}/api/services"))
+            .bearer_auth(&read_only)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(api_token(ctx, read_id)
+            .await
+            .unwrap()
+            .last_used_at
+            .is_some());
+
+        // Writing needs the scope.
+        let create_intervention = |token: &str| {
+            client
+                .post(format!("{url}/api/intervention"))
+                .bearer_auth(token)
+                .form(&testing::intervention_form(service_id))
+                .send()
+        };
+        let response = create_intervention(&read_only).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = create_intervention(&writer).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+
+        // Whatever the scopes, tokens are only accepted on the API.
+        for path in ["", "/users", "/api-tokens"] {
+            let response = client
+                .get(format!("{url}{path}"))
+                .bearer_auth(&writer)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{path}");
+        }
+        let response = client
+            .post(format!("{url}/logout"))
+            .bearer_auth(&writer)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn expired_and_revoked_api_tokens_are_refused() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let url = testing::serve_admin(ctx);
+        let client = testing::http_client();
+        let user_id = testing::insert_user(ctx, "alice", Role::Admin, None).await;
+        let now = chrono::Utc::now().naive_utc();
+        let (_, expired) =
+            testing::insert_api_token(ctx, user_id, &Scope::ALL, Some(now - Duration::minutes(1)))
+                .await;
+        let (expiring_id, expiring) =
+            testing::insert_api_token(ctx, user_id, &Scope::ALL, Some(now + Duration::days(1)))
+                .await;
+        let (revoked_id, revoked) =
+            testing::insert_api_token(ctx, user_id, &Scope::ALL, None).await;
+        {
+            let mut conn = ctx.db_connection.lock().await;
+            ApiToken::delete(revoked_id, &mut conn).await.unwrap();
+        }
+
+        let list_services = |token: &str| {
+            client
+                .get(format!("{url}/api/services"))
+                .bearer_auth(token)
+                .send()
+        };
+        for token in [&expired, &revoked, &"unknown".to_owned()] {
+            let response = list_services(token).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        }
+
+        // Until it expires, a token is accepted.
+        let response = list_services(&expiring).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(api_token(ctx, expiring_id)
+            .await
+            .unwrap()
+            .last_used_at
+            .is_some());
+    }
+}
@@ -0,0 +1,36 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension};
+use std::sync::Arc;
+use tracing as log;
+
+use crate::{db::models::heartbeats::Heartbeat, monitoring::heartbeats, AppContext};
+
+/// Ping of a heartbeat, by the service itself (e.g. `curl -fsS https://status.example.com/heartbeat/<token>`
+/// at the end of a cron job).
+pub(crate) async fn ping(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    let heartbeat = {
+        let mut conn = ctx.db_connection.lock().await;
+        Heartbeat::by_token(&token, &mut conn).await
+    };
+
+    let heartbeat = match heartbeat {
+        Ok(Some(heartbeat)) => heartbeat,
+        Ok(None) => return (StatusCode::NOT_FOUND, "unknown heartbeat\n"),
+        Err(err) => {
+            log::error!("error when retrieving a heartbeat by token: {err:#}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "internal error\n");
+        }
+    };
+
+    if let Err(err) = heartbeats::ping(&ctx, &heartbeat).await {
+        log::error!(
+            "error when recording a ping of the heartbeat of service {}: {err:#}",
+            heartbeat.service_id
+        );
+        return (StatusCode::INTERNAL_SERVER_ERROR, "internal error\n");
+    }
+
+    (StatusCode::OK, "OK\n")
+}
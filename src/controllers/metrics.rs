@@ -0,0 +1,421 @@
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use std::{collections::BTreeMap, sync::Arc};
+use tracing as log;
+
+use super::is_authorized;
+use crate::{
+    db::models::{
+        interventions::{Severity, Status},
+        services::Service,
+    },
+    metrics::Exposition,
+    status::{self, InterventionWithServices},
+    AppContext,
+};
+
+const SEVERITIES: [Severity; 3] = [
+    Severity::PerformanceIssue,
+    Severity::PartialOutage,
+    Severity::FullOutage,
+];
+
+const OPEN_STATUSES: [Status; 4] = [
+    Status::Planned,
+    Status::Ongoing,
+    Status::Identified,
+    Status::UnderSurveillance,
+];
+
+/// Metrics in the Prometheus text format.
+pub(crate) async fn get(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Some(token) = &ctx.config.metrics_token {
+        if !is_authorized(&headers, token) {
+            return (StatusCode::UNAUTHORIZED, "invalid token\n".to_owned()).into_response();
+        }
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+
+    let read = {
+        let mut conn = ctx.db_connection.lock().await;
+        match Service::get_all(&mut conn).await {
+            Ok(services) => InterventionWithServices::get_all(&mut conn)
+                .await
+                .map(|interventions| (services, interventions)),
+            Err(err) => Err(err),
+        }
+    };
+    let (services, interventions) = match read {
+        Ok(read) => read,
+        Err(err) => {
+            log::error!("error when reading the metrics: {err:#}");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal error\n".to_owned(),
+            )
+                .into_response();
+        }
+    };
+
+    let page = status::compute(&services, &interventions, now);
+
+    let mut out = Exposition::default();
+
+    out.family(
+        "rustatouille_service_status",
+        "gauge",
+        "Computed status of the service: 1 for the current status, 0 for the others.",
+    );
+    for s in &page.services {
+        let id = s.service.id.unwrap().to_string();
+        for status in status::ComputedStatus::ALL {
+            out.sample(
+                "rustatouille_service_status",
+                &[
+                    ("service_id", &id),
+                    ("service", &s.service.name),
+                    ("status", status.as_str()),
+                ],
+                i32::from(s.status == status),
+            );
+        }
+    }
+
+    out.family(
+        "rustatouille_service_severity",
+        "gauge",
+        "Computed status of the service, from 0 (operational) to 4 (full outage).",
+    );
+    for s in &page.services {
+        let id = s.service.id.unwrap().to_string();
+        out.sample(
+            "rustatouille_service_severity",
+            &[("service_id", &id), ("service", &s.service.name)],
+            s.status as i32,
+        );
+    }
+
+    let mut open = BTreeMap::new();
+    for int in &interventions {
+        let int = &int.intervention;
+        if int.status != Status::Resolved {
+            *open
+                .entry((int.severity.to_db_str(), int.status.to_db_str()))
+                .or_insert(0) += 1;
+        }
+    }
+    out.family(
+        "rustatouille_open_interventions",
+        "gauge",
+        "Number of interventions which aren't resolved yet.",
+    );
+    for severity in SEVERITIES {
+        for status in OPEN_STATUSES {
+            let (severity, status) = (severity.to_db_str(), status.to_db_str());
+            out.sample(
+                "rustatouille_open_interventions",
+                &[("severity", severity), ("status", status)],
+                open.get(&(severity, status)).copied().unwrap_or(0),
+            );
+        }
+    }
+
+    ctx.metrics.with_regenerations(|stats| {
+        if let Some(date) = stats.last_date {
+            out.family(
+                "rustatouille_last_regeneration_timestamp_seconds",
+                "gauge",
+                "When the pages were last regenerated.",
+            );
+            out.sample(
+                "rustatouille_last_regeneration_timestamp_seconds",
+                &[],
+                date.timestamp(),
+            );
+            out.family(
+                "rustatouille_last_regeneration_success",
+                "gauge",
+                "Whether the last regeneration of the pages succeeded.",
+            );
+            out.sample(
+                "rustatouille_last_regeneration_success",
+                &[],
+                i32::from(stats.last_success),
+            );
+            out.family(
+                "rustatouille_last_regeneration_duration_seconds",
+                "gauge",
+                "How long the last regeneration of the pages took.",
+            );
+            out.sample(
+                "rustatouille_last_regeneration_duration_seconds",
+                &[],
+                stats.last_duration.as_secs_f64(),
+            );
+        }
+        out.family(
+            "rustatouille_regeneration_duration_seconds",
+            "summary",
+            "Time spent regenerating the pages.",
+        );
+        out.sample(
+            "rustatouille_regeneration_duration_seconds_sum",
+            &[],
+            stats.total_duration.as_secs_f64(),
+        );
+        out.sample(
+            "rustatouille_regeneration_duration_seconds_count",
+            &[],
+            stats.count,
+        );
+    });
+
+    out.family(
+        "rustatouille_http_requests_total",
+        "counter",
+        "Number of handled HTTP requests.",
+    );
+    for ((method, route, code), count) in ctx.metrics.requests() {
+        out.sample(
+            "rustatouille_http_requests_total",
+            &[
+                ("method", &method),
+                ("route", &route),
+                ("status", &code.to_string()),
+            ],
+            count,
+        );
+    }
+
+    (
+        StatusCode::OK,
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        out.finish(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metrics, testing};
+    use axum::{routing::get, Router};
+    use std::collections::{BTreeSet, HashMap};
+
+    /// A sample of the exposition: name, labels and value.
+    type Sample = (String, BTreeMap<String, String>, f64);
+
+    fn is_metric_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+    }
+
+    /// Parse a sample line, checking its syntax.
+    fn parse_sample(line: &str) -> Sample {
+        let (series, value) = line.rsplit_once(' ').unwrap();
+        let value = value.parse().unwrap();
+        let (name, mut rest) = series.split_once('{').unwrap_or((series, ""));
+        assert!(is_metric_name(name), "{line}");
+
+        let mut labels = BTreeMap::new();
+        if !rest.is_empty() {
+            rest = rest.strip_suffix('}').unwrap();
+            while !rest.is_empty() {
+                let (key, value) = rest.split_once("=\"").unwrap();
+                assert!(is_metric_name(key), "{line}");
+                let mut unescaped = String::new();
+                let mut chars = value.char_indices();
+                let end = loop {
+                    match chars.next().unwrap() {
+                        (_, '\\') => match chars.next().unwrap().1 {
+                            'n' => unescaped.push('\n'),
+                            c @ ('\\' | '"') => unescaped.push(c),
+                            c => panic!("invalid escape \\{c} in {line}"),
+                        },
+                        (i, '"') => break i,
+                        (_, c) => unescaped.push(c),
+                    }
+                };
+                assert!(labels.insert(key.to_owned(), unescaped).is_none(), "{line}");
+                rest = &value[end + 1..];
+                rest = rest.strip_prefix(',').unwrap_or(rest);
+            }
+        }
+        (name.to_owned(), labels, value)
+    }
+
+    /// Parse the whole exposition, checking that every sample belongs to a family declared
+    /// beforehand, with its help and type, and that no series is repeated.
+    fn parse(text: &str) -> Vec<Sample> {
+        assert!(text.ends_with('\n'));
+        let mut types: HashMap<String, String> = HashMap::new();
+        let mut helped = BTreeSet::new();
+        let mut series = BTreeSet::new();
+        let mut samples = Vec::new();
+        for line in text.lines() {
+            if let Some(help) = line.strip_prefix("# HELP ") {
+                let (name, text) = help.split_once(' ').unwrap();
+                assert!(!text.is_empty());
+                assert!(helped.insert(name.to_owned()), "{line}");
+            } else if let Some(kind) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = kind.split_once(' ').unwrap();
+                assert!(helped.contains(name), "{line}");
+                assert!(["counter", "gauge", "summary"].contains(&kind), "{line}");
+                assert!(types.insert(name.to_owned(), kind.to_owned()).is_none());
+            } else {
+                assert!(!line.starts_with('#'), "{line}");
+                let sample = parse_sample(line);
+                let family = ["_sum", "_count"]
+                    .iter()
+                    .filter_map(|suffix| sample.0.strip_suffix(suffix))
+                    .find(|family| types.get(*family).is_some_and(|kind| kind == "summary"))
+                    .unwrap_or(&sample.0);
+                assert!(types.contains_key(family), "undeclared family: {line}");
+                assert!(
+                    series.insert((sample.0.clone(), sample.1.clone())),
+                    "{line}"
+                );
+                samples.push(sample);
+            }
+        }
+        samples
+    }
+
+    fn value(samples: &[Sample], name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        let labels: BTreeMap<_, _> = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        samples
+            .iter()
+            .find(|(n, l, _)| n == name && *l == labels)
+            .map(|(_, _, value)| *value)
+    }
+
+    fn serve_metrics(ctx: &Arc<AppContext>) -> String {
+        let router = Router::new()
+            .route("/metrics", get(super::get))
+            .layer(axum::middleware::from_fn(metrics::count_requests))
+            .layer(Extension(ctx.clone()));
+        format!("{}/metrics", testing::serve(router))
+    }
+
+    #[tokio::test]
+    async fn services_are_exposed() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let url = serve_metrics(ctx);
+        // Down, through the helper's intervention.
+        testing::insert_intervention(ctx, "Forge down").await;
+        let wiki_id =
+            testing::insert_service(ctx, "Wiki \"v2\"\\\nnew", "https://w.example.org").await;
+        let wiki_id = wiki_id.to_string();
+
+        let scrape = || async {
+            let response = reqwest::get(&url).await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::OK);
+            assert_eq!(
+                response.headers()[reqwest::header::CONTENT_TYPE],
+                "text/plain; version=0.0.4; charset=utf-8"
+            );
+            parse(&response.text().await.unwrap())
+        };
+        let samples = scrape().await;
+
+        // One gauge per status, only the current one being set.
+        for (service, current) in [
+            ("Forge", "full-outage"),
+            ("Wiki \"v2\"\\\nnew", "operational"),
+        ] {
+            for status in status::ComputedStatus::ALL {
+                let status = status.as_str();
+                let gauge = samples.iter().find(|(name, labels, _)| {
+                    name == "rustatouille_service_status"
+                        && labels["service"] == service
+                        && labels["status"] == status
+                });
+                let expected = if status == current { 1.0 } else { 0.0 };
+                assert_eq!(gauge.unwrap().2, expected, "{service} {status}");
+            }
+        }
+        assert_eq!(
+            value(
+                &samples,
+                "rustatouille_service_severity",
+                &[("service_id", &wiki_id), ("service", "Wiki \"v2\"\\\nnew")]
+            ),
+            Some(0.0)
+        );
+        assert_eq!(
+            value(
+                &samples,
+                "rustatouille_open_interventions",
+                &[("severity", "full_outage"), ("status", "ongoing")]
+            ),
+            Some(1.0)
+        );
+        assert_eq!(
+            value(
+                &samples,
+                "rustatouille_open_interventions",
+                &[("severity", "partial_outage"), ("status", "ongoing")]
+            ),
+            Some(0.0)
+        );
+        assert_eq!(
+            value(
+                &samples,
+                "rustatouille_regeneration_duration_seconds_count",
+                &[]
+            ),
+            Some(0.0)
+        );
+
+        // The scrapes themselves are counted.
+        let samples = scrape().await;
+        assert_eq!(
+            value(
+                &samples,
+                "rustatouille_http_requests_total",
+                &[("method", "GET"), ("route", "/metrics"), ("status", "200")]
+            ),
+            Some(1.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_may_require_a_token() {
+        let mut config = testing::config();
+        config.metrics_token = Some("scraper".to_owned());
+        let app = testing::app(config).await;
+        let url = serve_metrics(&app.ctx);
+        let client = reqwest::Client::new();
+
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let response = client.get(&url).bearer_auth("wrong").send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let response = client
+            .get(&url)
+            .bearer_auth("scraper")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        parse(&response.text().await.unwrap());
+    }
+}
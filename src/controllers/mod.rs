@@ -1,2 +1,56 @@
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+
+/// Unwraps a result, or logs the error and returns an internal server error response.
+macro_rules! try500 {
+    ($val:expr, $ctx:literal) => {
+        match $val {
+            Ok(r) => r,
+            Err(err) => {
+                log::error!("error when {}: {:?}", $ctx, err,);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Html("Ohnoes, something went wrong!").into_response(),
+                );
+            }
+        }
+    };
+}
+
+pub(crate) fn not_found(text: impl Into<String>) -> (StatusCode, Response) {
+    (StatusCode::NOT_FOUND, Html(text.into()).into_response())
+}
+
+pub(crate) fn forbidden() -> (StatusCode, Response) {
+    (
+        StatusCode::FORBIDDEN,
+        Html("You're not allowed to do this.").into_response(),
+    )
+}
+
+pub(crate) fn redirect(to_url: &str) -> (StatusCode, Response) {
+    let location = HeaderValue::from_str(to_url).expect("invalid redirection URL");
+    (
+        StatusCode::FOUND,
+        [(header::LOCATION, location)].into_response(),
+    )
+}
+
+/// Whether the request carries the expected bearer token.
+pub(crate) fn is_authorized(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| crate::tokens::secure_eq(value.trim(), token))
+}
+
 pub mod admin;
+pub mod alerts;
+pub mod auth;
+pub mod heartbeats;
+pub mod metrics;
 pub mod r#static;
+pub mod subscriptions;
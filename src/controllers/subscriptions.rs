@@ -0,0 +1,485 @@
+use axum::{
+    extract::{Query, RawForm},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    Extension, Form,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing as log;
+
+use super::not_found;
+use crate::{
+    db::models::{
+        interventions::Severity,
+        preferences::{Preferences, SubscriptionKind},
+        services::Service,
+        subscribers::Subscriber,
+    },
+    notifications::email,
+    AppContext,
+};
+
+/// Minimum delay between two confirmation emails sent to the same address.
+const CONFIRMATION_COOLDOWN_MINUTES: i64 = 10;
+
+#[derive(Serialize)]
+struct SubscriptionRenderCtx<'a> {
+    title: &'a str,
+    message: &'a str,
+}
+
+/// Render a page with a simple message for the (non-admin) user.
+fn render_message(
+    ctx: &AppContext,
+    status: StatusCode,
+    title: &str,
+    message: &str,
+) -> (StatusCode, Response) {
+    let render_ctx = try500!(
+        tera::Context::from_serialize(SubscriptionRenderCtx { title, message }),
+        "preparing context for subscription template"
+    );
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("subscription.html", &render_ctx),
+        "rendering subscription template"
+    );
+
+    (status, Html(page).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct SubscribeForm {
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    token: String,
+}
+
+pub(crate) async fn subscribe(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Form(payload): Form<SubscribeForm>,
+) -> impl IntoResponse {
+    let Some(mailer) = &ctx.mailer else {
+        return not_found("Email subscriptions are disabled.");
+    };
+
+    // TODO i18n for all the messages
+    let email = payload.email.trim();
+    if email.parse::<lettre::Address>().is_err() {
+        return render_message(
+            &ctx,
+            StatusCode::BAD_REQUEST,
+            "Abonnement",
+            "Cette adresse email n'est pas valide.",
+        );
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let cooldown = chrono::Duration::minutes(CONFIRMATION_COOLDOWN_MINUTES);
+
+    let subscriber = {
+        let mut conn = ctx.db_connection.lock().await;
+        let existing = try500!(
+            Subscriber::by_email(email, &mut conn).await,
+            "looking for an existing subscriber"
+        );
+        let subscriber = match existing {
+            Some(subscriber) if subscriber.confirmed => None,
+            // Don't let anyone flood an address with confirmation emails.
+            Some(subscriber)
+                if subscriber
+                    .last_sent_at
+                    .is_some_and(|last_sent_at| now - last_sent_at < cooldown) =>
+            {
+                log::debug!("not sending another confirmation email so soon");
+                None
+            }
+            Some(subscriber) => Some(subscriber),
+            None => {
+                let mut subscriber = Subscriber::new(email.to_owned());
+                let id = try500!(
+                    Subscriber::insert(&mut conn, &subscriber).await,
+                    "inserting a new subscriber"
+                );
+                subscriber.id = Some(id);
+                Some(subscriber)
+            }
+        };
+        // Mark the email as sent before releasing the lock, so that concurrent requests don't
+        // send it twice.
+        if let Some(subscriber) = &subscriber {
+            try500!(
+                Subscriber::mark_sent(subscriber.id.unwrap(), now, &mut conn).await,
+                "recording a confirmation email"
+            );
+        }
+        subscriber
+    };
+
+    if let Some(subscriber) = subscriber {
+        try500!(
+            email::send_confirmation(&ctx, mailer, &subscriber).await,
+            "sending a confirmation email"
+        );
+    }
+
+    // Answer the same thing, whether the address was already subscribed or not, or an email was
+    // sent recently, so as not to leak who's subscribed.
+    render_message(
+        &ctx,
+        StatusCode::OK,
+        "Abonnement",
+        "Un email vous a été envoyé : ouvrez le lien qu'il contient pour confirmer votre abonnement.",
+    )
+}
+
+#[derive(Serialize)]
+struct PreferencesServiceRenderCtx {
+    id: i64,
+    name: String,
+    selected: bool,
+}
+
+#[derive(Serialize)]
+struct PreferencesRenderCtx<'a> {
+    /// Message displayed above the form, if any.
+    notice: Option<&'a str>,
+    id: i64,
+    signature: &'a str,
+    unsubscribe_token: &'a str,
+    services: Vec<PreferencesServiceRenderCtx>,
+    min_severity: Option<Severity>,
+    include_planned: bool,
+}
+
+/// Render the preferences page of a subscriber.
+async fn render_preferences(
+    ctx: &AppContext,
+    subscriber: &Subscriber,
+    signature: &str,
+    notice: Option<&str>,
+) -> (StatusCode, Response) {
+    let id = subscriber.id.unwrap();
+
+    let (services, prefs) = {
+        let mut conn = ctx.db_connection.lock().await;
+        let services = try500!(
+            Service::get_all(&mut conn).await,
+            "retrieving services for the preferences page"
+        );
+        let prefs = try500!(
+            Preferences::read(SubscriptionKind::Subscriber, id, &mut conn).await,
+            "retrieving the preferences of a subscriber"
+        );
+        (services, prefs)
+    };
+
+    let render_ctx = try500!(
+        tera::Context::from_serialize(PreferencesRenderCtx {
+            notice,
+            id,
+            signature,
+            unsubscribe_token: &subscriber.token,
+            services: services
+                .into_iter()
+                .map(|s| {
+                    let id = s.id.unwrap();
+                    PreferencesServiceRenderCtx {
+                        id,
+                        name: s.name,
+                        selected: prefs.service_ids.contains(&id),
+                    }
+                })
+                .collect(),
+            min_severity: prefs.min_severity,
+            include_planned: prefs.include_planned,
+        }),
+        "preparing context for preferences template"
+    );
+
+    let page = try500!(
+        ctx.templates
+            .read()
+            .unwrap()
+            .render("preferences.html", &render_ctx),
+        "rendering preferences template"
+    );
+
+    (StatusCode::OK, Html(page).into_response())
+}
+
+pub(crate) async fn confirm(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Query(query): Query<TokenQuery>,
+) -> impl IntoResponse {
+    let subscriber = {
+        let mut conn = ctx.db_connection.lock().await;
+        let found = try500!(
+            Subscriber::confirm(&query.token, &mut conn).await,
+            "confirming a subscriber"
+        );
+        if found {
+            try500!(
+                Subscriber::by_token(&query.token, &mut conn).await,
+                "retrieving a confirmed subscriber"
+            )
+        } else {
+            None
+        }
+    };
+
+    let Some(subscriber) = subscriber else {
+        return render_message(
+            &ctx,
+            StatusCode::NOT_FOUND,
+            "Abonnement",
+            "Ce lien de confirmation n'est pas valide.",
+        );
+    };
+
+    // Let the new subscriber pick their services right away.
+    let signature = email::preferences_signature(&ctx, &subscriber);
+    render_preferences(
+        &ctx,
+        &subscriber,
+        &signature,
+        Some("Votre abonnement est confirmé ! Vous recevrez un email à chaque incident concernant les services choisis ci-dessous."),
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct PreferencesQuery {
+    id: i64,
+    signature: String,
+}
+
+#[derive(Deserialize)]
+pub struct PreferencesForm {
+    id: i64,
+    signature: String,
+    /// No service at all means all of them.
+    #[serde(default)]
+    services: Vec<i64>,
+    #[serde(rename = "min-severity", default)]
+    min_severity: Option<Severity>,
+    #[serde(rename = "include-planned", default)]
+    include_planned: Option<String>,
+}
+
+/// Find the subscriber designated by a signed preferences link.
+async fn signed_subscriber(
+    ctx: &AppContext,
+    id: i64,
+    signature: &str,
+) -> anyhow::Result<Option<Subscriber>> {
+    let subscriber = {
+        let mut conn = ctx.db_connection.lock().await;
+        Subscriber::by_id(id, &mut conn).await?
+    };
+    Ok(subscriber.filter(|s| email::check_preferences_signature(ctx, s, signature)))
+}
+
+fn invalid_preferences_link(ctx: &AppContext) -> (StatusCode, Response) {
+    render_message(
+        ctx,
+        StatusCode::NOT_FOUND,
+        "Préférences",
+        "Ce lien n'est pas valide, ou vous êtes désabonné·e.",
+    )
+}
+
+pub(crate) async fn preferences(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Query(query): Query<PreferencesQuery>,
+) -> impl IntoResponse {
+    let subscriber = try500!(
+        signed_subscriber(&ctx, query.id, &query.signature).await,
+        "retrieving a subscriber from a signed link"
+    );
+    let Some(subscriber) = subscriber else {
+        return invalid_preferences_link(&ctx);
+    };
+
+    render_preferences(&ctx, &subscriber, &query.signature, None).await
+}
+
+pub(crate) async fn update_preferences(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    RawForm(request_bytes): RawForm,
+) -> impl IntoResponse {
+    let payload: PreferencesForm = match serde_html_form::from_bytes(&request_bytes) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log::error!("error when parsing preferences request: {err:#}");
+            return (
+                StatusCode::BAD_REQUEST,
+                Html("invalid request").into_response(),
+            );
+        }
+    };
+
+    let subscriber = try500!(
+        signed_subscriber(&ctx, payload.id, &payload.signature).await,
+        "retrieving a subscriber from a signed link"
+    );
+    let Some(subscriber) = subscriber else {
+        return invalid_preferences_link(&ctx);
+    };
+
+    {
+        let mut conn = ctx.db_connection.lock().await;
+        let services = try500!(
+            Service::get_all(&mut conn).await,
+            "retrieving services when saving preferences"
+        );
+
+        let prefs = Preferences {
+            // Ignore the services which don't exist (anymore).
+            service_ids: payload
+                .services
+                .into_iter()
+                .filter(|id| services.iter().any(|s| s.id == Some(*id)))
+                .collect(),
+            min_severity: payload.min_severity,
+            include_planned: payload.include_planned.is_some(),
+        };
+        try500!(
+            prefs
+                .save(SubscriptionKind::Subscriber, payload.id, &mut conn)
+                .await,
+            "saving the preferences of a subscriber"
+        );
+    }
+
+    render_preferences(
+        &ctx,
+        &subscriber,
+        &payload.signature,
+        Some("Vos préférences ont été enregistrées."),
+    )
+    .await
+}
+
+/// Unsubscribe in a single click: this is reachable with both GET (link in the email body) and
+/// POST (`List-Unsubscribe-Post` header).
+pub(crate) async fn unsubscribe(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    Query(query): Query<TokenQuery>,
+) -> impl IntoResponse {
+    let found = {
+        let mut conn = ctx.db_connection.lock().await;
+        try500!(
+            Subscriber::delete_by_token(&query.token, &mut conn).await,
+            "removing a subscriber"
+        )
+    };
+
+    if !found {
+        return render_message(
+            &ctx,
+            StatusCode::NOT_FOUND,
+            "Désabonnement",
+            "Ce lien de désabonnement n'est pas valide, ou vous êtes déjà désabonné·e.",
+        );
+    }
+
+    render_message(
+        &ctx,
+        StatusCode::OK,
+        "Désabonnement",
+        "Vous êtes désabonné·e, vous ne recevrez plus d'email.",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    async fn insert_subscriber(ctx: &AppContext, email: &str) -> Subscriber {
+        let mut conn = ctx.db_connection.lock().await;
+        let mut subscriber = Subscriber::new(email.to_owned());
+        subscriber.confirmed = true;
+        subscriber.id = Some(Subscriber::insert(&mut conn, &subscriber).await.unwrap());
+        subscriber
+    }
+
+    async fn open(ctx: &Arc<AppContext>, id: i64, signature: &str) -> StatusCode {
+        let query = format!("id={id}&signature={signature}");
+        preferences(
+            Extension(ctx.clone()),
+            Query(serde_html_form::from_str(&query).unwrap()),
+        )
+        .await
+        .into_response()
+        .status()
+    }
+
+    async fn save(ctx: &Arc<AppContext>, id: i64, signature: &str) -> StatusCode {
+        let form = format!("id={id}&signature={signature}&min-severity=full-outage");
+        update_preferences(Extension(ctx.clone()), RawForm(form.into()))
+            .await
+            .into_response()
+            .status()
+    }
+
+    async fn min_severity(ctx: &AppContext, id: i64) -> Option<Severity> {
+        let mut conn = ctx.db_connection.lock().await;
+        Preferences::read(SubscriptionKind::Subscriber, id, &mut conn)
+            .await
+            .unwrap()
+            .min_severity
+    }
+
+    #[tokio::test]
+    async fn preferences_links() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+
+        let alice = insert_subscriber(ctx, "alice@example.org").await;
+        let id = alice.id.unwrap();
+        let signature = email::preferences_signature(ctx, &alice);
+
+        // A valid link opens the preferences, and saves them.
+        assert_eq!(open(ctx, id, &signature).await, StatusCode::OK);
+        assert_eq!(save(ctx, id, &signature).await, StatusCode::OK);
+        assert_eq!(min_severity(ctx, id).await, Some(Severity::FullOutage));
+
+        // A tampered one doesn't, nor does a valid one with another id.
+        let mut tampered = signature.clone();
+        let last = if tampered.pop() == Some('0') {
+            '1'
+        } else {
+            '0'
+        };
+        tampered.push(last);
+        assert_eq!(open(ctx, id, &tampered).await, StatusCode::NOT_FOUND);
+        assert_eq!(save(ctx, id, &tampered).await, StatusCode::NOT_FOUND);
+        assert_eq!(open(ctx, id + 1, &signature).await, StatusCode::NOT_FOUND);
+
+        // Once Alice has unsubscribed, her link doesn't work for the next subscriber who gets
+        // her id.
+        {
+            let mut conn = ctx.db_connection.lock().await;
+            assert!(Subscriber::delete_by_token(&alice.token, &mut conn)
+                .await
+                .unwrap());
+        }
+        let bob = insert_subscriber(ctx, "bob@example.org").await;
+        assert_eq!(bob.id, Some(id));
+        assert_eq!(open(ctx, id, &signature).await, StatusCode::NOT_FOUND);
+        assert_eq!(save(ctx, id, &signature).await, StatusCode::NOT_FOUND);
+        assert_eq!(min_severity(ctx, id).await, None);
+
+        let signature = email::preferences_signature(ctx, &bob);
+        assert_eq!(open(ctx, id, &signature).await, StatusCode::OK);
+    }
+}
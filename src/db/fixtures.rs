@@ -31,6 +31,7 @@ pub async fn insert_fixtures(conn: &mut AnyConnection) -> anyhow::Result<()> {
                 id: None,
                 name: s.0.to_owned(),
                 url: s.1.to_owned(),
+                team_id: None,
             },
         )
         .await?;
@@ -72,6 +73,7 @@ pub async fn insert_fixtures(conn: &mut AnyConnection) -> anyhow::Result<()> {
             status,
             severity,
             is_planned: status == Status::Planned,
+            auto_resolve: false,
             title: title.to_owned(),
             description: Some(LOREM_IPSUM.to_owned()),
         };
@@ -0,0 +1,47 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 10: heartbeats, pinged by the services which can't be probed from the outside.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 10 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE heartbeats (
+                id INTEGER PRIMARY KEY,
+                service_id INTEGER NOT NULL UNIQUE,
+                token VARCHAR(63) NOT NULL UNIQUE,
+                enabled BOOLEAN NOT NULL,
+                period_secs INTEGER NOT NULL,
+                grace_secs INTEGER NOT NULL,
+                created_at INTEGER NOT NULL,
+                last_ping_at INTEGER,
+                FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute(
+        r#"
+            CREATE TABLE heartbeat_pings (
+                id INTEGER PRIMARY KEY,
+                service_id INTEGER NOT NULL,
+                date INTEGER NOT NULL,
+                FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 10 WHERE version = 9;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 11: alerts received from external monitoring systems, and the interventions they
+/// opened.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 11 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE alerts (
+                id INTEGER PRIMARY KEY,
+                source VARCHAR(63) NOT NULL,
+                fingerprint VARCHAR(255) NOT NULL,
+                service_id INTEGER NOT NULL,
+                firing BOOLEAN NOT NULL,
+                intervention_id INTEGER,
+                updated_at INTEGER NOT NULL,
+                UNIQUE (source, fingerprint),
+                FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE,
+                FOREIGN KEY (intervention_id) REFERENCES interventions(id) ON DELETE SET NULL
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 11 WHERE version = 10;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 12: aggregates of the probe results, per hour and per day.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 12 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE probe_aggregates (
+                service_id INTEGER NOT NULL,
+                resolution VARCHAR(63) NOT NULL,
+                bucket_start INTEGER NOT NULL,
+                num_checks INTEGER NOT NULL,
+                num_successes INTEGER NOT NULL,
+                num_response_times INTEGER NOT NULL,
+                sum_response_time_ms INTEGER NOT NULL,
+                max_response_time_ms INTEGER,
+                PRIMARY KEY (service_id, resolution, bucket_start),
+                FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 12 WHERE version = 11;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
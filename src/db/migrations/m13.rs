@@ -0,0 +1,44 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 13: thresholds and flap detection of the automatic interventions.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 13 {
+        return Ok(());
+    }
+
+    for column in [
+        "success_threshold INTEGER NOT NULL DEFAULT 1",
+        "min_incident_secs INTEGER NOT NULL DEFAULT 0",
+        "flap_window_secs INTEGER NOT NULL DEFAULT 3600",
+        "flap_threshold INTEGER NOT NULL DEFAULT 0",
+    ] {
+        conn.execute(format!("ALTER TABLE probes ADD COLUMN {column};").as_str())
+            .await?;
+    }
+
+    conn.execute("ALTER TABLE monitor_states ADD COLUMN flapping BOOLEAN NOT NULL DEFAULT FALSE;")
+        .await?;
+
+    conn.execute(
+        r#"
+            CREATE TABLE monitor_changes (
+                id INTEGER PRIMARY KEY,
+                service_id INTEGER NOT NULL,
+                source VARCHAR(63) NOT NULL,
+                date INTEGER NOT NULL,
+                FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 13 WHERE version = 12;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 14: rules mapping the alerts received from external monitoring systems to services
+/// and severities.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 14 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE alert_rules (
+                id INTEGER PRIMARY KEY,
+                source VARCHAR(63),
+                label VARCHAR(255) NOT NULL,
+                pattern VARCHAR(255) NOT NULL,
+                service_id INTEGER NOT NULL,
+                severity VARCHAR(63),
+                FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 14 WHERE version = 13;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 15: administrator accounts, and who changed the interventions.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 15 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE users (
+                id INTEGER PRIMARY KEY,
+                username VARCHAR(255) NOT NULL UNIQUE,
+                password_hash VARCHAR(255) NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute(
+        r#"
+            CREATE TABLE intervention_changes (
+                id INTEGER PRIMARY KEY,
+                intervention_id INTEGER NOT NULL,
+                user_id INTEGER,
+                date INTEGER NOT NULL,
+                action VARCHAR(63) NOT NULL,
+                automatic BOOLEAN NOT NULL DEFAULT FALSE,
+                FOREIGN KEY (intervention_id) REFERENCES interventions(id) ON DELETE CASCADE,
+                FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 15 WHERE version = 14;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
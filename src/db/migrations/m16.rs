@@ -0,0 +1,33 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 16: sessions of the administrators.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 16 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE sessions (
+                id INTEGER PRIMARY KEY,
+                token_hash VARCHAR(255) NOT NULL UNIQUE,
+                user_id INTEGER NOT NULL,
+                csrf_token VARCHAR(255) NOT NULL,
+                created_at INTEGER NOT NULL,
+                last_seen_at INTEGER NOT NULL,
+                FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 16 WHERE version = 15;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 17: API tokens.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 17 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE api_tokens (
+                id INTEGER PRIMARY KEY,
+                name VARCHAR(255) NOT NULL,
+                token_hash VARCHAR(255) NOT NULL UNIQUE,
+                scopes VARCHAR(255) NOT NULL,
+                user_id INTEGER NOT NULL,
+                created_at INTEGER NOT NULL,
+                expires_at INTEGER,
+                last_used_at INTEGER,
+                FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 17 WHERE version = 16;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
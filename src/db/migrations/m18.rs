@@ -0,0 +1,42 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 18: roles of the users, and teams owning services.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 18 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE teams (
+                id INTEGER PRIMARY KEY,
+                name VARCHAR(255) NOT NULL UNIQUE
+            );
+        "#,
+    )
+    .await?;
+
+    // Existing users keep all their rights.
+    conn.execute("ALTER TABLE users ADD COLUMN role VARCHAR(63) NOT NULL DEFAULT 'admin';")
+        .await?;
+
+    for table in ["users", "services"] {
+        conn.execute(
+            format!(
+                "ALTER TABLE {table} ADD COLUMN team_id INTEGER REFERENCES teams(id) ON DELETE SET NULL;"
+            )
+            .as_str(),
+        )
+        .await?;
+    }
+
+    conn.execute("UPDATE migrations SET version = 18 WHERE version = 17;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use anyhow::Context as _;
+use sqlx::{any::AnyKind, AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 19: audit log of the changes made in the administration.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 19 {
+        return Ok(());
+    }
+
+    // The user isn't a foreign key: the entries must outlive the users they mention.
+    conn.execute(
+        r#"
+            CREATE TABLE audit_log (
+                id INTEGER PRIMARY KEY,
+                date INTEGER NOT NULL,
+                user_id INTEGER,
+                username VARCHAR(255) NOT NULL,
+                action VARCHAR(63) NOT NULL,
+                entity VARCHAR(63) NOT NULL,
+                entity_id INTEGER,
+                diff TEXT NOT NULL
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("CREATE INDEX audit_log_date ON audit_log (date);")
+        .await?;
+
+    // The application never changes the entries; make sure nothing else does either.
+    if conn.kind() == AnyKind::Sqlite {
+        for (name, event) in [("no_update", "UPDATE"), ("no_delete", "DELETE")] {
+            conn.execute(
+                format!(
+                    r#"
+                    CREATE TRIGGER audit_log_{name} BEFORE {event} ON audit_log
+                    BEGIN
+                        SELECT RAISE(ABORT, 'the audit log is append-only');
+                    END;
+                    "#
+                )
+                .as_str(),
+            )
+            .await?;
+        }
+    }
+
+    conn.execute("UPDATE migrations SET version = 19 WHERE version = 18;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
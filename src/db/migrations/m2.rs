@@ -0,0 +1,25 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 2: allow planned maintenances to be automatically resolved.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 2 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            ALTER TABLE interventions ADD COLUMN auto_resolve BOOLEAN NOT NULL DEFAULT FALSE;
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 2 WHERE version = 1;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 20: when the last confirmation email was sent to a subscriber.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 20 {
+        return Ok(());
+    }
+
+    conn.execute("ALTER TABLE subscribers ADD COLUMN last_sent_at INTEGER;")
+        .await?;
+
+    conn.execute("UPDATE migrations SET version = 20 WHERE version = 19;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 21: status of each service, as of the last notification about it.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 21 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE notified_statuses (
+                service_id INTEGER PRIMARY KEY,
+                status VARCHAR(63) NOT NULL,
+                FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 21 WHERE version = 20;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
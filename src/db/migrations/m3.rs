@@ -0,0 +1,31 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 3: email subscribers.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 3 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE subscribers (
+                id INTEGER PRIMARY KEY,
+                email VARCHAR(255) NOT NULL UNIQUE,
+                token VARCHAR(63) NOT NULL UNIQUE,
+                confirmed BOOLEAN NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 3 WHERE version = 2;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 4: outgoing webhooks, and their delivery log.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 4 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE webhooks (
+                id INTEGER PRIMARY KEY,
+                url VARCHAR(255) NOT NULL,
+                secret VARCHAR(255) NOT NULL,
+                events VARCHAR(255) NOT NULL,
+                enabled BOOLEAN NOT NULL
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute(
+        r#"
+            CREATE TABLE webhook_deliveries (
+                id INTEGER PRIMARY KEY,
+                webhook_id INTEGER NOT NULL,
+                event VARCHAR(63) NOT NULL,
+                payload TEXT NOT NULL,
+                attempt INTEGER NOT NULL,
+                date INTEGER NOT NULL,
+                status_code INTEGER,
+                error TEXT,
+                FOREIGN KEY (webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 4 WHERE version = 3;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 5: per-subscription preferences (services, severity threshold, planned
+/// maintenances), for email subscribers and webhooks.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 5 {
+        return Ok(());
+    }
+
+    for table in ["subscribers", "webhooks"] {
+        conn.execute(format!("ALTER TABLE {table} ADD COLUMN min_severity VARCHAR(63);").as_str())
+            .await?;
+        conn.execute(
+            format!(
+                "ALTER TABLE {table} ADD COLUMN include_planned BOOLEAN NOT NULL DEFAULT TRUE;"
+            )
+            .as_str(),
+        )
+        .await?;
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE subscribers_services (
+                id INTEGER PRIMARY KEY,
+                subscriber_id INTEGER NOT NULL,
+                service_id INTEGER NOT NULL,
+                FOREIGN KEY (subscriber_id) REFERENCES subscribers(id) ON DELETE CASCADE,
+                FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute(
+        r#"
+            CREATE TABLE webhooks_services (
+                id INTEGER PRIMARY KEY,
+                webhook_id INTEGER NOT NULL,
+                service_id INTEGER NOT NULL,
+                FOREIGN KEY (webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE,
+                FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 5 WHERE version = 4;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
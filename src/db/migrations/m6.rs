@@ -0,0 +1,34 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 6: posts made on remote services about interventions (e.g. Mastodon statuses), so
+/// later ones can refer to them.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 6 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE remote_posts (
+                id INTEGER PRIMARY KEY,
+                channel VARCHAR(63) NOT NULL,
+                target VARCHAR(255) NOT NULL,
+                intervention_id INTEGER NOT NULL,
+                remote_id VARCHAR(255) NOT NULL,
+                date INTEGER NOT NULL,
+                FOREIGN KEY (intervention_id) REFERENCES interventions(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 6 WHERE version = 5;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 7: outbox of the notifications waiting to be delivered.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 7 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE outbox (
+                id INTEGER PRIMARY KEY,
+                channel VARCHAR(63) NOT NULL,
+                target VARCHAR(255) NOT NULL,
+                event VARCHAR(63) NOT NULL,
+                payload TEXT NOT NULL,
+                status VARCHAR(63) NOT NULL,
+                attempts INTEGER NOT NULL,
+                next_attempt_at INTEGER NOT NULL,
+                last_error TEXT,
+                created_at INTEGER NOT NULL
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 7 WHERE version = 6;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
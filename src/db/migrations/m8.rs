@@ -0,0 +1,32 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 8: reminders sent before the planned maintenances.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 8 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE reminders (
+                id INTEGER PRIMARY KEY,
+                intervention_id INTEGER NOT NULL,
+                lead_time INTEGER NOT NULL,
+                remind_at INTEGER NOT NULL,
+                sent_at INTEGER,
+                FOREIGN KEY (intervention_id) REFERENCES interventions(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 8 WHERE version = 7;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
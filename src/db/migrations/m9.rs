@@ -0,0 +1,67 @@
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Executor as _};
+
+use super::read_latest_migration;
+
+/// Migration 9: health probes of the services, their results, and the state of the automatic
+/// interventions.
+pub(super) async fn run(conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let latest_version = read_latest_migration(conn).await?;
+    if latest_version >= 9 {
+        return Ok(());
+    }
+
+    conn.execute(
+        r#"
+            CREATE TABLE probes (
+                id INTEGER PRIMARY KEY,
+                service_id INTEGER NOT NULL UNIQUE,
+                kind VARCHAR(63) NOT NULL,
+                settings TEXT NOT NULL,
+                enabled BOOLEAN NOT NULL,
+                interval_secs INTEGER NOT NULL,
+                timeout_secs INTEGER NOT NULL,
+                failure_threshold INTEGER NOT NULL,
+                FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute(
+        r#"
+            CREATE TABLE probe_results (
+                id INTEGER PRIMARY KEY,
+                service_id INTEGER NOT NULL,
+                date INTEGER NOT NULL,
+                success BOOLEAN NOT NULL,
+                response_time_ms INTEGER,
+                message TEXT,
+                FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute(
+        r#"
+            CREATE TABLE monitor_states (
+                service_id INTEGER NOT NULL,
+                source VARCHAR(63) NOT NULL,
+                consecutive_failures INTEGER NOT NULL,
+                consecutive_successes INTEGER NOT NULL,
+                intervention_id INTEGER,
+                PRIMARY KEY (service_id, source),
+                FOREIGN KEY (service_id) REFERENCES services(id) ON DELETE CASCADE,
+                FOREIGN KEY (intervention_id) REFERENCES interventions(id) ON DELETE SET NULL
+            );
+        "#,
+    )
+    .await?;
+
+    conn.execute("UPDATE migrations SET version = 9 WHERE version = 8;")
+        .await
+        .context("when upgrading db version number")?;
+
+    Ok(())
+}
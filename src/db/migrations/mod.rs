@@ -2,6 +2,26 @@ use sqlx::{AnyConnection, Executor as _};
 use tracing::log;
 
 mod m1;
+mod m10;
+mod m11;
+mod m12;
+mod m13;
+mod m14;
+mod m15;
+mod m16;
+mod m17;
+mod m18;
+mod m19;
+mod m2;
+mod m20;
+mod m21;
+mod m3;
+mod m4;
+mod m5;
+mod m6;
+mod m7;
+mod m8;
+mod m9;
 
 async fn read_latest_migration(conn: &mut AnyConnection) -> anyhow::Result<i64> {
     let version: Result<(i64,), _> = sqlx::query_as("SELECT version FROM migrations;")
@@ -43,5 +63,25 @@ async fn create_migration_table(conn: &mut AnyConnection) -> anyhow::Result<()>
 
 pub(super) async fn run_migrations(conn: &mut AnyConnection) -> anyhow::Result<()> {
     m1::run(conn).await?;
+    m2::run(conn).await?;
+    m3::run(conn).await?;
+    m4::run(conn).await?;
+    m5::run(conn).await?;
+    m6::run(conn).await?;
+    m7::run(conn).await?;
+    m8::run(conn).await?;
+    m9::run(conn).await?;
+    m10::run(conn).await?;
+    m11::run(conn).await?;
+    m12::run(conn).await?;
+    m13::run(conn).await?;
+    m14::run(conn).await?;
+    m15::run(conn).await?;
+    m16::run(conn).await?;
+    m17::run(conn).await?;
+    m18::run(conn).await?;
+    m19::run(conn).await?;
+    m20::run(conn).await?;
+    m21::run(conn).await?;
     Ok(())
 }
@@ -0,0 +1,143 @@
+use sqlx::AnyConnection;
+use std::collections::BTreeMap;
+
+use super::interventions::Severity;
+
+/// A rule mapping the alerts received from external monitoring systems to a service.
+///
+/// Rules are tried in the order they were created; the first one matching an alert wins.
+#[derive(Clone, Debug)]
+pub struct AlertRule {
+    pub id: Option<i64>,
+    /// Only alerts from this source match, e.g. `grafana`; any source if missing.
+    pub source: Option<String>,
+    /// Label of the alert whose value is matched.
+    pub label: String,
+    /// Value of the label, where `*` matches any sequence of characters.
+    pub pattern: String,
+    pub service_id: i64,
+    /// Severity of the intervention; if missing, it's taken from the alert.
+    pub severity: Option<Severity>,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for AlertRule
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    Option<String>: sqlx::decode::Decode<'a, R::Database>,
+    Option<String>: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let id: i64 = row.try_get("id")?;
+        let source: Option<String> = row.try_get("source")?;
+        let label: String = row.try_get("label")?;
+        let pattern: String = row.try_get("pattern")?;
+        let service_id: i64 = row.try_get("service_id")?;
+        let severity: Option<String> = row.try_get("severity")?;
+        let severity = severity.map(|severity| Severity::from_db_str(&severity).unwrap());
+        Ok(AlertRule {
+            id: Some(id),
+            source,
+            label,
+            pattern,
+            service_id,
+            severity,
+        })
+    }
+}
+
+/// Does the value match the pattern, where `*` matches any sequence of characters?
+///
+/// The comparison is case-insensitive, like the lookup of services by name.
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let value = value.to_lowercase();
+
+    let mut parts = pattern.split('*');
+    // There's always a first part, maybe empty.
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<_> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard at all.
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+impl AlertRule {
+    /// Does this rule apply to an alert from the given source, with the given labels?
+    pub fn matches(&self, source: &str, labels: &BTreeMap<String, String>) -> bool {
+        if self.source.as_deref().is_some_and(|s| s != source) {
+            return false;
+        }
+        labels
+            .get(&self.label)
+            .is_some_and(|value| glob_matches(&self.pattern, value))
+    }
+
+    pub async fn get_all(conn: &mut AnyConnection) -> anyhow::Result<Vec<AlertRule>> {
+        let rules = sqlx::query_as::<_, AlertRule>(
+            r#"
+            SELECT * FROM alert_rules ORDER BY id ASC
+        "#,
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(rules)
+    }
+
+    pub async fn by_id(id: i64, conn: &mut AnyConnection) -> anyhow::Result<Option<AlertRule>> {
+        let rule = sqlx::query_as::<_, AlertRule>(
+            r#"
+            SELECT * FROM alert_rules WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+        Ok(rule)
+    }
+
+    pub async fn insert(conn: &mut AnyConnection, r: &AlertRule) -> anyhow::Result<i64> {
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            INSERT INTO alert_rules (source, label, pattern, service_id, severity)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id
+        "#,
+        )
+        .bind(&r.source)
+        .bind(&r.label)
+        .bind(&r.pattern)
+        .bind(r.service_id)
+        .bind(r.severity.map(Severity::to_db_str))
+        .fetch_one(conn)
+        .await?;
+        Ok(id)
+    }
+
+    pub async fn delete(id: i64, conn: &mut AnyConnection) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM alert_rules WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
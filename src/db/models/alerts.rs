@@ -0,0 +1,90 @@
+use chrono::NaiveDateTime;
+use sqlx::AnyConnection;
+
+/// Last known state of an alert received from an external monitoring system.
+#[derive(Clone, Debug)]
+pub struct Alert {
+    /// System the alert comes from, e.g. `alertmanager`.
+    pub source: String,
+    /// Identifies the alert within its source, across its notifications.
+    pub fingerprint: String,
+    pub service_id: i64,
+    pub firing: bool,
+    /// Intervention opened for the alert, if any.
+    pub intervention_id: Option<i64>,
+    pub updated_at: NaiveDateTime,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for Alert
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+    Option<i64>: sqlx::decode::Decode<'a, R::Database>,
+    Option<i64>: sqlx::types::Type<R::Database>,
+    bool: sqlx::decode::Decode<'a, R::Database>,
+    bool: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let source: String = row.try_get("source")?;
+        let fingerprint: String = row.try_get("fingerprint")?;
+        let service_id: i64 = row.try_get("service_id")?;
+        let firing: bool = row.try_get("firing")?;
+        let intervention_id: Option<i64> = row.try_get("intervention_id")?;
+        let updated_at: i64 = row.try_get("updated_at")?;
+        let updated_at = NaiveDateTime::from_timestamp_opt(updated_at, 0).unwrap();
+        Ok(Alert {
+            source,
+            fingerprint,
+            service_id,
+            firing,
+            intervention_id,
+            updated_at,
+        })
+    }
+}
+
+impl Alert {
+    pub async fn by_fingerprint(
+        source: &str,
+        fingerprint: &str,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Option<Alert>> {
+        let alert = sqlx::query_as::<_, Alert>(
+            r#"
+            SELECT * FROM alerts WHERE source = $1 AND fingerprint = $2
+        "#,
+        )
+        .bind(source)
+        .bind(fingerprint)
+        .fetch_optional(conn)
+        .await?;
+        Ok(alert)
+    }
+
+    /// Create or update the state of an alert.
+    pub async fn save(conn: &mut AnyConnection, a: &Alert) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO alerts (source, fingerprint, service_id, firing, intervention_id, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (source, fingerprint) DO UPDATE SET
+                service_id = excluded.service_id,
+                firing = excluded.firing,
+                intervention_id = excluded.intervention_id,
+                updated_at = excluded.updated_at
+        "#,
+        )
+        .bind(&a.source)
+        .bind(&a.fingerprint)
+        .bind(a.service_id)
+        .bind(a.firing)
+        .bind(a.intervention_id)
+        .bind(a.updated_at.timestamp())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
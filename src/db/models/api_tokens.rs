@@ -0,0 +1,191 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::AnyConnection;
+
+/// What an API token allows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Scope {
+    /// Read the services and the interventions.
+    Read,
+    /// Create and update interventions.
+    InterventionsWrite,
+    /// Create services.
+    ServicesWrite,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::Read, Scope::InterventionsWrite, Scope::ServicesWrite];
+
+    pub(crate) fn to_db_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::InterventionsWrite => "interventions:write",
+            Self::ServicesWrite => "services:write",
+        }
+    }
+
+    pub(crate) fn from_db_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "read" => Self::Read,
+            "interventions:write" => Self::InterventionsWrite,
+            "services:write" => Self::ServicesWrite,
+            _ => anyhow::bail!("unexpected value for API token scope: {s}"),
+        })
+    }
+}
+
+/// A token letting scripts use the API of the administration, on behalf of a user.
+#[derive(Clone, Debug)]
+pub struct ApiToken {
+    pub id: Option<i64>,
+    pub name: String,
+    /// SHA-256 hash of the token; the token itself is only shown once, when it's created.
+    pub token_hash: String,
+    pub scopes: Vec<Scope>,
+    /// User on whose behalf the token acts; deleting the user revokes their tokens.
+    pub user_id: i64,
+    pub created_at: NaiveDateTime,
+    /// The token is refused from this date, if set.
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for ApiToken
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+    Option<i64>: sqlx::decode::Decode<'a, R::Database>,
+    Option<i64>: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let id: i64 = row.try_get("id")?;
+        let name: String = row.try_get("name")?;
+        let token_hash: String = row.try_get("token_hash")?;
+        let scopes: String = row.try_get("scopes")?;
+        // Ignore the scopes which don't exist anymore.
+        let scopes = scopes
+            .split_whitespace()
+            .filter_map(|scope| Scope::from_db_str(scope).ok())
+            .collect();
+        let user_id: i64 = row.try_get("user_id")?;
+        let created_at: i64 = row.try_get("created_at")?;
+        let created_at = NaiveDateTime::from_timestamp_opt(created_at, 0).unwrap();
+        let expires_at: Option<i64> = row.try_get("expires_at")?;
+        let expires_at = expires_at.map(|date| NaiveDateTime::from_timestamp_opt(date, 0).unwrap());
+        let last_used_at: Option<i64> = row.try_get("last_used_at")?;
+        let last_used_at =
+            last_used_at.map(|date| NaiveDateTime::from_timestamp_opt(date, 0).unwrap());
+        Ok(ApiToken {
+            id: Some(id),
+            name,
+            token_hash,
+            scopes,
+            user_id,
+            created_at,
+            expires_at,
+            last_used_at,
+        })
+    }
+}
+
+impl ApiToken {
+    /// Has the token expired at the given date?
+    pub fn is_expired(&self, now: NaiveDateTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    pub async fn insert(conn: &mut AnyConnection, t: &ApiToken) -> anyhow::Result<i64> {
+        let scopes = t
+            .scopes
+            .iter()
+            .map(|scope| scope.to_db_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            INSERT INTO api_tokens (name, token_hash, scopes, user_id, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id
+        "#,
+        )
+        .bind(&t.name)
+        .bind(&t.token_hash)
+        .bind(scopes)
+        .bind(t.user_id)
+        .bind(t.created_at.timestamp())
+        .bind(t.expires_at.map(|date| date.timestamp()))
+        .fetch_one(conn)
+        .await?;
+        Ok(id)
+    }
+
+    pub async fn get_all(conn: &mut AnyConnection) -> anyhow::Result<Vec<ApiToken>> {
+        let tokens = sqlx::query_as::<_, ApiToken>(
+            r#"
+            SELECT * FROM api_tokens ORDER BY name ASC
+        "#,
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(tokens)
+    }
+
+    pub async fn by_id(id: i64, conn: &mut AnyConnection) -> anyhow::Result<Option<ApiToken>> {
+        let token = sqlx::query_as::<_, ApiToken>(
+            r#"
+            SELECT * FROM api_tokens WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+        Ok(token)
+    }
+
+    pub async fn by_token_hash(
+        token_hash: &str,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Option<ApiToken>> {
+        let token = sqlx::query_as::<_, ApiToken>(
+            r#"
+            SELECT * FROM api_tokens WHERE token_hash = $1
+        "#,
+        )
+        .bind(token_hash)
+        .fetch_optional(conn)
+        .await?;
+        Ok(token)
+    }
+
+    pub async fn touch(
+        id: i64,
+        date: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE api_tokens SET last_used_at = $1 WHERE id = $2
+        "#,
+        )
+        .bind(date.timestamp())
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(id: i64, conn: &mut AnyConnection) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM api_tokens WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::AnyConnection;
+
+/// What was done to an entity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 3] = [
+        AuditAction::Create,
+        AuditAction::Update,
+        AuditAction::Delete,
+    ];
+
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Create => "Created",
+            Self::Update => "Updated",
+            Self::Delete => "Deleted",
+        }
+    }
+
+    pub(crate) fn to_db_str(self) -> &'static str {
+        match self {
+            Self::Create => "create",
+            Self::Update => "update",
+            Self::Delete => "delete",
+        }
+    }
+
+    pub(crate) fn from_db_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "create" => Self::Create,
+            "update" => Self::Update,
+            "delete" => Self::Delete,
+            _ => anyhow::bail!("unexpected value for audit action: {s}"),
+        })
+    }
+}
+
+/// Kind of entity an audit entry is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum AuditEntity {
+    Service,
+    Intervention,
+    /// An update posted on an intervention.
+    Update,
+    User,
+    Team,
+    ApiToken,
+    Webhook,
+    AlertRule,
+    Probe,
+    Heartbeat,
+}
+
+impl AuditEntity {
+    pub const ALL: [AuditEntity; 10] = [
+        AuditEntity::Service,
+        AuditEntity::Intervention,
+        AuditEntity::Update,
+        AuditEntity::User,
+        AuditEntity::Team,
+        AuditEntity::ApiToken,
+        AuditEntity::Webhook,
+        AuditEntity::AlertRule,
+        AuditEntity::Probe,
+        AuditEntity::Heartbeat,
+    ];
+
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Service => "Service",
+            Self::Intervention => "Intervention",
+            Self::Update => "Update",
+            Self::User => "User",
+            Self::Team => "Team",
+            Self::ApiToken => "API token",
+            Self::Webhook => "Webhook",
+            Self::AlertRule => "Alert rule",
+            Self::Probe => "Probe",
+            Self::Heartbeat => "Heartbeat",
+        }
+    }
+
+    pub(crate) fn to_db_str(self) -> &'static str {
+        match self {
+            Self::Service => "service",
+            Self::Intervention => "intervention",
+            Self::Update => "update",
+            Self::User => "user",
+            Self::Team => "team",
+            Self::ApiToken => "api-token",
+            Self::Webhook => "webhook",
+            Self::AlertRule => "alert-rule",
+            Self::Probe => "probe",
+            Self::Heartbeat => "heartbeat",
+        }
+    }
+
+    pub(crate) fn from_db_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "service" => Self::Service,
+            "intervention" => Self::Intervention,
+            "update" => Self::Update,
+            "user" => Self::User,
+            "team" => Self::Team,
+            "api-token" => Self::ApiToken,
+            "webhook" => Self::Webhook,
+            "alert-rule" => Self::AlertRule,
+            "probe" => Self::Probe,
+            "heartbeat" => Self::Heartbeat,
+            _ => anyhow::bail!("unexpected value for audit entity: {s}"),
+        })
+    }
+}
+
+/// An entry of the audit log: who changed what, and when.
+///
+/// The log is append-only: entries are never updated nor deleted.
+#[derive(Clone, Debug)]
+pub struct AuditEntry {
+    pub id: Option<i64>,
+    pub date: NaiveDateTime,
+    /// Missing for the changes made automatically, and kept when the user is deleted.
+    pub user_id: Option<i64>,
+    /// Name of the user at the time of the change, or `system`.
+    pub username: String,
+    pub action: AuditAction,
+    pub entity: AuditEntity,
+    pub entity_id: Option<i64>,
+    /// JSON object with the `before` and `after` values of the fields which changed.
+    pub diff: String,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for AuditEntry
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+    Option<i64>: sqlx::decode::Decode<'a, R::Database>,
+    Option<i64>: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let id: i64 = row.try_get("id")?;
+        let date: i64 = row.try_get("date")?;
+        let date = NaiveDateTime::from_timestamp_opt(date, 0).unwrap();
+        let user_id: Option<i64> = row.try_get("user_id")?;
+        let username: String = row.try_get("username")?;
+        let action: String = row.try_get("action")?;
+        let action = AuditAction::from_db_str(&action).unwrap();
+        let entity: String = row.try_get("entity")?;
+        let entity = AuditEntity::from_db_str(&entity).unwrap();
+        let entity_id: Option<i64> = row.try_get("entity_id")?;
+        let diff: String = row.try_get("diff")?;
+        Ok(AuditEntry {
+            id: Some(id),
+            date,
+            user_id,
+            username,
+            action,
+            entity,
+            entity_id,
+            diff,
+        })
+    }
+}
+
+/// Which entries of the audit log to read; all of them if nothing is set.
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+    pub entity: Option<AuditEntity>,
+    pub entity_id: Option<i64>,
+    pub action: Option<AuditAction>,
+    pub username: Option<String>,
+    /// Only the entries from this date, included.
+    pub from: Option<NaiveDateTime>,
+    /// Only the entries before this date, excluded.
+    pub to: Option<NaiveDateTime>,
+    pub limit: Option<i64>,
+}
+
+impl AuditEntry {
+    pub async fn insert(conn: &mut AnyConnection, e: &AuditEntry) -> anyhow::Result<i64> {
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            INSERT INTO audit_log (date, user_id, username, action, entity, entity_id, diff)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id
+        "#,
+        )
+        .bind(e.date.timestamp())
+        .bind(e.user_id)
+        .bind(&e.username)
+        .bind(e.action.to_db_str())
+        .bind(e.entity.to_db_str())
+        .bind(e.entity_id)
+        .bind(&e.diff)
+        .fetch_one(conn)
+        .await?;
+        Ok(id)
+    }
+
+    /// Returns the entries matching the filter, most recent first.
+    pub async fn get_filtered(
+        filter: &AuditFilter,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Vec<AuditEntry>> {
+        let entries = sqlx::query_as::<_, AuditEntry>(
+            r#"
+            SELECT * FROM audit_log
+            WHERE ($1 IS NULL OR entity = $1)
+            AND ($2 IS NULL OR entity_id = $2)
+            AND ($3 IS NULL OR action = $3)
+            AND ($4 IS NULL OR username = $4)
+            AND ($5 IS NULL OR date >= $5)
+            AND ($6 IS NULL OR date < $6)
+            ORDER BY date DESC, id DESC
+            LIMIT $7
+        "#,
+        )
+        .bind(filter.entity.map(AuditEntity::to_db_str))
+        .bind(filter.entity_id)
+        .bind(filter.action.map(AuditAction::to_db_str))
+        .bind(filter.username.as_deref())
+        .bind(filter.from.map(|date| date.timestamp()))
+        .bind(filter.to.map(|date| date.timestamp()))
+        .bind(filter.limit.unwrap_or(-1))
+        .fetch_all(conn)
+        .await?;
+        Ok(entries)
+    }
+
+    /// Names of all the users who appear in the log.
+    pub async fn get_usernames(conn: &mut AnyConnection) -> anyhow::Result<Vec<String>> {
+        let usernames = sqlx::query_as::<_, (String,)>(
+            r#"
+            SELECT DISTINCT username FROM audit_log ORDER BY username ASC
+        "#,
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(usernames.into_iter().map(|(username,)| username).collect())
+    }
+}
@@ -1,8 +1,77 @@
 use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::AnyConnection;
 
-#[allow(dead_code)]
-#[derive(sqlx::FromRow)]
+/// An update posted on an intervention.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Comment {
-    date: NaiveDateTime,
-    description: String,
+    pub date: NaiveDateTime,
+    pub description: String,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for Comment
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let date: i64 = row.try_get("date")?;
+        let date = NaiveDateTime::from_timestamp_opt(date, 0).unwrap();
+        let description: String = row.try_get("description")?;
+        Ok(Comment { date, description })
+    }
+}
+
+impl Comment {
+    /// Insert a new comment, attached to the given intervention.
+    pub async fn insert_for_intervention(
+        conn: &mut AnyConnection,
+        intervention_id: i64,
+        c: &Comment,
+    ) -> anyhow::Result<i64> {
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            INSERT INTO comments (description, date) VALUES ($1, $2) RETURNING id
+        "#,
+        )
+        .bind(&c.description)
+        .bind(c.date.timestamp())
+        .fetch_one(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO interventions_comments (intervention_id, comment_id) VALUES ($1, $2)
+        "#,
+        )
+        .bind(intervention_id)
+        .bind(id)
+        .execute(conn)
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Returns all the comments of an intervention, most recent first.
+    pub async fn by_intervention(
+        intervention_id: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Vec<Comment>> {
+        let comments = sqlx::query_as::<_, Comment>(
+            r#"
+            SELECT c.description, c.date
+            FROM comments AS c, interventions_comments AS ic
+            WHERE ic.intervention_id = $1
+            AND ic.comment_id == c.id
+            ORDER BY c.date DESC, c.id DESC
+        "#,
+        )
+        .bind(intervention_id)
+        .fetch_all(conn)
+        .await?;
+        Ok(comments)
+    }
 }
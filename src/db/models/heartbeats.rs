@@ -0,0 +1,220 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::AnyConnection;
+
+/// A heartbeat: the service (e.g. a batch job) pings a secret URL, and is considered down when
+/// the pings stop.
+#[derive(Clone, Debug, Serialize)]
+pub struct Heartbeat {
+    pub id: Option<i64>,
+    pub service_id: i64,
+    /// Secret part of the URL to ping.
+    pub token: String,
+    pub enabled: bool,
+    /// Expected delay between two pings, in seconds.
+    pub period_secs: i64,
+    /// How late a ping can be before the service is considered down, in seconds.
+    pub grace_secs: i64,
+    pub created_at: NaiveDateTime,
+    pub last_ping_at: Option<NaiveDateTime>,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for Heartbeat
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+    Option<i64>: sqlx::decode::Decode<'a, R::Database>,
+    Option<i64>: sqlx::types::Type<R::Database>,
+    bool: sqlx::decode::Decode<'a, R::Database>,
+    bool: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let id: i64 = row.try_get("id")?;
+        let service_id: i64 = row.try_get("service_id")?;
+        let token: String = row.try_get("token")?;
+        let enabled: bool = row.try_get("enabled")?;
+        let period_secs: i64 = row.try_get("period_secs")?;
+        let grace_secs: i64 = row.try_get("grace_secs")?;
+        let created_at: i64 = row.try_get("created_at")?;
+        let created_at = NaiveDateTime::from_timestamp_opt(created_at, 0).unwrap();
+        let last_ping_at: Option<i64> = row.try_get("last_ping_at")?;
+        let last_ping_at =
+            last_ping_at.map(|date| NaiveDateTime::from_timestamp_opt(date, 0).unwrap());
+        Ok(Heartbeat {
+            id: Some(id),
+            service_id,
+            token,
+            enabled,
+            period_secs,
+            grace_secs,
+            created_at,
+            last_ping_at,
+        })
+    }
+}
+
+impl Heartbeat {
+    /// When the service is considered down if it hasn't pinged by then.
+    ///
+    /// Heartbeats which never got pinged are given a period from their creation.
+    pub fn deadline(&self) -> NaiveDateTime {
+        self.last_ping_at.unwrap_or(self.created_at)
+            + chrono::Duration::seconds(self.period_secs + self.grace_secs)
+    }
+
+    /// Create or update the heartbeat of a service; the token and the creation date of an
+    /// existing heartbeat are kept.
+    pub async fn save(conn: &mut AnyConnection, h: &Heartbeat) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO heartbeats (service_id, token, enabled, period_secs, grace_secs, created_at, last_ping_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (service_id) DO UPDATE SET
+                enabled = excluded.enabled,
+                period_secs = excluded.period_secs,
+                grace_secs = excluded.grace_secs
+        "#,
+        )
+        .bind(h.service_id)
+        .bind(&h.token)
+        .bind(h.enabled)
+        .bind(h.period_secs)
+        .bind(h.grace_secs)
+        .bind(h.created_at.timestamp())
+        .bind(h.last_ping_at.map(|date| date.timestamp()))
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn by_service(
+        service_id: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Option<Heartbeat>> {
+        let heartbeat = sqlx::query_as::<_, Heartbeat>(
+            r#"
+            SELECT * FROM heartbeats WHERE service_id = $1
+        "#,
+        )
+        .bind(service_id)
+        .fetch_optional(conn)
+        .await?;
+        Ok(heartbeat)
+    }
+
+    pub async fn by_token(
+        token: &str,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Option<Heartbeat>> {
+        let heartbeat = sqlx::query_as::<_, Heartbeat>(
+            r#"
+            SELECT * FROM heartbeats WHERE token = $1
+        "#,
+        )
+        .bind(token)
+        .fetch_optional(conn)
+        .await?;
+        Ok(heartbeat)
+    }
+
+    pub async fn get_enabled(conn: &mut AnyConnection) -> anyhow::Result<Vec<Heartbeat>> {
+        let heartbeats = sqlx::query_as::<_, Heartbeat>(
+            r#"
+            SELECT * FROM heartbeats WHERE enabled = $1 ORDER BY id ASC
+        "#,
+        )
+        .bind(true)
+        .fetch_all(conn)
+        .await?;
+        Ok(heartbeats)
+    }
+
+    pub async fn delete_for_service(
+        service_id: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM heartbeats WHERE service_id = $1
+        "#,
+        )
+        .bind(service_id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Record a ping of the heartbeat of a service.
+    pub async fn record_ping(
+        service_id: i64,
+        date: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE heartbeats SET last_ping_at = $1 WHERE service_id = $2
+        "#,
+        )
+        .bind(date.timestamp())
+        .bind(service_id)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO heartbeat_pings (service_id, date) VALUES ($1, $2)
+        "#,
+        )
+        .bind(service_id)
+        .bind(date.timestamp())
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Delete the pings of a service, but the given number of latest ones.
+    pub async fn delete_old_pings(
+        service_id: i64,
+        keep: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM heartbeat_pings WHERE service_id = $1 AND id <= (
+                SELECT id FROM heartbeat_pings WHERE service_id = $1
+                ORDER BY id DESC LIMIT 1 OFFSET $2
+            )
+        "#,
+        )
+        .bind(service_id)
+        .bind(keep)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the dates of the latest pings of a service, most recent first.
+    pub async fn get_latest_pings(
+        service_id: i64,
+        limit: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Vec<NaiveDateTime>> {
+        let dates = sqlx::query_as::<_, (i64,)>(
+            r#"
+            SELECT date FROM heartbeat_pings WHERE service_id = $1 ORDER BY id DESC LIMIT $2
+        "#,
+        )
+        .bind(service_id)
+        .bind(limit)
+        .fetch_all(conn)
+        .await?;
+        Ok(dates
+            .into_iter()
+            .map(|(date,)| NaiveDateTime::from_timestamp_opt(date, 0).unwrap())
+            .collect())
+    }
+}
@@ -1,15 +1,19 @@
 use chrono::NaiveDateTime;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sqlx::AnyConnection;
 
-#[derive(Deserialize, Clone, Copy, Debug)]
+/// Severity of an intervention.
+///
+/// Variants are declared from the least to the most severe, so that comparing two severities
+/// tells which one is worse.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Severity {
+    #[serde(rename = "performance-issue")]
+    PerformanceIssue,
     #[serde(rename = "partial-outage")]
     PartialOutage,
     #[serde(rename = "full-outage")]
     FullOutage,
-    #[serde(rename = "performance-issue")]
-    PerformanceIssue,
 }
 
 impl Severity {
@@ -30,7 +34,7 @@ impl Severity {
         }
     }
 
-    fn to_db_str(self) -> &'static str {
+    pub(crate) fn to_db_str(self) -> &'static str {
         match self {
             Self::PartialOutage => "partial_outage",
             Self::FullOutage => "full_outage",
@@ -38,7 +42,7 @@ impl Severity {
         }
     }
 
-    fn from_db_str(s: &str) -> anyhow::Result<Self> {
+    pub(crate) fn from_db_str(s: &str) -> anyhow::Result<Self> {
         Ok(match s {
             "partial_outage" => Self::PartialOutage,
             "full_outage" => Self::FullOutage,
@@ -48,7 +52,7 @@ impl Severity {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Status {
     #[serde(rename = "planned")]
     Planned,
@@ -74,7 +78,7 @@ impl Status {
         }
     }
 
-    fn to_db_str(self) -> &'static str {
+    pub(crate) fn to_db_str(self) -> &'static str {
         match self {
             Self::Planned => "planned",
             Self::Ongoing => "ongoing",
@@ -84,7 +88,7 @@ impl Status {
         }
     }
 
-    fn from_db_str(s: &str) -> anyhow::Result<Self> {
+    pub(crate) fn from_db_str(s: &str) -> anyhow::Result<Self> {
         Ok(match s {
             "planned" => Self::Planned,
             "ongoing" => Self::Ongoing,
@@ -96,7 +100,7 @@ impl Status {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Intervention {
     pub id: Option<i64>,
     pub start_date: NaiveDateTime,
@@ -106,6 +110,9 @@ pub struct Intervention {
     pub status: Status,
     pub severity: Severity,
     pub is_planned: bool,
+    /// For planned maintenances: should the intervention be resolved automatically, once the
+    /// estimated duration has elapsed?
+    pub auto_resolve: bool,
     pub title: String,
     pub description: Option<String>,
 }
@@ -139,6 +146,7 @@ where
         let severity = Severity::from_db_str(&severity).unwrap();
 
         let is_planned: bool = row.try_get("is_planned")?;
+        let auto_resolve: bool = row.try_get("auto_resolve")?;
         let title: String = row.try_get("title")?;
         let description: Option<String> = row.try_get("description")?;
 
@@ -150,6 +158,7 @@ where
             status,
             severity,
             is_planned,
+            auto_resolve,
             title,
             description,
         };
@@ -178,9 +187,9 @@ impl Intervention {
         let (id, ) = sqlx::query_as::<_, (i64, )>(
             r#"
             INSERT INTO interventions
-                (start_date, estimated_duration, end_date, status, severity, is_planned, title, description)
+                (start_date, estimated_duration, end_date, status, severity, is_planned, auto_resolve, title, description)
             VALUES
-                ($1, $2, $3, $4, $5, $6, $7, $8)
+                ($1, $2, $3, $4, $5, $6, $7, $8, $9)
             RETURNING ID;
         "#,
         )
@@ -190,6 +199,7 @@ impl Intervention {
             .bind(i.status.to_db_str())
             .bind(i.severity.to_db_str())
             .bind(i.is_planned)
+            .bind(i.auto_resolve)
             .bind(&i.title)
             .bind(&i.description)
             .fetch_one(conn)
@@ -208,6 +218,73 @@ impl Intervention {
         Ok(interventions)
     }
 
+    pub async fn by_id(id: i64, conn: &mut AnyConnection) -> anyhow::Result<Option<Intervention>> {
+        let intervention = sqlx::query_as::<_, Intervention>(
+            r#"
+            SELECT * FROM interventions WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+        Ok(intervention)
+    }
+
+    pub async fn update(conn: &mut AnyConnection, id: i64, i: &Intervention) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE interventions SET
+                start_date = $1,
+                estimated_duration = $2,
+                end_date = $3,
+                status = $4,
+                severity = $5,
+                is_planned = $6,
+                auto_resolve = $7,
+                title = $8,
+                description = $9
+            WHERE id = $10
+        "#,
+        )
+        .bind(i.start_date.timestamp())
+        .bind(i.estimated_duration)
+        .bind(i.end_date.map(|d| d.timestamp()))
+        .bind(i.status.to_db_str())
+        .bind(i.severity.to_db_str())
+        .bind(i.is_planned)
+        .bind(i.auto_resolve)
+        .bind(&i.title)
+        .bind(&i.description)
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_by_status(
+        status: Status,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Vec<Intervention>> {
+        let interventions = sqlx::query_as::<_, Intervention>(
+            r#"
+            SELECT * FROM interventions WHERE status = $1
+        "#,
+        )
+        .bind(status.to_db_str())
+        .fetch_all(conn)
+        .await?;
+        Ok(interventions)
+    }
+
+    /// If this intervention must be resolved automatically, at which time it should happen.
+    pub fn auto_resolve_date(&self) -> Option<NaiveDateTime> {
+        if !self.auto_resolve {
+            return None;
+        }
+        let duration = self.estimated_duration?;
+        Some(self.start_date + chrono::Duration::minutes(duration))
+    }
+
     pub async fn add_service(
         id: i64,
         service_id: i64,
@@ -226,6 +303,18 @@ impl Intervention {
         Ok(())
     }
 
+    pub async fn remove_services(id: i64, conn: &mut AnyConnection) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM interventions_services WHERE intervention_id = $1
+        "#,
+        )
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
     pub async fn get_service_ids(
         id: i64,
         conn: &mut AnyConnection,
@@ -244,10 +333,102 @@ impl Intervention {
         Ok(ids)
     }
 
-    pub fn is_ongoing(&self) -> bool {
-        self.status == Status::Ongoing
+    /// Record that an administrator (or the application itself, without a user) changed an
+    /// intervention.
+    pub async fn record_change(
+        id: i64,
+        user_id: Option<i64>,
+        action: ChangeAction,
+        date: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO intervention_changes (intervention_id, user_id, date, action, automatic)
+            VALUES ($1, $2, $3, $4, $5)
+        "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(date.timestamp())
+        .bind(action.to_db_str())
+        .bind(user_id.is_none())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the changes of an intervention, most recent first.
+    pub async fn get_changes(
+        id: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Vec<InterventionChange>> {
+        let changes = sqlx::query_as::<_, (i64, String, bool, Option<String>)>(
+            r#"
+            SELECT c.date, c.action, c.automatic, u.username
+            FROM intervention_changes AS c
+            LEFT JOIN users AS u ON u.id = c.user_id
+            WHERE c.intervention_id = $1
+            ORDER BY c.date DESC, c.id DESC
+        "#,
+        )
+        .bind(id)
+        .fetch_all(conn)
+        .await?;
+        changes
+            .into_iter()
+            .map(|(date, action, automatic, username)| {
+                Ok(InterventionChange {
+                    date: NaiveDateTime::from_timestamp_opt(date, 0).unwrap(),
+                    action: ChangeAction::from_db_str(&action)?,
+                    username: if automatic {
+                        Some("system".to_owned())
+                    } else {
+                        username
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+/// What an administrator did to an intervention.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChangeAction {
+    Created,
+    Updated,
+}
+
+impl ChangeAction {
+    // TODO i18n???
+    pub fn label(&self) -> &str {
+        match *self {
+            Self::Created => "Created",
+            Self::Updated => "Updated",
+        }
     }
-    pub fn is_planned(&self) -> bool {
-        self.status == Status::Planned
+
+    pub(crate) fn to_db_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+        }
+    }
+
+    pub(crate) fn from_db_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "created" => Self::Created,
+            "updated" => Self::Updated,
+            _ => anyhow::bail!("unexpected value for change action: {s}"),
+        })
     }
 }
+
+/// A change of an intervention, along with who made it.
+#[derive(Clone, Debug)]
+pub struct InterventionChange {
+    pub date: NaiveDateTime,
+    pub action: ChangeAction,
+    /// `system` for the changes made automatically; missing if the user has been deleted since.
+    pub username: Option<String>,
+}
@@ -1,3 +1,20 @@
+pub mod alert_rules;
+pub mod alerts;
+pub mod api_tokens;
+pub mod audit;
 pub mod comments;
+pub mod heartbeats;
 pub mod interventions;
+pub mod monitors;
+pub mod notified_statuses;
+pub mod outbox;
+pub mod preferences;
+pub mod probes;
+pub mod reminders;
+pub mod remote_posts;
 pub mod services;
+pub mod sessions;
+pub mod subscribers;
+pub mod teams;
+pub mod users;
+pub mod webhooks;
@@ -0,0 +1,139 @@
+use chrono::NaiveDateTime;
+use sqlx::AnyConnection;
+
+/// What detects that a service is up or down, and may open interventions automatically.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MonitorSource {
+    Probe,
+    Heartbeat,
+}
+
+impl MonitorSource {
+    fn to_db_str(self) -> &'static str {
+        match self {
+            Self::Probe => "probe",
+            Self::Heartbeat => "heartbeat",
+        }
+    }
+}
+
+/// Where a service stands according to one of its monitors.
+#[derive(Clone, Debug)]
+pub struct MonitorState {
+    pub service_id: i64,
+    pub source: MonitorSource,
+    pub consecutive_failures: i64,
+    pub consecutive_successes: i64,
+    /// The intervention opened automatically by this monitor, if it's still open.
+    pub intervention_id: Option<i64>,
+    /// Whether the intervention was opened because the service was flapping.
+    pub flapping: bool,
+}
+
+impl MonitorState {
+    /// Read the state of a monitor; monitors which never reported anything start fresh.
+    pub async fn read(
+        service_id: i64,
+        source: MonitorSource,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<MonitorState> {
+        let row = sqlx::query_as::<_, (i64, i64, Option<i64>, bool)>(
+            r#"
+            SELECT consecutive_failures, consecutive_successes, intervention_id, flapping
+            FROM monitor_states
+            WHERE service_id = $1 AND source = $2
+        "#,
+        )
+        .bind(service_id)
+        .bind(source.to_db_str())
+        .fetch_optional(conn)
+        .await?;
+
+        let (consecutive_failures, consecutive_successes, intervention_id, flapping) =
+            row.unwrap_or((0, 0, None, false));
+
+        Ok(MonitorState {
+            service_id,
+            source,
+            consecutive_failures,
+            consecutive_successes,
+            intervention_id,
+            flapping,
+        })
+    }
+
+    pub async fn save(&self, conn: &mut AnyConnection) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO monitor_states (service_id, source, consecutive_failures, consecutive_successes, intervention_id, flapping)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (service_id, source) DO UPDATE SET
+                consecutive_failures = excluded.consecutive_failures,
+                consecutive_successes = excluded.consecutive_successes,
+                intervention_id = excluded.intervention_id,
+                flapping = excluded.flapping
+        "#,
+        )
+        .bind(self.service_id)
+        .bind(self.source.to_db_str())
+        .bind(self.consecutive_failures)
+        .bind(self.consecutive_successes)
+        .bind(self.intervention_id)
+        .bind(self.flapping)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Record that the monitor switched between success and failure, and forget about the
+    /// changes older than `forget_before`.
+    pub async fn record_change(
+        &self,
+        date: NaiveDateTime,
+        forget_before: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM monitor_changes WHERE service_id = $1 AND source = $2 AND date < $3
+        "#,
+        )
+        .bind(self.service_id)
+        .bind(self.source.to_db_str())
+        .bind(forget_before.timestamp())
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO monitor_changes (service_id, source, date) VALUES ($1, $2, $3)
+        "#,
+        )
+        .bind(self.service_id)
+        .bind(self.source.to_db_str())
+        .bind(date.timestamp())
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Number of changes between success and failure since the given date.
+    pub async fn count_changes_since(
+        &self,
+        since: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<i64> {
+        let (count,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            SELECT COUNT(*) FROM monitor_changes WHERE service_id = $1 AND source = $2 AND date >= $3
+        "#,
+        )
+        .bind(self.service_id)
+        .bind(self.source.to_db_str())
+        .bind(since.timestamp())
+        .fetch_one(conn)
+        .await?;
+        Ok(count)
+    }
+}
@@ -0,0 +1,38 @@
+use sqlx::AnyConnection;
+
+/// Status of a service, as of the last notification about it, or when it was first seen.
+#[derive(sqlx::FromRow)]
+pub struct NotifiedStatus {
+    pub service_id: i64,
+    /// Machine-friendly name of the status.
+    pub status: String,
+}
+
+impl NotifiedStatus {
+    pub async fn get_all(conn: &mut AnyConnection) -> anyhow::Result<Vec<NotifiedStatus>> {
+        let statuses = sqlx::query_as::<_, NotifiedStatus>(
+            r#"
+            SELECT * FROM notified_statuses
+        "#,
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(statuses)
+    }
+
+    /// Create or update the status of a service.
+    pub async fn save(conn: &mut AnyConnection, s: &NotifiedStatus) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO notified_statuses (service_id, status)
+            VALUES ($1, $2)
+            ON CONFLICT (service_id) DO UPDATE SET status = excluded.status
+        "#,
+        )
+        .bind(s.service_id)
+        .bind(&s.status)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use chrono::NaiveDateTime;
+use sqlx::AnyConnection;
+
+/// Delivery status of an outbox entry.
+///
+/// Delivered entries are removed from the outbox, so there's no variant for them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutboxStatus {
+    /// Waiting for its (next) delivery attempt.
+    Pending,
+    /// All the delivery attempts failed; only a manual retry will deliver it.
+    Failed,
+}
+
+impl OutboxStatus {
+    fn to_db_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Failed => "failed",
+        }
+    }
+
+    fn from_db_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "pending" => Self::Pending,
+            "failed" => Self::Failed,
+            _ => anyhow::bail!("unexpected value for outbox status: {s}"),
+        })
+    }
+}
+
+/// A notification waiting to be delivered to a single destination.
+#[derive(Clone, Debug)]
+pub struct OutboxEntry {
+    pub id: Option<i64>,
+    /// Notification channel, e.g. "email".
+    pub channel: String,
+    /// Destination within the channel: a subscriber id, a room id, etc.
+    pub target: String,
+    /// Name of the event.
+    pub event: String,
+    /// Everything needed to deliver the notification, serialized as JSON.
+    pub payload: String,
+    pub status: OutboxStatus,
+    /// Number of failed delivery attempts so far.
+    pub attempts: i64,
+    pub next_attempt_at: NaiveDateTime,
+    /// Error of the last failed attempt, if any.
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for OutboxEntry
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    Option<String>: sqlx::decode::Decode<'a, R::Database>,
+    Option<String>: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let id: i64 = row.try_get("id")?;
+        let channel: String = row.try_get("channel")?;
+        let target: String = row.try_get("target")?;
+        let event: String = row.try_get("event")?;
+        let payload: String = row.try_get("payload")?;
+        let status: String = row.try_get("status")?;
+        let status = OutboxStatus::from_db_str(&status).unwrap();
+        let attempts: i64 = row.try_get("attempts")?;
+        let next_attempt_at: i64 = row.try_get("next_attempt_at")?;
+        let next_attempt_at = NaiveDateTime::from_timestamp_opt(next_attempt_at, 0).unwrap();
+        let last_error: Option<String> = row.try_get("last_error")?;
+        let created_at: i64 = row.try_get("created_at")?;
+        let created_at = NaiveDateTime::from_timestamp_opt(created_at, 0).unwrap();
+        Ok(OutboxEntry {
+            id: Some(id),
+            channel,
+            target,
+            event,
+            payload,
+            status,
+            attempts,
+            next_attempt_at,
+            last_error,
+            created_at,
+        })
+    }
+}
+
+impl OutboxEntry {
+    /// Create a new entry, to be delivered right away.
+    pub fn new(channel: &str, target: String, event: &str, payload: String) -> Self {
+        let now = chrono::Utc::now().naive_utc();
+        Self {
+            id: None,
+            channel: channel.to_owned(),
+            target,
+            event: event.to_owned(),
+            payload,
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            created_at: now,
+        }
+    }
+
+    /// Key identifying the delivery of this entry, so that the remote side can ignore the retry of
+    /// a request which actually succeeded.
+    ///
+    /// The ids of deleted entries may be reused, so the payload is part of the key too.
+    pub fn idempotency_key(&self) -> String {
+        use sha2::{Digest as _, Sha256};
+        let mut hasher = Sha256::new();
+        hasher.update(self.id.unwrap().to_string());
+        hasher.update(":");
+        hasher.update(&self.payload);
+        hex::encode(hasher.finalize())
+    }
+
+    pub async fn insert(conn: &mut AnyConnection, e: &OutboxEntry) -> anyhow::Result<i64> {
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            INSERT INTO outbox (channel, target, event, payload, status, attempts, next_attempt_at, last_error, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id
+        "#,
+        )
+        .bind(&e.channel)
+        .bind(&e.target)
+        .bind(&e.event)
+        .bind(&e.payload)
+        .bind(e.status.to_db_str())
+        .bind(e.attempts)
+        .bind(e.next_attempt_at.timestamp())
+        .bind(&e.last_error)
+        .bind(e.created_at.timestamp())
+        .fetch_one(conn)
+        .await?;
+        Ok(id)
+    }
+
+    /// Returns all the entries with the given status, oldest first.
+    pub async fn get_by_status(
+        status: OutboxStatus,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Vec<OutboxEntry>> {
+        let entries = sqlx::query_as::<_, OutboxEntry>(
+            r#"
+            SELECT * FROM outbox WHERE status = $1 ORDER BY id ASC
+        "#,
+        )
+        .bind(status.to_db_str())
+        .fetch_all(conn)
+        .await?;
+        Ok(entries)
+    }
+
+    /// Remove a delivered entry.
+    pub async fn delete(id: i64, conn: &mut AnyConnection) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM outbox WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Record a failed attempt: the entry will be retried at the given date, or is dead-lettered
+    /// if there's no such date.
+    pub async fn record_failure(
+        id: i64,
+        error: &str,
+        next_attempt_at: Option<NaiveDateTime>,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        let status = match next_attempt_at {
+            Some(_) => OutboxStatus::Pending,
+            None => OutboxStatus::Failed,
+        };
+        sqlx::query(
+            r#"
+            UPDATE outbox
+            SET attempts = attempts + 1, last_error = $1, status = $2, next_attempt_at = $3
+            WHERE id = $4
+        "#,
+        )
+        .bind(error)
+        .bind(status.to_db_str())
+        .bind(next_attempt_at.map_or(0, |date| date.timestamp()))
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Put a failed entry back in the queue, for a fresh series of attempts.
+    ///
+    /// Returns false if there's no such failed entry.
+    pub async fn retry(id: i64, conn: &mut AnyConnection) -> anyhow::Result<bool> {
+        let res = sqlx::query(
+            r#"
+            UPDATE outbox SET status = $1, attempts = 0, next_attempt_at = $2
+            WHERE id = $3 AND status = $4
+        "#,
+        )
+        .bind(OutboxStatus::Pending.to_db_str())
+        .bind(chrono::Utc::now().naive_utc().timestamp())
+        .bind(id)
+        .bind(OutboxStatus::Failed.to_db_str())
+        .execute(conn)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+}
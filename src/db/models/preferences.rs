@@ -0,0 +1,171 @@
+use sqlx::{AnyConnection, Connection as _};
+
+use super::interventions::{Intervention, Severity};
+
+/// Kinds of subscriptions which have preferences.
+#[derive(Clone, Copy, Debug)]
+pub enum SubscriptionKind {
+    /// Email subscribers.
+    Subscriber,
+    Webhook,
+}
+
+impl SubscriptionKind {
+    /// Table containing the subscriptions.
+    fn table(self) -> &'static str {
+        match self {
+            Self::Subscriber => "subscribers",
+            Self::Webhook => "webhooks",
+        }
+    }
+
+    /// Join table between the subscriptions and the services.
+    fn services_table(self) -> &'static str {
+        match self {
+            Self::Subscriber => "subscribers_services",
+            Self::Webhook => "webhooks_services",
+        }
+    }
+
+    /// Column referring to the subscription, in the join table.
+    fn id_column(self) -> &'static str {
+        match self {
+            Self::Subscriber => "subscriber_id",
+            Self::Webhook => "webhook_id",
+        }
+    }
+}
+
+/// What a subscription wants to be notified about.
+#[derive(Clone, Debug)]
+pub struct Preferences {
+    /// Services of interest; empty means all of them.
+    pub service_ids: Vec<i64>,
+    /// Only notify about interventions at least this severe; `None` means all of them.
+    pub min_severity: Option<Severity>,
+    /// Notify about planned maintenances too?
+    pub include_planned: bool,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            service_ids: Vec::new(),
+            min_severity: None,
+            include_planned: true,
+        }
+    }
+}
+
+impl Preferences {
+    /// Is the service of interest?
+    pub fn wants_service(&self, service_id: i64) -> bool {
+        self.service_ids.is_empty() || self.service_ids.contains(&service_id)
+    }
+
+    /// Is the intervention, affecting the given services, of interest?
+    pub fn wants_intervention(&self, int: &Intervention, service_ids: &[i64]) -> bool {
+        if int.is_planned && !self.include_planned {
+            return false;
+        }
+        if self.min_severity.is_some_and(|min| int.severity < min) {
+            return false;
+        }
+        self.service_ids.is_empty() || service_ids.iter().any(|sid| self.wants_service(*sid))
+    }
+
+    pub async fn read(
+        kind: SubscriptionKind,
+        id: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Preferences> {
+        let (min_severity, include_planned) = sqlx::query_as::<_, (Option<String>, bool)>(
+            format!(
+                "SELECT min_severity, include_planned FROM {} WHERE id = $1",
+                kind.table()
+            )
+            .as_str(),
+        )
+        .bind(id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        let min_severity = min_severity
+            .map(|s| Severity::from_db_str(&s))
+            .transpose()?;
+
+        let service_ids = sqlx::query_as::<_, (i64,)>(
+            format!(
+                "SELECT service_id FROM {} WHERE {} = $1",
+                kind.services_table(),
+                kind.id_column()
+            )
+            .as_str(),
+        )
+        .bind(id)
+        .fetch_all(conn)
+        .await?
+        .into_iter()
+        .map(|(sid,)| sid)
+        .collect();
+
+        Ok(Preferences {
+            service_ids,
+            min_severity,
+            include_planned,
+        })
+    }
+
+    pub async fn save(
+        &self,
+        kind: SubscriptionKind,
+        id: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        // Readers never see the services half-replaced.
+        let mut tx = conn.begin().await?;
+
+        sqlx::query(
+            format!(
+                "UPDATE {} SET min_severity = $1, include_planned = $2 WHERE id = $3",
+                kind.table()
+            )
+            .as_str(),
+        )
+        .bind(self.min_severity.map(|s| s.to_db_str()))
+        .bind(self.include_planned)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            format!(
+                "DELETE FROM {} WHERE {} = $1",
+                kind.services_table(),
+                kind.id_column()
+            )
+            .as_str(),
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+        for service_id in &self.service_ids {
+            sqlx::query(
+                format!(
+                    "INSERT INTO {} ({}, service_id) VALUES ($1, $2)",
+                    kind.services_table(),
+                    kind.id_column()
+                )
+                .as_str(),
+            )
+            .bind(id)
+            .bind(service_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
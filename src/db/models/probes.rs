@@ -0,0 +1,537 @@
+use anyhow::Context as _;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::AnyConnection;
+
+/// Settings of an HTTP probe.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HttpCheck {
+    /// URL to GET; if not set, the service's URL is used.
+    pub url: Option<String>,
+    pub expected_status: u16,
+    /// Text that must appear in the response body, if any.
+    pub body_match: Option<String>,
+}
+
+/// Settings of a TCP probe, which only checks that a connection can be established.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TcpCheck {
+    pub host: String,
+    pub port: u16,
+}
+
+/// Settings of a TLS probe, which checks the handshake and the expiry of the certificate.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TlsCheck {
+    pub host: String,
+    pub port: u16,
+    /// The probe fails when the certificate expires in less than this many days.
+    pub expiry_warning_days: i64,
+}
+
+/// Settings of a DNS probe, which checks that a name resolves.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DnsCheck {
+    pub name: String,
+    /// Address the name must resolve to, among others, if any.
+    pub expected_address: Option<String>,
+}
+
+/// What a probe checks, along with the settings specific to that kind of check.
+#[derive(Clone, Debug)]
+pub enum ProbeCheck {
+    Http(HttpCheck),
+    Tcp(TcpCheck),
+    Tls(TlsCheck),
+    Dns(DnsCheck),
+}
+
+impl ProbeCheck {
+    /// Names of all the kinds of checks.
+    pub const KINDS: &'static [&'static str] = &["http", "tcp", "tls", "dns"];
+
+    /// Name of the kind of check, as stored in the database.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Http(_) => "http",
+            Self::Tcp(_) => "tcp",
+            Self::Tls(_) => "tls",
+            Self::Dns(_) => "dns",
+        }
+    }
+
+    fn settings_to_json(&self) -> anyhow::Result<String> {
+        Ok(match self {
+            Self::Http(check) => serde_json::to_string(check)?,
+            Self::Tcp(check) => serde_json::to_string(check)?,
+            Self::Tls(check) => serde_json::to_string(check)?,
+            Self::Dns(check) => serde_json::to_string(check)?,
+        })
+    }
+
+    fn from_db(kind: &str, settings: &str) -> anyhow::Result<Self> {
+        Ok(match kind {
+            "http" => Self::Http(serde_json::from_str(settings).context("invalid HTTP settings")?),
+            "tcp" => Self::Tcp(serde_json::from_str(settings).context("invalid TCP settings")?),
+            "tls" => Self::Tls(serde_json::from_str(settings).context("invalid TLS settings")?),
+            "dns" => Self::Dns(serde_json::from_str(settings).context("invalid DNS settings")?),
+            _ => anyhow::bail!("unexpected kind of probe: {kind}"),
+        })
+    }
+}
+
+/// A health check running periodically against a service.
+#[derive(Clone, Debug)]
+pub struct Probe {
+    pub id: Option<i64>,
+    pub service_id: i64,
+    pub enabled: bool,
+    /// Delay between two checks, in seconds.
+    pub interval_secs: i64,
+    /// In seconds.
+    pub timeout_secs: i64,
+    /// Number of consecutive failures before an intervention is opened.
+    pub failure_threshold: i64,
+    /// Number of consecutive successes before the intervention is resolved.
+    pub success_threshold: i64,
+    /// Interventions aren't resolved before they've lasted that long, in seconds.
+    pub min_incident_secs: i64,
+    /// Window over which the changes of state are counted, to detect flapping, in seconds.
+    pub flap_window_secs: i64,
+    /// Number of changes of state within the window making the service flapping; 0 disables
+    /// flap detection.
+    pub flap_threshold: i64,
+    pub check: ProbeCheck,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for Probe
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+    bool: sqlx::decode::Decode<'a, R::Database>,
+    bool: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let id: i64 = row.try_get("id")?;
+        let service_id: i64 = row.try_get("service_id")?;
+        let kind: String = row.try_get("kind")?;
+        let settings: String = row.try_get("settings")?;
+        let check =
+            ProbeCheck::from_db(&kind, &settings).map_err(|err| sqlx::Error::Decode(err.into()))?;
+        let enabled: bool = row.try_get("enabled")?;
+        let interval_secs: i64 = row.try_get("interval_secs")?;
+        let timeout_secs: i64 = row.try_get("timeout_secs")?;
+        let failure_threshold: i64 = row.try_get("failure_threshold")?;
+        let success_threshold: i64 = row.try_get("success_threshold")?;
+        let min_incident_secs: i64 = row.try_get("min_incident_secs")?;
+        let flap_window_secs: i64 = row.try_get("flap_window_secs")?;
+        let flap_threshold: i64 = row.try_get("flap_threshold")?;
+        Ok(Probe {
+            id: Some(id),
+            service_id,
+            enabled,
+            interval_secs,
+            timeout_secs,
+            failure_threshold,
+            success_threshold,
+            min_incident_secs,
+            flap_window_secs,
+            flap_threshold,
+            check,
+        })
+    }
+}
+
+impl Probe {
+    /// Create or replace the probe of a service.
+    pub async fn save(conn: &mut AnyConnection, p: &Probe) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO probes (service_id, kind, settings, enabled, interval_secs, timeout_secs, failure_threshold, success_threshold, min_incident_secs, flap_window_secs, flap_threshold)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (service_id) DO UPDATE SET
+                kind = excluded.kind,
+                settings = excluded.settings,
+                enabled = excluded.enabled,
+                interval_secs = excluded.interval_secs,
+                timeout_secs = excluded.timeout_secs,
+                failure_threshold = excluded.failure_threshold,
+                success_threshold = excluded.success_threshold,
+                min_incident_secs = excluded.min_incident_secs,
+                flap_window_secs = excluded.flap_window_secs,
+                flap_threshold = excluded.flap_threshold
+        "#,
+        )
+        .bind(p.service_id)
+        .bind(p.check.kind())
+        .bind(p.check.settings_to_json()?)
+        .bind(p.enabled)
+        .bind(p.interval_secs)
+        .bind(p.timeout_secs)
+        .bind(p.failure_threshold)
+        .bind(p.success_threshold)
+        .bind(p.min_incident_secs)
+        .bind(p.flap_window_secs)
+        .bind(p.flap_threshold)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn by_service(
+        service_id: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Option<Probe>> {
+        let probe = sqlx::query_as::<_, Probe>(
+            r#"
+            SELECT * FROM probes WHERE service_id = $1
+        "#,
+        )
+        .bind(service_id)
+        .fetch_optional(conn)
+        .await?;
+        Ok(probe)
+    }
+
+    pub async fn get_enabled(conn: &mut AnyConnection) -> anyhow::Result<Vec<Probe>> {
+        let probes = sqlx::query_as::<_, Probe>(
+            r#"
+            SELECT * FROM probes WHERE enabled = $1 ORDER BY id ASC
+        "#,
+        )
+        .bind(true)
+        .fetch_all(conn)
+        .await?;
+        Ok(probes)
+    }
+
+    pub async fn delete_for_service(
+        service_id: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM probes WHERE service_id = $1
+        "#,
+        )
+        .bind(service_id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Outcome of a single check.
+#[derive(Clone, Debug, Serialize)]
+pub struct ProbeResult {
+    pub service_id: i64,
+    pub date: NaiveDateTime,
+    pub success: bool,
+    /// How long the check took, if it got a response at all.
+    pub response_time_ms: Option<i64>,
+    /// What went wrong, or what was observed.
+    pub message: Option<String>,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for ProbeResult
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    Option<String>: sqlx::decode::Decode<'a, R::Database>,
+    Option<String>: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+    Option<i64>: sqlx::decode::Decode<'a, R::Database>,
+    Option<i64>: sqlx::types::Type<R::Database>,
+    bool: sqlx::decode::Decode<'a, R::Database>,
+    bool: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let service_id: i64 = row.try_get("service_id")?;
+        let date: i64 = row.try_get("date")?;
+        let date = NaiveDateTime::from_timestamp_opt(date, 0).unwrap();
+        let success: bool = row.try_get("success")?;
+        let response_time_ms: Option<i64> = row.try_get("response_time_ms")?;
+        let message: Option<String> = row.try_get("message")?;
+        Ok(ProbeResult {
+            service_id,
+            date,
+            success,
+            response_time_ms,
+            message,
+        })
+    }
+}
+
+impl ProbeResult {
+    pub async fn insert(conn: &mut AnyConnection, r: &ProbeResult) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO probe_results (service_id, date, success, response_time_ms, message)
+            VALUES ($1, $2, $3, $4, $5)
+        "#,
+        )
+        .bind(r.service_id)
+        .bind(r.date.timestamp())
+        .bind(r.success)
+        .bind(r.response_time_ms)
+        .bind(&r.message)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the latest results for a service, most recent first.
+    pub async fn get_latest(
+        service_id: i64,
+        limit: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Vec<ProbeResult>> {
+        let results = sqlx::query_as::<_, ProbeResult>(
+            r#"
+            SELECT * FROM probe_results WHERE service_id = $1 ORDER BY id DESC LIMIT $2
+        "#,
+        )
+        .bind(service_id)
+        .bind(limit)
+        .fetch_all(conn)
+        .await?;
+        Ok(results)
+    }
+
+    /// Returns the results recorded since the given date, oldest first.
+    pub async fn get_since(
+        since: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Vec<ProbeResult>> {
+        let results = sqlx::query_as::<_, ProbeResult>(
+            r#"
+            SELECT * FROM probe_results WHERE date >= $1 ORDER BY date ASC
+        "#,
+        )
+        .bind(since.timestamp())
+        .fetch_all(conn)
+        .await?;
+        Ok(results)
+    }
+
+    pub async fn delete_before(
+        date: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM probe_results WHERE date < $1
+        "#,
+        )
+        .bind(date.timestamp())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Duration covered by a probe aggregate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resolution {
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    fn to_db_str(self) -> &'static str {
+        match self {
+            Self::Hour => "hour",
+            Self::Day => "day",
+        }
+    }
+
+    pub fn duration(self) -> chrono::Duration {
+        match self {
+            Self::Hour => chrono::Duration::hours(1),
+            Self::Day => chrono::Duration::days(1),
+        }
+    }
+
+    /// Start of the bucket containing the given date.
+    pub fn bucket_start(self, date: NaiveDateTime) -> NaiveDateTime {
+        let secs = self.duration().num_seconds();
+        let timestamp = date.timestamp();
+        NaiveDateTime::from_timestamp_opt(timestamp - timestamp.rem_euclid(secs), 0).unwrap()
+    }
+}
+
+/// Probe results of a service over an hour or a day.
+#[derive(Clone, Debug)]
+pub struct ProbeAggregate {
+    pub service_id: i64,
+    pub bucket_start: NaiveDateTime,
+    pub num_checks: i64,
+    pub num_successes: i64,
+    /// Number of checks which measured a response time.
+    pub num_response_times: i64,
+    pub sum_response_time_ms: i64,
+    pub max_response_time_ms: Option<i64>,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for ProbeAggregate
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+    Option<i64>: sqlx::decode::Decode<'a, R::Database>,
+    Option<i64>: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let service_id: i64 = row.try_get("service_id")?;
+        let bucket_start: i64 = row.try_get("bucket_start")?;
+        let bucket_start = NaiveDateTime::from_timestamp_opt(bucket_start, 0).unwrap();
+        let num_checks: i64 = row.try_get("num_checks")?;
+        let num_successes: i64 = row.try_get("num_successes")?;
+        let num_response_times: i64 = row.try_get("num_response_times")?;
+        let sum_response_time_ms: i64 = row.try_get("sum_response_time_ms")?;
+        let max_response_time_ms: Option<i64> = row.try_get("max_response_time_ms")?;
+        Ok(ProbeAggregate {
+            service_id,
+            bucket_start,
+            num_checks,
+            num_successes,
+            num_response_times,
+            sum_response_time_ms,
+            max_response_time_ms,
+        })
+    }
+}
+
+impl ProbeAggregate {
+    /// An empty aggregate, to which results or other aggregates can be added.
+    pub fn empty(service_id: i64, bucket_start: NaiveDateTime) -> Self {
+        Self {
+            service_id,
+            bucket_start,
+            num_checks: 0,
+            num_successes: 0,
+            num_response_times: 0,
+            sum_response_time_ms: 0,
+            max_response_time_ms: None,
+        }
+    }
+
+    pub fn add_result(&mut self, result: &ProbeResult) {
+        self.num_checks += 1;
+        if result.success {
+            self.num_successes += 1;
+        }
+        if let Some(response_time_ms) = result.response_time_ms {
+            self.num_response_times += 1;
+            self.sum_response_time_ms += response_time_ms;
+            self.max_response_time_ms = Some(
+                self.max_response_time_ms
+                    .map_or(response_time_ms, |max| max.max(response_time_ms)),
+            );
+        }
+    }
+
+    pub fn add_aggregate(&mut self, other: &ProbeAggregate) {
+        self.num_checks += other.num_checks;
+        self.num_successes += other.num_successes;
+        self.num_response_times += other.num_response_times;
+        self.sum_response_time_ms += other.sum_response_time_ms;
+        self.max_response_time_ms = match (self.max_response_time_ms, other.max_response_time_ms) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    /// Average response time, in milliseconds, if any was measured.
+    pub fn avg_response_time_ms(&self) -> Option<f64> {
+        (self.num_response_times > 0)
+            .then(|| self.sum_response_time_ms as f64 / self.num_response_times as f64)
+    }
+
+    /// Create or replace an aggregate.
+    pub async fn save(
+        conn: &mut AnyConnection,
+        resolution: Resolution,
+        a: &ProbeAggregate,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO probe_aggregates (service_id, resolution, bucket_start, num_checks, num_successes, num_response_times, sum_response_time_ms, max_response_time_ms)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (service_id, resolution, bucket_start) DO UPDATE SET
+                num_checks = excluded.num_checks,
+                num_successes = excluded.num_successes,
+                num_response_times = excluded.num_response_times,
+                sum_response_time_ms = excluded.sum_response_time_ms,
+                max_response_time_ms = excluded.max_response_time_ms
+        "#,
+        )
+        .bind(a.service_id)
+        .bind(resolution.to_db_str())
+        .bind(a.bucket_start.timestamp())
+        .bind(a.num_checks)
+        .bind(a.num_successes)
+        .bind(a.num_response_times)
+        .bind(a.sum_response_time_ms)
+        .bind(a.max_response_time_ms)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the aggregates of all the services since the given date, oldest first.
+    pub async fn get_since(
+        resolution: Resolution,
+        since: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Vec<ProbeAggregate>> {
+        let aggregates = sqlx::query_as::<_, ProbeAggregate>(
+            r#"
+            SELECT * FROM probe_aggregates
+            WHERE resolution = $1 AND bucket_start >= $2
+            ORDER BY bucket_start ASC
+        "#,
+        )
+        .bind(resolution.to_db_str())
+        .bind(since.timestamp())
+        .fetch_all(conn)
+        .await?;
+        Ok(aggregates)
+    }
+
+    /// Returns the start of the latest aggregate of any service, if any.
+    pub async fn latest_bucket_start(
+        resolution: Resolution,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Option<NaiveDateTime>> {
+        let (latest,) = sqlx::query_as::<_, (Option<i64>,)>(
+            r#"
+            SELECT MAX(bucket_start) FROM probe_aggregates WHERE resolution = $1
+        "#,
+        )
+        .bind(resolution.to_db_str())
+        .fetch_one(conn)
+        .await?;
+        Ok(latest.map(|date| NaiveDateTime::from_timestamp_opt(date, 0).unwrap()))
+    }
+
+    pub async fn delete_before(
+        resolution: Resolution,
+        date: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM probe_aggregates WHERE resolution = $1 AND bucket_start < $2
+        "#,
+        )
+        .bind(resolution.to_db_str())
+        .bind(date.timestamp())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use chrono::NaiveDateTime;
+use sqlx::AnyConnection;
+
+/// A reminder about a planned maintenance, sent some time before it starts.
+#[derive(Clone, Debug)]
+pub struct Reminder {
+    pub id: Option<i64>,
+    pub intervention_id: i64,
+    /// How long before the start of the maintenance the reminder is sent, in minutes.
+    pub lead_time: i64,
+    pub remind_at: NaiveDateTime,
+    /// When the reminder was sent, if it was.
+    pub sent_at: Option<NaiveDateTime>,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for Reminder
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+    Option<i64>: sqlx::decode::Decode<'a, R::Database>,
+    Option<i64>: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let id: i64 = row.try_get("id")?;
+        let intervention_id: i64 = row.try_get("intervention_id")?;
+        let lead_time: i64 = row.try_get("lead_time")?;
+        let remind_at: i64 = row.try_get("remind_at")?;
+        let remind_at = NaiveDateTime::from_timestamp_opt(remind_at, 0).unwrap();
+        let sent_at: Option<i64> = row.try_get("sent_at")?;
+        let sent_at = sent_at.map(|date| NaiveDateTime::from_timestamp_opt(date, 0).unwrap());
+        Ok(Reminder {
+            id: Some(id),
+            intervention_id,
+            lead_time,
+            remind_at,
+            sent_at,
+        })
+    }
+}
+
+impl Reminder {
+    pub async fn insert(conn: &mut AnyConnection, r: &Reminder) -> anyhow::Result<i64> {
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            INSERT INTO reminders (intervention_id, lead_time, remind_at, sent_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+        "#,
+        )
+        .bind(r.intervention_id)
+        .bind(r.lead_time)
+        .bind(r.remind_at.timestamp())
+        .bind(r.sent_at.map(|date| date.timestamp()))
+        .fetch_one(conn)
+        .await?;
+        Ok(id)
+    }
+
+    /// Returns all the reminders which haven't been sent yet, soonest first.
+    pub async fn get_pending(conn: &mut AnyConnection) -> anyhow::Result<Vec<Reminder>> {
+        let reminders = sqlx::query_as::<_, Reminder>(
+            r#"
+            SELECT * FROM reminders WHERE sent_at IS NULL ORDER BY remind_at ASC, id ASC
+        "#,
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(reminders)
+    }
+
+    /// Remove the reminders of an intervention which haven't been sent yet.
+    pub async fn delete_pending_for(
+        intervention_id: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM reminders WHERE intervention_id = $1 AND sent_at IS NULL
+        "#,
+        )
+        .bind(intervention_id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn mark_sent(
+        id: i64,
+        date: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE reminders SET sent_at = $1 WHERE id = $2
+        "#,
+        )
+        .bind(date.timestamp())
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use chrono::NaiveDateTime;
+use sqlx::AnyConnection;
+
+/// A post made on a remote service about an intervention, e.g. a Mastodon status or a Matrix
+/// message.
+#[derive(Clone, Debug)]
+pub struct RemotePost {
+    /// Notification channel, e.g. "mastodon".
+    pub channel: String,
+    /// Where the post was made, within the channel: an instance URL, a room id, etc.
+    pub target: String,
+    pub intervention_id: i64,
+    /// Identifier of the post on the remote service.
+    pub remote_id: String,
+    pub date: NaiveDateTime,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for RemotePost
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let channel: String = row.try_get("channel")?;
+        let target: String = row.try_get("target")?;
+        let intervention_id: i64 = row.try_get("intervention_id")?;
+        let remote_id: String = row.try_get("remote_id")?;
+        let date: i64 = row.try_get("date")?;
+        let date = NaiveDateTime::from_timestamp_opt(date, 0).unwrap();
+        Ok(RemotePost {
+            channel,
+            target,
+            intervention_id,
+            remote_id,
+            date,
+        })
+    }
+}
+
+impl RemotePost {
+    pub async fn insert(conn: &mut AnyConnection, p: &RemotePost) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO remote_posts (channel, target, intervention_id, remote_id, date)
+            VALUES ($1, $2, $3, $4, $5)
+        "#,
+        )
+        .bind(&p.channel)
+        .bind(&p.target)
+        .bind(p.intervention_id)
+        .bind(&p.remote_id)
+        .bind(p.date.timestamp())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns all the posts about an intervention on a given channel and target, oldest first.
+    pub async fn get_all_for(
+        channel: &str,
+        target: &str,
+        intervention_id: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Vec<RemotePost>> {
+        let posts = sqlx::query_as::<_, RemotePost>(
+            r#"
+            SELECT * FROM remote_posts
+            WHERE channel = $1 AND target = $2 AND intervention_id = $3
+            ORDER BY id ASC
+        "#,
+        )
+        .bind(channel)
+        .bind(target)
+        .bind(intervention_id)
+        .fetch_all(conn)
+        .await?;
+        Ok(posts)
+    }
+}
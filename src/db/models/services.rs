@@ -1,11 +1,13 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use sqlx::AnyConnection;
 
-#[derive(sqlx::FromRow)]
+#[derive(sqlx::FromRow, Serialize, Deserialize)]
 pub struct Service {
     pub id: Option<i64>,
     pub name: String,
     pub url: String,
+    /// Team managing the interventions of the service; any editor can if it's missing.
+    pub team_id: Option<i64>,
 }
 
 #[derive(sqlx::FromRow, Serialize)]
@@ -13,6 +15,7 @@ pub struct ServiceWithNumInterventions {
     pub id: i64,
     pub name: String,
     pub url: String,
+    pub team_id: Option<i64>,
     pub num_interventions: i64,
 }
 
@@ -20,11 +23,12 @@ impl Service {
     pub async fn insert(conn: &mut AnyConnection, s: &Service) -> anyhow::Result<i64> {
         let (id,) = sqlx::query_as::<_, (i64,)>(
             r#"
-            INSERT INTO services (name, url) VALUES ($1, $2) RETURNING id
+            INSERT INTO services (name, url, team_id) VALUES ($1, $2, $3) RETURNING id
         "#,
         )
         .bind(&s.name)
         .bind(&s.url)
+        .bind(s.team_id)
         .fetch_one(conn)
         .await?;
         Ok(id)
@@ -33,7 +37,7 @@ impl Service {
     pub async fn by_id(id: i64, conn: &mut AnyConnection) -> anyhow::Result<Option<Service>> {
         let services = sqlx::query_as::<_, Service>(
             r#"
-            SELECT id, name, url FROM services WHERE id = $1;
+            SELECT id, name, url, team_id FROM services WHERE id = $1;
         "#,
         )
         .bind(id)
@@ -42,10 +46,23 @@ impl Service {
         Ok(services)
     }
 
+    /// Find a service by its name, ignoring the case.
+    pub async fn by_name(name: &str, conn: &mut AnyConnection) -> anyhow::Result<Option<Service>> {
+        let services = sqlx::query_as::<_, Service>(
+            r#"
+            SELECT id, name, url, team_id FROM services WHERE LOWER(name) = LOWER($1);
+        "#,
+        )
+        .bind(name)
+        .fetch_optional(conn)
+        .await?;
+        Ok(services)
+    }
+
     pub async fn get_all(conn: &mut AnyConnection) -> anyhow::Result<Vec<Service>> {
         let services = sqlx::query_as::<_, Service>(
             r#"
-            SELECT id, name, url FROM services;
+            SELECT id, name, url, team_id FROM services;
         "#,
         )
         .fetch_all(conn)
@@ -62,7 +79,8 @@ impl Service {
                 s.id,
                 count(is_.id) as num_interventions,
                 s.name,
-                s.url
+                s.url,
+                s.team_id
             FROM services as s
             LEFT JOIN interventions_services as is_ on s.id == is_.service_id
             GROUP BY s.id;
@@ -72,4 +90,22 @@ impl Service {
         .await?;
         Ok(services)
     }
+
+    /// Give the service to a team, or to nobody.
+    pub async fn set_team(
+        id: i64,
+        team_id: Option<i64>,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE services SET team_id = $1 WHERE id = $2
+        "#,
+        )
+        .bind(team_id)
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
 }
@@ -0,0 +1,154 @@
+use chrono::NaiveDateTime;
+use sqlx::AnyConnection;
+
+/// A session of an administrator, opened by logging in.
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub id: Option<i64>,
+    /// SHA-256 hash of the token stored in the session cookie.
+    pub token_hash: String,
+    pub user_id: i64,
+    /// Token the forms of the session must send back, against cross-site request forgery.
+    pub csrf_token: String,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for Session
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let id: i64 = row.try_get("id")?;
+        let token_hash: String = row.try_get("token_hash")?;
+        let user_id: i64 = row.try_get("user_id")?;
+        let csrf_token: String = row.try_get("csrf_token")?;
+        let created_at: i64 = row.try_get("created_at")?;
+        let created_at = NaiveDateTime::from_timestamp_opt(created_at, 0).unwrap();
+        let last_seen_at: i64 = row.try_get("last_seen_at")?;
+        let last_seen_at = NaiveDateTime::from_timestamp_opt(last_seen_at, 0).unwrap();
+        Ok(Session {
+            id: Some(id),
+            token_hash,
+            user_id,
+            csrf_token,
+            created_at,
+            last_seen_at,
+        })
+    }
+}
+
+impl Session {
+    /// Is the session still valid at the given date, with the given timeouts?
+    pub fn is_alive(
+        &self,
+        now: NaiveDateTime,
+        idle_timeout: chrono::Duration,
+        lifetime: chrono::Duration,
+    ) -> bool {
+        now - self.last_seen_at < idle_timeout && now - self.created_at < lifetime
+    }
+
+    pub async fn insert(conn: &mut AnyConnection, s: &Session) -> anyhow::Result<i64> {
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            INSERT INTO sessions (token_hash, user_id, csrf_token, created_at, last_seen_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id
+        "#,
+        )
+        .bind(&s.token_hash)
+        .bind(s.user_id)
+        .bind(&s.csrf_token)
+        .bind(s.created_at.timestamp())
+        .bind(s.last_seen_at.timestamp())
+        .fetch_one(conn)
+        .await?;
+        Ok(id)
+    }
+
+    pub async fn by_token_hash(
+        token_hash: &str,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Option<Session>> {
+        let session = sqlx::query_as::<_, Session>(
+            r#"
+            SELECT * FROM sessions WHERE token_hash = $1
+        "#,
+        )
+        .bind(token_hash)
+        .fetch_optional(conn)
+        .await?;
+        Ok(session)
+    }
+
+    pub async fn touch(
+        id: i64,
+        date: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE sessions SET last_seen_at = $1 WHERE id = $2
+        "#,
+        )
+        .bind(date.timestamp())
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(id: i64, conn: &mut AnyConnection) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM sessions WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Close the sessions of a user, except the given one.
+    pub async fn delete_for_user(
+        user_id: i64,
+        except_id: Option<i64>,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM sessions WHERE user_id = $1 AND id != $2
+        "#,
+        )
+        .bind(user_id)
+        .bind(except_id.unwrap_or(-1))
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Remove the sessions which timed out at the given date.
+    pub async fn delete_expired(
+        now: NaiveDateTime,
+        idle_timeout: chrono::Duration,
+        lifetime: chrono::Duration,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM sessions WHERE last_seen_at <= $1 OR created_at <= $2
+        "#,
+        )
+        .bind((now - idle_timeout).timestamp())
+        .bind((now - lifetime).timestamp())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
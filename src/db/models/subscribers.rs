@@ -0,0 +1,183 @@
+use chrono::NaiveDateTime;
+use sqlx::AnyConnection;
+
+use crate::tokens::random_token;
+
+/// Someone who wants to receive emails about interventions.
+#[derive(Clone, Debug)]
+pub struct Subscriber {
+    pub id: Option<i64>,
+    pub email: String,
+    /// Secret token, used in the confirmation and unsubscribe links.
+    pub token: String,
+    /// Has the subscriber confirmed their email address?
+    pub confirmed: bool,
+    pub created_at: NaiveDateTime,
+    /// When the last confirmation email was sent, to avoid flooding the address.
+    pub last_sent_at: Option<NaiveDateTime>,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for Subscriber
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+    Option<i64>: sqlx::decode::Decode<'a, R::Database>,
+    Option<i64>: sqlx::types::Type<R::Database>,
+    bool: sqlx::decode::Decode<'a, R::Database>,
+    bool: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let id: i64 = row.try_get("id")?;
+        let email: String = row.try_get("email")?;
+        let token: String = row.try_get("token")?;
+        let confirmed: bool = row.try_get("confirmed")?;
+        let created_at: i64 = row.try_get("created_at")?;
+        let created_at = NaiveDateTime::from_timestamp_opt(created_at, 0).unwrap();
+        let last_sent_at: Option<i64> = row.try_get("last_sent_at")?;
+        let last_sent_at =
+            last_sent_at.map(|date| NaiveDateTime::from_timestamp_opt(date, 0).unwrap());
+        Ok(Subscriber {
+            id: Some(id),
+            email,
+            token,
+            confirmed,
+            created_at,
+            last_sent_at,
+        })
+    }
+}
+
+impl Subscriber {
+    /// Create a new, unconfirmed, subscriber with a fresh token.
+    pub fn new(email: String) -> Self {
+        Self {
+            id: None,
+            email,
+            token: random_token(),
+            confirmed: false,
+            created_at: chrono::Utc::now().naive_utc(),
+            last_sent_at: None,
+        }
+    }
+
+    pub async fn insert(conn: &mut AnyConnection, s: &Subscriber) -> anyhow::Result<i64> {
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            INSERT INTO subscribers (email, token, confirmed, created_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+        "#,
+        )
+        .bind(&s.email)
+        .bind(&s.token)
+        .bind(s.confirmed)
+        .bind(s.created_at.timestamp())
+        .fetch_one(conn)
+        .await?;
+        Ok(id)
+    }
+
+    pub async fn by_email(
+        email: &str,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Option<Subscriber>> {
+        let subscriber = sqlx::query_as::<_, Subscriber>(
+            r#"
+            SELECT * FROM subscribers WHERE email = $1
+        "#,
+        )
+        .bind(email)
+        .fetch_optional(conn)
+        .await?;
+        Ok(subscriber)
+    }
+
+    pub async fn by_id(id: i64, conn: &mut AnyConnection) -> anyhow::Result<Option<Subscriber>> {
+        let subscriber = sqlx::query_as::<_, Subscriber>(
+            r#"
+            SELECT * FROM subscribers WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+        Ok(subscriber)
+    }
+
+    pub async fn by_token(
+        token: &str,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Option<Subscriber>> {
+        let subscriber = sqlx::query_as::<_, Subscriber>(
+            r#"
+            SELECT * FROM subscribers WHERE token = $1
+        "#,
+        )
+        .bind(token)
+        .fetch_optional(conn)
+        .await?;
+        Ok(subscriber)
+    }
+
+    pub async fn get_confirmed(conn: &mut AnyConnection) -> anyhow::Result<Vec<Subscriber>> {
+        let subscribers = sqlx::query_as::<_, Subscriber>(
+            r#"
+            SELECT * FROM subscribers WHERE confirmed = TRUE
+        "#,
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(subscribers)
+    }
+
+    /// Record that a confirmation email is being sent to the subscriber.
+    pub async fn mark_sent(
+        id: i64,
+        date: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE subscribers SET last_sent_at = $1 WHERE id = $2
+        "#,
+        )
+        .bind(date.timestamp())
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Confirms the subscriber with the given token.
+    ///
+    /// Returns false if there's no such subscriber.
+    pub async fn confirm(token: &str, conn: &mut AnyConnection) -> anyhow::Result<bool> {
+        let res = sqlx::query(
+            r#"
+            UPDATE subscribers SET confirmed = TRUE WHERE token = $1
+        "#,
+        )
+        .bind(token)
+        .execute(conn)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Removes the subscriber with the given token.
+    ///
+    /// Returns false if there's no such subscriber.
+    pub async fn delete_by_token(token: &str, conn: &mut AnyConnection) -> anyhow::Result<bool> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM subscribers WHERE token = $1
+        "#,
+        )
+        .bind(token)
+        .execute(conn)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+}
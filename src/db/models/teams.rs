@@ -0,0 +1,59 @@
+use serde::Serialize;
+use sqlx::AnyConnection;
+
+/// A team of users, which may own services: only its members (and the administrators) can then
+/// manage the interventions of these services.
+#[derive(sqlx::FromRow, Serialize)]
+pub struct Team {
+    pub id: Option<i64>,
+    pub name: String,
+}
+
+impl Team {
+    pub async fn insert(conn: &mut AnyConnection, t: &Team) -> anyhow::Result<i64> {
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            INSERT INTO teams (name) VALUES ($1) RETURNING id
+        "#,
+        )
+        .bind(&t.name)
+        .fetch_one(conn)
+        .await?;
+        Ok(id)
+    }
+
+    pub async fn by_id(id: i64, conn: &mut AnyConnection) -> anyhow::Result<Option<Team>> {
+        let team = sqlx::query_as::<_, Team>(
+            r#"
+            SELECT id, name FROM teams WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+        Ok(team)
+    }
+
+    pub async fn get_all(conn: &mut AnyConnection) -> anyhow::Result<Vec<Team>> {
+        let teams = sqlx::query_as::<_, Team>(
+            r#"
+            SELECT id, name FROM teams ORDER BY name ASC
+        "#,
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(teams)
+    }
+
+    pub async fn delete(id: i64, conn: &mut AnyConnection) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM teams WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::AnyConnection;
+
+/// What a user may do in the administration; each role can do everything the previous ones can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Role {
+    /// Read the administration.
+    Viewer,
+    /// Create and update interventions, and post their updates.
+    Editor,
+    /// Manage the services, their monitoring, the notifications and the users.
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Viewer, Role::Editor, Role::Admin];
+
+    // TODO i18n???
+    pub fn label(&self) -> &str {
+        match *self {
+            Self::Viewer => "Viewer",
+            Self::Editor => "Editor",
+            Self::Admin => "Administrator",
+        }
+    }
+
+    pub(crate) fn to_db_str(self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Editor => "editor",
+            Self::Admin => "admin",
+        }
+    }
+
+    pub(crate) fn from_db_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "viewer" => Self::Viewer,
+            "editor" => Self::Editor,
+            "admin" => Self::Admin,
+            _ => anyhow::bail!("unexpected value for role: {s}"),
+        })
+    }
+}
+
+/// An account of the administration.
+#[derive(Clone, Debug)]
+pub struct User {
+    pub id: Option<i64>,
+    pub username: String,
+    /// Argon2 hash of the password, in the PHC string format.
+    pub password_hash: String,
+    pub created_at: NaiveDateTime,
+    pub role: Role,
+    /// Team of the user, whose services they can manage.
+    pub team_id: Option<i64>,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for User
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+    Option<i64>: sqlx::decode::Decode<'a, R::Database>,
+    Option<i64>: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let id: i64 = row.try_get("id")?;
+        let username: String = row.try_get("username")?;
+        let password_hash: String = row.try_get("password_hash")?;
+        let created_at: i64 = row.try_get("created_at")?;
+        let created_at = NaiveDateTime::from_timestamp_opt(created_at, 0).unwrap();
+        let role: String = row.try_get("role")?;
+        let role = Role::from_db_str(&role).unwrap();
+        let team_id: Option<i64> = row.try_get("team_id")?;
+        Ok(User {
+            id: Some(id),
+            username,
+            password_hash,
+            created_at,
+            role,
+            team_id,
+        })
+    }
+}
+
+impl User {
+    pub async fn insert(conn: &mut AnyConnection, u: &User) -> anyhow::Result<i64> {
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            INSERT INTO users (username, password_hash, created_at, role, team_id)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id
+        "#,
+        )
+        .bind(&u.username)
+        .bind(&u.password_hash)
+        .bind(u.created_at.timestamp())
+        .bind(u.role.to_db_str())
+        .bind(u.team_id)
+        .fetch_one(conn)
+        .await?;
+        Ok(id)
+    }
+
+    pub async fn get_all(conn: &mut AnyConnection) -> anyhow::Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(
+            r#"
+            SELECT * FROM users ORDER BY username ASC
+        "#,
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(users)
+    }
+
+    pub async fn by_id(id: i64, conn: &mut AnyConnection) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT * FROM users WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+        Ok(user)
+    }
+
+    pub async fn by_username(
+        username: &str,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT * FROM users WHERE username = $1
+        "#,
+        )
+        .bind(username)
+        .fetch_optional(conn)
+        .await?;
+        Ok(user)
+    }
+
+    pub async fn count(conn: &mut AnyConnection) -> anyhow::Result<i64> {
+        let (count,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            SELECT COUNT(*) FROM users
+        "#,
+        )
+        .fetch_one(conn)
+        .await?;
+        Ok(count)
+    }
+
+    pub async fn update_password(
+        id: i64,
+        password_hash: &str,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE users SET password_hash = $1 WHERE id = $2
+        "#,
+        )
+        .bind(password_hash)
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn update_role(
+        id: i64,
+        role: Role,
+        team_id: Option<i64>,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE users SET role = $1, team_id = $2 WHERE id = $3
+        "#,
+        )
+        .bind(role.to_db_str())
+        .bind(team_id)
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(id: i64, conn: &mut AnyConnection) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM users WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use chrono::NaiveDateTime;
+use sqlx::AnyConnection;
+
+/// An HTTP endpoint that gets notified about events.
+#[derive(Clone, Debug)]
+pub struct Webhook {
+    pub id: Option<i64>,
+    pub url: String,
+    /// Secret shared with the receiver, used to sign the payloads.
+    pub secret: String,
+    /// Names of the events the receiver is interested in.
+    pub events: Vec<String>,
+    pub enabled: bool,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for Webhook
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+    bool: sqlx::decode::Decode<'a, R::Database>,
+    bool: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let id: i64 = row.try_get("id")?;
+        let url: String = row.try_get("url")?;
+        let secret: String = row.try_get("secret")?;
+        let events: String = row.try_get("events")?;
+        let events = events
+            .split(',')
+            .filter(|e| !e.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+        let enabled: bool = row.try_get("enabled")?;
+        Ok(Webhook {
+            id: Some(id),
+            url,
+            secret,
+            events,
+            enabled,
+        })
+    }
+}
+
+impl Webhook {
+    pub async fn insert(conn: &mut AnyConnection, w: &Webhook) -> anyhow::Result<i64> {
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            INSERT INTO webhooks (url, secret, events, enabled)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+        "#,
+        )
+        .bind(&w.url)
+        .bind(&w.secret)
+        .bind(w.events.join(","))
+        .bind(w.enabled)
+        .fetch_one(conn)
+        .await?;
+        Ok(id)
+    }
+
+    pub async fn get_all(conn: &mut AnyConnection) -> anyhow::Result<Vec<Webhook>> {
+        let webhooks = sqlx::query_as::<_, Webhook>(
+            r#"
+            SELECT * FROM webhooks
+        "#,
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(webhooks)
+    }
+
+    pub async fn by_id(id: i64, conn: &mut AnyConnection) -> anyhow::Result<Option<Webhook>> {
+        let webhook = sqlx::query_as::<_, Webhook>(
+            r#"
+            SELECT * FROM webhooks WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+        Ok(webhook)
+    }
+
+    pub async fn delete(id: i64, conn: &mut AnyConnection) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM webhooks WHERE id = $1
+        "#,
+        )
+        .bind(id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Is this webhook interested in the event with the given name?
+    pub fn wants(&self, event: &str) -> bool {
+        self.enabled && self.events.iter().any(|e| e == event)
+    }
+}
+
+/// A single attempt at delivering an event to a webhook.
+#[derive(Clone, Debug)]
+pub struct WebhookDelivery {
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: String,
+    /// Attempt number, starting at 1.
+    pub attempt: i64,
+    pub date: NaiveDateTime,
+    /// HTTP status code of the response, if a response was received at all.
+    pub status_code: Option<i64>,
+    /// Error message, if the delivery failed.
+    pub error: Option<String>,
+}
+
+impl<'a, R: sqlx::Row> sqlx::FromRow<'a, R> for WebhookDelivery
+where
+    &'a std::primitive::str: sqlx::ColumnIndex<R>,
+    String: sqlx::decode::Decode<'a, R::Database>,
+    String: sqlx::types::Type<R::Database>,
+    Option<String>: sqlx::decode::Decode<'a, R::Database>,
+    Option<String>: sqlx::types::Type<R::Database>,
+    i64: sqlx::decode::Decode<'a, R::Database>,
+    i64: sqlx::types::Type<R::Database>,
+    Option<i64>: sqlx::decode::Decode<'a, R::Database>,
+    Option<i64>: sqlx::types::Type<R::Database>,
+{
+    fn from_row(row: &'a R) -> Result<Self, sqlx::Error> {
+        let webhook_id: i64 = row.try_get("webhook_id")?;
+        let event: String = row.try_get("event")?;
+        let payload: String = row.try_get("payload")?;
+        let attempt: i64 = row.try_get("attempt")?;
+        let date: i64 = row.try_get("date")?;
+        let date = NaiveDateTime::from_timestamp_opt(date, 0).unwrap();
+        let status_code: Option<i64> = row.try_get("status_code")?;
+        let error: Option<String> = row.try_get("error")?;
+        Ok(WebhookDelivery {
+            webhook_id,
+            event,
+            payload,
+            attempt,
+            date,
+            status_code,
+            error,
+        })
+    }
+}
+
+impl WebhookDelivery {
+    pub async fn insert(conn: &mut AnyConnection, d: &WebhookDelivery) -> anyhow::Result<i64> {
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+            INSERT INTO webhook_deliveries
+                (webhook_id, event, payload, attempt, date, status_code, error)
+            VALUES
+                ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id
+        "#,
+        )
+        .bind(d.webhook_id)
+        .bind(&d.event)
+        .bind(&d.payload)
+        .bind(d.attempt)
+        .bind(d.date.timestamp())
+        .bind(d.status_code)
+        .bind(&d.error)
+        .fetch_one(conn)
+        .await?;
+        Ok(id)
+    }
+
+    /// Returns the most recent deliveries, most recent first.
+    pub async fn get_recent(
+        limit: i64,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<Vec<WebhookDelivery>> {
+        let deliveries = sqlx::query_as::<_, WebhookDelivery>(
+            r#"
+            SELECT * FROM webhook_deliveries ORDER BY date DESC, id DESC LIMIT $1
+        "#,
+        )
+        .bind(limit)
+        .fetch_all(conn)
+        .await?;
+        Ok(deliveries)
+    }
+
+    pub async fn delete_before(
+        date: NaiveDateTime,
+        conn: &mut AnyConnection,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM webhook_deliveries WHERE date < $1
+        "#,
+        )
+        .bind(date.timestamp())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
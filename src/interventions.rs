@@ -0,0 +1,197 @@
+//! Changes of the interventions, whether they're made by an administrator or automatically.
+//!
+//! Every change goes through here, so that it's recorded in the audit log and in the change
+//! history of the intervention, that the reminders follow it, and that it's notified. These
+//! functions run within the caller's transaction; the caller wakes up the workers, once it's
+//! committed.
+
+use crate::{
+    audit::{self, Actor, InterventionUpdate, InterventionWithServiceIds},
+    db::models::{
+        comments::Comment,
+        interventions::{ChangeAction, Intervention, Status},
+    },
+    notifications::{self, Event},
+    scheduler, AppContext,
+};
+use anyhow::Context as _;
+use sqlx::AnyConnection;
+
+/// Create an intervention about the given services, and return its id.
+pub(crate) async fn create(
+    app: &AppContext,
+    conn: &mut AnyConnection,
+    actor: &Actor,
+    intervention: &Intervention,
+    service_ids: &[i64],
+) -> anyhow::Result<i64> {
+    let now = chrono::Utc::now().naive_utc();
+
+    let id = Intervention::insert(conn, intervention).await?;
+    for service_id in service_ids {
+        Intervention::add_service(id, *service_id, conn).await?;
+    }
+
+    audit::created(
+        conn,
+        actor,
+        id,
+        &InterventionWithServiceIds(intervention, service_ids.to_vec()),
+    )
+    .await?;
+    Intervention::record_change(id, actor.user_id(), ChangeAction::Created, now, conn).await?;
+    scheduler::schedule_reminders(app, conn, id, intervention).await?;
+    notifications::enqueue(app, conn, Event::InterventionCreated(id)).await?;
+
+    Ok(id)
+}
+
+/// Update an intervention, replacing its services if some are given, and post an update on it if
+/// the text isn't empty.
+pub(crate) async fn update(
+    app: &AppContext,
+    conn: &mut AnyConnection,
+    actor: &Actor,
+    id: i64,
+    intervention: &Intervention,
+    service_ids: Option<&[i64]>,
+    update: Option<String>,
+) -> anyhow::Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+
+    let previous = Intervention::by_id(id, conn)
+        .await?
+        .with_context(|| format!("unknown intervention with id {id}"))?;
+    let previous_service_ids: Vec<_> = Intervention::get_service_ids(id, conn)
+        .await?
+        .into_iter()
+        .map(|sid| sid.0)
+        .collect();
+
+    Intervention::update(conn, id, intervention).await?;
+
+    let service_ids = match service_ids {
+        Some(service_ids) => {
+            Intervention::remove_services(id, conn).await?;
+            for service_id in service_ids {
+                Intervention::add_service(id, *service_id, conn).await?;
+            }
+            service_ids.to_vec()
+        }
+        None => previous_service_ids.clone(),
+    };
+
+    audit::updated(
+        conn,
+        actor,
+        id,
+        &InterventionWithServiceIds(&previous, previous_service_ids),
+        &InterventionWithServiceIds(intervention, service_ids),
+    )
+    .await?;
+
+    if let Some(update) = update.filter(|update| !update.trim().is_empty()) {
+        let comment = Comment {
+            date: now,
+            description: update,
+        };
+        let comment_id = Comment::insert_for_intervention(conn, id, &comment).await?;
+        audit::created(conn, actor, comment_id, &InterventionUpdate(id, &comment)).await?;
+    }
+
+    Intervention::record_change(id, actor.user_id(), ChangeAction::Updated, now, conn).await?;
+    scheduler::schedule_reminders(app, conn, id, intervention).await?;
+
+    let event = if intervention.status == Status::Resolved && previous.status != Status::Resolved {
+        Event::InterventionResolved(id)
+    } else {
+        Event::InterventionUpdated(id)
+    };
+    notifications::enqueue(app, conn, event).await?;
+
+    Ok(())
+}
+
+/// Resolve an intervention now, with a final update.
+pub(crate) async fn resolve(
+    app: &AppContext,
+    conn: &mut AnyConnection,
+    actor: &Actor,
+    id: i64,
+    update: String,
+) -> anyhow::Result<()> {
+    let intervention = Intervention::by_id(id, conn)
+        .await?
+        .with_context(|| format!("unknown intervention with id {id}"))?;
+    let resolved = Intervention {
+        status: Status::Resolved,
+        end_date: Some(chrono::Utc::now().naive_utc()),
+        ..intervention
+    };
+    self::update(app, conn, actor, id, &resolved, None, Some(update)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::models::{interventions::Severity, reminders::Reminder},
+        testing,
+    };
+
+    #[tokio::test]
+    async fn system_changes_are_recorded() {
+        let mut config = testing::config();
+        config.reminder_lead_times = vec![60];
+        let app = testing::app(config).await;
+        let ctx = &app.ctx;
+        let service_id = testing::insert_service(ctx, "Forge", "https://forge.example.org").await;
+
+        let mut conn = ctx.db_connection.lock().await;
+        let planned = Intervention {
+            id: None,
+            start_date: chrono::Utc::now().naive_utc() + chrono::Duration::days(1),
+            estimated_duration: Some(60),
+            end_date: None,
+            status: Status::Planned,
+            severity: Severity::PartialOutage,
+            is_planned: true,
+            auto_resolve: false,
+            title: "Mise à jour".to_owned(),
+            description: None,
+        };
+        let id = create(ctx, &mut conn, &Actor::system(), &planned, &[service_id])
+            .await
+            .unwrap();
+        assert_eq!(Reminder::get_pending(&mut conn).await.unwrap().len(), 1);
+
+        resolve(ctx, &mut conn, &Actor::system(), id, "Annulée.".to_owned())
+            .await
+            .unwrap();
+
+        let resolved = Intervention::by_id(id, &mut conn).await.unwrap().unwrap();
+        assert_eq!(resolved.status, Status::Resolved);
+        assert!(resolved.end_date.is_some());
+        let service_ids = Intervention::get_service_ids(id, &mut conn).await.unwrap();
+        assert_eq!(service_ids.len(), 1);
+        let updates = Comment::by_intervention(id, &mut conn).await.unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].description, "Annulée.");
+
+        // The reminders don't outlive the maintenance.
+        assert!(Reminder::get_pending(&mut conn).await.unwrap().is_empty());
+
+        let changes = Intervention::get_changes(id, &mut conn).await.unwrap();
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|c| (c.action, c.username.as_deref()))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (ChangeAction::Updated, Some("system")),
+                (ChangeAction::Created, Some("system")),
+            ]
+        );
+    }
+}
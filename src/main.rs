@@ -12,18 +12,46 @@ use std::{
     path::PathBuf,
     process::exit,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 use std::{fs, net::SocketAddr};
 use tera::Tera;
 use tokio::sync::{mpsc, Mutex};
-use tower_http::validate_request::ValidateRequestHeaderLayer;
 use tracing as log;
 
-use crate::db::insert_fixtures;
+use crate::{
+    db::{
+        insert_fixtures,
+        models::audit::AuditEntry,
+        models::users::{Role, User},
+    },
+    metrics::Metrics,
+    monitoring::{
+        alertmanager::AlertmanagerConfig,
+        history::{self, HistoryRetention},
+    },
+    notifications::{
+        email::{Mailer, SmtpConfig},
+        mastodon::MastodonConfig,
+        matrix::MatrixConfig,
+    },
+};
 
+mod audit;
+mod charts;
 mod controllers;
 mod db;
+mod interventions;
+mod metrics;
+mod monitoring;
+mod notifications;
 mod regenerate;
+mod reports;
+mod scheduler;
+mod status;
+#[cfg(test)]
+mod testing;
+mod tokens;
 
 pub(crate) struct AppConfig {
     /// which port the app is listening on
@@ -46,8 +74,53 @@ pub(crate) struct AppConfig {
     /// Should the server also respond to static queries, in dev mode?
     dev_server: bool,
 
-    /// What's the administrator password?
-    admin_password: String,
+    /// Account created at the first start, if there's no administrator yet: username and
+    /// password.
+    bootstrap_admin: Option<(String, String)>,
+
+    /// For how long are resolved interventions displayed on the home page?
+    resolved_retention: chrono::Duration,
+
+    /// Public URL of the status page, without a trailing slash; used to generate links in
+    /// notifications.
+    public_url: String,
+
+    /// SMTP server used to send emails; if not set, email subscriptions are disabled.
+    smtp: Option<SmtpConfig>,
+
+    /// Mastodon account posting about the interventions; if not set, nothing is posted.
+    mastodon: Option<MastodonConfig>,
+
+    /// Matrix account sending messages about the interventions; if not set, nothing is sent.
+    matrix: Option<MatrixConfig>,
+
+    /// Key used to sign the links sent to the outside world.
+    secret_key: String,
+
+    /// How long before planned maintenances reminders are sent, in minutes.
+    reminder_lead_times: Vec<i64>,
+
+    /// How long the history of the probes is kept.
+    history_retention: HistoryRetention,
+
+    /// Receiver of the Alertmanager webhooks; if not set, the endpoint is disabled.
+    alertmanager: Option<AlertmanagerConfig>,
+
+    /// Bearer token of the generic alert receivers; if not set, they're disabled.
+    alerts_token: Option<String>,
+
+    /// An alert firing again within this delay after its intervention was resolved reopens it,
+    /// instead of opening another one.
+    alerts_reopen_window: chrono::Duration,
+
+    /// Bearer token required to read the metrics; if not set, they're public.
+    metrics_token: Option<String>,
+
+    /// Sessions of the administrators are closed after this much inactivity.
+    session_idle_timeout: chrono::Duration,
+
+    /// Sessions of the administrators are closed after this long, even if they're active.
+    session_lifetime: chrono::Duration,
 }
 
 pub(crate) struct AppContext {
@@ -66,6 +139,28 @@ pub(crate) struct AppContext {
     toast: RwLock<Option<String>>,
 
     regenerate_pages: mpsc::Sender<()>,
+
+    /// Wakes up the outbox worker, so it delivers newly queued notifications.
+    wake_outbox: mpsc::Sender<()>,
+
+    /// Email sender, if email subscriptions are enabled.
+    mailer: Option<Mailer>,
+
+    /// HTTP client for all the outgoing requests.
+    http_client: reqwest::Client,
+
+    /// Wakes up the scheduler, so it takes new or modified interventions into account.
+    wake_scheduler: mpsc::Sender<()>,
+
+    /// Wakes up the prober, so it takes new or modified probes into account.
+    wake_prober: mpsc::Sender<()>,
+
+    /// Wakes up the heartbeat watcher, so it takes pings and new or modified heartbeats into
+    /// account.
+    wake_heartbeats: mpsc::Sender<()>,
+
+    /// Internal metrics, exposed at `/metrics`.
+    metrics: Metrics,
 }
 
 fn parse_app_config() -> anyhow::Result<AppConfig> {
@@ -107,7 +202,176 @@ fn parse_app_config() -> anyhow::Result<AppConfig> {
         .to_lowercase();
     let dev_server = ["true", "yes", "y"].iter().any(|v| dev_server == *v);
 
-    let admin_password = env::var("ADMIN_PASSWORD").context("missing ADMIN_PASSWORD env")?;
+    let bootstrap_admin = env::var("ADMIN_PASSWORD").ok().map(|password| {
+        let username = env::var("ADMIN_USERNAME").unwrap_or_else(|_| "admin".to_owned());
+        (username, password)
+    });
+
+    let resolved_retention = match env::var("RESOLVED_RETENTION_DAYS") {
+        Ok(days) => days
+            .parse()
+            .context("RESOLVED_RETENTION_DAYS isn't an integer value")?,
+        Err(_) => 7,
+    };
+    let resolved_retention = chrono::Duration::days(resolved_retention);
+
+    let public_url = env::var("PUBLIC_URL")
+        .unwrap_or_else(|_| format!("http://{interface_ipv4}:{port}"))
+        .trim_end_matches('/')
+        .to_owned();
+
+    let smtp = match env::var("SMTP_HOST") {
+        Ok(host) => {
+            let port = env::var("SMTP_PORT")
+                .ok()
+                .map(|port| port.parse())
+                .transpose()
+                .context("SMTP_PORT isn't a u16 value")?;
+
+            let tls = env::var("SMTP_TLS")
+                .unwrap_or_else(|_| "starttls".to_owned())
+                .to_lowercase()
+                .parse()?;
+
+            let credentials = match env::var("SMTP_USERNAME") {
+                Ok(username) => {
+                    let password =
+                        env::var("SMTP_PASSWORD").context("missing SMTP_PASSWORD env")?;
+                    Some((username, password))
+                }
+                Err(_) => None,
+            };
+
+            let from = env::var("SMTP_FROM")
+                .context("missing SMTP_FROM env")?
+                .parse()
+                .context("SMTP_FROM must be an email address")?;
+
+            Some(SmtpConfig {
+                host,
+                port,
+                tls,
+                credentials,
+                from,
+            })
+        }
+        Err(_) => None,
+    };
+
+    let mastodon = match env::var("MASTODON_URL") {
+        Ok(url) => {
+            let access_token =
+                env::var("MASTODON_ACCESS_TOKEN").context("missing MASTODON_ACCESS_TOKEN env")?;
+            let visibility =
+                env::var("MASTODON_VISIBILITY").unwrap_or_else(|_| "public".to_owned());
+            Some(MastodonConfig {
+                url: url.trim_end_matches('/').to_owned(),
+                access_token,
+                visibility,
+            })
+        }
+        Err(_) => None,
+    };
+
+    let matrix = match env::var("MATRIX_HOMESERVER_URL") {
+        Ok(homeserver_url) => {
+            let access_token =
+                env::var("MATRIX_ACCESS_TOKEN").context("missing MATRIX_ACCESS_TOKEN env")?;
+            let room_ids = env::var("MATRIX_ROOM_IDS")
+                .context("missing MATRIX_ROOM_IDS env")?
+                .split(',')
+                .map(|room_id| room_id.trim().to_owned())
+                .filter(|room_id| !room_id.is_empty())
+                .collect();
+            Some(MatrixConfig {
+                homeserver_url: homeserver_url.trim_end_matches('/').to_owned(),
+                access_token,
+                room_ids,
+            })
+        }
+        Err(_) => None,
+    };
+
+    let secret_key = match env::var("SECRET_KEY") {
+        Ok(key) => key,
+        Err(_) => {
+            log::warn!("missing SECRET_KEY env; links sent so far will stop working at restart");
+            tokens::random_token()
+        }
+    };
+
+    let reminder_lead_times = match env::var("REMINDER_LEAD_TIMES") {
+        Ok(lead_times) => lead_times
+            .split(',')
+            .map(str::trim)
+            .filter(|lead_time| !lead_time.is_empty())
+            .map(|lead_time| match lead_time.parse::<i64>() {
+                Ok(minutes) if minutes > 0 => Ok(minutes),
+                _ => anyhow::bail!("REMINDER_LEAD_TIMES must be a list of positive integers"),
+            })
+            .collect::<anyhow::Result<_>>()?,
+        Err(_) => Vec::new(),
+    };
+
+    let retention_days = |var: &str, default: i64| -> anyhow::Result<chrono::Duration> {
+        let days = match env::var(var) {
+            Ok(days) => days
+                .parse()
+                .with_context(|| format!("{var} isn't an integer value"))?,
+            Err(_) => default,
+        };
+        Ok(chrono::Duration::days(days))
+    };
+    let history_retention = HistoryRetention {
+        raw: retention_days("HISTORY_RAW_RETENTION_DAYS", 7)?,
+        hourly: retention_days("HISTORY_HOURLY_RETENTION_DAYS", 90)?,
+        daily: retention_days("HISTORY_DAILY_RETENTION_DAYS", 730)?,
+    };
+    if history_retention.raw < chrono::Duration::days(history::RECOMPUTE_WINDOW_DAYS) {
+        anyhow::bail!(
+            "HISTORY_RAW_RETENTION_DAYS must be at least {} days",
+            history::RECOMPUTE_WINDOW_DAYS
+        );
+    }
+
+    let alertmanager = match env::var("ALERTMANAGER_TOKEN") {
+        Ok(token) => Some(AlertmanagerConfig {
+            token,
+            service_label: env::var("ALERTMANAGER_SERVICE_LABEL")
+                .unwrap_or_else(|_| "service".to_owned()),
+            severity_label: env::var("ALERTMANAGER_SEVERITY_LABEL")
+                .unwrap_or_else(|_| "severity".to_owned()),
+        }),
+        Err(_) => None,
+    };
+
+    let alerts_token = env::var("ALERTS_TOKEN").ok();
+
+    let alerts_reopen_window = match env::var("ALERTS_REOPEN_WINDOW_MINUTES") {
+        Ok(minutes) => minutes
+            .parse()
+            .context("ALERTS_REOPEN_WINDOW_MINUTES isn't an integer value")?,
+        Err(_) => 60,
+    };
+    let alerts_reopen_window = chrono::Duration::minutes(alerts_reopen_window);
+
+    let metrics_token = env::var("METRICS_TOKEN").ok();
+
+    let session_idle_timeout = match env::var("SESSION_IDLE_TIMEOUT_MINUTES") {
+        Ok(minutes) => minutes
+            .parse()
+            .context("SESSION_IDLE_TIMEOUT_MINUTES isn't an integer value")?,
+        Err(_) => 60,
+    };
+    let session_idle_timeout = chrono::Duration::minutes(session_idle_timeout);
+
+    let session_lifetime = match env::var("SESSION_LIFETIME_HOURS") {
+        Ok(hours) => hours
+            .parse()
+            .context("SESSION_LIFETIME_HOURS isn't an integer value")?,
+        Err(_) => 12,
+    };
+    let session_lifetime = chrono::Duration::hours(session_lifetime);
 
     Ok(AppConfig {
         port,
@@ -116,10 +380,52 @@ fn parse_app_config() -> anyhow::Result<AppConfig> {
         template_dir,
         db_connection_string,
         dev_server,
-        admin_password,
+        bootstrap_admin,
+        resolved_retention,
+        public_url,
+        smtp,
+        mastodon,
+        matrix,
+        secret_key,
+        reminder_lead_times,
+        history_retention,
+        alertmanager,
+        alerts_token,
+        alerts_reopen_window,
+        metrics_token,
+        session_idle_timeout,
+        session_lifetime,
     })
 }
 
+/// Create the first administrator account from the configuration, if there's none yet.
+async fn bootstrap_admin(config: &AppConfig, conn: &mut AnyConnection) -> anyhow::Result<()> {
+    if User::count(conn).await? > 0 {
+        if config.bootstrap_admin.is_some() {
+            log::info!("ADMIN_PASSWORD is ignored, since there are administrator accounts already");
+        }
+        return Ok(());
+    }
+
+    let Some((username, password)) = &config.bootstrap_admin else {
+        anyhow::bail!("there's no administrator account yet: set ADMIN_PASSWORD to create one");
+    };
+
+    let user = User {
+        id: None,
+        username: username.clone(),
+        password_hash: tokens::hash_password(password)?,
+        created_at: chrono::Utc::now().naive_utc(),
+        role: Role::Admin,
+        team_id: None,
+    };
+    let id = User::insert(conn, &user).await?;
+    audit::created(conn, &audit::Actor::system(), id, &user).await?;
+    log::info!("created the administrator account {username}");
+
+    Ok(())
+}
+
 /// Copy the static files to the cache directory.
 fn copy_static_files_to_cache_dir(config: &AppConfig) -> anyhow::Result<()> {
     // Copy CSS and JavaScript files.
@@ -141,8 +447,10 @@ fn copy_static_files_to_cache_dir(config: &AppConfig) -> anyhow::Result<()> {
 }
 
 async fn real_main(args: Vec<String>) -> anyhow::Result<()> {
-    // Initialize tracing.
-    tracing_subscriber::fmt::init();
+    // Initialize tracing; logs go to stderr, so they don't mix with the output of the commands.
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
 
     // Parse the configuration.
     let config = parse_app_config()?;
@@ -157,6 +465,31 @@ async fn real_main(args: Vec<String>) -> anyhow::Result<()> {
                 exit(0);
             }
 
+            "report" => {
+                let params = reports::ReportParams::from_args(&args[1..])?;
+                let now = chrono::Utc::now().naive_utc();
+                let options = reports::ReportOptions::new(&params, now)?;
+                let report = reports::generate(&options, now, &mut conn).await?;
+                match params.format.as_deref().unwrap_or("csv") {
+                    "csv" => print!("{}", report.to_csv()),
+                    "json" => println!("{}", report.to_json()?),
+                    format => anyhow::bail!("unknown report format: {format}"),
+                }
+                exit(0);
+            }
+
+            "audit" => {
+                let params = audit::AuditParams::from_args(&args[1..])?;
+                let filter = params.to_filter()?;
+                let entries = AuditEntry::get_filtered(&filter, &mut conn).await?;
+                match params.format.as_deref().unwrap_or("csv") {
+                    "csv" => print!("{}", audit::to_csv(&entries)),
+                    "json" => println!("{}", audit::to_json(&entries)?),
+                    format => anyhow::bail!("unknown audit export format: {format}"),
+                }
+                exit(0);
+            }
+
             "serve" => {
                 // fallthrough
             }
@@ -168,11 +501,30 @@ async fn real_main(args: Vec<String>) -> anyhow::Result<()> {
         }
     }
 
-    // Initialize the template engine.
-    let templates = Tera::new(&config.template_dir.join("*.html").to_string_lossy())
-        .context("initializing tera")?;
+    bootstrap_admin(&config, &mut conn).await?;
+
+    // Initialize the template engine, with the pages and the notifications.
+    let templates = Tera::new(
+        &config
+            .template_dir
+            .join("**/*.{html,txt,json}")
+            .to_string_lossy(),
+    )
+    .context("initializing tera")?;
+
+    let mailer = config.smtp.as_ref().map(Mailer::new).transpose()?;
+
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .user_agent(concat!("rustatouille/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .context("initializing the HTTP client")?;
 
     let (sender, receiver) = mpsc::channel(128);
+    let (outbox_sender, outbox_receiver) = mpsc::channel(128);
+    let (scheduler_sender, scheduler_receiver) = mpsc::channel(128);
+    let (prober_sender, prober_receiver) = mpsc::channel(128);
+    let (heartbeats_sender, heartbeats_receiver) = mpsc::channel(128);
 
     let ctx = Arc::new(AppContext {
         config,
@@ -180,9 +532,24 @@ async fn real_main(args: Vec<String>) -> anyhow::Result<()> {
         templates: RwLock::new(templates),
         toast: RwLock::new(None),
         regenerate_pages: sender,
+        wake_outbox: outbox_sender,
+        mailer,
+        http_client,
+        wake_scheduler: scheduler_sender,
+        wake_prober: prober_sender,
+        wake_heartbeats: heartbeats_sender,
+        metrics: Metrics::default(),
     });
 
     tokio::spawn(regenerate::pages(ctx.clone(), receiver));
+    tokio::spawn(notifications::outbox::run(ctx.clone(), outbox_receiver));
+    tokio::spawn(scheduler::run(ctx.clone(), scheduler_receiver));
+    tokio::spawn(monitoring::probes::run(ctx.clone(), prober_receiver));
+    tokio::spawn(monitoring::heartbeats::run(
+        ctx.clone(),
+        heartbeats_receiver,
+    ));
+    tokio::spawn(history::run(ctx.clone()));
 
     // Generate the full web site initially.
     copy_static_files_to_cache_dir(&ctx.config)?;
@@ -199,26 +566,40 @@ async fn real_main(args: Vec<String>) -> anyhow::Result<()> {
         _watcher = Some(setup_hot_reload(ctx.clone()).await?);
     }
 
-    let admin_router = Router::new()
-        .route("/", get(controllers::admin::index))
-        .route_with_tsr("/service/new", get(controllers::admin::create_service_form))
+    app = app.nest("/admin", admin_router());
+
+    // Email subscriptions, heartbeats, alert receivers and metrics are the only public dynamic
+    // endpoints.
+    app = app
+        .route("/metrics", get(controllers::metrics::get))
         .route_with_tsr(
-            "/intervention/new",
-            get(controllers::admin::create_intervention_form),
+            "/api/alerts/alertmanager",
+            post(controllers::alerts::alertmanager),
         )
-        .route_with_tsr("/api/service", post(controllers::admin::create_service))
+        .route_with_tsr("/api/alerts/:source", post(controllers::alerts::receive))
         .route_with_tsr(
-            "/api/intervention",
-            post(controllers::admin::create_intervention),
+            "/heartbeat/:token",
+            get(controllers::heartbeats::ping).post(controllers::heartbeats::ping),
         )
-        .route_layer(ValidateRequestHeaderLayer::basic(
-            "admin",
-            &ctx.config.admin_password,
-        ));
-
-    app = app.nest("/admin", admin_router);
+        .route_with_tsr("/subscribe", post(controllers::subscriptions::subscribe))
+        .route_with_tsr(
+            "/subscribe/confirm",
+            get(controllers::subscriptions::confirm),
+        )
+        .route_with_tsr(
+            "/subscription/preferences",
+            get(controllers::subscriptions::preferences)
+                .post(controllers::subscriptions::update_preferences),
+        )
+        .route_with_tsr(
+            "/unsubscribe",
+            get(controllers::subscriptions::unsubscribe)
+                .post(controllers::subscriptions::unsubscribe),
+        );
 
-    app = app.layer(Extension(ctx.clone()));
+    app = app
+        .layer(axum::middleware::from_fn(metrics::count_requests))
+        .layer(Extension(ctx.clone()));
 
     let listen_addr = SocketAddr::from((ctx.config.interface_ipv4, ctx.config.port));
     log::info!("listening on {}", listen_addr);
@@ -231,6 +612,117 @@ async fn real_main(args: Vec<String>) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Routes of the administration, to be nested under `/admin`; everything but the login page
+/// requires an administrator.
+fn admin_router() -> Router {
+    Router::new()
+        .route("/", get(controllers::admin::index))
+        .route_with_tsr("/logout", post(controllers::auth::logout))
+        .route_with_tsr("/service/new", get(controllers::admin::create_service_form))
+        .route_with_tsr(
+            "/intervention/new",
+            get(controllers::admin::create_intervention_form),
+        )
+        .route_with_tsr(
+            "/intervention/:id/edit",
+            get(controllers::admin::edit_intervention_form),
+        )
+        .route_with_tsr("/service/:id/probe", get(controllers::admin::probe))
+        .route_with_tsr("/api/service", post(controllers::admin::create_service))
+        .route_with_tsr(
+            "/api/service/:id/probe",
+            post(controllers::admin::save_probe),
+        )
+        .route_with_tsr(
+            "/api/service/:id/probe/test",
+            post(controllers::admin::test_probe),
+        )
+        .route_with_tsr(
+            "/api/service/:id/probe/delete",
+            post(controllers::admin::delete_probe),
+        )
+        .route_with_tsr("/service/:id/heartbeat", get(controllers::admin::heartbeat))
+        .route_with_tsr(
+            "/api/service/:id/heartbeat",
+            post(controllers::admin::save_heartbeat),
+        )
+        .route_with_tsr(
+            "/api/service/:id/heartbeat/delete",
+            post(controllers::admin::delete_heartbeat),
+        )
+        .route_with_tsr(
+            "/api/intervention",
+            post(controllers::admin::create_intervention),
+        )
+        .route_with_tsr(
+            "/api/intervention/:id",
+            post(controllers::admin::update_intervention),
+        )
+        .route_with_tsr("/webhooks", get(controllers::admin::webhooks))
+        .route_with_tsr("/api/webhook", post(controllers::admin::create_webhook))
+        .route_with_tsr(
+            "/api/webhook/:id/delete",
+            post(controllers::admin::delete_webhook),
+        )
+        .route_with_tsr("/outbox", get(controllers::admin::outbox))
+        .route_with_tsr("/reports", get(controllers::admin::reports))
+        .route_with_tsr("/audit", get(controllers::admin::audit_log))
+        .route_with_tsr("/users", get(controllers::admin::users))
+        .route_with_tsr("/api-tokens", get(controllers::admin::api_tokens))
+        .route_with_tsr("/api/api-token", post(controllers::admin::create_api_token))
+        .route_with_tsr(
+            "/api/api-token/:id/delete",
+            post(controllers::admin::delete_api_token),
+        )
+        .route_with_tsr("/api/services", get(controllers::admin::api_services))
+        .route_with_tsr(
+            "/api/interventions",
+            get(controllers::admin::api_interventions),
+        )
+        .route_with_tsr("/api/user", post(controllers::admin::create_user))
+        .route_with_tsr(
+            "/api/user/:id/password",
+            post(controllers::admin::change_user_password),
+        )
+        .route_with_tsr(
+            "/api/user/:id/role",
+            post(controllers::admin::change_user_role),
+        )
+        .route_with_tsr(
+            "/api/user/:id/delete",
+            post(controllers::admin::delete_user),
+        )
+        .route_with_tsr("/teams", get(controllers::admin::teams))
+        .route_with_tsr("/api/team", post(controllers::admin::create_team))
+        .route_with_tsr(
+            "/api/team/:id/delete",
+            post(controllers::admin::delete_team),
+        )
+        .route_with_tsr(
+            "/api/service/:id/team",
+            post(controllers::admin::set_service_team),
+        )
+        .route_with_tsr("/alert-rules", get(controllers::admin::alert_rules))
+        .route_with_tsr(
+            "/api/alert-rule",
+            post(controllers::admin::create_alert_rule),
+        )
+        .route_with_tsr(
+            "/api/alert-rule/:id/delete",
+            post(controllers::admin::delete_alert_rule),
+        )
+        .route_with_tsr(
+            "/api/outbox/:id/retry",
+            post(controllers::admin::retry_outbox_entry),
+        )
+        .route_layer(axum::middleware::from_fn(controllers::auth::require_csrf))
+        .route_layer(axum::middleware::from_fn(controllers::auth::require_user))
+        .route_with_tsr(
+            "/login",
+            get(controllers::auth::login_form).post(controllers::auth::login),
+        )
+}
+
 async fn setup_hot_reload(app: Arc<AppContext>) -> anyhow::Result<notify::RecommendedWatcher> {
     let rt_handle = tokio::runtime::Handle::current();
 
@@ -253,10 +745,10 @@ async fn setup_hot_reload(app: Arc<AppContext>) -> anyhow::Result<notify::Recomm
                     }
                 }
 
-                // If any path is a CSS or HTML file,
+                // If any path is a CSS file or a template,
                 if event.paths.iter().any(|path| {
                     if let Some(ext) = path.extension() {
-                        ext == "css" || ext == "html"
+                        ext == "css" || ext == "html" || ext == "txt" || ext == "json"
                     } else {
                         false
                     }
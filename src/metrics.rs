@@ -0,0 +1,113 @@
+//! Internal metrics of the application, exposed along with the status of the services in the
+//! Prometheus text format.
+
+use axum::{extract::MatchedPath, http::Request, middleware::Next, response::Response, Extension};
+use chrono::NaiveDateTime;
+use std::{collections::BTreeMap, fmt::Write as _, sync::Arc, sync::Mutex, time::Duration};
+
+use crate::AppContext;
+
+/// Outcome of the last regeneration of the pages, and the time spent regenerating them so far.
+#[derive(Default)]
+pub(crate) struct RegenerationStats {
+    pub last_date: Option<NaiveDateTime>,
+    pub last_success: bool,
+    pub last_duration: Duration,
+    pub total_duration: Duration,
+    pub count: u64,
+}
+
+/// Counters updated by the server and the background tasks.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    /// Number of handled requests, by method, route and status code.
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    regenerations: Mutex<RegenerationStats>,
+}
+
+impl Metrics {
+    pub fn record_request(&self, method: &str, route: &str, status: u16) {
+        let mut requests = self.requests.lock().unwrap();
+        *requests
+            .entry((method.to_owned(), route.to_owned(), status))
+            .or_default() += 1;
+    }
+
+    pub fn record_regeneration(&self, date: NaiveDateTime, success: bool, duration: Duration) {
+        let mut stats = self.regenerations.lock().unwrap();
+        stats.last_date = Some(date);
+        stats.last_success = success;
+        stats.last_duration = duration;
+        stats.total_duration += duration;
+        stats.count += 1;
+    }
+
+    /// Copy of the request counters.
+    pub fn requests(&self) -> BTreeMap<(String, String, u16), u64> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn with_regenerations<T>(&self, f: impl FnOnce(&RegenerationStats) -> T) -> T {
+        f(&self.regenerations.lock().unwrap())
+    }
+}
+
+/// Middleware counting the handled requests.
+///
+/// Requests are labelled with their route rather than their path, so the number of series
+/// doesn't grow with the ids and tokens in the URLs.
+pub(crate) async fn count_requests<B>(
+    Extension(ctx): Extension<Arc<AppContext>>,
+    matched_path: Option<MatchedPath>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = request.method().to_string();
+    let route = match &matched_path {
+        Some(path) => path.as_str().to_owned(),
+        None => "unmatched".to_owned(),
+    };
+    let response = next.run(request).await;
+    ctx.metrics
+        .record_request(&method, &route, response.status().as_u16());
+    response
+}
+
+/// Writer of metrics in the Prometheus text format.
+#[derive(Default)]
+pub(crate) struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    /// Start a new metric family.
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {name} {help}");
+        let _ = writeln!(self.text, "# TYPE {name} {kind}");
+    }
+
+    /// Add a sample to the current family.
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(key, value)| format!("{key}=\"{}\"", escape_label_value(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = write!(self.text, "{{{labels}}}");
+        }
+        let _ = writeln!(self.text, " {value}");
+    }
+
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
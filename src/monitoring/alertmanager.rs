@@ -0,0 +1,93 @@
+//! Receiver for the webhook notifications of Prometheus' Alertmanager.
+//!
+//! See https://prometheus.io/docs/alerting/latest/configuration/#webhook_config for the format.
+
+use super::alerts::{self, InboundAlert};
+use serde::Deserialize;
+use sqlx::AnyConnection;
+use std::collections::HashMap;
+use tracing as log;
+
+/// Name of the source, as stored along with the alerts.
+pub(crate) const SOURCE: &str = "alertmanager";
+
+/// Configuration of the Alertmanager receiver.
+pub(crate) struct AlertmanagerConfig {
+    /// Bearer token Alertmanager must send.
+    pub token: String,
+    /// Label whose value is the name (or the id) of the affected service.
+    pub service_label: String,
+    /// Label whose value gives the severity of the intervention.
+    pub severity_label: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Payload {
+    alerts: Vec<PayloadAlert>,
+}
+
+#[derive(Deserialize)]
+struct PayloadAlert {
+    status: String,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    fingerprint: String,
+}
+
+/// Convert the alerts of a payload; alerts which can't be mapped to a service are skipped.
+pub(crate) async fn parse(
+    config: &AlertmanagerConfig,
+    payload: Payload,
+    conn: &mut AnyConnection,
+) -> anyhow::Result<Vec<InboundAlert>> {
+    let mut alerts = Vec::with_capacity(payload.alerts.len());
+
+    for alert in payload.alerts {
+        let Some(service_name) = alert.labels.get(&config.service_label) else {
+            log::warn!(
+                "skipping alert {} without a {} label",
+                alert.fingerprint,
+                config.service_label
+            );
+            continue;
+        };
+        let Some(service) = alerts::find_service(service_name, conn).await? else {
+            log::warn!(
+                "skipping alert {} about unknown service {service_name}",
+                alert.fingerprint
+            );
+            continue;
+        };
+
+        let alert_name = alert
+            .labels
+            .get("alertname")
+            .map_or("alerte", String::as_str);
+
+        // TODO i18n
+        let title = match alert.annotations.get("summary") {
+            Some(summary) => summary.clone(),
+            None => format!("{alert_name} sur {}", service.name),
+        };
+        let description = alert
+            .annotations
+            .get("description")
+            .cloned()
+            .unwrap_or_else(|| format!("Alerte {alert_name} déclenchée automatiquement."));
+
+        alerts.push(InboundAlert {
+            fingerprint: alert.fingerprint,
+            service_id: service.id.unwrap(),
+            severity: alerts::parse_severity(
+                alert.labels.get(&config.severity_label).map(String::as_str),
+            ),
+            firing: alert.status == "firing",
+            title,
+            description,
+        });
+    }
+
+    Ok(alerts)
+}
@@ -0,0 +1,270 @@
+//! Alerts received from external monitoring systems: a firing alert opens an intervention, which
+//! is resolved along with the alert.
+
+use crate::{
+    audit::Actor,
+    db::models::{
+        alerts::Alert,
+        interventions::{Intervention, Severity, Status},
+        services::Service,
+    },
+    interventions, AppContext,
+};
+use sqlx::{AnyConnection, Connection as _};
+use tracing as log;
+
+/// An alert, as parsed from the payload of an external monitoring system.
+#[derive(Clone, Debug)]
+pub(crate) struct InboundAlert {
+    /// Identifies the alert within its source, across its notifications.
+    pub fingerprint: String,
+    pub service_id: i64,
+    pub severity: Severity,
+    /// False once the alert is resolved.
+    pub firing: bool,
+    pub title: String,
+    pub description: String,
+}
+
+/// Map a severity label, as commonly used in alerting rules, to the severity of an intervention.
+///
+/// Our own severity names are accepted too. Unknown or missing severities are considered partial
+/// outages.
+pub(crate) fn parse_severity(label: Option<&str>) -> Severity {
+    match label.map(str::to_lowercase).as_deref() {
+        Some("critical" | "page" | "full-outage") => Severity::FullOutage,
+        Some("info" | "low" | "performance-issue") => Severity::PerformanceIssue,
+        _ => Severity::PartialOutage,
+    }
+}
+
+/// Find the service designated by a label value, either by name or by id.
+pub(crate) async fn find_service(
+    value: &str,
+    conn: &mut AnyConnection,
+) -> anyhow::Result<Option<Service>> {
+    if let Some(service) = Service::by_name(value, conn).await? {
+        return Ok(Some(service));
+    }
+    match value.parse() {
+        Ok(id) => Service::by_id(id, conn).await,
+        Err(_) => Ok(None),
+    }
+}
+
+/// Apply an alert: open an intervention when it starts firing, update its severity while it
+/// fires, and resolve it when the alert is resolved.
+///
+/// Alerts are deduplicated by fingerprint, so notifications repeated by the source don't create
+/// more interventions; an alert firing again shortly after being resolved reopens its
+/// intervention, so that flapping alerts don't create more interventions either.
+pub(crate) async fn handle(
+    app: &AppContext,
+    source: &str,
+    alert: &InboundAlert,
+) -> anyhow::Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+
+    let changed = {
+        let mut conn = app.db_connection.lock().await;
+        let mut tx = conn.begin().await?;
+
+        let previous = Alert::by_fingerprint(source, &alert.fingerprint, &mut tx).await?;
+
+        // The intervention opened for this alert, unless it has been resolved (maybe by hand)
+        // for too long to be reopened.
+        let mut intervention = None;
+        if let Some(id) = previous.as_ref().and_then(|prev| prev.intervention_id) {
+            let reopen_after = now - app.config.alerts_reopen_window;
+            intervention = Intervention::by_id(id, &mut tx).await?.filter(|int| {
+                int.status != Status::Resolved
+                    || int
+                        .end_date
+                        .is_some_and(|end_date| end_date >= reopen_after)
+            });
+        }
+
+        let mut intervention_id = intervention.as_ref().and_then(|int| int.id);
+        let mut changed = false;
+
+        match (alert.firing, intervention) {
+            (true, None) => {
+                let id = super::open_intervention(
+                    app,
+                    &mut tx,
+                    alert.service_id,
+                    alert.severity,
+                    alert.title.clone(),
+                    alert.description.clone(),
+                )
+                .await?;
+                log::info!(
+                    "{source} alert {} is firing, opened intervention {id}",
+                    alert.fingerprint
+                );
+                intervention_id = Some(id);
+                changed = true;
+            }
+
+            (true, Some(mut intervention)) if intervention.status == Status::Resolved => {
+                let id = intervention.id.unwrap();
+                log::info!(
+                    "{source} alert {} is firing again, reopening intervention {id}",
+                    alert.fingerprint
+                );
+                intervention.status = Status::Ongoing;
+                intervention.end_date = None;
+                intervention.severity = alert.severity;
+                // TODO i18n
+                let update = format!("L'alerte s'est de nouveau déclenchée : {}", alert.title);
+                interventions::update(
+                    app,
+                    &mut tx,
+                    &Actor::system(),
+                    id,
+                    &intervention,
+                    None,
+                    Some(update),
+                )
+                .await?;
+                changed = true;
+            }
+
+            (true, Some(mut intervention)) => {
+                if intervention.severity != alert.severity {
+                    let id = intervention.id.unwrap();
+                    intervention.severity = alert.severity;
+                    // TODO i18n
+                    let update = format!("Gravité : {}.", alert.severity.label());
+                    interventions::update(
+                        app,
+                        &mut tx,
+                        &Actor::system(),
+                        id,
+                        &intervention,
+                        None,
+                        Some(update),
+                    )
+                    .await?;
+                    changed = true;
+                }
+            }
+
+            (false, Some(intervention)) if intervention.status != Status::Resolved => {
+                let id = intervention.id.unwrap();
+                log::info!(
+                    "{source} alert {} is resolved, resolving intervention {id}",
+                    alert.fingerprint
+                );
+                // TODO i18n
+                let update = "L'alerte est résolue.".to_owned();
+                interventions::resolve(app, &mut tx, &Actor::system(), id, update).await?;
+                // Keep the intervention, so it can be reopened if the alert fires again soon.
+                changed = true;
+            }
+
+            (false, _) => {}
+        }
+
+        Alert::save(
+            &mut tx,
+            &Alert {
+                source: source.to_owned(),
+                fingerprint: alert.fingerprint.clone(),
+                service_id: alert.service_id,
+                firing: alert.firing,
+                intervention_id,
+                updated_at: now,
+            },
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        changed
+    };
+
+    if changed {
+        app.regenerate_pages.send(()).await?;
+        app.wake_outbox.send(()).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn alert(service_id: i64, firing: bool) -> InboundAlert {
+        InboundAlert {
+            fingerprint: "disk-full-db1".to_owned(),
+            service_id,
+            severity: Severity::PartialOutage,
+            firing,
+            title: "Disk full on db1".to_owned(),
+            description: "Less than 1% of free space left.".to_owned(),
+        }
+    }
+
+    async fn interventions(ctx: &AppContext) -> Vec<Intervention> {
+        let mut conn = ctx.db_connection.lock().await;
+        Intervention::get_all(&mut conn).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn flapping_alert_reopens_its_intervention() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let service_id = testing::insert_service(ctx, "Database", "https://db.example.org").await;
+
+        // Repeated notifications of a firing alert open a single intervention.
+        handle(ctx, "generic", &alert(service_id, true))
+            .await
+            .unwrap();
+        handle(ctx, "generic", &alert(service_id, true))
+            .await
+            .unwrap();
+        let opened = interventions(ctx).await;
+        assert_eq!(opened.len(), 1);
+        assert_eq!(opened[0].status, Status::Ongoing);
+        let id = opened[0].id.unwrap();
+
+        handle(ctx, "generic", &alert(service_id, false))
+            .await
+            .unwrap();
+        assert_eq!(interventions(ctx).await[0].status, Status::Resolved);
+
+        // Firing again soon after reopens it.
+        handle(ctx, "generic", &alert(service_id, true))
+            .await
+            .unwrap();
+        let reopened = interventions(ctx).await;
+        assert_eq!(reopened.len(), 1);
+        assert_eq!(reopened[0].id, Some(id));
+        assert_eq!(reopened[0].status, Status::Ongoing);
+        assert_eq!(reopened[0].end_date, None);
+
+        // Firing again long after opens another one.
+        handle(ctx, "generic", &alert(service_id, false))
+            .await
+            .unwrap();
+        {
+            let mut conn = ctx.db_connection.lock().await;
+            let mut intervention = Intervention::by_id(id, &mut conn).await.unwrap().unwrap();
+            intervention.end_date =
+                Some(chrono::Utc::now().naive_utc() - chrono::Duration::hours(2));
+            Intervention::update(&mut conn, id, &intervention)
+                .await
+                .unwrap();
+        }
+        handle(ctx, "generic", &alert(service_id, true))
+            .await
+            .unwrap();
+        let all = interventions(ctx).await;
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].status, Status::Resolved);
+        assert_eq!(all[1].status, Status::Ongoing);
+    }
+}
@@ -0,0 +1,147 @@
+//! Just enough DER parsing to read the expiry date of an X.509 certificate.
+
+use chrono::NaiveDateTime;
+
+const SEQUENCE: u8 = 0x30;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+/// Explicit tag of the (optional) version of a certificate.
+const VERSION: u8 = 0xa0;
+
+/// Read the DER element at the start of the input; returns its tag, its content and what comes
+/// after it.
+fn read_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first_len_byte, mut input) = input.split_first()?;
+
+    let len = if first_len_byte & 0x80 == 0 {
+        first_len_byte as usize
+    } else {
+        // Long form: the lower bits give the number of bytes of the length.
+        let num_bytes = (first_len_byte & 0x7f) as usize;
+        if num_bytes == 0 || num_bytes > 4 {
+            return None;
+        }
+        let (len_bytes, rest) = input.split_at_checked(num_bytes)?;
+        input = rest;
+        len_bytes
+            .iter()
+            .fold(0usize, |len, &byte| (len << 8) | byte as usize)
+    };
+
+    let (content, rest) = input.split_at_checked(len)?;
+    Some((tag, content, rest))
+}
+
+/// Read the element at the start of the input, checking its tag.
+fn expect_element(input: &[u8], expected_tag: u8) -> Option<(&[u8], &[u8])> {
+    let (tag, content, rest) = read_element(input)?;
+    (tag == expected_tag).then_some((content, rest))
+}
+
+/// Returns the end of the validity period of a DER-encoded certificate.
+pub(crate) fn not_after(certificate: &[u8]) -> Option<NaiveDateTime> {
+    let (certificate, _) = expect_element(certificate, SEQUENCE)?;
+    let (tbs_certificate, _) = expect_element(certificate, SEQUENCE)?;
+
+    // Skip the version, if any, and the serial number.
+    let (tag, _, mut rest) = read_element(tbs_certificate)?;
+    if tag == VERSION {
+        (_, _, rest) = read_element(rest)?;
+    }
+
+    // Skip the signature algorithm and the issuer.
+    for _ in 0..2 {
+        (_, _, rest) = read_element(rest)?;
+    }
+
+    let (validity, _) = expect_element(rest, SEQUENCE)?;
+    let (_, _, validity) = read_element(validity)?; // Not before.
+    let (tag, time, _) = read_element(validity)?;
+    let time = std::str::from_utf8(time).ok()?;
+
+    let format = match tag {
+        UTC_TIME => "%y%m%d%H%M%SZ",
+        GENERALIZED_TIME => "%Y%m%d%H%M%SZ",
+        _ => return None,
+    };
+    NaiveDateTime::parse_from_str(time, format).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Self-signed certificates; before 2050, dates are encoded as `UTCTime`, and as
+    /// `GeneralizedTime` after.
+    const CERTIFICATE: &[u8] = include_bytes!("fixtures/certificate.der");
+    const CERTIFICATE_2054: &[u8] = include_bytes!("fixtures/certificate-2054.der");
+
+    fn date(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn expiry_dates_are_read() {
+        assert_eq!(not_after(CERTIFICATE), Some(date("2029-07-14 16:30:01")));
+        assert_eq!(
+            not_after(CERTIFICATE_2054),
+            Some(date("2054-03-05 16:30:01"))
+        );
+
+        // Trailing data is ignored.
+        let padded = [CERTIFICATE, b"\0\0\0"].concat();
+        assert_eq!(not_after(&padded), Some(date("2029-07-14 16:30:01")));
+    }
+
+    #[test]
+    fn invalid_certificates_are_rejected() {
+        for len in 0..CERTIFICATE.len() {
+            assert_eq!(
+                not_after(&CERTIFICATE[..len]),
+                None,
+                "truncated to {len} bytes"
+            );
+        }
+
+        // Offset of the expiry date, in the second fixture.
+        let not_after_offset = 95;
+        assert_eq!(CERTIFICATE_2054[not_after_offset], GENERALIZED_TIME);
+        let with = |offset: usize, byte: u8| {
+            let mut certificate = CERTIFICATE_2054.to_vec();
+            certificate[offset] = byte;
+            certificate
+        };
+        // The date isn't a date.
+        assert_eq!(not_after(&with(not_after_offset, 0x04)), None);
+        // The date doesn't match its type.
+        assert_eq!(not_after(&with(not_after_offset, UTC_TIME)), None);
+        // The date isn't valid.
+        assert_eq!(not_after(&with(not_after_offset + 6, b'9')), None);
+        assert_eq!(not_after(&with(not_after_offset + 6, 0xff)), None);
+        // Not a certificate at all.
+        assert_eq!(not_after(&with(0, 0x31)), None);
+
+        for input in [
+            &b"not a certificate"[..],
+            // Long-form lengths without any byte, with too many bytes, or longer than the input.
+            &[SEQUENCE, 0x80],
+            &[SEQUENCE, 0x85, 0, 0, 0, 0, 1, 0],
+            &[SEQUENCE, 0x84, 0xff, 0xff, 0xff, 0xff],
+            &[SEQUENCE, 0x82, 0x01],
+            // A certificate without anything but its validity.
+            &[SEQUENCE, 0x04, SEQUENCE, 0x02, SEQUENCE, 0x00],
+        ] {
+            assert_eq!(not_after(input), None, "{input:?}");
+        }
+
+        // Whatever byte is corrupted, reading the certificate doesn't panic.
+        for offset in 0..CERTIFICATE.len() {
+            for byte in [0x00, 0x7f, 0x80, 0x84, 0xff] {
+                let mut certificate = CERTIFICATE.to_vec();
+                certificate[offset] = byte;
+                let _ = not_after(&certificate);
+            }
+        }
+    }
+}
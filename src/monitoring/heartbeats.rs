@@ -0,0 +1,188 @@
+//! Heartbeats: services ping a secret URL, and are considered down when the pings stop.
+
+use super::Thresholds;
+use crate::{
+    db::models::{heartbeats::Heartbeat, interventions::Severity, monitors::MonitorSource},
+    AppContext,
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+use tracing as log;
+
+/// How long to wait before trying again, after failing to read the heartbeats.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How long to sleep when no heartbeat is due. Anything that could change this will wake up the
+/// watcher anyways.
+const IDLE_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Number of pings kept for each heartbeat; the admin only displays the latest ones.
+const KEPT_PINGS: i64 = 100;
+
+/// Record a ping of a heartbeat, and resolve the intervention opened when it was late, if any.
+pub(crate) async fn ping(app: &AppContext, heartbeat: &Heartbeat) -> anyhow::Result<()> {
+    {
+        let mut conn = app.db_connection.lock().await;
+        Heartbeat::record_ping(
+            heartbeat.service_id,
+            chrono::Utc::now().naive_utc(),
+            &mut conn,
+        )
+        .await?;
+        Heartbeat::delete_old_pings(heartbeat.service_id, KEPT_PINGS, &mut conn).await?;
+    }
+
+    super::report(
+        app,
+        heartbeat.service_id,
+        MonitorSource::Heartbeat,
+        true,
+        Severity::FullOutage,
+        "ping received",
+        &Thresholds::immediate(),
+    )
+    .await?;
+
+    // The deadline moved.
+    app.wake_heartbeats.send(()).await?;
+
+    Ok(())
+}
+
+/// Background task opening an intervention for every enabled heartbeat which is late.
+///
+/// A message on the receiver makes the task reload the heartbeats, so it takes pings and new or
+/// modified heartbeats into account.
+pub(crate) async fn run(app: Arc<AppContext>, mut receiver: mpsc::Receiver<()>) {
+    // Deadline which has already been reported as missed, by heartbeat id, so a late heartbeat is
+    // only reported once.
+    let mut reported: HashMap<i64, chrono::NaiveDateTime> = HashMap::new();
+
+    loop {
+        let heartbeats = {
+            let mut conn = app.db_connection.lock().await;
+            Heartbeat::get_enabled(&mut conn).await
+        };
+
+        let delay = match heartbeats {
+            Ok(heartbeats) => {
+                reported.retain(|id, _| heartbeats.iter().any(|h| h.id == Some(*id)));
+
+                let now = chrono::Utc::now().naive_utc();
+                let mut delay = IDLE_DELAY;
+
+                for heartbeat in heartbeats {
+                    let id = heartbeat.id.unwrap();
+                    let deadline = heartbeat.deadline();
+
+                    if deadline > now {
+                        let until_deadline = (deadline - now).to_std().unwrap_or_default();
+                        delay = delay.min(until_deadline);
+                        continue;
+                    }
+
+                    if reported.get(&id) == Some(&deadline) {
+                        continue;
+                    }
+
+                    // TODO i18n
+                    let message = match heartbeat.last_ping_at {
+                        Some(last_ping_at) => {
+                            format!("aucun signal de vie depuis le {last_ping_at} (UTC)")
+                        }
+                        None => "aucun signal de vie reçu".to_owned(),
+                    };
+
+                    let res = super::report(
+                        &app,
+                        heartbeat.service_id,
+                        MonitorSource::Heartbeat,
+                        false,
+                        Severity::FullOutage,
+                        &message,
+                        &Thresholds::immediate(),
+                    )
+                    .await;
+
+                    match res {
+                        Ok(()) => {
+                            reported.insert(id, deadline);
+                        }
+                        Err(err) => {
+                            log::error!(
+                                "unable to report the late heartbeat of service {}: {err:#}",
+                                heartbeat.service_id
+                            );
+                            delay = delay.min(RETRY_DELAY);
+                        }
+                    }
+                }
+
+                delay
+            }
+
+            Err(err) => {
+                log::error!("Unable to read the heartbeats: {err:#}");
+                RETRY_DELAY
+            }
+        };
+
+        tokio::select! {
+            received = receiver.recv() => {
+                if received.is_none() {
+                    // okthxbye
+                    break;
+                }
+            }
+
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn only_the_latest_pings_are_kept() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let service_id = testing::insert_service(ctx, "Forge", "https://forge.example.org").await;
+        let other_id = testing::insert_service(ctx, "Wiki", "https://wiki.example.org").await;
+
+        let mut conn = ctx.db_connection.lock().await;
+        let start = chrono::Utc::now().naive_utc();
+        for i in 0..5 {
+            let date = start + chrono::Duration::minutes(i);
+            Heartbeat::record_ping(service_id, date, &mut conn)
+                .await
+                .unwrap();
+            Heartbeat::record_ping(other_id, date, &mut conn)
+                .await
+                .unwrap();
+        }
+
+        Heartbeat::delete_old_pings(service_id, 3, &mut conn)
+            .await
+            .unwrap();
+
+        let pings = Heartbeat::get_latest_pings(service_id, 10, &mut conn)
+            .await
+            .unwrap();
+        // Pings are stored to the second.
+        let expected: Vec<_> = (2..5)
+            .rev()
+            .map(|i| (start + chrono::Duration::minutes(i)).timestamp())
+            .collect();
+        let pings: Vec<_> = pings.iter().map(|date| date.timestamp()).collect();
+        assert_eq!(pings, expected);
+
+        // The other services keep their pings.
+        let pings = Heartbeat::get_latest_pings(other_id, 10, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(pings.len(), 5);
+    }
+}
@@ -0,0 +1,169 @@
+//! History of the probe results: raw results are downsampled into hourly and daily aggregates,
+//! which are kept longer and used to draw the charts of the status pages.
+
+use crate::{
+    db::models::probes::{ProbeAggregate, ProbeResult, Resolution},
+    AppContext,
+};
+use sqlx::{AnyConnection, Connection as _};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tracing as log;
+
+/// How often aggregates are updated, and the pages regenerated with the new charts.
+const AGGREGATION_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How far back aggregates are computed, at most; older ones are considered final.
+///
+/// Raw results must be kept at least that long.
+pub(crate) const RECOMPUTE_WINDOW_DAYS: i64 = 2;
+
+/// How long each kind of data is kept.
+pub(crate) struct HistoryRetention {
+    /// Raw probe results.
+    pub raw: chrono::Duration,
+    pub hourly: chrono::Duration,
+    pub daily: chrono::Duration,
+}
+
+/// Update the aggregates since the last run, and delete the data older than the retention
+/// periods.
+///
+/// Only the hours since the latest aggregated one are recomputed, so that the database isn't held
+/// for long; the hour before it is included too, in case a result was recorded late.
+async fn aggregate(conn: &mut AnyConnection, retention: &HistoryRetention) -> anyhow::Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    let window_start = now - chrono::Duration::days(RECOMPUTE_WINDOW_DAYS);
+
+    let mut tx = conn.begin().await?;
+
+    // Hourly aggregates, from the raw results; the current hour is included, even if it's
+    // incomplete, so the charts are up to date.
+    let hourly_start = match ProbeAggregate::latest_bucket_start(Resolution::Hour, &mut tx).await? {
+        Some(latest) => (latest - chrono::Duration::hours(1)).max(window_start),
+        None => window_start,
+    };
+    let hourly_start = Resolution::Hour.bucket_start(hourly_start);
+    let mut hourly: BTreeMap<(i64, chrono::NaiveDateTime), ProbeAggregate> = BTreeMap::new();
+    for result in ProbeResult::get_since(hourly_start, &mut tx).await? {
+        let bucket_start = Resolution::Hour.bucket_start(result.date);
+        hourly
+            .entry((result.service_id, bucket_start))
+            .or_insert_with(|| ProbeAggregate::empty(result.service_id, bucket_start))
+            .add_result(&result);
+    }
+    for aggregate in hourly.values() {
+        ProbeAggregate::save(&mut tx, Resolution::Hour, aggregate).await?;
+    }
+
+    // Daily aggregates, from the hourly ones.
+    let daily_start = Resolution::Day.bucket_start(hourly_start);
+    let mut daily: BTreeMap<(i64, chrono::NaiveDateTime), ProbeAggregate> = BTreeMap::new();
+    for hour in ProbeAggregate::get_since(Resolution::Hour, daily_start, &mut tx).await? {
+        let bucket_start = Resolution::Day.bucket_start(hour.bucket_start);
+        daily
+            .entry((hour.service_id, bucket_start))
+            .or_insert_with(|| ProbeAggregate::empty(hour.service_id, bucket_start))
+            .add_aggregate(&hour);
+    }
+    for aggregate in daily.values() {
+        ProbeAggregate::save(&mut tx, Resolution::Day, aggregate).await?;
+    }
+
+    ProbeResult::delete_before(now - retention.raw, &mut tx).await?;
+    ProbeAggregate::delete_before(Resolution::Hour, now - retention.hourly, &mut tx).await?;
+    ProbeAggregate::delete_before(Resolution::Day, now - retention.daily, &mut tx).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Background task updating the aggregates periodically, and regenerating the pages so the
+/// charts follow.
+pub(crate) async fn run(app: Arc<AppContext>) {
+    let mut interval = tokio::time::interval(AGGREGATION_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let res = {
+            let mut conn = app.db_connection.lock().await;
+            aggregate(&mut conn, &app.config.history_retention).await
+        };
+
+        match res {
+            Ok(()) => {
+                if let Err(err) = app.regenerate_pages.send(()).await {
+                    log::error!("unable to regenerate the pages: {err:#}");
+                }
+            }
+            Err(err) => log::error!("Unable to aggregate the probe results: {err:#}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn only_the_recent_hours_are_recomputed() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let service_id = testing::insert_service(ctx, "Forge", "https://forge.example.org").await;
+        let retention = &ctx.config.history_retention;
+
+        let now = chrono::Utc::now().naive_utc();
+        let earlier = now - chrono::Duration::hours(5);
+        let result = |date| ProbeResult {
+            service_id,
+            date,
+            success: true,
+            response_time_ms: Some(100),
+            message: None,
+        };
+        let num_checks = |aggregates: &[ProbeAggregate], date| {
+            let bucket_start = Resolution::Hour.bucket_start(date);
+            aggregates
+                .iter()
+                .find(|a| a.bucket_start == bucket_start)
+                .map(|a| a.num_checks)
+        };
+
+        let mut conn = ctx.db_connection.lock().await;
+        ProbeResult::insert(&mut conn, &result(earlier))
+            .await
+            .unwrap();
+        ProbeResult::insert(&mut conn, &result(now)).await.unwrap();
+        aggregate(&mut conn, retention).await.unwrap();
+
+        let hourly = ProbeAggregate::get_since(
+            Resolution::Hour,
+            Resolution::Hour.bucket_start(earlier),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(num_checks(&hourly, earlier), Some(1));
+        assert_eq!(num_checks(&hourly, now), Some(1));
+
+        // Older hours are final, while the current one follows the new results.
+        ProbeResult::insert(&mut conn, &result(earlier))
+            .await
+            .unwrap();
+        ProbeResult::insert(&mut conn, &result(now)).await.unwrap();
+        aggregate(&mut conn, retention).await.unwrap();
+
+        let hourly = ProbeAggregate::get_since(
+            Resolution::Hour,
+            Resolution::Hour.bucket_start(earlier),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(num_checks(&hourly, earlier), Some(1));
+        assert_eq!(num_checks(&hourly, now), Some(2));
+    }
+}
@@ -0,0 +1,444 @@
+//! Automatic detection of outages: monitors report whether the services work, and interventions
+//! are opened and resolved accordingly.
+
+use crate::{
+    audit::Actor,
+    db::models::{
+        interventions::{Intervention, Severity, Status},
+        monitors::{MonitorSource, MonitorState},
+        services::Service,
+    },
+    interventions, AppContext,
+};
+use anyhow::Context as _;
+use sqlx::{AnyConnection, Connection as _};
+use tracing as log;
+
+pub(crate) mod alertmanager;
+pub(crate) mod alerts;
+mod certificates;
+pub(crate) mod heartbeats;
+pub(crate) mod history;
+pub(crate) mod probes;
+pub(crate) mod receivers;
+
+/// Open an intervention about a single service, on behalf of the system.
+pub(crate) async fn open_intervention(
+    app: &AppContext,
+    conn: &mut AnyConnection,
+    service_id: i64,
+    severity: Severity,
+    title: String,
+    description: String,
+) -> anyhow::Result<i64> {
+    let intervention = Intervention {
+        id: None,
+        title,
+        description: Some(description),
+        status: Status::Ongoing,
+        start_date: chrono::Utc::now().naive_utc(),
+        estimated_duration: None,
+        end_date: None,
+        severity,
+        is_planned: false,
+        auto_resolve: false,
+    };
+    interventions::create(app, conn, &Actor::system(), &intervention, &[service_id]).await
+}
+
+/// When a monitor opens and resolves interventions.
+#[derive(Clone, Debug)]
+pub(crate) struct Thresholds {
+    /// Number of consecutive failures before an intervention is opened.
+    pub failures_to_open: i64,
+    /// Number of consecutive successes before the intervention is resolved.
+    pub successes_to_close: i64,
+    /// Interventions aren't resolved before they've lasted that long.
+    pub min_incident_duration: chrono::Duration,
+    /// Window over which the changes between success and failure are counted.
+    pub flap_window: chrono::Duration,
+    /// Number of changes within the window making the service flapping; 0 disables flap
+    /// detection.
+    pub flap_threshold: i64,
+}
+
+impl Thresholds {
+    /// Open an intervention at the first failure, and resolve it at the first success.
+    pub fn immediate() -> Self {
+        Self {
+            failures_to_open: 1,
+            successes_to_close: 1,
+            min_incident_duration: chrono::Duration::zero(),
+            flap_window: chrono::Duration::zero(),
+            flap_threshold: 0,
+        }
+    }
+}
+
+/// Record what a monitor observed about a service.
+///
+/// An intervention with the given severity is opened once the monitor has failed enough times in
+/// a row, and resolved once it has succeeded enough times in a row, and the intervention lasted
+/// long enough.
+///
+/// A service switching too often between success and failure is flapping: instead of a series of
+/// short outages, a single performance issue is opened, and resolved once the service is stable
+/// again. It's escalated if the service ends up failing steadily.
+pub(crate) async fn report(
+    app: &AppContext,
+    service_id: i64,
+    source: MonitorSource,
+    success: bool,
+    severity: Severity,
+    message: &str,
+    thresholds: &Thresholds,
+) -> anyhow::Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+
+    let changed = {
+        let mut conn = app.db_connection.lock().await;
+        let mut tx = conn.begin().await?;
+
+        let mut state = MonitorState::read(service_id, source, &mut tx).await?;
+
+        // Forget about the interventions which have been resolved by hand in the meanwhile.
+        let mut intervention = None;
+        if let Some(id) = state.intervention_id {
+            intervention = Intervention::by_id(id, &mut tx)
+                .await?
+                .filter(|int| int.status != Status::Resolved);
+            if intervention.is_none() {
+                state.intervention_id = None;
+                state.flapping = false;
+            }
+        }
+
+        let previous_success = if state.consecutive_successes > 0 {
+            Some(true)
+        } else if state.consecutive_failures > 0 {
+            Some(false)
+        } else {
+            None
+        };
+
+        if success {
+            state.consecutive_failures = 0;
+            state.consecutive_successes += 1;
+        } else {
+            state.consecutive_successes = 0;
+            state.consecutive_failures += 1;
+        }
+
+        let mut num_changes = 0;
+        if thresholds.flap_threshold > 0 {
+            let window_start = now - thresholds.flap_window;
+            if previous_success.is_some_and(|previous| previous != success) {
+                state.record_change(now, window_start, &mut tx).await?;
+            }
+            num_changes = state.count_changes_since(window_start, &mut tx).await?;
+        }
+        let flapping = thresholds.flap_threshold > 0 && num_changes >= thresholds.flap_threshold;
+
+        let mut changed = false;
+
+        match intervention {
+            Some(mut intervention) => {
+                let id = intervention.id.unwrap();
+
+                if state.flapping
+                    && !flapping
+                    && state.consecutive_failures >= thresholds.failures_to_open
+                    && severity > intervention.severity
+                {
+                    log::info!("service {service_id} stopped flapping and is down, escalating intervention {id}");
+                    intervention.severity = severity;
+                    // TODO i18n
+                    let update = format!("Le service est désormais en panne : {message}");
+                    interventions::update(
+                        app,
+                        &mut tx,
+                        &Actor::system(),
+                        id,
+                        &intervention,
+                        None,
+                        Some(update),
+                    )
+                    .await?;
+
+                    state.flapping = false;
+                    changed = true;
+                } else if success
+                    && !flapping
+                    && state.consecutive_successes >= thresholds.successes_to_close
+                    && now - intervention.start_date >= thresholds.min_incident_duration
+                {
+                    log::info!("service {service_id} is back, resolving intervention {id}");
+                    // TODO i18n
+                    let update = if state.flapping {
+                        "Le service est de nouveau stable."
+                    } else {
+                        "Le service fonctionne de nouveau."
+                    };
+                    interventions::resolve(app, &mut tx, &Actor::system(), id, update.to_owned())
+                        .await?;
+
+                    state.intervention_id = None;
+                    state.flapping = false;
+                    changed = true;
+                }
+            }
+
+            None => {
+                let opening = if flapping {
+                    Some(Severity::PerformanceIssue)
+                } else if state.consecutive_failures >= thresholds.failures_to_open {
+                    Some(severity)
+                } else {
+                    None
+                };
+
+                if let Some(opening_severity) = opening {
+                    let service = Service::by_id(service_id, &mut tx)
+                        .await?
+                        .with_context(|| format!("unknown service with id {service_id}"))?;
+
+                    // TODO i18n
+                    let (title, description) = if flapping {
+                        (
+                            format!("Instabilité détectée sur {}", service.name),
+                            format!(
+                                "Le service alterne entre fonctionnement et panne ({num_changes} changements en {} minutes), détecté automatiquement : {message}",
+                                thresholds.flap_window.num_minutes()
+                            ),
+                        )
+                    } else {
+                        let title = match opening_severity {
+                            Severity::FullOutage => format!("{} ne répond plus", service.name),
+                            Severity::PartialOutage | Severity::PerformanceIssue => {
+                                format!("Problème détecté sur {}", service.name)
+                            }
+                        };
+                        (title, format!("Panne détectée automatiquement : {message}"))
+                    };
+
+                    let id = open_intervention(
+                        app,
+                        &mut tx,
+                        service_id,
+                        opening_severity,
+                        title,
+                        description,
+                    )
+                    .await?;
+                    log::info!(
+                        "service {service_id} is {}, opened intervention {id}",
+                        if flapping { "flapping" } else { "down" }
+                    );
+
+                    state.intervention_id = Some(id);
+                    state.flapping = flapping;
+                    changed = true;
+                }
+            }
+        }
+
+        state.save(&mut tx).await?;
+        tx.commit().await?;
+
+        changed
+    };
+
+    if changed {
+        app.regenerate_pages.send(()).await?;
+        app.wake_outbox.send(()).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use chrono::Duration;
+
+    const THRESHOLDS: Thresholds = Thresholds {
+        failures_to_open: 3,
+        successes_to_close: 2,
+        min_incident_duration: Duration::zero(),
+        flap_window: Duration::zero(),
+        flap_threshold: 0,
+    };
+
+    async fn probe(ctx: &AppContext, service_id: i64, success: bool, thresholds: &Thresholds) {
+        report(
+            ctx,
+            service_id,
+            MonitorSource::Probe,
+            success,
+            Severity::FullOutage,
+            "connection refused",
+            thresholds,
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn interventions(ctx: &AppContext) -> Vec<Intervention> {
+        let mut conn = ctx.db_connection.lock().await;
+        Intervention::get_all(&mut conn).await.unwrap()
+    }
+
+    /// Move the open interventions and the recorded changes back in time.
+    async fn age(ctx: &AppContext, duration: Duration) {
+        let mut conn = ctx.db_connection.lock().await;
+        for mut intervention in Intervention::get_all(&mut conn).await.unwrap() {
+            intervention.start_date -= duration;
+            Intervention::update(&mut conn, intervention.id.unwrap(), &intervention)
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE monitor_changes SET date = date - $1")
+            .bind(duration.num_seconds())
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn interventions_follow_the_thresholds() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let service_id = testing::insert_service(ctx, "Forge", "https://forge.example.org").await;
+
+        // A few failures don't open anything.
+        probe(ctx, service_id, false, &THRESHOLDS).await;
+        probe(ctx, service_id, false, &THRESHOLDS).await;
+        probe(ctx, service_id, true, &THRESHOLDS).await;
+        probe(ctx, service_id, false, &THRESHOLDS).await;
+        probe(ctx, service_id, false, &THRESHOLDS).await;
+        assert!(interventions(ctx).await.is_empty());
+
+        probe(ctx, service_id, false, &THRESHOLDS).await;
+        let opened = interventions(ctx).await;
+        assert_eq!(opened.len(), 1);
+        assert_eq!(opened[0].status, Status::Ongoing);
+        assert_eq!(opened[0].severity, Severity::FullOutage);
+        assert_eq!(opened[0].title, "Forge ne répond plus");
+
+        // Nor does a single success close it.
+        probe(ctx, service_id, true, &THRESHOLDS).await;
+        probe(ctx, service_id, false, &THRESHOLDS).await;
+        probe(ctx, service_id, true, &THRESHOLDS).await;
+        assert_eq!(interventions(ctx).await[0].status, Status::Ongoing);
+
+        probe(ctx, service_id, true, &THRESHOLDS).await;
+        let closed = interventions(ctx).await;
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].status, Status::Resolved);
+    }
+
+    #[tokio::test]
+    async fn interventions_last_the_minimum_duration() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let service_id = testing::insert_service(ctx, "Forge", "https://forge.example.org").await;
+        let thresholds = Thresholds {
+            min_incident_duration: Duration::minutes(10),
+            ..Thresholds::immediate()
+        };
+
+        // A service back up right away doesn't resolve the outage, to avoid a series of short
+        // ones.
+        probe(ctx, service_id, false, &thresholds).await;
+        probe(ctx, service_id, true, &thresholds).await;
+        probe(ctx, service_id, false, &thresholds).await;
+        probe(ctx, service_id, true, &thresholds).await;
+        let opened = interventions(ctx).await;
+        assert_eq!(opened.len(), 1);
+        assert_eq!(opened[0].status, Status::Ongoing);
+
+        age(ctx, Duration::minutes(11)).await;
+        probe(ctx, service_id, true, &thresholds).await;
+        let closed = interventions(ctx).await;
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].status, Status::Resolved);
+    }
+
+    #[tokio::test]
+    async fn flapping_opens_a_performance_issue() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let service_id = testing::insert_service(ctx, "Forge", "https://forge.example.org").await;
+        let thresholds = Thresholds {
+            flap_window: Duration::minutes(30),
+            flap_threshold: 4,
+            ..THRESHOLDS
+        };
+
+        // Never enough failures in a row for an outage, but too many changes.
+        for success in [false, true, false, true] {
+            probe(ctx, service_id, success, &thresholds).await;
+        }
+        assert!(interventions(ctx).await.is_empty());
+        probe(ctx, service_id, false, &thresholds).await;
+        let opened = interventions(ctx).await;
+        assert_eq!(opened.len(), 1);
+        assert_eq!(opened[0].severity, Severity::PerformanceIssue);
+        assert_eq!(opened[0].title, "Instabilité détectée sur Forge");
+
+        // Successes don't resolve it while the service flaps…
+        probe(ctx, service_id, true, &thresholds).await;
+        probe(ctx, service_id, true, &thresholds).await;
+        assert_eq!(interventions(ctx).await[0].status, Status::Ongoing);
+
+        // …but once it has been stable over the window.
+        age(ctx, Duration::minutes(31)).await;
+        probe(ctx, service_id, true, &thresholds).await;
+        let closed = interventions(ctx).await;
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].status, Status::Resolved);
+        assert_eq!(closed[0].severity, Severity::PerformanceIssue);
+    }
+
+    #[tokio::test]
+    async fn flapping_escalates_to_an_outage() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let service_id = testing::insert_service(ctx, "Forge", "https://forge.example.org").await;
+        let thresholds = Thresholds {
+            flap_window: Duration::minutes(30),
+            flap_threshold: 4,
+            ..THRESHOLDS
+        };
+
+        for success in [false, true, false, true, false] {
+            probe(ctx, service_id, success, &thresholds).await;
+        }
+        let opened = interventions(ctx).await;
+        assert_eq!(opened[0].severity, Severity::PerformanceIssue);
+
+        // Failing steadily while the changes are still recent isn't enough…
+        probe(ctx, service_id, false, &thresholds).await;
+        probe(ctx, service_id, false, &thresholds).await;
+        assert_eq!(
+            interventions(ctx).await[0].severity,
+            Severity::PerformanceIssue
+        );
+
+        // …the same intervention becomes an outage once the service stopped flapping.
+        age(ctx, Duration::minutes(31)).await;
+        probe(ctx, service_id, false, &thresholds).await;
+        let escalated = interventions(ctx).await;
+        assert_eq!(escalated.len(), 1);
+        assert_eq!(escalated[0].id, opened[0].id);
+        assert_eq!(escalated[0].status, Status::Ongoing);
+        assert_eq!(escalated[0].severity, Severity::FullOutage);
+
+        // It's then resolved like any outage.
+        probe(ctx, service_id, true, &thresholds).await;
+        assert_eq!(interventions(ctx).await[0].status, Status::Ongoing);
+        probe(ctx, service_id, true, &thresholds).await;
+        assert_eq!(interventions(ctx).await[0].status, Status::Resolved);
+    }
+}
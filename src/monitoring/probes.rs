@@ -0,0 +1,544 @@
+//! Periodic health checks of the services.
+
+use super::{certificates, Thresholds};
+use crate::{
+    db::models::{
+        interventions::Severity,
+        monitors::MonitorSource,
+        probes::{DnsCheck, HttpCheck, Probe, ProbeCheck, ProbeResult, TcpCheck, TlsCheck},
+        services::Service,
+    },
+    AppContext,
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::{net::TcpStream, sync::mpsc};
+use tokio_rustls::{
+    rustls::{self, pki_types::ServerName},
+    TlsConnector,
+};
+use tracing as log;
+
+/// How long to wait before trying again, after failing to read the probes.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How long to sleep when there's no probe. Anything that could change this will wake up the
+/// prober anyways.
+const IDLE_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Outcome of a single check.
+pub(crate) struct Outcome {
+    pub success: bool,
+    /// How bad a failure is.
+    pub severity: Severity,
+    pub response_time: Option<Duration>,
+    pub message: String,
+}
+
+impl Outcome {
+    fn success(response_time: Duration, message: String) -> Self {
+        Self {
+            success: true,
+            severity: Severity::FullOutage,
+            response_time: Some(response_time),
+            message,
+        }
+    }
+
+    fn failure(message: String) -> Self {
+        Self {
+            success: false,
+            severity: Severity::FullOutage,
+            response_time: None,
+            message,
+        }
+    }
+}
+
+async fn check_http(
+    app: &AppContext,
+    check: &HttpCheck,
+    service: &Service,
+    timeout: Duration,
+) -> Outcome {
+    let url = check.url.as_deref().unwrap_or(&service.url);
+
+    let start = Instant::now();
+    let response = match app.http_client.get(url).timeout(timeout).send().await {
+        Ok(response) => response,
+        Err(err) => return Outcome::failure(format!("{err:#}")),
+    };
+
+    let status = response.status();
+    if status.as_u16() != check.expected_status {
+        return Outcome {
+            response_time: Some(start.elapsed()),
+            ..Outcome::failure(format!("unexpected status {status}"))
+        };
+    }
+
+    if let Some(needle) = &check.body_match {
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(err) => return Outcome::failure(format!("unable to read the body: {err:#}")),
+        };
+        if !body.contains(needle.as_str()) {
+            return Outcome {
+                response_time: Some(start.elapsed()),
+                ..Outcome::failure(format!("the body doesn't contain {needle:?}"))
+            };
+        }
+    }
+
+    Outcome::success(start.elapsed(), format!("status {status}"))
+}
+
+async fn check_tcp(check: &TcpCheck, timeout: Duration) -> Outcome {
+    let start = Instant::now();
+    let connect = TcpStream::connect((check.host.as_str(), check.port));
+    match tokio::time::timeout(timeout, connect).await {
+        Ok(Ok(_)) => Outcome::success(start.elapsed(), "connected".to_owned()),
+        Ok(Err(err)) => Outcome::failure(format!("unable to connect: {err}")),
+        Err(_) => Outcome::failure("timed out".to_owned()),
+    }
+}
+
+/// TLS connector trusting the usual web certificate authorities.
+fn tls_connector() -> &'static TlsConnector {
+    static CONNECTOR: OnceLock<TlsConnector> = OnceLock::new();
+    CONNECTOR.get_or_init(|| {
+        let roots = rustls::RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .expect("the default protocol versions are supported")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        TlsConnector::from(Arc::new(config))
+    })
+}
+
+async fn check_tls(check: &TlsCheck, timeout: Duration) -> Outcome {
+    let start = Instant::now();
+
+    let handshake = async {
+        let server_name = ServerName::try_from(check.host.clone())?;
+        let stream = TcpStream::connect((check.host.as_str(), check.port)).await?;
+        let stream = tls_connector().connect(server_name, stream).await?;
+        anyhow::Ok(stream)
+    };
+    let stream = match tokio::time::timeout(timeout, handshake).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(err)) => return Outcome::failure(format!("TLS handshake failed: {err:#}")),
+        Err(_) => return Outcome::failure("timed out".to_owned()),
+    };
+    let response_time = start.elapsed();
+
+    let not_after = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(|cert| certificates::not_after(cert));
+    let Some(not_after) = not_after else {
+        return Outcome::failure("unable to read the expiry date of the certificate".to_owned());
+    };
+
+    // Expired certificates don't even get through the handshake.
+    let days_left = (not_after - chrono::Utc::now().naive_utc()).num_days();
+    let message = format!("the certificate expires in {days_left} days, on {not_after}");
+    if days_left < check.expiry_warning_days {
+        return Outcome {
+            severity: Severity::PerformanceIssue,
+            response_time: Some(response_time),
+            ..Outcome::failure(message)
+        };
+    }
+
+    Outcome::success(response_time, message)
+}
+
+async fn check_dns(check: &DnsCheck, timeout: Duration) -> Outcome {
+    let start = Instant::now();
+    let lookup = tokio::net::lookup_host((check.name.as_str(), 0));
+    let addresses: Vec<IpAddr> = match tokio::time::timeout(timeout, lookup).await {
+        Ok(Ok(addresses)) => addresses.map(|address| address.ip()).collect(),
+        Ok(Err(err)) => return Outcome::failure(format!("unable to resolve: {err}")),
+        Err(_) => return Outcome::failure("timed out".to_owned()),
+    };
+    let response_time = start.elapsed();
+
+    let resolved = addresses
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    if let Some(expected) = &check.expected_address {
+        let found = expected
+            .parse::<IpAddr>()
+            .is_ok_and(|expected| addresses.contains(&expected));
+        if !found {
+            return Outcome {
+                response_time: Some(response_time),
+                ..Outcome::failure(format!("resolved to {resolved}, not to {expected}"))
+            };
+        }
+    }
+
+    Outcome::success(response_time, format!("resolved to {resolved}"))
+}
+
+/// Run the check of a probe once, without recording anything.
+pub(crate) async fn check(app: &AppContext, probe: &Probe, service: &Service) -> Outcome {
+    let timeout = Duration::from_secs(probe.timeout_secs as u64);
+    match &probe.check {
+        ProbeCheck::Http(check) => check_http(app, check, service, timeout).await,
+        ProbeCheck::Tcp(check) => check_tcp(check, timeout).await,
+        ProbeCheck::Tls(check) => check_tls(check, timeout).await,
+        ProbeCheck::Dns(check) => check_dns(check, timeout).await,
+    }
+}
+
+/// When the probe of a service opens and resolves interventions.
+fn thresholds(probe: &Probe) -> Thresholds {
+    Thresholds {
+        failures_to_open: probe.failure_threshold,
+        successes_to_close: probe.success_threshold,
+        min_incident_duration: chrono::Duration::seconds(probe.min_incident_secs),
+        flap_window: chrono::Duration::seconds(probe.flap_window_secs),
+        flap_threshold: probe.flap_threshold,
+    }
+}
+
+/// Run the check of a probe, record its result and open or resolve an intervention if needed.
+async fn run_probe(app: &AppContext, probe: &Probe) -> anyhow::Result<()> {
+    let service = {
+        let mut conn = app.db_connection.lock().await;
+        Service::by_id(probe.service_id, &mut conn).await?
+    };
+    let Some(service) = service else {
+        return Ok(());
+    };
+
+    let outcome = check(app, probe, &service).await;
+
+    {
+        let mut conn = app.db_connection.lock().await;
+        ProbeResult::insert(
+            &mut conn,
+            &ProbeResult {
+                service_id: probe.service_id,
+                date: chrono::Utc::now().naive_utc(),
+                success: outcome.success,
+                response_time_ms: outcome.response_time.map(|d| d.as_millis() as i64),
+                message: Some(outcome.message.clone()),
+            },
+        )
+        .await?;
+    }
+
+    super::report(
+        app,
+        probe.service_id,
+        MonitorSource::Probe,
+        outcome.success,
+        outcome.severity,
+        &outcome.message,
+        &thresholds(probe),
+    )
+    .await
+}
+
+/// Background task running the enabled probes, each at its own interval.
+///
+/// Checks run concurrently, so a slow service doesn't delay the others. A message on the receiver
+/// makes the task reload the probes, so it takes new or modified ones into account.
+pub(crate) async fn run(app: Arc<AppContext>, mut receiver: mpsc::Receiver<()>) {
+    // When each probe should run next, by probe id.
+    let mut next_runs: HashMap<i64, Instant> = HashMap::new();
+
+    loop {
+        let probes = {
+            let mut conn = app.db_connection.lock().await;
+            Probe::get_enabled(&mut conn).await
+        };
+
+        let delay = match probes {
+            Ok(probes) => {
+                next_runs.retain(|id, _| probes.iter().any(|p| p.id == Some(*id)));
+
+                let now = Instant::now();
+                let mut delay = IDLE_DELAY;
+
+                for probe in probes {
+                    let id = probe.id.unwrap();
+                    let interval = Duration::from_secs(probe.interval_secs as u64);
+
+                    // The interval may have been shortened in the meanwhile.
+                    let next_run = next_runs
+                        .get(&id)
+                        .map_or(now, |next_run| (*next_run).min(now + interval));
+
+                    if next_run > now {
+                        next_runs.insert(id, next_run);
+                        delay = delay.min(next_run - now);
+                        continue;
+                    }
+
+                    next_runs.insert(id, now + interval);
+                    delay = delay.min(interval);
+
+                    let app = app.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = run_probe(&app, &probe).await {
+                            log::error!(
+                                "unable to run the probe of service {}: {err:#}",
+                                probe.service_id
+                            );
+                        }
+                    });
+                }
+
+                delay
+            }
+
+            Err(err) => {
+                log::error!("Unable to read the probes: {err:#}");
+                RETRY_DELAY
+            }
+        };
+
+        tokio::select! {
+            received = receiver.recv() => {
+                if received.is_none() {
+                    // okthxbye
+                    break;
+                }
+            }
+
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::models::interventions::{Intervention, Status},
+        testing,
+    };
+    use axum::{http::StatusCode, routing::get, Router};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Start a local HTTP server, whose `/health` endpoint works as long as the flag is set;
+    /// returns its URL.
+    fn local_server(healthy: Arc<AtomicBool>) -> String {
+        let router = Router::new()
+            .route("/ok", get(|| async { "all good" }))
+            .route(
+                "/error",
+                get(|| async { (StatusCode::SERVICE_UNAVAILABLE, "oops") }),
+            )
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    "finally"
+                }),
+            )
+            .route(
+                "/health",
+                get(move || async move {
+                    if healthy.load(Ordering::SeqCst) {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    }
+                }),
+            );
+        testing::serve(router)
+    }
+
+    fn http_probe(
+        service_id: i64,
+        url: Option<String>,
+        expected_status: u16,
+        body_match: Option<&str>,
+    ) -> Probe {
+        Probe {
+            id: None,
+            service_id,
+            enabled: true,
+            interval_secs: 60,
+            timeout_secs: 1,
+            failure_threshold: 3,
+            success_threshold: 2,
+            min_incident_secs: 0,
+            flap_window_secs: 0,
+            flap_threshold: 0,
+            check: ProbeCheck::Http(HttpCheck {
+                url,
+                expected_status,
+                body_match: body_match.map(str::to_owned),
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn http_check() {
+        let url = local_server(Arc::new(AtomicBool::new(true)));
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let service_id = testing::insert_service(ctx, "Forge", &format!("{url}/ok")).await;
+        let service = {
+            let mut conn = ctx.db_connection.lock().await;
+            Service::by_id(service_id, &mut conn)
+                .await
+                .unwrap()
+                .unwrap()
+        };
+
+        let run = |url: Option<String>, expected_status, body_match| {
+            let probe = http_probe(service_id, url, expected_status, body_match);
+            let service = &service;
+            async move { check(ctx, &probe, service).await }
+        };
+
+        // The service's URL is checked, unless the probe has its own.
+        let outcome = run(None, 200, None).await;
+        assert!(outcome.success, "{}", outcome.message);
+        assert!(outcome.response_time.is_some());
+
+        // The status code must be the expected one.
+        let outcome = run(None, 204, None).await;
+        assert!(!outcome.success);
+        assert_eq!(outcome.message, "unexpected status 200 OK");
+        let outcome = run(Some(format!("{url}/error")), 200, None).await;
+        assert!(!outcome.success);
+        assert_eq!(outcome.message, "unexpected status 503 Service Unavailable");
+        let outcome = run(Some(format!("{url}/error")), 503, None).await;
+        assert!(outcome.success, "{}", outcome.message);
+
+        // So must the body, if required.
+        let outcome = run(None, 200, Some("good")).await;
+        assert!(outcome.success, "{}", outcome.message);
+        let outcome = run(None, 200, Some("bad")).await;
+        assert!(!outcome.success);
+        assert_eq!(outcome.message, "the body doesn't contain \"bad\"");
+
+        // Slow responses time out.
+        let start = Instant::now();
+        let outcome = run(Some(format!("{url}/slow")), 200, None).await;
+        assert!(!outcome.success);
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn tcp_check() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let check = TcpCheck {
+            host: "127.0.0.1".to_owned(),
+            port,
+        };
+
+        let outcome = check_tcp(&check, Duration::from_secs(1)).await;
+        assert!(outcome.success, "{}", outcome.message);
+        assert!(outcome.response_time.is_some());
+
+        // Nothing listens on the port anymore.
+        drop(listener);
+        let outcome = check_tcp(&check, Duration::from_secs(1)).await;
+        assert!(!outcome.success);
+        assert!(
+            outcome.message.starts_with("unable to connect"),
+            "{}",
+            outcome.message
+        );
+    }
+
+    #[tokio::test]
+    async fn dns_check() {
+        let check = |name: &str, expected_address: Option<&str>| DnsCheck {
+            name: name.to_owned(),
+            expected_address: expected_address.map(str::to_owned),
+        };
+        let timeout = Duration::from_secs(1);
+
+        let outcome = check_dns(&check("localhost", None), timeout).await;
+        assert!(outcome.success, "{}", outcome.message);
+        let outcome = check_dns(&check("localhost", Some("127.0.0.1")), timeout).await;
+        assert!(outcome.success, "{}", outcome.message);
+        assert!(outcome.message.contains("127.0.0.1"), "{}", outcome.message);
+
+        // The name must resolve to the expected address.
+        let outcome = check_dns(&check("localhost", Some("192.0.2.1")), timeout).await;
+        assert!(!outcome.success);
+        assert!(
+            outcome.message.ends_with(", not to 192.0.2.1"),
+            "{}",
+            outcome.message
+        );
+        assert!(outcome.response_time.is_some());
+
+        // Names of the reserved `.invalid` domain never resolve.
+        let outcome = check_dns(&check("status.invalid", None), timeout).await;
+        assert!(!outcome.success);
+    }
+
+    #[tokio::test]
+    async fn interventions_follow_thresholds() {
+        let healthy = Arc::new(AtomicBool::new(false));
+        let url = local_server(healthy.clone());
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let service_id = testing::insert_service(ctx, "Forge", &format!("{url}/health")).await;
+        let probe = http_probe(service_id, None, 200, None);
+
+        let interventions = || async {
+            let mut conn = ctx.db_connection.lock().await;
+            Intervention::get_all(&mut conn).await.unwrap()
+        };
+
+        // An intervention is opened after 3 consecutive failures.
+        for _ in 0..2 {
+            run_probe(ctx, &probe).await.unwrap();
+        }
+        assert!(interventions().await.is_empty());
+        run_probe(ctx, &probe).await.unwrap();
+        let opened = interventions().await;
+        assert_eq!(opened.len(), 1);
+        assert_eq!(opened[0].status, Status::Ongoing);
+        assert_eq!(opened[0].severity, Severity::FullOutage);
+        assert_eq!(opened[0].title, "Forge ne répond plus");
+
+        // It's resolved after 2 consecutive successes, and a failure in between starts over.
+        healthy.store(true, Ordering::SeqCst);
+        run_probe(ctx, &probe).await.unwrap();
+        healthy.store(false, Ordering::SeqCst);
+        run_probe(ctx, &probe).await.unwrap();
+        healthy.store(true, Ordering::SeqCst);
+        run_probe(ctx, &probe).await.unwrap();
+        assert_eq!(interventions().await[0].status, Status::Ongoing);
+        run_probe(ctx, &probe).await.unwrap();
+        let resolved = interventions().await;
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].status, Status::Resolved);
+        assert!(resolved[0].end_date.is_some());
+
+        // Every result is recorded.
+        let mut conn = ctx.db_connection.lock().await;
+        let results = ProbeResult::get_latest(service_id, 10, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 7);
+    }
+}
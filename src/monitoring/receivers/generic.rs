@@ -0,0 +1,81 @@
+//! A simple JSON format, for the systems which can send custom webhooks.
+//!
+//! The payload is a single alert or an array of alerts, like:
+//!
+//! ```json
+//! {
+//!     "fingerprint": "disk-full-db1",
+//!     "status": "firing",
+//!     "service": "Database",
+//!     "severity": "critical",
+//!     "title": "Disk full on db1",
+//!     "description": "Less than 1% of free space left.",
+//!     "labels": { "host": "db1" }
+//! }
+//! ```
+//!
+//! Only the fingerprint is required; the status defaults to `firing`.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use super::{PayloadParser, ReceivedAlert};
+
+pub(super) struct Generic;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Payload {
+    One(PayloadAlert),
+    Many(Vec<PayloadAlert>),
+}
+
+#[derive(Deserialize)]
+struct PayloadAlert {
+    fingerprint: String,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    service: Option<String>,
+    #[serde(default)]
+    severity: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+}
+
+impl PayloadParser for Generic {
+    fn parse(&self, body: &[u8]) -> anyhow::Result<Vec<ReceivedAlert>> {
+        let alerts = match serde_json::from_slice(body)? {
+            Payload::One(alert) => vec![alert],
+            Payload::Many(alerts) => alerts,
+        };
+
+        Ok(alerts
+            .into_iter()
+            .map(|alert| {
+                let mut labels = alert.labels;
+                if let Some(service) = alert.service {
+                    labels.insert("service".to_owned(), service);
+                }
+                if let Some(severity) = alert.severity {
+                    labels.insert("severity".to_owned(), severity);
+                }
+                labels
+                    .entry("alertname".to_owned())
+                    .or_insert_with(|| alert.fingerprint.clone());
+                ReceivedAlert {
+                    firing: alert.status.as_deref() != Some("resolved"),
+                    fingerprint: alert.fingerprint,
+                    labels,
+                    title: alert.title,
+                    description: alert.description,
+                }
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,45 @@
+//! Payloads of Grafana's webhook contact points.
+//!
+//! See https://grafana.com/docs/grafana/latest/alerting/configure-notifications/manage-contact-points/integrations/webhook-notifier/
+//! for the format, which is close to Alertmanager's.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use super::{PayloadParser, ReceivedAlert};
+
+pub(super) struct Grafana;
+
+#[derive(Deserialize)]
+struct Payload {
+    #[serde(default)]
+    alerts: Vec<PayloadAlert>,
+}
+
+#[derive(Deserialize)]
+struct PayloadAlert {
+    status: String,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+    fingerprint: String,
+}
+
+impl PayloadParser for Grafana {
+    fn parse(&self, body: &[u8]) -> anyhow::Result<Vec<ReceivedAlert>> {
+        let payload: Payload = serde_json::from_slice(body)?;
+        Ok(payload
+            .alerts
+            .into_iter()
+            .map(|mut alert| ReceivedAlert {
+                fingerprint: alert.fingerprint,
+                firing: alert.status == "firing",
+                title: alert.annotations.remove("summary"),
+                description: alert.annotations.remove("description"),
+                labels: alert.labels,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,142 @@
+//! Generic receiver for the alerts of external monitoring systems.
+//!
+//! Each system has its own payload format, turned into [`ReceivedAlert`]s by a
+//! [`PayloadParser`]; alerts are then sent to a service and a severity by the [`AlertRule`]s, and
+//! go through [`alerts::handle`] like the Alertmanager ones.
+
+use std::collections::BTreeMap;
+
+use sqlx::AnyConnection;
+use tracing as log;
+
+use super::alerts::{self, InboundAlert};
+use crate::db::models::{alert_rules::AlertRule, services::Service};
+
+mod generic;
+mod grafana;
+mod uptime_kuma;
+
+/// An alert, as read from a payload, before it's mapped to a service.
+#[derive(Clone, Debug)]
+pub(crate) struct ReceivedAlert {
+    /// Identifies the alert within its source, across its notifications.
+    pub fingerprint: String,
+    /// False once the alert is resolved.
+    pub firing: bool,
+    /// Labels of the alert, which the rules match; parsers fill `alertname`, and `service` and
+    /// `severity` when the payload tells them.
+    pub labels: BTreeMap<String, String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Parser of the payloads sent by a monitoring system.
+pub(crate) trait PayloadParser: Sync {
+    /// Read the alerts of a payload; a payload may carry no alert at all (e.g. a test message).
+    fn parse(&self, body: &[u8]) -> anyhow::Result<Vec<ReceivedAlert>>;
+}
+
+/// Sources of the alerts, as used in the URL of the receiver, with their parsers.
+const PARSERS: &[(&str, &dyn PayloadParser)] = &[
+    ("grafana", &grafana::Grafana),
+    ("uptime-kuma", &uptime_kuma::UptimeKuma),
+    ("generic", &generic::Generic),
+];
+
+/// Names of the sources which have a parser.
+pub(crate) fn sources() -> impl Iterator<Item = &'static str> {
+    PARSERS.iter().map(|(source, _)| *source)
+}
+
+/// Parser for the payloads of the given source, if it's supported.
+pub(crate) fn parser(source: &str) -> Option<&'static dyn PayloadParser> {
+    PARSERS
+        .iter()
+        .find(|(name, _)| *name == source)
+        .map(|(_, parser)| *parser)
+}
+
+/// Send an alert to a service and a severity: the first matching rule decides, and if there's
+/// none, the `service` label designates the service.
+///
+/// Returns nothing if the alert can't be mapped to any service.
+async fn map(
+    source: &str,
+    alert: ReceivedAlert,
+    rules: &[AlertRule],
+    conn: &mut AnyConnection,
+) -> anyhow::Result<Option<InboundAlert>> {
+    let payload_severity = alert.labels.get("severity").map(String::as_str);
+
+    let (service, severity) = match rules
+        .iter()
+        .find(|rule| rule.matches(source, &alert.labels))
+    {
+        Some(rule) => {
+            let Some(service) = Service::by_id(rule.service_id, conn).await? else {
+                return Ok(None);
+            };
+            let severity = rule
+                .severity
+                .unwrap_or_else(|| alerts::parse_severity(payload_severity));
+            (service, severity)
+        }
+        None => {
+            let Some(service_name) = alert.labels.get("service") else {
+                log::warn!(
+                    "skipping {source} alert {} matching no rule, without a service label",
+                    alert.fingerprint
+                );
+                return Ok(None);
+            };
+            let Some(service) = alerts::find_service(service_name, conn).await? else {
+                log::warn!(
+                    "skipping {source} alert {} about unknown service {service_name}",
+                    alert.fingerprint
+                );
+                return Ok(None);
+            };
+            (service, alerts::parse_severity(payload_severity))
+        }
+    };
+
+    let alert_name = alert
+        .labels
+        .get("alertname")
+        .map_or("alerte", String::as_str);
+
+    // TODO i18n
+    let title = alert
+        .title
+        .clone()
+        .unwrap_or_else(|| format!("{alert_name} sur {}", service.name));
+    let description = alert
+        .description
+        .clone()
+        .unwrap_or_else(|| format!("Alerte {alert_name} déclenchée automatiquement."));
+
+    Ok(Some(InboundAlert {
+        fingerprint: alert.fingerprint,
+        service_id: service.id.unwrap(),
+        severity,
+        firing: alert.firing,
+        title,
+        description,
+    }))
+}
+
+/// Map all the alerts of a payload; alerts which can't be mapped to a service are skipped.
+pub(crate) async fn map_all(
+    source: &str,
+    received: Vec<ReceivedAlert>,
+    conn: &mut AnyConnection,
+) -> anyhow::Result<Vec<InboundAlert>> {
+    let rules = AlertRule::get_all(conn).await?;
+    let mut alerts = Vec::with_capacity(received.len());
+    for alert in received {
+        if let Some(alert) = map(source, alert, &rules, conn).await? {
+            alerts.push(alert);
+        }
+    }
+    Ok(alerts)
+}
@@ -0,0 +1,73 @@
+//! Payloads of Uptime Kuma's webhook notifications.
+//!
+//! Uptime Kuma notifies each change of its monitors: the monitor is the alert, which fires while
+//! it's down.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use super::{PayloadParser, ReceivedAlert};
+
+pub(super) struct UptimeKuma;
+
+/// Status of a heartbeat, when the monitor is down.
+const DOWN: i64 = 0;
+
+/// Status of a heartbeat, when the monitor is up.
+const UP: i64 = 1;
+
+#[derive(Deserialize)]
+struct Payload {
+    /// Missing from test notifications.
+    heartbeat: Option<Heartbeat>,
+    monitor: Option<Monitor>,
+}
+
+#[derive(Deserialize)]
+struct Heartbeat {
+    status: i64,
+    #[serde(default)]
+    msg: String,
+}
+
+#[derive(Deserialize)]
+struct Monitor {
+    id: i64,
+    name: String,
+    url: Option<String>,
+}
+
+impl PayloadParser for UptimeKuma {
+    fn parse(&self, body: &[u8]) -> anyhow::Result<Vec<ReceivedAlert>> {
+        let payload: Payload = serde_json::from_slice(body)?;
+        let (Some(heartbeat), Some(monitor)) = (payload.heartbeat, payload.monitor) else {
+            return Ok(Vec::new());
+        };
+
+        // Pending and maintenance heartbeats don't change anything.
+        let firing = match heartbeat.status {
+            DOWN => true,
+            UP => false,
+            _ => return Ok(Vec::new()),
+        };
+
+        let mut labels = BTreeMap::new();
+        labels.insert("alertname".to_owned(), monitor.name.clone());
+        labels.insert("service".to_owned(), monitor.name.clone());
+        labels.insert("monitor".to_owned(), monitor.name.clone());
+        labels.insert("monitor_id".to_owned(), monitor.id.to_string());
+        if let Some(url) = monitor.url {
+            labels.insert("url".to_owned(), url);
+        }
+
+        // TODO i18n
+        Ok(vec![ReceivedAlert {
+            fingerprint: monitor.id.to_string(),
+            firing,
+            labels,
+            title: Some(format!("{} est indisponible", monitor.name)),
+            description: (!heartbeat.msg.is_empty()).then_some(heartbeat.msg),
+        }])
+    }
+}
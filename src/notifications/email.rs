@@ -0,0 +1,418 @@
+//! Email notifications, sent over SMTP to the confirmed subscribers.
+
+use super::{is_wanted, render, Event, InterventionDetails, Notification};
+use crate::{
+    db::models::{
+        preferences::{Preferences, SubscriptionKind},
+        subscribers::Subscriber,
+    },
+    tokens, AppContext,
+};
+use anyhow::Context as _;
+use lettre::{
+    message::{
+        header::{ContentType, Header, HeaderName, HeaderValue},
+        Mailbox,
+    },
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport as _, Message, Tokio1Executor,
+};
+use sqlx::AnyConnection;
+use std::str::FromStr;
+
+/// Name of the channel, as used for the templates.
+const CHANNEL: &str = "email";
+
+/// How to secure the connection to the SMTP server.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum SmtpTls {
+    /// No encryption at all. Only use this for local testing, e.g. with a local SMTP sink.
+    None,
+    /// Upgrade the connection with STARTTLS.
+    StartTls,
+    /// Implicit TLS, aka SMTPS.
+    Tls,
+}
+
+impl FromStr for SmtpTls {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "none" => Self::None,
+            "starttls" => Self::StartTls,
+            "tls" => Self::Tls,
+            _ => anyhow::bail!("unexpected value for SMTP TLS mode: {s}"),
+        })
+    }
+}
+
+/// Configuration of the SMTP server used to send emails.
+pub(crate) struct SmtpConfig {
+    pub host: String,
+    /// Defaults to the standard port for the TLS mode.
+    pub port: Option<u16>,
+    pub tls: SmtpTls,
+    /// Username and password, if the server requires authentication.
+    pub credentials: Option<(String, String)>,
+    /// Sender of all the emails.
+    pub from: Mailbox,
+}
+
+/// The `List-Unsubscribe` header (RFC 2369), so that mail clients can show an unsubscribe button.
+#[derive(Clone)]
+struct ListUnsubscribe(String);
+
+impl Header for ListUnsubscribe {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe")
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(s.to_owned()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.clone())
+    }
+}
+
+/// The `List-Unsubscribe-Post` header (RFC 8058), indicating the unsubscribe link works with a
+/// single POST request.
+#[derive(Clone)]
+struct ListUnsubscribePost;
+
+impl Header for ListUnsubscribePost {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe-Post")
+    }
+
+    fn parse(_s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self)
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), "List-Unsubscribe=One-Click".to_owned())
+    }
+}
+
+/// Sends emails through the configured SMTP server.
+pub(crate) struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    pub fn new(config: &SmtpConfig) -> anyhow::Result<Self> {
+        let mut builder = match config.tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+        };
+
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+
+        if let Some((username, password)) = &config.credentials {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from: config.from.clone(),
+        })
+    }
+
+    /// Send a plain text email.
+    ///
+    /// If an unsubscribe URL is given, it's advertised in the headers, so that mail clients can
+    /// offer a one-click unsubscribe button.
+    async fn send(
+        &self,
+        to: &str,
+        subject: String,
+        body: String,
+        unsubscribe_url: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse().context("invalid recipient address")?)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN);
+
+        if let Some(url) = unsubscribe_url {
+            builder = builder
+                .header(ListUnsubscribe(format!("<{url}>")))
+                .header(ListUnsubscribePost);
+        }
+
+        let message = builder.body(body)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+fn confirmation_url(app: &AppContext, subscriber: &Subscriber) -> String {
+    format!(
+        "{}/subscribe/confirm?token={}",
+        app.config.public_url, subscriber.token
+    )
+}
+
+fn unsubscribe_url(app: &AppContext, subscriber: &Subscriber) -> String {
+    format!(
+        "{}/unsubscribe?token={}",
+        app.config.public_url, subscriber.token
+    )
+}
+
+/// Message signed in the links to the preferences page of a subscriber.
+///
+/// Ids are reused after an unsubscription, so the message includes the random token of the
+/// subscriber: the links of a former subscriber don't work for the next one with the same id.
+fn preferences_message(subscriber: &Subscriber) -> String {
+    format!(
+        "preferences:{}:{}",
+        subscriber.id.unwrap(),
+        subscriber.token
+    )
+}
+
+/// Signature of the links to the preferences page of a subscriber.
+pub(crate) fn preferences_signature(app: &AppContext, subscriber: &Subscriber) -> String {
+    tokens::sign(
+        &app.config.secret_key,
+        preferences_message(subscriber).as_bytes(),
+    )
+}
+
+/// Signed link to the preferences page of a subscriber, so they can change them without an account.
+fn preferences_url(app: &AppContext, subscriber: &Subscriber) -> String {
+    format!(
+        "{}/subscription/preferences?id={}&signature={}",
+        app.config.public_url,
+        subscriber.id.unwrap(),
+        preferences_signature(app, subscriber)
+    )
+}
+
+/// Is the signature of the link to the preferences page of a subscriber valid?
+pub(crate) fn check_preferences_signature(
+    app: &AppContext,
+    subscriber: &Subscriber,
+    signature: &str,
+) -> bool {
+    tokens::verify(
+        &app.config.secret_key,
+        preferences_message(subscriber).as_bytes(),
+        signature,
+    )
+}
+
+/// Send the email asking a new subscriber to confirm their address.
+pub(crate) async fn send_confirmation(
+    app: &AppContext,
+    mailer: &Mailer,
+    subscriber: &Subscriber,
+) -> anyhow::Result<()> {
+    // TODO i18n
+    let body = format!(
+        "Bonjour,\n\
+        \n\
+        Quelqu'un (probablement vous) a demandé à recevoir par email les incidents des services \
+        Framasoft.\n\
+        \n\
+        Pour confirmer votre abonnement, ouvrez le lien suivant :\n\
+        {}\n\
+        \n\
+        Si vous n'êtes pas à l'origine de cette demande, ignorez simplement ce message.\n",
+        confirmation_url(app, subscriber)
+    );
+
+    mailer
+        .send(
+            &subscriber.email,
+            "Confirmez votre abonnement".to_owned(),
+            body,
+            None,
+        )
+        .await
+}
+
+/// Confirmed subscribers interested in the event.
+pub(super) async fn recipients(
+    conn: &mut AnyConnection,
+    event: Event,
+    details: &InterventionDetails,
+) -> anyhow::Result<Vec<i64>> {
+    let mut recipients = Vec::new();
+    for subscriber in Subscriber::get_confirmed(conn).await? {
+        let id = subscriber.id.unwrap();
+        let prefs = Preferences::read(SubscriptionKind::Subscriber, id, conn).await?;
+        if is_wanted(&prefs, event, Some(details)) {
+            recipients.push(id);
+        }
+    }
+    Ok(recipients)
+}
+
+/// Send an email about the event to a single subscriber.
+///
+/// Subscribers who unsubscribed in the meanwhile are silently skipped.
+pub(super) async fn deliver(
+    app: &AppContext,
+    mailer: &Mailer,
+    subscriber_id: i64,
+    notification: &Notification,
+) -> anyhow::Result<()> {
+    let subscriber = {
+        let mut conn = app.db_connection.lock().await;
+        Subscriber::by_id(subscriber_id, &mut conn).await?
+    };
+    let Some(subscriber) = subscriber.filter(|s| s.confirmed) else {
+        return Ok(());
+    };
+
+    let unsubscribe_url = unsubscribe_url(app, &subscriber);
+
+    let mut ctx = notification.ctx(app)?;
+    ctx.insert("preferences_url", &preferences_url(app, &subscriber));
+    ctx.insert("unsubscribe_url", &unsubscribe_url);
+
+    let event = notification.event;
+    // The subject must fit on a single line.
+    let subject = render(app, CHANNEL, event, "subject.txt", &ctx)?
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let body = render(app, CHANNEL, event, "body.txt", &ctx)?;
+
+    mailer
+        .send(&subscriber.email, subject, body, Some(&unsubscribe_url))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        controllers::subscriptions,
+        notifications::{self, outbox},
+        testing::{self, SmtpSink},
+    };
+    use axum::{extract::Query, http::StatusCode, response::IntoResponse as _, Extension, Form};
+
+    /// Value of the `token` parameter of the first link in the text.
+    fn token_in(text: &str) -> String {
+        let (_, rest) = text.split_once("token=").unwrap();
+        rest.chars()
+            .take_while(char::is_ascii_alphanumeric)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn subscription_flow() {
+        let sink = SmtpSink::start().await;
+        let mut config = testing::config();
+        config.smtp = Some(SmtpConfig {
+            host: "127.0.0.1".to_owned(),
+            port: Some(sink.port),
+            tls: SmtpTls::None,
+            credentials: None,
+            from: "status@example.org".parse().unwrap(),
+        });
+        let app = testing::app(config).await;
+        let ctx = app.ctx.clone();
+
+        let subscribe = || async {
+            subscriptions::subscribe(
+                Extension(ctx.clone()),
+                Form(serde_html_form::from_str("email=alice@example.org").unwrap()),
+            )
+            .await
+            .into_response()
+            .status()
+        };
+
+        // Subscribing sends a confirmation email.
+        assert_eq!(subscribe().await, StatusCode::OK);
+        let mails = sink.take();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].recipients, ["alice@example.org"]);
+        let body = mails[0].body();
+        assert!(body.contains("https://status.example.org/subscribe/confirm?token="));
+        let token = token_in(&body);
+
+        // Subscribing again right away answers the same, without sending another email.
+        assert_eq!(subscribe().await, StatusCode::OK);
+        assert!(sink.take().is_empty());
+
+        // Nothing is sent before the address is confirmed.
+        let intervention_id = testing::insert_intervention(&ctx, "Forge en panne").await;
+        {
+            let mut conn = ctx.db_connection.lock().await;
+            notifications::enqueue(&ctx, &mut conn, Event::InterventionCreated(intervention_id))
+                .await
+                .unwrap();
+        }
+        outbox::deliver_due(&ctx).await.unwrap();
+        assert!(sink.take().is_empty());
+
+        let status = subscriptions::confirm(
+            Extension(ctx.clone()),
+            Query(serde_html_form::from_str(&format!("token={token}")).unwrap()),
+        )
+        .await
+        .into_response()
+        .status();
+        assert_eq!(status, StatusCode::OK);
+
+        // Once confirmed, the subscriber is notified, with a link to unsubscribe.
+        {
+            let mut conn = ctx.db_connection.lock().await;
+            notifications::enqueue(&ctx, &mut conn, Event::InterventionUpdated(intervention_id))
+                .await
+                .unwrap();
+        }
+        outbox::deliver_due(&ctx).await.unwrap();
+        let mails = sink.take();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].recipients, ["alice@example.org"]);
+        assert!(mails[0].body().contains("Forge en panne"));
+        let unsubscribe_url = format!("<https://status.example.org/unsubscribe?token={token}>");
+        assert_eq!(
+            mails[0].header("List-Unsubscribe"),
+            Some(unsubscribe_url.as_str())
+        );
+        assert_eq!(
+            mails[0].header("List-Unsubscribe-Post"),
+            Some("List-Unsubscribe=One-Click")
+        );
+
+        let status = subscriptions::unsubscribe(
+            Extension(ctx.clone()),
+            Query(serde_html_form::from_str(&format!("token={token}")).unwrap()),
+        )
+        .await
+        .into_response()
+        .status();
+        assert_eq!(status, StatusCode::OK);
+
+        // Nothing is sent anymore.
+        {
+            let mut conn = ctx.db_connection.lock().await;
+            notifications::enqueue(
+                &ctx,
+                &mut conn,
+                Event::InterventionResolved(intervention_id),
+            )
+            .await
+            .unwrap();
+        }
+        outbox::deliver_due(&ctx).await.unwrap();
+        assert!(sink.take().is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+//! Mastodon notifications: statuses are posted through the client API of a Mastodon-compatible
+//! instance, and later updates are threaded as replies.
+
+use super::{render, Event, Notification};
+use crate::{db::models::remote_posts::RemotePost, AppContext};
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+
+/// Name of the channel, as stored along with the remote posts and used for the templates.
+const CHANNEL: &str = "mastodon";
+
+/// Maximum length of a status, on a default Mastodon instance; the templates should keep well
+/// within it, this is only a safety net.
+const MAX_LENGTH: usize = 500;
+
+/// Configuration of the Mastodon account posting the statuses.
+pub(crate) struct MastodonConfig {
+    /// Base URL of the instance, without a trailing slash.
+    pub url: String,
+    /// Access token of the account, with the `write:statuses` scope.
+    pub access_token: String,
+    /// Visibility of the statuses: `public`, `unlisted`, `private` or `direct`.
+    pub visibility: String,
+}
+
+#[derive(Serialize)]
+struct NewStatus<'a> {
+    status: &'a str,
+    visibility: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    in_reply_to_id: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct PostedStatus {
+    id: String,
+}
+
+/// Truncate the text to the given number of characters, marking it as such.
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_owned();
+    }
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Post a status, possibly as a reply to another one; returns the id of the new status.
+///
+/// The instance posts a single status for a given idempotency key, so that retrying after a
+/// failure which happened once the status was posted doesn't post it twice.
+async fn post_status(
+    app: &AppContext,
+    config: &MastodonConfig,
+    status: &str,
+    in_reply_to_id: Option<&str>,
+    idempotency_key: &str,
+) -> anyhow::Result<String> {
+    let response = app
+        .http_client
+        .post(format!("{}/api/v1/statuses", config.url))
+        .bearer_auth(&config.access_token)
+        .header("Idempotency-Key", idempotency_key)
+        .json(&NewStatus {
+            status,
+            visibility: &config.visibility,
+            in_reply_to_id,
+        })
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let posted: PostedStatus = response
+        .json()
+        .await
+        .context("unexpected response from the Mastodon instance")?;
+    Ok(posted.id)
+}
+
+/// Post a status about the event.
+///
+/// New interventions start a thread; updates and resolutions are posted as replies to the latest
+/// status of the thread. Updates of interventions which were never posted about are ignored, so as
+/// not to start a thread in the middle of an intervention.
+pub(super) async fn notify(
+    app: &AppContext,
+    config: &MastodonConfig,
+    notification: &Notification,
+    idempotency_key: &str,
+) -> anyhow::Result<()> {
+    let event = notification.event;
+    let details = notification
+        .details
+        .as_ref()
+        .context("missing intervention details")?;
+    let intervention_id = details.intervention.id.unwrap();
+
+    let previous = {
+        let mut conn = app.db_connection.lock().await;
+        RemotePost::get_all_for(CHANNEL, &config.url, intervention_id, &mut conn)
+            .await?
+            .pop()
+    };
+
+    if let (Event::InterventionUpdated(_), None) = (event, &previous) {
+        return Ok(());
+    }
+
+    let status = render(app, CHANNEL, event, "txt", &notification.ctx(app)?)?;
+    let status = truncate(status.trim(), MAX_LENGTH);
+    let remote_id = post_status(
+        app,
+        config,
+        &status,
+        previous.as_ref().map(|p| p.remote_id.as_str()),
+        idempotency_key,
+    )
+    .await?;
+
+    let mut conn = app.db_connection.lock().await;
+    RemotePost::insert(
+        &mut conn,
+        &RemotePost {
+            channel: CHANNEL.to_owned(),
+            target: config.url.clone(),
+            intervention_id,
+            remote_id,
+            date: chrono::Utc::now().naive_utc(),
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use axum::{http::HeaderMap, routing::post, Json, Router};
+    use std::sync::{Arc, Mutex};
+
+    /// A request received by the mock instance.
+    struct Request {
+        authorization: Option<String>,
+        idempotency_key: Option<String>,
+        body: serde_json::Value,
+    }
+
+    /// Start a mock Mastodon instance, returning its URL and the requests it receives.
+    fn mock_instance() -> (String, Arc<Mutex<Vec<Request>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        let router = Router::new().route(
+            "/api/v1/statuses",
+            post(
+                move |headers: HeaderMap, Json(body): Json<serde_json::Value>| {
+                    let received = received.clone();
+                    async move {
+                        let header = |name: &str| {
+                            let value = headers.get(name)?;
+                            Some(value.to_str().unwrap().to_owned())
+                        };
+                        let mut received = received.lock().unwrap();
+                        received.push(Request {
+                            authorization: header("authorization"),
+                            idempotency_key: header("idempotency-key"),
+                            body,
+                        });
+                        Json(serde_json::json!({ "id": received.len().to_string() }))
+                    }
+                },
+            ),
+        );
+        (testing::serve(router), requests)
+    }
+
+    #[tokio::test]
+    async fn statuses_are_threaded() {
+        let (url, requests) = mock_instance();
+        let mut config = testing::config();
+        config.mastodon = Some(MastodonConfig {
+            url,
+            access_token: "token".to_owned(),
+            visibility: "unlisted".to_owned(),
+        });
+        let app = testing::app(config).await;
+        let ctx = &app.ctx;
+
+        // An update of an intervention which was never posted about doesn't start a thread.
+        let skipped_id = testing::insert_intervention(ctx, "Forge en panne").await;
+        testing::notify(ctx, Event::InterventionUpdated(skipped_id)).await;
+        assert!(requests.lock().unwrap().is_empty());
+
+        // A new intervention starts a thread.
+        let id = testing::insert_intervention(ctx, "Nuage en panne").await;
+        let created_key = testing::notify(ctx, Event::InterventionCreated(id)).await;
+        {
+            let requests = requests.lock().unwrap();
+            assert_eq!(requests.len(), 1);
+            let request = &requests[0];
+            assert_eq!(request.authorization.as_deref(), Some("Bearer token"));
+            assert_eq!(
+                request.idempotency_key.as_deref(),
+                Some(created_key.as_str())
+            );
+            assert_eq!(request.body["visibility"], "unlisted");
+            assert!(request.body.get("in_reply_to_id").is_none());
+            let status = request.body["status"].as_str().unwrap();
+            assert!(status.contains("Nuage en panne"));
+            assert!(status.contains(&format!("https://status.example.org/intervention/{id}/")));
+        }
+
+        // Later events are replies to the latest status of the thread.
+        let updated_key = testing::notify(ctx, Event::InterventionUpdated(id)).await;
+        let resolved_key = testing::notify(ctx, Event::InterventionResolved(id)).await;
+        {
+            let requests = requests.lock().unwrap();
+            assert_eq!(requests.len(), 3);
+            assert_eq!(requests[1].body["in_reply_to_id"], "1");
+            assert_eq!(
+                requests[1].idempotency_key.as_deref(),
+                Some(updated_key.as_str())
+            );
+            assert_eq!(requests[2].body["in_reply_to_id"], "2");
+            assert_eq!(
+                requests[2].idempotency_key.as_deref(),
+                Some(resolved_key.as_str())
+            );
+        }
+        assert_ne!(created_key, updated_key);
+        assert_ne!(updated_key, resolved_key);
+
+        let mut conn = ctx.db_connection.lock().await;
+        let posts = RemotePost::get_all_for(
+            CHANNEL,
+            &app.ctx.config.mastodon.as_ref().unwrap().url,
+            id,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let remote_ids: Vec<_> = posts.iter().map(|p| p.remote_id.as_str()).collect();
+        assert_eq!(remote_ids, ["1", "2", "3"]);
+    }
+}
@@ -0,0 +1,283 @@
+//! Matrix notifications: a message is sent to the configured rooms through the client-server API
+//! for each intervention, and edited (`m.replace`) as the intervention evolves.
+
+use super::{render, Notification};
+use crate::{db::models::remote_posts::RemotePost, AppContext};
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+
+/// Name of the channel, as stored along with the remote posts and used for the templates.
+const CHANNEL: &str = "matrix";
+
+/// Configuration of the Matrix account sending the messages.
+pub(crate) struct MatrixConfig {
+    /// Base URL of the homeserver's client-server API, without a trailing slash.
+    pub homeserver_url: String,
+    /// Access token of the account, which must have joined the rooms.
+    pub access_token: String,
+    /// Identifiers of the rooms to send the messages to, e.g. `!abcdef:example.org`.
+    pub room_ids: Vec<String>,
+}
+
+/// Content of an `m.room.message` event, formatted as HTML.
+#[derive(Clone, Serialize)]
+struct MessageContent {
+    msgtype: &'static str,
+    body: String,
+    format: &'static str,
+    formatted_body: String,
+}
+
+impl MessageContent {
+    fn new(body: String, formatted_body: String) -> Self {
+        Self {
+            msgtype: "m.text",
+            body,
+            format: "org.matrix.custom.html",
+            formatted_body,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RelatesTo<'a> {
+    rel_type: &'static str,
+    event_id: &'a str,
+}
+
+/// Content of an event replacing a previous message.
+///
+/// The top-level content is a fallback for clients which don't support edits.
+#[derive(Serialize)]
+struct EditContent<'a> {
+    #[serde(flatten)]
+    fallback: MessageContent,
+    #[serde(rename = "m.new_content")]
+    new_content: MessageContent,
+    #[serde(rename = "m.relates_to")]
+    relates_to: RelatesTo<'a>,
+}
+
+#[derive(Deserialize)]
+struct SentEvent {
+    event_id: String,
+}
+
+/// Render the plain text and HTML versions of the message about an intervention, reflecting its
+/// current state.
+fn render_content(app: &AppContext, notification: &Notification) -> anyhow::Result<MessageContent> {
+    let ctx = notification.ctx(app)?;
+    let body = render(app, CHANNEL, notification.event, "txt", &ctx)?;
+    let html = render(app, CHANNEL, notification.event, "html", &ctx)?;
+    Ok(MessageContent::new(
+        body.trim().to_owned(),
+        html.trim().to_owned(),
+    ))
+}
+
+/// Send a message event to a room; returns the id of the new event.
+///
+/// The homeserver sends a single event for a given transaction id, so that retrying after a
+/// failure which happened once the event was sent doesn't send it twice.
+async fn send(
+    app: &AppContext,
+    config: &MatrixConfig,
+    room_id: &str,
+    content: &impl Serialize,
+    transaction_id: &str,
+) -> anyhow::Result<String> {
+    let mut url =
+        reqwest::Url::parse(&config.homeserver_url).context("invalid Matrix homeserver URL")?;
+    url.path_segments_mut()
+        .map_err(|()| anyhow::anyhow!("invalid Matrix homeserver URL"))?
+        .pop_if_empty()
+        .extend([
+            "_matrix",
+            "client",
+            "v3",
+            "rooms",
+            room_id,
+            "send",
+            "m.room.message",
+            transaction_id,
+        ]);
+
+    let response = app
+        .http_client
+        .put(url)
+        .bearer_auth(&config.access_token)
+        .json(content)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let sent: SentEvent = response
+        .json()
+        .await
+        .context("unexpected response from the Matrix homeserver")?;
+    Ok(sent.event_id)
+}
+
+/// Send a message about the event to a single room.
+///
+/// There's a single message per intervention and room: updates and resolutions edit it, so the
+/// rooms don't get flooded.
+pub(super) async fn notify(
+    app: &AppContext,
+    config: &MatrixConfig,
+    room_id: &str,
+    notification: &Notification,
+    transaction_id: &str,
+) -> anyhow::Result<()> {
+    let details = notification
+        .details
+        .as_ref()
+        .context("missing intervention details")?;
+    let intervention_id = details.intervention.id.unwrap();
+    let content = render_content(app, notification)?;
+
+    let original = {
+        let mut conn = app.db_connection.lock().await;
+        RemotePost::get_all_for(CHANNEL, room_id, intervention_id, &mut conn)
+            .await?
+            .into_iter()
+            .next()
+    };
+
+    if let Some(original) = original {
+        let edit = EditContent {
+            fallback: MessageContent::new(
+                format!("* {}", content.body),
+                format!("* {}", content.formatted_body),
+            ),
+            new_content: content,
+            relates_to: RelatesTo {
+                rel_type: "m.replace",
+                event_id: &original.remote_id,
+            },
+        };
+        send(app, config, room_id, &edit, transaction_id).await?;
+        return Ok(());
+    }
+
+    // First message about this intervention in this room: remember it, so it can be edited later.
+    let event_id = send(app, config, room_id, &content, transaction_id).await?;
+
+    let mut conn = app.db_connection.lock().await;
+    RemotePost::insert(
+        &mut conn,
+        &RemotePost {
+            channel: CHANNEL.to_owned(),
+            target: room_id.to_owned(),
+            intervention_id,
+            remote_id: event_id,
+            date: chrono::Utc::now().naive_utc(),
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{notifications::Event, testing};
+    use axum::{extract::Path, http::HeaderMap, routing::put, Json, Router};
+    use std::sync::{Arc, Mutex};
+
+    /// An event sent to the stub homeserver.
+    struct SentMessage {
+        authorization: Option<String>,
+        room_id: String,
+        transaction_id: String,
+        content: serde_json::Value,
+    }
+
+    /// Start a stub homeserver, returning its URL and the events sent to it.
+    fn stub_homeserver() -> (String, Arc<Mutex<Vec<SentMessage>>>) {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let received = messages.clone();
+        let router = Router::new().route(
+            "/_matrix/client/v3/rooms/:room_id/send/m.room.message/:transaction_id",
+            put(
+                move |Path((room_id, transaction_id)): Path<(String, String)>,
+                      headers: HeaderMap,
+                      Json(content): Json<serde_json::Value>| {
+                    let received = received.clone();
+                    async move {
+                        let authorization = headers
+                            .get("authorization")
+                            .map(|value| value.to_str().unwrap().to_owned());
+                        let mut received = received.lock().unwrap();
+                        received.push(SentMessage {
+                            authorization,
+                            room_id,
+                            transaction_id,
+                            content,
+                        });
+                        Json(serde_json::json!({ "event_id": format!("${}", received.len()) }))
+                    }
+                },
+            ),
+        );
+        (testing::serve(router), messages)
+    }
+
+    #[tokio::test]
+    async fn message_is_edited() {
+        let (homeserver_url, messages) = stub_homeserver();
+        let mut config = testing::config();
+        config.matrix = Some(MatrixConfig {
+            homeserver_url,
+            access_token: "token".to_owned(),
+            room_ids: vec!["!room:example.org".to_owned()],
+        });
+        let app = testing::app(config).await;
+        let ctx = &app.ctx;
+
+        // The first event sends a message.
+        let id = testing::insert_intervention(ctx, "Forge en panne").await;
+        let created_key = testing::notify(ctx, Event::InterventionCreated(id)).await;
+        {
+            let messages = messages.lock().unwrap();
+            assert_eq!(messages.len(), 1);
+            let message = &messages[0];
+            assert_eq!(message.authorization.as_deref(), Some("Bearer token"));
+            assert_eq!(message.room_id, "!room:example.org");
+            assert_eq!(message.transaction_id, created_key);
+            assert_eq!(message.content["msgtype"], "m.text");
+            assert_eq!(message.content["format"], "org.matrix.custom.html");
+            assert!(message.content["body"]
+                .as_str()
+                .unwrap()
+                .contains("Forge en panne"));
+            assert!(message.content.get("m.relates_to").is_none());
+        }
+
+        // The next ones edit it.
+        let updated_key = testing::notify(ctx, Event::InterventionUpdated(id)).await;
+        let resolved_key = testing::notify(ctx, Event::InterventionResolved(id)).await;
+        {
+            let messages = messages.lock().unwrap();
+            assert_eq!(messages.len(), 3);
+            for (message, key) in messages[1..].iter().zip([updated_key, resolved_key]) {
+                assert_eq!(message.transaction_id, key);
+                let content = &message.content;
+                assert_eq!(content["m.relates_to"]["rel_type"], "m.replace");
+                assert_eq!(content["m.relates_to"]["event_id"], "$1");
+                let new_body = content["m.new_content"]["body"].as_str().unwrap();
+                assert!(new_body.contains("Forge en panne"));
+                assert_eq!(content["body"], format!("* {new_body}"));
+            }
+        }
+
+        // Only the original message is remembered.
+        let mut conn = ctx.db_connection.lock().await;
+        let posts = RemotePost::get_all_for(CHANNEL, "!room:example.org", id, &mut conn)
+            .await
+            .unwrap();
+        let remote_ids: Vec<_> = posts.iter().map(|p| p.remote_id.as_str()).collect();
+        assert_eq!(remote_ids, ["$1"]);
+    }
+}
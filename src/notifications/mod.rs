@@ -0,0 +1,512 @@
+//! Notifications sent to the outside world when something happens to an intervention or a
+//! service.
+
+use crate::{
+    db::models::{
+        comments::Comment, interventions::Intervention, outbox::OutboxEntry,
+        preferences::Preferences, services::Service,
+    },
+    regenerate::InterventionCtx,
+    status::ComputedStatus,
+    AppContext,
+};
+use anyhow::Context as _;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::AnyConnection;
+
+pub(crate) mod email;
+pub(crate) mod mastodon;
+pub(crate) mod matrix;
+pub(crate) mod outbox;
+pub(crate) mod webhooks;
+
+/// Something that happened, which is worth notifying about.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub(crate) enum Event {
+    InterventionCreated(i64),
+    InterventionUpdated(i64),
+    InterventionResolved(i64),
+    /// A planned maintenance is about to start.
+    InterventionReminder {
+        intervention_id: i64,
+        /// How long before the start of the maintenance, in minutes.
+        lead_time: i64,
+    },
+    /// The computed status of a service changed.
+    ServiceStatusChanged {
+        service_id: i64,
+        previous: ComputedStatus,
+        current: ComputedStatus,
+    },
+}
+
+impl Event {
+    /// Names of all the events, as exposed to the outside world.
+    pub const NAMES: &'static [&'static str] = &[
+        "intervention.created",
+        "intervention.updated",
+        "intervention.resolved",
+        "intervention.reminder",
+        "service.status_changed",
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::InterventionCreated(_) => "intervention.created",
+            Event::InterventionUpdated(_) => "intervention.updated",
+            Event::InterventionResolved(_) => "intervention.resolved",
+            Event::InterventionReminder { .. } => "intervention.reminder",
+            Event::ServiceStatusChanged { .. } => "service.status_changed",
+        }
+    }
+
+    /// The intervention this event is about, if any.
+    pub fn intervention_id(self) -> Option<i64> {
+        match self {
+            Event::InterventionCreated(id)
+            | Event::InterventionUpdated(id)
+            | Event::InterventionResolved(id)
+            | Event::InterventionReminder {
+                intervention_id: id,
+                ..
+            } => Some(id),
+            Event::ServiceStatusChanged { .. } => None,
+        }
+    }
+}
+
+/// Everything a notification channel may need to know about the intervention of an event.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct InterventionDetails {
+    pub intervention: Intervention,
+    pub services: Vec<Service>,
+    /// Updates posted on the intervention, most recent first.
+    #[serde(default)]
+    pub updates: Vec<Comment>,
+}
+
+impl InterventionDetails {
+    async fn read(id: i64, conn: &mut AnyConnection) -> anyhow::Result<Self> {
+        let intervention = Intervention::by_id(id, conn)
+            .await?
+            .with_context(|| format!("unknown intervention with id {id}"))?;
+
+        let mut services = Vec::new();
+        for service_id in Intervention::get_service_ids(id, conn).await? {
+            if let Some(service) = Service::by_id(service_id.0, conn).await? {
+                services.push(service);
+            }
+        }
+
+        let updates = Comment::by_intervention(id, conn).await?;
+
+        Ok(Self {
+            intervention,
+            services,
+            updates,
+        })
+    }
+
+    /// Render context of the intervention, as used by the pages.
+    fn ctx(&self) -> InterventionCtx {
+        let services: Vec<_> = self.services.iter().collect();
+        InterventionCtx::new(&self.intervention, &services, &self.updates)
+    }
+}
+
+/// A notification about an event, as stored in the outbox.
+///
+/// The details are captured when the event happens, so that the notification reflects the state
+/// of the intervention at that time, however late it's delivered.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Notification {
+    pub event: Event,
+    pub date: NaiveDateTime,
+    pub details: Option<InterventionDetails>,
+}
+
+/// Where a notification gets delivered.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Destination {
+    Email { subscriber_id: i64 },
+    Webhook { webhook_id: i64 },
+    Mastodon,
+    Matrix { room_id: String },
+}
+
+impl Destination {
+    /// Name of the channel, as stored in the outbox.
+    pub fn channel(&self) -> &'static str {
+        match self {
+            Self::Email { .. } => "email",
+            Self::Webhook { .. } => "webhook",
+            Self::Mastodon => "mastodon",
+            Self::Matrix { .. } => "matrix",
+        }
+    }
+
+    /// Destination within the channel, as stored in the outbox.
+    fn target(&self) -> String {
+        match self {
+            Self::Email { subscriber_id } => subscriber_id.to_string(),
+            Self::Webhook { webhook_id } => webhook_id.to_string(),
+            Self::Mastodon => String::new(),
+            Self::Matrix { room_id } => room_id.clone(),
+        }
+    }
+
+    pub fn parse(channel: &str, target: &str) -> anyhow::Result<Self> {
+        Ok(match channel {
+            "email" => Self::Email {
+                subscriber_id: target.parse().context("invalid subscriber id")?,
+            },
+            "webhook" => Self::Webhook {
+                webhook_id: target.parse().context("invalid webhook id")?,
+            },
+            "mastodon" => Self::Mastodon,
+            "matrix" => Self::Matrix {
+                room_id: target.to_owned(),
+            },
+            _ => anyhow::bail!("unexpected notification channel: {channel}"),
+        })
+    }
+}
+
+/// Public link to the permalink page of an intervention.
+fn intervention_url(app: &AppContext, intervention_id: i64) -> String {
+    format!("{}/intervention/{intervention_id}/", app.config.public_url)
+}
+
+/// Render context common to all the notifications.
+#[derive(Serialize)]
+struct NotificationCtx<'a> {
+    /// Name of the event.
+    event: &'static str,
+    date: NaiveDateTime,
+    public_url: &'a str,
+    /// Link to the page of the intervention, if the event is about one.
+    url: Option<String>,
+    intervention: Option<InterventionCtx>,
+    /// How long before the start of the maintenance, for reminders.
+    lead_time: Option<String>,
+}
+
+/// Human-readable lead time of a reminder, given in minutes.
+fn format_lead_time(minutes: i64) -> String {
+    // TODO i18n
+    const DAY: i64 = 24 * 60;
+    match minutes {
+        DAY => "1 jour".to_owned(),
+        _ if minutes % DAY == 0 => format!("{} jours", minutes / DAY),
+        _ if minutes % 60 == 0 => format!("{} h", minutes / 60),
+        _ => format!("{minutes} min"),
+    }
+}
+
+impl Notification {
+    /// Render context for the templates of the notification; channels may add their own
+    /// variables.
+    fn ctx(&self, app: &AppContext) -> anyhow::Result<tera::Context> {
+        Ok(tera::Context::from_serialize(NotificationCtx {
+            event: self.event.name(),
+            date: self.date,
+            public_url: &app.config.public_url,
+            url: self
+                .details
+                .as_ref()
+                .map(|details| intervention_url(app, details.intervention.id.unwrap())),
+            intervention: self.details.as_ref().map(InterventionDetails::ctx),
+            lead_time: match self.event {
+                Event::InterventionReminder { lead_time, .. } => Some(format_lead_time(lead_time)),
+                _ => None,
+            },
+        })?)
+    }
+}
+
+/// Render a part of a notification, with the `notifications/{channel}/{event}.{part}` template.
+fn render(
+    app: &AppContext,
+    channel: &str,
+    event: Event,
+    part: &str,
+    ctx: &tera::Context,
+) -> anyhow::Result<String> {
+    let template = format!("notifications/{channel}/{}.{part}", event.name());
+    app.templates
+        .read()
+        .unwrap()
+        .render(&template, ctx)
+        .with_context(|| format!("when rendering {template}"))
+}
+
+/// Does a subscription with the given preferences want to be notified about the event?
+///
+/// Service status changes only go through the services filter, since they don't have a severity
+/// per se.
+fn is_wanted(prefs: &Preferences, event: Event, details: Option<&InterventionDetails>) -> bool {
+    match (event, details) {
+        (Event::ServiceStatusChanged { service_id, .. }, _) => prefs.wants_service(service_id),
+        (_, Some(details)) => {
+            let service_ids: Vec<_> = details.services.iter().map(|s| s.id.unwrap()).collect();
+            prefs.wants_intervention(&details.intervention, &service_ids)
+        }
+        (_, None) => true,
+    }
+}
+
+/// Queue notifications about the event, for all the interested destinations.
+///
+/// This should be called within the same transaction as the change the event is about, so that
+/// notifications are sent if and only if the change happened. Wake up the outbox worker, once the
+/// transaction is committed.
+pub(crate) async fn enqueue(
+    app: &AppContext,
+    conn: &mut AnyConnection,
+    event: Event,
+) -> anyhow::Result<()> {
+    let details = match event.intervention_id() {
+        Some(id) => Some(InterventionDetails::read(id, conn).await?),
+        None => None,
+    };
+
+    let mut destinations: Vec<_> = webhooks::recipients(conn, event, details.as_ref())
+        .await?
+        .into_iter()
+        .map(|webhook_id| Destination::Webhook { webhook_id })
+        .collect();
+
+    // Only webhooks get notified about changes of the services' statuses.
+    if let Some(details) = &details {
+        if app.mailer.is_some() {
+            destinations.extend(
+                email::recipients(conn, event, details)
+                    .await?
+                    .into_iter()
+                    .map(|subscriber_id| Destination::Email { subscriber_id }),
+            );
+        }
+
+        if app.config.mastodon.is_some() {
+            destinations.push(Destination::Mastodon);
+        }
+
+        if let Some(config) = &app.config.matrix {
+            destinations.extend(config.room_ids.iter().map(|room_id| Destination::Matrix {
+                room_id: room_id.clone(),
+            }));
+        }
+    }
+
+    if destinations.is_empty() {
+        return Ok(());
+    }
+
+    let payload = serde_json::to_string(&Notification {
+        event,
+        date: chrono::Utc::now().naive_utc(),
+        details,
+    })?;
+
+    for destination in destinations {
+        OutboxEntry::insert(
+            conn,
+            &OutboxEntry::new(
+                destination.channel(),
+                destination.target(),
+                event.name(),
+                payload.clone(),
+            ),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{status::ComputedStatus, testing, tokens};
+    use std::{collections::BTreeMap, path::Path};
+
+    /// Parts of the notifications of each channel, as rendered when delivering them.
+    const CHANNELS: [(&str, &[&str]); 4] = [
+        ("email", &["subject.txt", "body.txt"]),
+        ("mastodon", &["txt"]),
+        ("matrix", &["txt", "html"]),
+        ("webhook", &["json"]),
+    ];
+
+    fn copy_dir(from: &Path, to: &Path) {
+        std::fs::create_dir_all(to).unwrap();
+        for entry in std::fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let target = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                copy_dir(&path, &target);
+            } else {
+                std::fs::copy(&path, &target).unwrap();
+            }
+        }
+    }
+
+    /// Insert an ongoing outage with an update, and return its id and the id of its service.
+    async fn insert_intervention(ctx: &AppContext) -> (i64, i64) {
+        let id = testing::insert_intervention(ctx, "Forge down").await;
+        let mut conn = ctx.db_connection.lock().await;
+        let comment = Comment {
+            date: chrono::Utc::now().naive_utc(),
+            description: "The disks are being replaced.".to_owned(),
+        };
+        Comment::insert_for_intervention(&mut conn, id, &comment)
+            .await
+            .unwrap();
+        let service_id = Intervention::get_service_ids(id, &mut conn).await.unwrap()[0].0;
+        (id, service_id)
+    }
+
+    /// Render every part of the notifications of every event, on every channel notified about
+    /// it; returns them by template.
+    async fn render_all(
+        app: &AppContext,
+        intervention_id: i64,
+        service_id: i64,
+    ) -> BTreeMap<String, String> {
+        let events = [
+            Event::InterventionCreated(intervention_id),
+            Event::InterventionUpdated(intervention_id),
+            Event::InterventionResolved(intervention_id),
+            Event::InterventionReminder {
+                intervention_id,
+                lead_time: 60,
+            },
+            Event::ServiceStatusChanged {
+                service_id,
+                previous: ComputedStatus::Operational,
+                current: ComputedStatus::FullOutage,
+            },
+        ];
+        let mut rendered = BTreeMap::new();
+        for event in events {
+            let details = match event.intervention_id() {
+                Some(id) => {
+                    let mut conn = app.db_connection.lock().await;
+                    Some(InterventionDetails::read(id, &mut conn).await.unwrap())
+                }
+                None => None,
+            };
+            let notification = Notification {
+                event,
+                date: NaiveDateTime::from_timestamp_opt(1_700_000_000, 0).unwrap(),
+                details,
+            };
+            let mut ctx = notification.ctx(app).unwrap();
+            // The variables added by the channels.
+            ctx.insert("preferences_url", "https://status.example.org/preferences");
+            ctx.insert("unsubscribe_url", "https://status.example.org/unsubscribe");
+            ctx.insert("payload", &serde_json::json!({ "event": event.name() }));
+
+            for (channel, parts) in CHANNELS {
+                // Only webhooks get notified about changes of the services' statuses.
+                if notification.details.is_none() && channel != "webhook" {
+                    continue;
+                }
+                for part in parts {
+                    let text = render(app, channel, event, part, &ctx).unwrap();
+                    assert!(!text.trim().is_empty(), "{channel} {} {part}", event.name());
+                    let template = format!("notifications/{channel}/{}.{part}", event.name());
+                    rendered.insert(template, text);
+                }
+            }
+        }
+        rendered
+    }
+
+    #[tokio::test]
+    async fn default_templates() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let (id, service_id) = insert_intervention(ctx).await;
+
+        let rendered = render_all(ctx, id, service_id).await;
+        assert_eq!(rendered.len(), 4 * 6 + 1);
+        for (template, text) in &rendered {
+            if template.ends_with(".json") {
+                let payload: serde_json::Value = serde_json::from_str(text).unwrap();
+                assert!(template.contains(payload["event"].as_str().unwrap()));
+                continue;
+            }
+            assert!(text.contains("Forge down"), "{template}: {text}");
+            // Links are escaped in HTML.
+            if template.ends_with(".txt") && !template.ends_with("subject.txt") {
+                assert!(
+                    text.contains(&intervention_url(ctx, id)),
+                    "{template}: {text}"
+                );
+            }
+        }
+        assert!(
+            rendered["notifications/email/intervention.created.body.txt"]
+                .contains("https://status.example.org/unsubscribe")
+        );
+        assert!(rendered["notifications/matrix/intervention.updated.html"]
+            .contains("The disks are being replaced."));
+        assert!(rendered["notifications/mastodon/intervention.reminder.txt"].contains("1 h"));
+    }
+
+    #[tokio::test]
+    async fn templates_can_be_overridden() {
+        let app = testing::app(testing::config()).await;
+        let (id, service_id) = insert_intervention(&app.ctx).await;
+        let defaults = render_all(&app.ctx, id, service_id).await;
+
+        // A copy of the templates, with some of them replaced.
+        let template_dir =
+            std::env::temp_dir().join(format!("rustatouille-templates-{}", tokens::random_token()));
+        copy_dir(Path::new("./templates"), &template_dir);
+        let notifications = template_dir.join("notifications");
+        std::fs::write(
+            notifications.join("mastodon/intervention.created.txt"),
+            "Oh no, {{ intervention.title }}! {{ url }}",
+        )
+        .unwrap();
+        // Partials too.
+        std::fs::write(
+            notifications.join("email/_footer.txt"),
+            "-- \nThe status page team",
+        )
+        .unwrap();
+
+        let mut config = testing::config();
+        config.template_dir = template_dir.clone();
+        let app = testing::app(config).await;
+        let (id, service_id) = insert_intervention(&app.ctx).await;
+        let overridden = render_all(&app.ctx, id, service_id).await;
+        std::fs::remove_dir_all(template_dir).unwrap();
+
+        assert_eq!(
+            overridden.keys().collect::<Vec<_>>(),
+            defaults.keys().collect::<Vec<_>>()
+        );
+        for (template, text) in &overridden {
+            let default = &defaults[template];
+            if template == "notifications/mastodon/intervention.created.txt" {
+                assert_eq!(
+                    text,
+                    &format!("Oh no, Forge down! {}", intervention_url(&app.ctx, id))
+                );
+            } else if template.starts_with("notifications/email/") && template.ends_with("body.txt")
+            {
+                assert!(
+                    text.trim_end().ends_with("-- \nThe status page team"),
+                    "{text}"
+                );
+                assert_ne!(text, default);
+            } else {
+                assert_eq!(text, default, "{template}");
+            }
+        }
+    }
+}
@@ -0,0 +1,394 @@
+//! Durable delivery of the notifications.
+//!
+//! Notifications are first written to the `outbox` table, along with the change they're about;
+//! this background task then delivers them, retrying with an exponential backoff, and eventually
+//! gives up on them. Failed notifications can be retried manually from the admin.
+
+use super::{email, mastodon, matrix, webhooks, Destination, Notification};
+use crate::{
+    db::models::{
+        outbox::{OutboxEntry, OutboxStatus},
+        webhooks::WebhookDelivery,
+    },
+    AppContext,
+};
+use anyhow::Context as _;
+use chrono::NaiveDateTime;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+use tracing as log;
+
+/// Maximum number of delivery attempts, before giving up on a notification.
+const MAX_ATTEMPTS: i64 = 8;
+
+/// Delay before the first retry; it's doubled after each failed attempt.
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// How long to wait before trying again, after failing to read the outbox.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How long to sleep when there's nothing to deliver. Anything that could change this will wake
+/// up the worker anyways.
+const IDLE_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long to keep the log of the webhook deliveries.
+const DELIVERIES_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Try to deliver a single entry.
+async fn deliver(app: &AppContext, entry: &OutboxEntry) -> anyhow::Result<()> {
+    let destination = Destination::parse(&entry.channel, &entry.target)?;
+    let notification: Notification =
+        serde_json::from_str(&entry.payload).context("invalid notification payload")?;
+
+    match destination {
+        Destination::Webhook { webhook_id } => {
+            webhooks::deliver(app, webhook_id, &notification, entry.attempts + 1).await
+        }
+
+        Destination::Email { subscriber_id } => {
+            let mailer = app
+                .mailer
+                .as_ref()
+                .context("email notifications are disabled")?;
+            email::deliver(app, mailer, subscriber_id, &notification).await
+        }
+
+        Destination::Mastodon => {
+            let config = app
+                .config
+                .mastodon
+                .as_ref()
+                .context("Mastodon notifications are disabled")?;
+            mastodon::notify(app, config, &notification, &entry.idempotency_key()).await
+        }
+
+        Destination::Matrix { room_id } => {
+            let config = app
+                .config
+                .matrix
+                .as_ref()
+                .context("Matrix notifications are disabled")?;
+            matrix::notify(
+                app,
+                config,
+                &room_id,
+                &notification,
+                &entry.idempotency_key(),
+            )
+            .await
+        }
+    }
+}
+
+/// Deliver all the entries that are due, and return the date of the next attempt, if any.
+///
+/// Entries for the same destination are delivered in order: an entry waiting for a retry holds
+/// back the ones after it, so that e.g. an update is never posted before the intervention itself.
+pub(crate) async fn deliver_due(app: &AppContext) -> anyhow::Result<Option<NaiveDateTime>> {
+    let entries = {
+        let mut conn = app.db_connection.lock().await;
+        OutboxEntry::get_by_status(OutboxStatus::Pending, &mut conn).await?
+    };
+
+    let now = chrono::Utc::now().naive_utc();
+    let mut next_date: Option<NaiveDateTime> = None;
+
+    // Destinations with an entry waiting for a retry.
+    let mut blocked = HashSet::new();
+
+    for entry in entries {
+        let destination = (entry.channel.clone(), entry.target.clone());
+        if blocked.contains(&destination) {
+            continue;
+        }
+
+        if entry.next_attempt_at > now {
+            next_date =
+                Some(next_date.map_or(entry.next_attempt_at, |d| d.min(entry.next_attempt_at)));
+            blocked.insert(destination);
+            continue;
+        }
+
+        let id = entry.id.unwrap();
+        let res = deliver(app, &entry).await;
+
+        let mut conn = app.db_connection.lock().await;
+        match res {
+            Ok(()) => {
+                OutboxEntry::delete(id, &mut conn).await?;
+            }
+
+            Err(err) => {
+                let attempts = entry.attempts + 1;
+                let next_attempt_at = (attempts < MAX_ATTEMPTS).then(|| {
+                    let delay = FIRST_RETRY_DELAY * 2u32.pow(attempts as u32 - 1);
+                    now + chrono::Duration::from_std(delay).unwrap()
+                });
+
+                match next_attempt_at {
+                    Some(date) => {
+                        log::warn!(
+                            "unable to deliver {} to {} {}, retrying at {date}: {err:#}",
+                            entry.event,
+                            entry.channel,
+                            entry.target
+                        );
+                        next_date = Some(next_date.map_or(date, |d| d.min(date)));
+                        blocked.insert(destination);
+                    }
+                    None => {
+                        log::error!(
+                            "giving up delivering {} to {} {} after {attempts} attempts: {err:#}",
+                            entry.event,
+                            entry.channel,
+                            entry.target
+                        );
+                    }
+                }
+
+                OutboxEntry::record_failure(id, &format!("{err:#}"), next_attempt_at, &mut conn)
+                    .await?;
+            }
+        }
+    }
+
+    Ok(next_date)
+}
+
+/// Forget about the webhook deliveries older than the retention period.
+async fn prune_deliveries(app: &AppContext) -> anyhow::Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    let mut conn = app.db_connection.lock().await;
+    let retention = chrono::Duration::from_std(DELIVERIES_RETENTION).unwrap();
+    WebhookDelivery::delete_before(now - retention, &mut conn).await
+}
+
+/// Background task delivering the notifications from the outbox.
+///
+/// The task sleeps until the next retry is due; a message on the receiver wakes it up, so it can
+/// deliver new notifications right away. Old webhook deliveries are pruned along the way.
+pub(crate) async fn run(app: Arc<AppContext>, mut receiver: mpsc::Receiver<()>) {
+    loop {
+        if let Err(err) = prune_deliveries(&app).await {
+            log::error!("Unable to prune the webhook deliveries: {err:#}");
+        }
+
+        let delay = match deliver_due(&app).await {
+            Ok(Some(next_date)) => {
+                let now = chrono::Utc::now().naive_utc();
+                // A negative duration means the next attempt is already due.
+                (next_date - now).to_std().unwrap_or(Duration::ZERO)
+            }
+            Ok(None) => IDLE_DELAY,
+            Err(err) => {
+                log::error!("Unable to deliver the notifications: {err:#}");
+                RETRY_DELAY
+            }
+        };
+
+        tokio::select! {
+            received = receiver.recv() => {
+                if received.is_none() {
+                    // okthxbye
+                    break;
+                }
+            }
+
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::models::{users::Role, webhooks::Webhook},
+        notifications::{self, Event},
+        testing,
+    };
+    use axum::{http::HeaderMap, http::StatusCode, routing::post, Router};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+
+    /// A webhook endpoint, which fails while told so.
+    #[derive(Default)]
+    struct Endpoint {
+        failing: AtomicBool,
+        /// Events received, including the failed attempts.
+        received: Mutex<Vec<String>>,
+    }
+
+    impl Endpoint {
+        fn received(&self) -> Vec<String> {
+            self.received.lock().unwrap().clone()
+        }
+    }
+
+    /// Start an endpoint and register it as a webhook interested in the interventions.
+    async fn start_endpoint(ctx: &AppContext, failing: bool) -> Arc<Endpoint> {
+        let endpoint = Arc::new(Endpoint {
+            failing: AtomicBool::new(failing),
+            ..Default::default()
+        });
+        let state = endpoint.clone();
+        let router = Router::new().route(
+            "/",
+            post(move |headers: HeaderMap| {
+                let state = state.clone();
+                async move {
+                    let event = headers["x-rustatouille-event"].to_str().unwrap().to_owned();
+                    state.received.lock().unwrap().push(event);
+                    if state.failing.load(Ordering::SeqCst) {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::OK
+                    }
+                }
+            }),
+        );
+
+        let mut conn = ctx.db_connection.lock().await;
+        Webhook::insert(
+            &mut conn,
+            &Webhook {
+                id: None,
+                url: testing::serve(router),
+                secret: "secret".to_owned(),
+                events: vec![
+                    "intervention.created".to_owned(),
+                    "intervention.updated".to_owned(),
+                ],
+                enabled: true,
+            },
+        )
+        .await
+        .unwrap();
+        endpoint
+    }
+
+    async fn enqueue(ctx: &AppContext, event: Event) {
+        let mut conn = ctx.db_connection.lock().await;
+        notifications::enqueue(ctx, &mut conn, event).await.unwrap();
+    }
+
+    async fn entries(ctx: &AppContext, status: OutboxStatus) -> Vec<OutboxEntry> {
+        let mut conn = ctx.db_connection.lock().await;
+        OutboxEntry::get_by_status(status, &mut conn).await.unwrap()
+    }
+
+    /// Make the retries due right away.
+    async fn skip_delays(ctx: &AppContext) {
+        let mut conn = ctx.db_connection.lock().await;
+        sqlx::query("UPDATE outbox SET next_attempt_at = $1")
+            .bind(chrono::Utc::now().naive_utc().timestamp())
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn failures_are_retried_then_given_up() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let endpoint = start_endpoint(ctx, true).await;
+        let id = testing::insert_intervention(ctx, "Forge down").await;
+        enqueue(ctx, Event::InterventionCreated(id)).await;
+
+        // The delay doubles after each failure, starting at 30 seconds.
+        let mut delay = chrono::Duration::seconds(30);
+        for attempts in 1..MAX_ATTEMPTS {
+            let before = chrono::Utc::now().naive_utc();
+            let next_date = deliver_due(ctx).await.unwrap().unwrap();
+            let pending = entries(ctx, OutboxStatus::Pending).await;
+            assert_eq!(pending.len(), 1);
+            assert_eq!(pending[0].attempts, attempts);
+            assert!(pending[0].last_error.as_ref().unwrap().contains("500"));
+            // Dates are stored to the second.
+            assert_eq!(
+                pending[0].next_attempt_at.timestamp(),
+                next_date.timestamp()
+            );
+            let actual_delay = next_date - before;
+            assert!(
+                actual_delay >= delay && actual_delay <= delay + chrono::Duration::seconds(5),
+                "{actual_delay} instead of {delay}"
+            );
+
+            // Nothing is attempted before the delay.
+            deliver_due(ctx).await.unwrap();
+            assert_eq!(endpoint.received().len(), attempts as usize);
+
+            skip_delays(ctx).await;
+            delay = delay * 2;
+        }
+
+        // The last attempt moves the notification to the failed ones.
+        assert_eq!(deliver_due(ctx).await.unwrap(), None);
+        assert_eq!(endpoint.received().len(), MAX_ATTEMPTS as usize);
+        assert!(entries(ctx, OutboxStatus::Pending).await.is_empty());
+        let failed = entries(ctx, OutboxStatus::Failed).await;
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].attempts, MAX_ATTEMPTS);
+        deliver_due(ctx).await.unwrap();
+        assert_eq!(endpoint.received().len(), MAX_ATTEMPTS as usize);
+
+        // An administrator can retry it, once the endpoint is fixed.
+        endpoint.failing.store(false, Ordering::SeqCst);
+        let url = testing::serve_admin(ctx);
+        let user_id = testing::insert_user(ctx, "alice", Role::Admin, None).await;
+        let session = testing::open_session(ctx, user_id).await;
+        let response = testing::http_client()
+            .post(format!("{url}/api/outbox/{}/retry", failed[0].id.unwrap()))
+            .header(reqwest::header::COOKIE, session.cookie())
+            .form(&[("csrf-token", &session.csrf_token)])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::FOUND);
+        let pending = entries(ctx, OutboxStatus::Pending).await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].attempts, 0);
+
+        assert_eq!(deliver_due(ctx).await.unwrap(), None);
+        assert_eq!(endpoint.received().len(), MAX_ATTEMPTS as usize + 1);
+        assert!(entries(ctx, OutboxStatus::Pending).await.is_empty());
+        assert!(entries(ctx, OutboxStatus::Failed).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn retries_hold_back_their_destination_only() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let failing = start_endpoint(ctx, true).await;
+        let working = start_endpoint(ctx, false).await;
+        let id = testing::insert_intervention(ctx, "Forge down").await;
+        enqueue(ctx, Event::InterventionCreated(id)).await;
+        enqueue(ctx, Event::InterventionUpdated(id)).await;
+
+        // The update isn't sent before the intervention itself…
+        deliver_due(ctx).await.unwrap();
+        assert_eq!(failing.received(), ["intervention.created"]);
+        // …which doesn't delay the other destinations.
+        assert_eq!(
+            working.received(),
+            ["intervention.created", "intervention.updated"]
+        );
+        assert_eq!(entries(ctx, OutboxStatus::Pending).await.len(), 2);
+
+        failing.failing.store(false, Ordering::SeqCst);
+        skip_delays(ctx).await;
+        assert_eq!(deliver_due(ctx).await.unwrap(), None);
+        assert_eq!(
+            failing.received(),
+            [
+                "intervention.created",
+                "intervention.created",
+                "intervention.updated"
+            ]
+        );
+        assert!(entries(ctx, OutboxStatus::Pending).await.is_empty());
+    }
+}
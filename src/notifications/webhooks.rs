@@ -0,0 +1,347 @@
+//! Outgoing webhooks: events are POSTed as JSON to the configured endpoints, signed with
+//! HMAC-SHA256.
+//!
+//! Every attempt is logged, so the administrators can see what was sent and what failed.
+
+use super::{is_wanted, render, Event, InterventionDetails, Notification};
+use crate::{
+    db::models::{
+        interventions::{Severity, Status},
+        preferences::{Preferences, SubscriptionKind},
+        services::Service,
+        webhooks::{Webhook, WebhookDelivery},
+    },
+    tokens, AppContext,
+};
+use anyhow::Context as _;
+use chrono::NaiveDateTime;
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use sqlx::AnyConnection;
+use tracing as log;
+
+/// Name of the channel, as used for the templates.
+const CHANNEL: &str = "webhook";
+
+/// Header containing the signature of the payload, as `sha256=<hex digest>`.
+const SIGNATURE_HEADER: &str = "X-Rustatouille-Signature";
+
+/// Header containing the name of the event.
+const EVENT_HEADER: &str = "X-Rustatouille-Event";
+
+#[derive(Serialize)]
+struct ServicePayload {
+    id: i64,
+    name: String,
+    url: String,
+}
+
+impl From<&Service> for ServicePayload {
+    fn from(s: &Service) -> Self {
+        Self {
+            id: s.id.unwrap(),
+            name: s.name.clone(),
+            url: s.url.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct UpdatePayload {
+    date: NaiveDateTime,
+    description: String,
+}
+
+#[derive(Serialize)]
+struct InterventionPayload {
+    id: i64,
+    title: String,
+    description: Option<String>,
+    status: Status,
+    severity: Severity,
+    is_planned: bool,
+    start_date: NaiveDateTime,
+    end_date: Option<NaiveDateTime>,
+    /// In minutes.
+    estimated_duration: Option<i64>,
+    services: Vec<ServicePayload>,
+    latest_update: Option<UpdatePayload>,
+}
+
+impl From<&InterventionDetails> for InterventionPayload {
+    fn from(details: &InterventionDetails) -> Self {
+        let int = &details.intervention;
+        Self {
+            id: int.id.unwrap(),
+            title: int.title.clone(),
+            description: int.description.clone(),
+            status: int.status,
+            severity: int.severity,
+            is_planned: int.is_planned,
+            start_date: int.start_date,
+            end_date: int.end_date,
+            estimated_duration: int.estimated_duration,
+            services: details.services.iter().map(From::from).collect(),
+            latest_update: details.updates.first().map(|c| UpdatePayload {
+                date: c.date,
+                description: c.description.clone(),
+            }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ServiceStatusPayload {
+    #[serde(flatten)]
+    service: ServicePayload,
+    previous_status: &'static str,
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+struct Payload {
+    event: &'static str,
+    date: NaiveDateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    intervention: Option<InterventionPayload>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    service: Option<ServiceStatusPayload>,
+    /// For reminders: how long before the start of the maintenance, in minutes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lead_time: Option<i64>,
+}
+
+async fn build_payload(app: &AppContext, notification: &Notification) -> anyhow::Result<Payload> {
+    let event = notification.event;
+    let service = match event {
+        Event::ServiceStatusChanged {
+            service_id,
+            previous,
+            current,
+        } => {
+            let mut conn = app.db_connection.lock().await;
+            Service::by_id(service_id, &mut conn)
+                .await?
+                .map(|service| ServiceStatusPayload {
+                    service: (&service).into(),
+                    previous_status: previous.as_str(),
+                    status: current.as_str(),
+                })
+        }
+        Event::InterventionCreated(_)
+        | Event::InterventionUpdated(_)
+        | Event::InterventionResolved(_)
+        | Event::InterventionReminder { .. } => None,
+    };
+
+    let lead_time = match event {
+        Event::InterventionReminder { lead_time, .. } => Some(lead_time),
+        _ => None,
+    };
+
+    Ok(Payload {
+        event: event.name(),
+        date: notification.date,
+        intervention: notification.details.as_ref().map(From::from),
+        service,
+        lead_time,
+    })
+}
+
+/// Sign the payload with the webhook's secret.
+fn sign(secret: &str, payload: &[u8]) -> String {
+    format!("sha256={}", tokens::sign(secret, payload))
+}
+
+/// Try to deliver the payload once; returns the HTTP status code, if any, and the error, if the
+/// delivery failed.
+async fn send_once(
+    app: &AppContext,
+    webhook: &Webhook,
+    event: &str,
+    payload: &str,
+) -> (Option<i64>, Option<String>) {
+    let res = app
+        .http_client
+        .post(&webhook.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, event)
+        .header(SIGNATURE_HEADER, sign(&webhook.secret, payload.as_bytes()))
+        .body(payload.to_owned())
+        .send()
+        .await;
+
+    match res {
+        Ok(response) => {
+            let status = response.status();
+            let error = (!status.is_success()).then(|| format!("unexpected status {status}"));
+            (Some(status.as_u16().into()), error)
+        }
+        Err(err) => (None, Some(format!("{err:#}"))),
+    }
+}
+
+/// Enabled webhooks interested in the event, according to their events and preferences.
+pub(super) async fn recipients(
+    conn: &mut AnyConnection,
+    event: Event,
+    details: Option<&InterventionDetails>,
+) -> anyhow::Result<Vec<i64>> {
+    let mut recipients = Vec::new();
+    for webhook in Webhook::get_all(conn).await? {
+        if !webhook.wants(event.name()) {
+            continue;
+        }
+        let id = webhook.id.unwrap();
+        let prefs = Preferences::read(SubscriptionKind::Webhook, id, conn).await?;
+        if is_wanted(&prefs, event, details) {
+            recipients.push(id);
+        }
+    }
+    Ok(recipients)
+}
+
+/// Deliver the notification to a single webhook, logging the attempt.
+///
+/// Webhooks which have been deleted in the meanwhile are silently skipped.
+pub(super) async fn deliver(
+    app: &AppContext,
+    webhook_id: i64,
+    notification: &Notification,
+    attempt: i64,
+) -> anyhow::Result<()> {
+    let webhook = {
+        let mut conn = app.db_connection.lock().await;
+        Webhook::by_id(webhook_id, &mut conn).await?
+    };
+    let Some(webhook) = webhook else {
+        return Ok(());
+    };
+
+    let event = notification.event.name();
+
+    // The templates get the default payload, which they can reshape as they see fit.
+    let mut ctx = notification.ctx(app)?;
+    ctx.insert("payload", &build_payload(app, notification).await?);
+    let payload = render(app, CHANNEL, notification.event, "json", &ctx)?;
+    serde_json::from_str::<serde_json::Value>(&payload)
+        .with_context(|| format!("the webhook template for {event} doesn't render valid JSON"))?;
+
+    let (status_code, error) = send_once(app, &webhook, event, &payload).await;
+
+    {
+        let mut conn = app.db_connection.lock().await;
+        let delivery = WebhookDelivery {
+            webhook_id,
+            event: event.to_owned(),
+            payload,
+            attempt,
+            date: chrono::Utc::now().naive_utc(),
+            status_code,
+            error: error.clone(),
+        };
+        if let Err(err) = WebhookDelivery::insert(&mut conn, &delivery).await {
+            log::error!("unable to log a webhook delivery: {err:#}");
+        }
+    }
+
+    match error {
+        Some(error) => Err(anyhow::anyhow!(error)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use axum::{body::Bytes, http::HeaderMap, routing::post, Router};
+    use hmac::{Hmac, Mac as _};
+    use sha2::Sha256;
+    use std::sync::{Arc, Mutex};
+
+    /// A request received by the mock endpoint.
+    struct Request {
+        headers: HeaderMap,
+        body: Bytes,
+    }
+
+    /// Start a mock endpoint, returning its URL and the requests it receives.
+    fn mock_endpoint() -> (String, Arc<Mutex<Vec<Request>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        let router = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: Bytes| {
+                let received = received.clone();
+                async move {
+                    received.lock().unwrap().push(Request { headers, body });
+                }
+            }),
+        );
+        (format!("{}/hook", testing::serve(router)), requests)
+    }
+
+    #[tokio::test]
+    async fn payloads_are_signed_json() {
+        let (url, requests) = mock_endpoint();
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let secret = "shared secret";
+        let webhook_id = {
+            let mut conn = ctx.db_connection.lock().await;
+            Webhook::insert(
+                &mut conn,
+                &Webhook {
+                    id: None,
+                    url,
+                    secret: secret.to_owned(),
+                    events: vec!["intervention.created".to_owned()],
+                    enabled: true,
+                },
+            )
+            .await
+            .unwrap()
+        };
+
+        // A title which would break naively written JSON, or be mangled by HTML escaping.
+        let title = "Forge \"down\" <again> & slow\\\nsee https://forge.example.org/?a=1";
+        let id = testing::insert_intervention(ctx, title).await;
+        testing::notify(ctx, Event::InterventionCreated(id)).await;
+
+        let requests = std::mem::take(&mut *requests.lock().unwrap());
+        assert_eq!(requests.len(), 1);
+        let request = &requests[0];
+        let header = |name: &str| request.headers[name].to_str().unwrap();
+        assert_eq!(header("content-type"), "application/json");
+        assert_eq!(header("x-rustatouille-event"), "intervention.created");
+
+        // The receiver can check the signature with the shared secret.
+        let signature = header("x-rustatouille-signature")
+            .strip_prefix("sha256=")
+            .unwrap();
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(&request.body);
+        assert_eq!(signature, hex::encode(mac.finalize().into_bytes()));
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"another secret").unwrap();
+        mac.update(&request.body);
+        assert_ne!(signature, hex::encode(mac.finalize().into_bytes()));
+
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["event"], "intervention.created");
+        assert_eq!(body["intervention"]["id"], id);
+        assert_eq!(body["intervention"]["title"], title);
+        assert_eq!(body["intervention"]["status"], "ongoing");
+        assert_eq!(body["intervention"]["severity"], "full-outage");
+        assert_eq!(body["intervention"]["services"][0]["name"], "Forge");
+
+        // The delivery is logged with what was sent.
+        let mut conn = ctx.db_connection.lock().await;
+        let deliveries = WebhookDelivery::get_recent(10, &mut conn).await.unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].webhook_id, webhook_id);
+        assert_eq!(deliveries[0].status_code, Some(200));
+        assert_eq!(deliveries[0].error, None);
+        assert_eq!(deliveries[0].payload.as_bytes(), &request.body[..]);
+    }
+}
@@ -1,16 +1,46 @@
 use crate::{
+    charts::{self, AvailabilityChart, ResponseTimeChart},
     db::models::{
-        interventions::{Intervention, ServiceId},
+        comments::Comment,
+        interventions::Intervention,
+        notified_statuses::NotifiedStatus,
+        probes::{ProbeAggregate, Resolution},
         services::Service,
     },
+    notifications::{self, Event},
+    status::{self, ComputedStatus, InterventionWithServices, PageStatus},
     AppContext,
 };
 use anyhow::Context as _;
+use chrono::NaiveDateTime;
 use serde::Serialize;
-use std::{collections::BTreeMap, fs, sync::Arc, time::Instant};
+use sqlx::{AnyConnection, Connection as _};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc;
 use tracing as log;
 
+/// Render context for an update posted on an intervention.
+#[derive(Clone, Serialize)]
+struct UpdateCtx {
+    date: String, // TODO?
+    description: String,
+}
+
+impl From<&Comment> for UpdateCtx {
+    fn from(c: &Comment) -> Self {
+        Self {
+            date: c.date.to_string(),
+            description: c.description.clone(),
+        }
+    }
+}
+
 /// Render context for a single intervention on a given service.
 #[derive(Clone, Serialize)]
 struct ServiceInterventionCtx {
@@ -19,17 +49,33 @@ struct ServiceInterventionCtx {
     start_date: String, // TODO?
     estimated_duration: String,
     description: Option<String>,
+    updates: Vec<UpdateCtx>,
+}
+
+/// Charts of the history of a service; each is missing if there's no data for its period.
+#[derive(Clone, Serialize)]
+struct ServiceChartsCtx {
+    /// Last 24 hours, for the tiles.
+    response_time_day: Option<ResponseTimeChart>,
+    /// Last 7 days, for the service page.
+    response_time_week: Option<ResponseTimeChart>,
+    /// Last 30 days, for the tiles.
+    availability_month: Option<AvailabilityChart>,
+    /// Last 90 days, for the service page.
+    availability_quarter: Option<AvailabilityChart>,
 }
 
 /// Render context for a given service.
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 struct ServiceCtx {
     id: i64,
     section_class: String,
+    status: String,
     url: String,
     title: String,
-    planned: Vec<ServiceInterventionCtx>,
-    ongoing: Vec<ServiceInterventionCtx>,
+    upcoming: Vec<ServiceInterventionCtx>,
+    active: Vec<ServiceInterventionCtx>,
+    charts: ServiceChartsCtx,
 }
 
 /// Render context for a service associated to a given intervention.
@@ -40,181 +86,440 @@ struct InterventionServiceDetailsCtx {
 }
 
 /// Render context for a given intervention.
+///
+/// Also used to render the notifications, so they show the same things as the pages.
 #[derive(Clone, Serialize)]
-struct InterventionCtx {
+pub(crate) struct InterventionCtx {
     id: i64,
     title: String,
     start_date: String, // TODO?
+    end_date: Option<String>,
+    status: String,
     severity_class: String,
     severity: String,
+    is_planned: bool,
     estimated_duration: String,
     rendered_description: String,
     services: Vec<InterventionServiceDetailsCtx>,
+    /// Most recent first.
+    updates: Vec<UpdateCtx>,
+}
+
+impl InterventionCtx {
+    /// Build the context of an intervention, given its services and its updates (most recent
+    /// first).
+    pub(crate) fn new(int: &Intervention, services: &[&Service], updates: &[Comment]) -> Self {
+        Self {
+            id: int.id.unwrap(),
+            title: int.title.clone(),
+            start_date: int.start_date.to_string(),
+            end_date: int.end_date.map(|d| d.to_string()),
+            status: int.status.label().to_owned(),
+            severity_class: int.severity.to_css_class().to_owned(),
+            severity: int.severity.label().to_owned(),
+            is_planned: int.is_planned,
+            estimated_duration: format_estimated_duration(int),
+            rendered_description: int
+                .description // TODO render as markdown?
+                .clone()
+                .unwrap_or_else(|| "<???>".to_owned()), // TODO???
+            services: services
+                .iter()
+                .map(|service| InterventionServiceDetailsCtx {
+                    id: service.id.unwrap(),
+                    title: service.name.clone(),
+                })
+                .collect(),
+            updates: updates.iter().map(From::from).collect(),
+        }
+    }
+}
+
+/// Render context for the status of the whole page.
+#[derive(Serialize)]
+struct OverallStatusCtx {
+    status: String,
+    css_class: String,
 }
 
 #[derive(Serialize)]
 struct RegenerateIndexCtx {
-    ongoing: Vec<InterventionCtx>,
-    planned: Vec<InterventionCtx>,
-    interventions: Vec<InterventionCtx>,
+    /// Should the form to subscribe by email be displayed?
+    subscriptions_enabled: bool,
+    overall: OverallStatusCtx,
+    active: Vec<InterventionCtx>,
+    upcoming: Vec<InterventionCtx>,
+    recently_resolved: Vec<InterventionCtx>,
     services: Vec<ServiceCtx>,
 }
 
-async fn regenerate_index(ctx: &Arc<AppContext>) -> anyhow::Result<()> {
-    log::debug!("regenerating the index");
-    let timer = Instant::now();
+#[derive(Serialize)]
+struct RegenerateHistoryCtx {
+    past: Vec<InterventionCtx>,
+}
+
+#[derive(Serialize)]
+struct RegenerateInterventionCtx {
+    intervention: InterventionCtx,
+}
+
+#[derive(Serialize)]
+struct RegenerateServiceCtx<'a> {
+    service: &'a ServiceCtx,
+    past: Vec<ServiceInterventionCtx>,
+}
 
-    let mut conn = ctx.db_connection.lock().await;
+/// All the data required to render the pages, read from the database at once.
+struct Snapshot {
+    services: Vec<Service>,
+    interventions: Vec<InterventionWithServices>,
+    /// Updates for each intervention, by intervention id.
+    updates: BTreeMap<i64, Vec<Comment>>,
+    /// Hourly aggregates of the probe results, over the longest period displayed.
+    hourly_aggregates: Vec<ProbeAggregate>,
+    /// Daily aggregates of the probe results, over the longest period displayed.
+    daily_aggregates: Vec<ProbeAggregate>,
+}
 
-    // Internally within a single category, sort by priority: full outage > partial > performance
-    let services = Service::get_all(&mut conn).await?;
+/// Number of hours displayed in the response time charts of the tiles.
+const TILE_CHART_HOURS: usize = 24;
+
+/// Number of hours displayed in the response time charts of the service pages.
+const PAGE_CHART_HOURS: usize = 7 * 24;
+
+/// Number of days displayed in the availability charts of the tiles.
+const TILE_CHART_DAYS: usize = 30;
+
+/// Number of days displayed in the availability charts of the service pages.
+const PAGE_CHART_DAYS: usize = 90;
+
+impl Snapshot {
+    async fn read(conn: &mut AnyConnection, now: NaiveDateTime) -> anyhow::Result<Self> {
+        let services = Service::get_all(conn).await?;
+
+        let hourly_aggregates = ProbeAggregate::get_since(
+            Resolution::Hour,
+            chart_start(Resolution::Hour, now, PAGE_CHART_HOURS),
+            conn,
+        )
+        .await?;
+        let daily_aggregates = ProbeAggregate::get_since(
+            Resolution::Day,
+            chart_start(Resolution::Day, now, PAGE_CHART_DAYS),
+            conn,
+        )
+        .await?;
+
+        let interventions = InterventionWithServices::get_all(conn).await?;
+        let mut updates = BTreeMap::new();
+        for int in &interventions {
+            let id = int.intervention.id.unwrap();
+            updates.insert(id, Comment::by_intervention(id, conn).await?);
+        }
 
-    let mut interventions = Intervention::get_all(&mut conn).await?;
+        Ok(Self {
+            services,
+            interventions,
+            updates,
+            hourly_aggregates,
+            daily_aggregates,
+        })
+    }
+
+    fn charts_ctx(&self, service_id: i64, now: NaiveDateTime) -> ServiceChartsCtx {
+        let hourly: Vec<_> = self
+            .hourly_aggregates
+            .iter()
+            .filter(|a| a.service_id == service_id)
+            .collect();
+        let daily: Vec<_> = self
+            .daily_aggregates
+            .iter()
+            .filter(|a| a.service_id == service_id)
+            .collect();
+
+        let response_time = |num_buckets| {
+            let from = chart_start(Resolution::Hour, now, num_buckets);
+            charts::response_time(&hourly, Resolution::Hour, from, num_buckets)
+        };
+        let availability = |num_buckets| {
+            let from = chart_start(Resolution::Day, now, num_buckets);
+            charts::availability(&daily, Resolution::Day, from, num_buckets)
+        };
 
-    // Sort interventions: most recent go first.
-    interventions.sort_by_key(|int| -int.start_date.timestamp());
+        ServiceChartsCtx {
+            response_time_day: response_time(TILE_CHART_HOURS),
+            response_time_week: response_time(PAGE_CHART_HOURS),
+            availability_month: availability(TILE_CHART_DAYS),
+            availability_quarter: availability(PAGE_CHART_DAYS),
+        }
+    }
 
-    let mut intervention_by_service: BTreeMap<ServiceId, Vec<&Intervention>> = BTreeMap::new();
+    fn updates(&self, id: i64) -> &[Comment] {
+        self.updates.get(&id).map_or(&[], Vec::as_slice)
+    }
 
-    let mut interventions_ctx = Vec::with_capacity(interventions.len());
-    for intervention in &interventions {
-        let affected_services =
-            Intervention::get_service_ids(intervention.id.unwrap(), &mut conn).await?;
+    fn service_intervention_ctx(&self, int: &InterventionWithServices) -> ServiceInterventionCtx {
+        let int = &int.intervention;
+        let id = int.id.unwrap();
+        ServiceInterventionCtx {
+            id,
+            title: int.title.clone(),
+            start_date: int.start_date.to_string(),
+            description: int.description.clone(), // TODO markdown
+            estimated_duration: format_estimated_duration(int),
+            updates: self.updates(id).iter().map(From::from).collect(),
+        }
+    }
 
+    fn intervention_ctx(&self, int: &InterventionWithServices) -> anyhow::Result<InterventionCtx> {
         // linear search ftw
-        let service_names = affected_services
-            .into_iter()
+        let services = int
+            .service_ids
+            .iter()
             .map(|service_id| {
-                intervention_by_service
-                    .entry(service_id)
-                    .or_default()
-                    .push(intervention);
-
-                let service = services
+                self.services
                     .iter()
                     .find(|service| service.id.unwrap() == service_id.0)
-                    .context("unknown service with id {sid}")?;
-
-                Ok(InterventionServiceDetailsCtx {
-                    id: service.id.unwrap(),
-                    title: service.name.clone(),
-                })
+                    .with_context(|| format!("unknown service with id {}", service_id.0))
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
 
-        interventions_ctx.push(InterventionCtx {
-            id: intervention.id.unwrap(),
-            title: intervention.title.clone(),
-            start_date: intervention.start_date.to_string(),
-            severity_class: intervention.severity.to_css_class().to_owned(),
-            severity: intervention.severity.label().to_owned(),
-            estimated_duration: intervention
-                .estimated_duration
-                .map(|int| format!("{int} minutes")) // TODO i18n
-                .unwrap_or_else(|| "unknown".to_owned()), // TODO i18n
-            rendered_description: intervention
-                .description // TODO render as markdown?
-                .clone()
-                .unwrap_or_else(|| "<???>".to_owned()), // TODO???
-            services: service_names,
-        });
+        let int = &int.intervention;
+        Ok(InterventionCtx::new(
+            int,
+            &services,
+            self.updates(int.id.unwrap()),
+        ))
     }
 
-    let mut services_ctx = Vec::with_capacity(services.len());
-    for s in &services {
-        let interventions = intervention_by_service
-            .get(&ServiceId(s.id.unwrap()))
-            .cloned()
-            .unwrap_or_default();
+    fn interventions_ctx(
+        &self,
+        interventions: &[&InterventionWithServices],
+    ) -> anyhow::Result<Vec<InterventionCtx>> {
+        interventions
+            .iter()
+            .map(|int| self.intervention_ctx(int))
+            .collect()
+    }
+}
 
-        let section_class = if interventions.iter().any(|i| i.is_ongoing()) {
-            "error"
-        } else if interventions.iter().any(|i| i.is_planned()) {
-            "warning"
-        } else {
-            "success"
-        };
+/// Start of a chart with the given number of buckets, the last one being the current one.
+fn chart_start(resolution: Resolution, now: NaiveDateTime, num_buckets: usize) -> NaiveDateTime {
+    resolution.bucket_start(now) - resolution.duration() * (num_buckets as i32 - 1)
+}
+
+fn format_estimated_duration(int: &Intervention) -> String {
+    int.estimated_duration
+        .map(|int| format!("{int} minutes")) // TODO i18n
+        .unwrap_or_else(|| "unknown".to_owned()) // TODO i18n
+}
+
+/// Render the given template into the given path, relative to the cache directory.
+fn render_page(
+    ctx: &AppContext,
+    template: &str,
+    render_ctx: &tera::Context,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let content = ctx.templates.read().unwrap().render(template, render_ctx)?;
+
+    let path = ctx.config.cache_dir.join(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
 
-        let ctx_interventions: Vec<_> = interventions
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// Notify about the services whose status changed since the last notification.
+///
+/// The notified statuses are stored along with the notifications, so that no change is missed
+/// across restarts, or when a generation is interrupted. New services are only recorded, since
+/// there's nothing to compare against.
+async fn notify_status_changes(ctx: &AppContext, page: &PageStatus<'_>) -> anyhow::Result<()> {
+    let changed = {
+        let mut conn = ctx.db_connection.lock().await;
+        let mut tx = conn.begin().await?;
+
+        let previous_statuses: BTreeMap<_, _> = NotifiedStatus::get_all(&mut tx)
+            .await?
             .into_iter()
-            .map(|int| {
-                let int_ctx = ServiceInterventionCtx {
-                    id: int.id.unwrap(),
-                    title: int.title.clone(),
-                    start_date: int.start_date.to_string(),
-                    description: int.description.clone(), // TODO markdown
-                    estimated_duration: int
-                        .estimated_duration
-                        .map(|int| format!("{int} minutes")) // TODO i18n
-                        .unwrap_or_else(|| "unknown".to_owned()), // TODO i18n
-                };
-                (int.is_ongoing(), int_ctx)
-            })
+            .filter_map(|s| Some((s.service_id, ComputedStatus::from_str(&s.status)?)))
             .collect();
 
-        services_ctx.push(ServiceCtx {
-            id: s.id.unwrap(),
-            section_class: section_class.to_owned(),
-            url: s.url.clone(),
-            title: s.name.clone(),
+        let mut changed = false;
+        for service in &page.services {
+            let service_id = service.service.id.unwrap();
+            let current = service.status;
+            match previous_statuses.get(&service_id) {
+                Some(&previous) if previous == current => continue,
+                Some(&previous) => {
+                    let event = Event::ServiceStatusChanged {
+                        service_id,
+                        previous,
+                        current,
+                    };
+                    notifications::enqueue(ctx, &mut tx, event).await?;
+                    changed = true;
+                }
+                None => {}
+            }
+            NotifiedStatus::save(
+                &mut tx,
+                &NotifiedStatus {
+                    service_id,
+                    status: current.as_str().to_owned(),
+                },
+            )
+            .await?;
+        }
 
-            planned: ctx_interventions
-                .iter()
-                .filter_map(|(is_ongoing, int)| (!is_ongoing).then_some(int))
-                .cloned()
-                .collect(),
+        tx.commit().await?;
+        changed
+    };
 
-            ongoing: ctx_interventions
-                .into_iter()
-                .filter_map(|(is_ongoing, int)| is_ongoing.then_some(int))
-                .collect(),
-        });
+    if changed {
+        ctx.wake_outbox.send(()).await?;
     }
 
-    // Current interventions are sorted because interventions are sorted.
-    let mut ongoing_ctx = Vec::new();
-    let mut planned_ctx = Vec::new();
-    for (int, ctx) in interventions.iter().zip(interventions_ctx.iter()) {
-        if int.is_ongoing() {
-            ongoing_ctx.push(ctx.clone());
-        } else {
-            planned_ctx.push(ctx.clone());
-        }
-    }
+    Ok(())
+}
 
+/// How long to sleep when the pages can't get stale by themselves. Anything that could change
+/// this will ask for a regeneration anyways.
+const IDLE_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// When the index gets stale by itself, because the oldest of the recently resolved interventions
+/// it shows leaves the retention window.
+fn stale_at(
+    recently_resolved: &[&InterventionWithServices],
+    retention: chrono::Duration,
+) -> Option<NaiveDateTime> {
+    // Most recently finished first; past the date, it's left out of the index.
+    let oldest = recently_resolved.last()?;
+    Some(status::finished_at(&oldest.intervention) + retention + chrono::Duration::seconds(1))
+}
+
+/// Regenerate all the pages, and return the date at which they get stale by themselves, if any.
+async fn regenerate_all(ctx: &Arc<AppContext>) -> anyhow::Result<Option<NaiveDateTime>> {
+    log::debug!("regenerating the pages");
+
+    let now = chrono::Utc::now().naive_utc();
+
+    let snapshot = {
+        let mut conn = ctx.db_connection.lock().await;
+        Snapshot::read(&mut conn, now).await?
+    };
+
+    let page = status::compute(&snapshot.services, &snapshot.interventions, now);
+
+    notify_status_changes(ctx, &page).await?;
+
+    let services_ctx: Vec<_> = page
+        .services
+        .iter()
+        .map(|s| ServiceCtx {
+            id: s.service.id.unwrap(),
+            section_class: s.status.css_class().to_owned(),
+            status: s.status.as_str().to_owned(),
+            url: s.service.url.clone(),
+            title: s.service.name.clone(),
+            upcoming: s
+                .interventions
+                .upcoming
+                .iter()
+                .map(|int| snapshot.service_intervention_ctx(int))
+                .collect(),
+            active: s
+                .interventions
+                .active
+                .iter()
+                .map(|int| snapshot.service_intervention_ctx(int))
+                .collect(),
+            charts: snapshot.charts_ctx(s.service.id.unwrap(), now),
+        })
+        .collect();
+
+    // Index: only show what's happening now, will happen, or has recently been resolved.
+    let recently_resolved = page
+        .interventions
+        .finished_since(now - ctx.config.resolved_retention);
+    let stale_at = stale_at(recently_resolved, ctx.config.resolved_retention);
     let index_ctx = tera::Context::from_serialize(RegenerateIndexCtx {
-        ongoing: ongoing_ctx,
-        planned: planned_ctx,
-        interventions: interventions_ctx,
-        services: services_ctx,
+        subscriptions_enabled: ctx.mailer.is_some(),
+        overall: OverallStatusCtx {
+            status: page.overall.as_str().to_owned(),
+            css_class: page.overall.css_class().to_owned(),
+        },
+        active: snapshot.interventions_ctx(&page.interventions.active)?,
+        upcoming: snapshot.interventions_ctx(&page.interventions.upcoming)?,
+        recently_resolved: snapshot.interventions_ctx(recently_resolved)?,
+        services: services_ctx.clone(),
     })?;
+    render_page(ctx, "index.html", &index_ctx, Path::new("index.html"))?;
 
-    let index_content = ctx
-        .templates
-        .read()
-        .unwrap()
-        .render("index.html", &index_ctx)?;
-
-    fs::write(ctx.config.cache_dir.join("index.html"), index_content)?;
+    // History: all the past interventions.
+    let history_ctx = tera::Context::from_serialize(RegenerateHistoryCtx {
+        past: snapshot.interventions_ctx(&page.interventions.past)?,
+    })?;
+    render_page(
+        ctx,
+        "history.html",
+        &history_ctx,
+        Path::new("history/index.html"),
+    )?;
+
+    // One page per service, with its full history.
+    for (s, service_ctx) in page.services.iter().zip(services_ctx.iter()) {
+        let service_page_ctx = tera::Context::from_serialize(RegenerateServiceCtx {
+            service: service_ctx,
+            past: s
+                .interventions
+                .past
+                .iter()
+                .map(|int| snapshot.service_intervention_ctx(int))
+                .collect(),
+        })?;
+        render_page(
+            ctx,
+            "service.html",
+            &service_page_ctx,
+            &Path::new("service")
+                .join(service_ctx.id.to_string())
+                .join("index.html"),
+        )?;
+    }
 
-    log::debug!(
-        "regenerating the index took {}ms",
-        timer.elapsed().as_millis()
-    );
+    // One permalink page per intervention, so notifications can link to it.
+    for int in &snapshot.interventions {
+        let intervention = snapshot.intervention_ctx(int)?;
+        let path = Path::new("intervention")
+            .join(intervention.id.to_string())
+            .join("index.html");
+        let intervention_page_ctx =
+            tera::Context::from_serialize(RegenerateInterventionCtx { intervention })?;
+        render_page(ctx, "intervention.html", &intervention_page_ctx, &path)?;
+    }
 
-    Ok(())
+    Ok(stale_at)
 }
 
 pub(crate) async fn pages(app: Arc<AppContext>, mut receiver: mpsc::Receiver<()>) {
     let mut start = false;
 
+    // When the pages get stale by themselves, as of the last generation.
+    let mut stale_at = None;
+
     // Small mechanism to regenerate all the pages, at most once at a time:
-    // - either wait for a start message,
+    // - either wait for a start message, or for the pages to get stale,
     // - or, start a task and wait for another start message; if the latter arrives, then restart
     // the loop immediately.
 
     loop {
         if start {
+            let timer = Instant::now();
             tokio::select! {
                 biased;
 
@@ -224,25 +529,184 @@ pub(crate) async fn pages(app: Arc<AppContext>, mut receiver: mpsc::Receiver<()>
                     continue;
                 }
 
-                res = regenerate_index(&app) => {
+                res = regenerate_all(&app) => {
                     start = false;
-                    if let Err(err) = res {
-                        log::error!("Unable to render the index: {err:#}");
-                    }
+                    let elapsed = timer.elapsed();
+                    log::debug!("regenerating the pages took {}ms", elapsed.as_millis());
+                    let now = chrono::Utc::now().naive_utc();
+                    app.metrics.record_regeneration(now, res.is_ok(), elapsed);
+                    stale_at = match res {
+                        Ok(stale_at) => stale_at,
+                        Err(err) => {
+                            log::error!("Unable to render the pages: {err:#}");
+                            None
+                        }
+                    };
                 }
             }
         } else {
-            let received = receiver.recv().await;
-            match received {
-                Some(_) => {
-                    // On the next iteration, start an actual regenerate task.
-                    start = true;
+            let delay = match stale_at {
+                Some(date) => {
+                    let now = chrono::Utc::now().naive_utc();
+                    // A negative duration means the pages are already stale.
+                    (date - now).to_std().unwrap_or(Duration::ZERO)
                 }
-                None => {
-                    // okthxbye
-                    break;
+                None => IDLE_DELAY,
+            };
+
+            tokio::select! {
+                received = receiver.recv() => {
+                    match received {
+                        Some(_) => {
+                            // On the next iteration, start an actual regenerate task.
+                            start = true;
+                        }
+                        None => {
+                            // okthxbye
+                            break;
+                        }
+                    }
+                }
+
+                _ = tokio::time::sleep(delay) => {
+                    start = stale_at.is_some();
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::models::{
+            interventions::{Severity, Status},
+            outbox::{OutboxEntry, OutboxStatus},
+            webhooks::Webhook,
+        },
+        testing,
+    };
+
+    /// Compute the page as the generation does, and notify about the changes.
+    async fn notify(ctx: &AppContext) {
+        let now = chrono::Utc::now().naive_utc();
+        let snapshot = {
+            let mut conn = ctx.db_connection.lock().await;
+            Snapshot::read(&mut conn, now).await.unwrap()
+        };
+        let page = status::compute(&snapshot.services, &snapshot.interventions, now);
+        notify_status_changes(ctx, &page).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn status_changes_are_compared_with_the_notified_ones() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        let service_id = testing::insert_service(ctx, "Forge", "https://forge.example.org").await;
+        {
+            let mut conn = ctx.db_connection.lock().await;
+            Webhook::insert(
+                &mut conn,
+                &Webhook {
+                    id: None,
+                    url: "https://hooks.example.org".to_owned(),
+                    secret: "secret".to_owned(),
+                    events: vec!["service.status_changed".to_owned()],
+                    enabled: true,
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        // A new service is only recorded.
+        notify(ctx).await;
+        let mut conn = ctx.db_connection.lock().await;
+        assert!(OutboxEntry::get_by_status(OutboxStatus::Pending, &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // The change happens while no generation runs, e.g. across a restart.
+        let id = Intervention::insert(
+            &mut conn,
+            &Intervention {
+                id: None,
+                start_date: chrono::Utc::now().naive_utc() - chrono::Duration::minutes(1),
+                estimated_duration: None,
+                end_date: None,
+                status: Status::Ongoing,
+                severity: Severity::FullOutage,
+                is_planned: false,
+                auto_resolve: false,
+                title: "Panne".to_owned(),
+                description: None,
+            },
+        )
+        .await
+        .unwrap();
+        Intervention::add_service(id, service_id, &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+
+        notify(ctx).await;
+        let mut conn = ctx.db_connection.lock().await;
+        let statuses = NotifiedStatus::get_all(&mut conn).await.unwrap();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].status, ComputedStatus::FullOutage.as_str());
+        let pending = OutboxEntry::get_by_status(OutboxStatus::Pending, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(pending.len(), 1);
+        drop(conn);
+
+        // The change is announced once.
+        notify(ctx).await;
+        let mut conn = ctx.db_connection.lock().await;
+        let pending = OutboxEntry::get_by_status(OutboxStatus::Pending, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(pending.len(), 1);
+    }
+
+    #[test]
+    fn index_gets_stale_when_resolved_interventions_age_off() {
+        let now = chrono::Utc::now().naive_utc();
+        let retention = chrono::Duration::days(7);
+        let resolved = |id, days_ago| InterventionWithServices {
+            intervention: Intervention {
+                id: Some(id),
+                start_date: now - chrono::Duration::days(days_ago + 1),
+                estimated_duration: None,
+                end_date: Some(now - chrono::Duration::days(days_ago)),
+                status: Status::Resolved,
+                severity: Severity::PartialOutage,
+                is_planned: false,
+                auto_resolve: false,
+                title: "Panne".to_owned(),
+                description: None,
+            },
+            service_ids: Vec::new(),
+        };
+        let interventions = [resolved(1, 1), resolved(2, 3), resolved(3, 10)];
+
+        let page = status::compute(&[], &interventions, now);
+        let recently_resolved = page.interventions.finished_since(now - retention);
+        assert_eq!(recently_resolved.len(), 2);
+
+        // The one resolved 3 days ago leaves first; the older one is already gone.
+        let date = stale_at(recently_resolved, retention).unwrap();
+        assert!(date > now + chrono::Duration::days(4));
+        assert!(date <= now + chrono::Duration::days(4) + chrono::Duration::seconds(1));
+
+        let later = status::compute(&[], &interventions, date);
+        assert_eq!(
+            later.interventions.finished_since(date - retention).len(),
+            1
+        );
+
+        assert_eq!(stale_at(&[], retention), None);
+    }
+}
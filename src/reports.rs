@@ -0,0 +1,477 @@
+//! Availability reports: uptime, number of incidents and mean time to recovery of each service
+//! over a period, computed from the interventions.
+
+use anyhow::Context as _;
+use chrono::{Datelike as _, Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use sqlx::AnyConnection;
+use std::fmt::Write as _;
+
+use crate::{
+    db::models::{
+        interventions::{Intervention, ServiceId, Severity, Status},
+        services::Service,
+    },
+    status::InterventionWithServices,
+};
+
+/// Format of the dates of the reports' parameters.
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Format of the months of the reports' parameters.
+const MONTH_FORMAT: &str = "%Y-%m";
+
+/// Longest period a report can cover, in days.
+const MAX_PERIOD_DAYS: i64 = 366;
+
+/// Parameters of a report, as given in the query string of the admin page or on the command line.
+///
+/// All of them are optional: by default, the report covers the previous month.
+#[derive(Default, Deserialize)]
+pub(crate) struct ReportParams {
+    /// Month to report on, as `YYYY-MM`.
+    pub month: Option<String>,
+    /// First day of the period, as `YYYY-MM-DD`; overrides the month, along with `to`.
+    pub from: Option<String>,
+    /// Last day of the period, included, as `YYYY-MM-DD`.
+    pub to: Option<String>,
+    #[serde(rename = "weight-full-outage")]
+    pub weight_full_outage: Option<String>,
+    #[serde(rename = "weight-partial-outage")]
+    pub weight_partial_outage: Option<String>,
+    #[serde(rename = "weight-performance-issue")]
+    pub weight_performance_issue: Option<String>,
+    /// Set (to anything) to ignore planned maintenances.
+    #[serde(rename = "exclude-planned")]
+    pub exclude_planned: Option<String>,
+    /// Format of the report: `html` (in the admin only), `csv` or `json`.
+    pub format: Option<String>,
+}
+
+impl ReportParams {
+    /// Read the parameters from command line arguments, like `--month 2024-05 --exclude-planned`.
+    pub fn from_args(args: &[String]) -> anyhow::Result<Self> {
+        let mut params = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let name = arg
+                .strip_prefix("--")
+                .with_context(|| format!("unexpected argument: {arg}"))?;
+            if name == "exclude-planned" {
+                params.exclude_planned = Some("on".to_owned());
+                continue;
+            }
+            let value = args
+                .next()
+                .with_context(|| format!("missing value for --{name}"))?
+                .clone();
+            let field = match name {
+                "month" => &mut params.month,
+                "from" => &mut params.from,
+                "to" => &mut params.to,
+                "weight-full-outage" => &mut params.weight_full_outage,
+                "weight-partial-outage" => &mut params.weight_partial_outage,
+                "weight-performance-issue" => &mut params.weight_performance_issue,
+                "format" => &mut params.format,
+                _ => anyhow::bail!("unknown option: --{name}"),
+            };
+            *field = Some(value);
+        }
+        Ok(params)
+    }
+}
+
+/// Part of the time during which an intervention of a given severity counts as downtime.
+#[derive(Clone, Copy, Serialize)]
+pub(crate) struct Weights {
+    pub full_outage: f64,
+    pub partial_outage: f64,
+    pub performance_issue: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            full_outage: 1.0,
+            partial_outage: 0.5,
+            performance_issue: 0.0,
+        }
+    }
+}
+
+impl Weights {
+    fn of(&self, severity: Severity) -> f64 {
+        match severity {
+            Severity::FullOutage => self.full_outage,
+            Severity::PartialOutage => self.partial_outage,
+            Severity::PerformanceIssue => self.performance_issue,
+        }
+    }
+}
+
+/// What a report covers, and how.
+pub(crate) struct ReportOptions {
+    /// Start of the period.
+    pub from: NaiveDate,
+    /// Last day of the period, included.
+    pub to: NaiveDate,
+    /// Day after the period, at which it ends.
+    end: NaiveDate,
+    pub weights: Weights,
+    pub exclude_planned: bool,
+}
+
+/// Parse an optional weight, which must be between 0 and 1.
+fn parse_weight(value: Option<&String>, default: f64, name: &str) -> anyhow::Result<f64> {
+    let Some(value) = value
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty())
+    else {
+        return Ok(default);
+    };
+    match value.parse::<f64>() {
+        Ok(weight) if (0.0..=1.0).contains(&weight) => Ok(weight),
+        _ => anyhow::bail!("{name} must be a number between 0 and 1"),
+    }
+}
+
+/// First day of the month containing the given date.
+fn first_day_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap()
+}
+
+/// Last day of the month containing the given date, unless it's the last representable month.
+fn last_day_of_month(date: NaiveDate) -> Option<NaiveDate> {
+    let first_day = first_day_of_month(date);
+    let next_month = if first_day.month() == 12 {
+        NaiveDate::from_ymd_opt(first_day.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(first_day.year(), first_day.month() + 1, 1)
+    };
+    next_month?.pred_opt()
+}
+
+impl ReportOptions {
+    pub fn new(params: &ReportParams, now: NaiveDateTime) -> anyhow::Result<Self> {
+        let non_empty = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_owned)
+        };
+
+        let (from, to) = match (non_empty(&params.from), non_empty(&params.to)) {
+            (Some(from), Some(to)) => {
+                let from = NaiveDate::parse_from_str(&from, DATE_FORMAT)
+                    .context("the start of the period must be a date, as YYYY-MM-DD")?;
+                let to = NaiveDate::parse_from_str(&to, DATE_FORMAT)
+                    .context("the end of the period must be a date, as YYYY-MM-DD")?;
+                (from, to)
+            }
+            (None, None) => {
+                let month = match non_empty(&params.month) {
+                    Some(month) => NaiveDate::parse_from_str(&format!("{month}-01"), DATE_FORMAT)
+                        .context("the month must be given as YYYY-MM")?,
+                    None => first_day_of_month(now.date()).pred_opt().unwrap(),
+                };
+                let last_day =
+                    last_day_of_month(month).context("the month is too far in the future")?;
+                (first_day_of_month(month), last_day)
+            }
+            _ => anyhow::bail!("both the start and the end of the period must be given"),
+        };
+        if to < from {
+            anyhow::bail!("the end of the period must not be before its start");
+        }
+        if (to - from).num_days() >= MAX_PERIOD_DAYS {
+            anyhow::bail!("the period must not be longer than {MAX_PERIOD_DAYS} days");
+        }
+        let end = to
+            .succ_opt()
+            .context("the end of the period is too far in the future")?;
+
+        let defaults = Weights::default();
+        let weights = Weights {
+            full_outage: parse_weight(
+                params.weight_full_outage.as_ref(),
+                defaults.full_outage,
+                "the weight of full outages",
+            )?,
+            partial_outage: parse_weight(
+                params.weight_partial_outage.as_ref(),
+                defaults.partial_outage,
+                "the weight of partial outages",
+            )?,
+            performance_issue: parse_weight(
+                params.weight_performance_issue.as_ref(),
+                defaults.performance_issue,
+                "the weight of performance issues",
+            )?,
+        };
+
+        Ok(Self {
+            from,
+            to,
+            end,
+            weights,
+            exclude_planned: params.exclude_planned.is_some(),
+        })
+    }
+
+    /// Month covered by the report, if it covers exactly one.
+    pub fn month(&self) -> Option<String> {
+        (self.from == first_day_of_month(self.from)
+            && Some(self.to) == last_day_of_month(self.from))
+        .then(|| self.from.format(MONTH_FORMAT).to_string())
+    }
+
+    /// Query string giving these options, for links to the exports.
+    pub fn to_query(&self) -> String {
+        let mut query = format!(
+            "from={}&to={}&weight-full-outage={}&weight-partial-outage={}&weight-performance-issue={}",
+            self.from.format(DATE_FORMAT),
+            self.to.format(DATE_FORMAT),
+            self.weights.full_outage,
+            self.weights.partial_outage,
+            self.weights.performance_issue,
+        );
+        if self.exclude_planned {
+            query.push_str("&exclude-planned=on");
+        }
+        query
+    }
+}
+
+/// Availability of a single service over the period of a report.
+#[derive(Serialize)]
+pub(crate) struct ServiceReport {
+    pub service_id: i64,
+    pub service: String,
+    /// Percentage of the elapsed part of the period during which the service was available; none
+    /// if the period hasn't started yet.
+    pub uptime_percent: Option<f64>,
+    /// Time during which the service was unavailable, weighted by the severity of the
+    /// interventions.
+    pub downtime_minutes: f64,
+    /// Number of unplanned interventions which started during the period.
+    pub incidents: usize,
+    /// Mean time to recovery of those incidents which have been resolved.
+    pub mttr_minutes: Option<f64>,
+}
+
+/// Availability of all the services over a period.
+#[derive(Serialize)]
+pub(crate) struct Report {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub weights: Weights,
+    pub exclude_planned: bool,
+    pub services: Vec<ServiceReport>,
+}
+
+/// When the intervention stopped affecting its services, as far as we know at the given date.
+fn end_of(int: &Intervention, now: NaiveDateTime) -> NaiveDateTime {
+    match int.end_date {
+        Some(end_date) => end_date,
+        None if int.status == Status::Resolved => int.start_date,
+        None => now,
+    }
+}
+
+fn minutes(duration: Duration) -> f64 {
+    duration.num_seconds() as f64 / 60.0
+}
+
+/// Downtime over `[from, to)`, weighted by the worst severity at any time, so overlapping
+/// interventions aren't counted twice.
+fn weighted_downtime(
+    interventions: &[&Intervention],
+    weights: &Weights,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    now: NaiveDateTime,
+) -> Duration {
+    let intervals: Vec<_> = interventions
+        .iter()
+        .map(|int| {
+            (
+                int.start_date.max(from),
+                end_of(int, now).min(to),
+                weights.of(int.severity),
+            )
+        })
+        .filter(|(start, end, weight)| start < end && *weight > 0.0)
+        .collect();
+
+    let mut boundaries: Vec<_> = intervals
+        .iter()
+        .flat_map(|(start, end, _)| [*start, *end])
+        .collect();
+    boundaries.sort();
+    boundaries.dedup();
+
+    let mut seconds = 0.0;
+    for window in boundaries.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        let weight = intervals
+            .iter()
+            .filter(|(int_start, int_end, _)| *int_start <= start && end <= *int_end)
+            .map(|(_, _, weight)| *weight)
+            .fold(0.0, f64::max);
+        seconds += weight * (end - start).num_seconds() as f64;
+    }
+    Duration::seconds(seconds.round() as i64)
+}
+
+/// Compute the report of every service, as of the given date.
+pub(crate) fn compute(
+    services: &[Service],
+    interventions: &[InterventionWithServices],
+    options: &ReportOptions,
+    now: NaiveDateTime,
+) -> Report {
+    let from = options.from.and_time(NaiveTime::MIN);
+    let to = options.end.and_time(NaiveTime::MIN);
+    // Only the past can be reported on.
+    let elapsed_to = to.min(now);
+
+    let services = services
+        .iter()
+        .map(|service| {
+            let service_id = ServiceId(service.id.unwrap());
+            let interventions: Vec<_> = interventions
+                .iter()
+                .filter(|int| int.service_ids.contains(&service_id))
+                .map(|int| &int.intervention)
+                .filter(|int| !(options.exclude_planned && int.is_planned))
+                .collect();
+
+            let uptime_percent = (from < elapsed_to).then(|| {
+                let downtime =
+                    weighted_downtime(&interventions, &options.weights, from, elapsed_to, now);
+                let period = (elapsed_to - from).num_seconds() as f64;
+                100.0 * (1.0 - downtime.num_seconds() as f64 / period)
+            });
+            let downtime = weighted_downtime(&interventions, &options.weights, from, to, now);
+
+            let incidents: Vec<_> = interventions
+                .iter()
+                .filter(|int| !int.is_planned && from <= int.start_date && int.start_date < to)
+                .collect();
+            let recovery_times: Vec<_> = incidents
+                .iter()
+                .filter(|int| int.status == Status::Resolved)
+                .map(|int| end_of(int, now) - int.start_date)
+                .collect();
+            let mttr_minutes = (!recovery_times.is_empty()).then(|| {
+                recovery_times.iter().map(|d| minutes(*d)).sum::<f64>()
+                    / recovery_times.len() as f64
+            });
+
+            ServiceReport {
+                service_id: service_id.0,
+                service: service.name.clone(),
+                uptime_percent,
+                downtime_minutes: minutes(downtime),
+                incidents: incidents.len(),
+                mttr_minutes,
+            }
+        })
+        .collect();
+
+    Report {
+        from: options.from,
+        to: options.to,
+        weights: options.weights,
+        exclude_planned: options.exclude_planned,
+        services,
+    }
+}
+
+/// Read the services and the interventions, and compute the report.
+pub(crate) async fn generate(
+    options: &ReportOptions,
+    now: NaiveDateTime,
+    conn: &mut AnyConnection,
+) -> anyhow::Result<Report> {
+    let services = Service::get_all(conn).await?;
+    let interventions = InterventionWithServices::get_all(conn).await?;
+    Ok(compute(&services, &interventions, options, now))
+}
+
+/// Quote a CSV field, if needed.
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+impl Report {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "service_id,service,from,to,uptime_percent,downtime_minutes,incidents,mttr_minutes\n",
+        );
+        for s in &self.services {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{:.1},{},{}",
+                s.service_id,
+                csv_field(&s.service),
+                self.from.format(DATE_FORMAT),
+                self.to.format(DATE_FORMAT),
+                s.uptime_percent
+                    .map(|uptime| format!("{uptime:.3}"))
+                    .unwrap_or_default(),
+                s.downtime_minutes,
+                s.incidents,
+                s.mttr_minutes
+                    .map(|mttr| format!("{mttr:.1}"))
+                    .unwrap_or_default(),
+            );
+        }
+        csv
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(from: &str, to: &str) -> anyhow::Result<ReportOptions> {
+        let params = ReportParams {
+            from: Some(from.to_owned()),
+            to: Some(to.to_owned()),
+            ..Default::default()
+        };
+        ReportOptions::new(&params, chrono::Utc::now().naive_utc())
+    }
+
+    #[test]
+    fn invalid_periods_are_rejected() {
+        let year = options("2024-01-01", "2024-12-31").unwrap();
+        assert_eq!(year.end, NaiveDate::from_ymd_opt(2025, 1, 1).unwrap());
+
+        assert!(options("2024-03-01", "2024-02-01").is_err());
+        assert!(options("2024-01-01", "2025-01-01").is_err());
+
+        let last = NaiveDate::MAX.format(DATE_FORMAT).to_string();
+        let err = options(&last, &last).err().unwrap();
+        assert!(err.to_string().contains("too far in the future"));
+
+        let params = ReportParams {
+            month: Some(NaiveDate::MAX.format(MONTH_FORMAT).to_string()),
+            ..Default::default()
+        };
+        let err = ReportOptions::new(&params, chrono::Utc::now().naive_utc())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("too far in the future"));
+    }
+}
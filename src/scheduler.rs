@@ -0,0 +1,550 @@
+use crate::{
+    audit::Actor,
+    db::models::{
+        interventions::{Intervention, Status},
+        reminders::Reminder,
+    },
+    interventions,
+    notifications::{self, Event},
+    AppContext,
+};
+use chrono::NaiveDateTime;
+use sqlx::{AnyConnection, Connection as _};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+use tracing as log;
+
+/// How long to wait before trying again, after a failure.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How long to sleep when there's nothing to schedule. Anything that could change this will wake
+/// up the scheduler anyways.
+const IDLE_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// (Re)schedule the reminders of an intervention, after it's been created or modified.
+///
+/// Reminders which haven't been sent yet are replaced, so that they follow the new start date.
+/// Those which would already be due are skipped: the intervention has just been announced anyways.
+pub(crate) async fn schedule_reminders(
+    app: &AppContext,
+    conn: &mut AnyConnection,
+    intervention_id: i64,
+    int: &Intervention,
+) -> anyhow::Result<()> {
+    Reminder::delete_pending_for(intervention_id, conn).await?;
+
+    if int.status != Status::Planned {
+        return Ok(());
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    for &lead_time in &app.config.reminder_lead_times {
+        let remind_at = int.start_date - chrono::Duration::minutes(lead_time);
+        if remind_at <= now {
+            continue;
+        }
+        Reminder::insert(
+            conn,
+            &Reminder {
+                id: None,
+                intervention_id,
+                lead_time,
+                remind_at,
+                sent_at: None,
+            },
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Apply all the transitions that are due at the given date, and return the date of the next one,
+/// if any.
+async fn run_due_transitions(
+    app: &Arc<AppContext>,
+    now: NaiveDateTime,
+) -> anyhow::Result<Option<NaiveDateTime>> {
+    let mut next_date: Option<NaiveDateTime> = None;
+    let mut changed = false;
+    let mut reminded = false;
+
+    {
+        let mut conn = app.db_connection.lock().await;
+
+        // Send the reminders about the planned maintenances.
+        let mut due_reminders: BTreeMap<i64, Vec<Reminder>> = BTreeMap::new();
+        for reminder in Reminder::get_pending(&mut conn).await? {
+            if reminder.remind_at > now {
+                next_date =
+                    Some(next_date.map_or(reminder.remind_at, |d| d.min(reminder.remind_at)));
+                continue;
+            }
+            due_reminders
+                .entry(reminder.intervention_id)
+                .or_default()
+                .push(reminder);
+        }
+
+        for (id, reminders) in due_reminders {
+            // If several reminders are due, e.g. after a downtime, only the latest one is worth
+            // sending.
+            let lead_time = reminders.iter().map(|r| r.lead_time).min().unwrap();
+
+            let int = Intervention::by_id(id, &mut conn).await?;
+            let still_planned =
+                int.is_some_and(|int| int.status == Status::Planned && int.start_date > now);
+
+            let mut tx = conn.begin().await?;
+            if still_planned {
+                log::info!("sending a reminder for planned intervention {id}");
+                notifications::enqueue(
+                    app,
+                    &mut tx,
+                    Event::InterventionReminder {
+                        intervention_id: id,
+                        lead_time,
+                    },
+                )
+                .await?;
+                reminded = true;
+            }
+            for reminder in &reminders {
+                Reminder::mark_sent(reminder.id.unwrap(), now, &mut tx).await?;
+            }
+            tx.commit().await?;
+        }
+
+        // Start the planned maintenances.
+        for int in Intervention::get_by_status(Status::Planned, &mut conn).await? {
+            if int.start_date > now {
+                next_date = Some(next_date.map_or(int.start_date, |d| d.min(int.start_date)));
+                continue;
+            }
+
+            let id = int.id.unwrap();
+            log::info!("starting planned intervention {id}");
+            let mut tx = conn.begin().await?;
+            let started = Intervention {
+                status: Status::Ongoing,
+                end_date: None,
+                ..int.clone()
+            };
+            let update = "Début de la maintenance.".to_owned(); // TODO i18n
+            interventions::update(
+                app,
+                &mut tx,
+                &Actor::system(),
+                id,
+                &started,
+                None,
+                Some(update),
+            )
+            .await?;
+            tx.commit().await?;
+            changed = true;
+
+            // It might have to be resolved later.
+            if let Some(end_date) = int.auto_resolve_date() {
+                next_date = Some(next_date.map_or(end_date, |d| d.min(end_date)));
+            }
+        }
+
+        // Resolve the planned maintenances which are over.
+        for status in [
+            Status::Ongoing,
+            Status::Identified,
+            Status::UnderSurveillance,
+        ] {
+            for int in Intervention::get_by_status(status, &mut conn).await? {
+                let Some(end_date) = int.auto_resolve_date() else {
+                    continue;
+                };
+
+                if end_date > now {
+                    next_date = Some(next_date.map_or(end_date, |d| d.min(end_date)));
+                    continue;
+                }
+
+                let id = int.id.unwrap();
+                log::info!("automatically resolving intervention {id}");
+                let mut tx = conn.begin().await?;
+                let resolved = Intervention {
+                    status: Status::Resolved,
+                    end_date: Some(end_date),
+                    ..int.clone()
+                };
+                let update = "Fin de la maintenance.".to_owned(); // TODO i18n
+                interventions::update(
+                    app,
+                    &mut tx,
+                    &Actor::system(),
+                    id,
+                    &resolved,
+                    None,
+                    Some(update),
+                )
+                .await?;
+                tx.commit().await?;
+                changed = true;
+            }
+        }
+    }
+
+    if changed {
+        app.regenerate_pages.send(()).await?;
+    }
+    if changed || reminded {
+        app.wake_outbox.send(()).await?;
+    }
+
+    Ok(next_date)
+}
+
+/// Background task applying the time-based transitions of the interventions:
+/// - reminders are sent before the planned interventions start,
+/// - planned interventions become ongoing at their start date,
+/// - planned interventions that should be automatically resolved are resolved, once their
+///   estimated duration has elapsed.
+///
+/// The task sleeps until the next transition is due; a message on the receiver wakes it up, so it
+/// can take new or modified interventions into account.
+pub(crate) async fn run(app: Arc<AppContext>, mut receiver: mpsc::Receiver<()>) {
+    loop {
+        let delay = match run_due_transitions(&app, chrono::Utc::now().naive_utc()).await {
+            Ok(Some(next_date)) => {
+                let now = chrono::Utc::now().naive_utc();
+                // A negative duration means the next transition is already due.
+                (next_date - now).to_std().unwrap_or(Duration::ZERO)
+            }
+            Ok(None) => IDLE_DELAY,
+            Err(err) => {
+                log::error!("Unable to run the scheduled transitions: {err:#}");
+                RETRY_DELAY
+            }
+        };
+
+        tokio::select! {
+            received = receiver.recv() => {
+                if received.is_none() {
+                    // okthxbye
+                    break;
+                }
+            }
+
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::models::{
+            comments::Comment,
+            interventions::Severity,
+            outbox::{OutboxEntry, OutboxStatus},
+            webhooks::Webhook,
+        },
+        testing,
+    };
+    use chrono::Duration as ChronoDuration;
+
+    /// Register a webhook interested in every event about the interventions, so that they're
+    /// queued in the outbox; nothing is delivered.
+    async fn insert_webhook(ctx: &AppContext) {
+        let mut conn = ctx.db_connection.lock().await;
+        Webhook::insert(
+            &mut conn,
+            &Webhook {
+                id: None,
+                url: "http://127.0.0.1:9/hook".to_owned(),
+                secret: "secret".to_owned(),
+                events: [
+                    "intervention.created",
+                    "intervention.updated",
+                    "intervention.resolved",
+                    "intervention.reminder",
+                ]
+                .map(str::to_owned)
+                .to_vec(),
+                enabled: true,
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    /// Events queued since the last call.
+    async fn take_queued_events(ctx: &AppContext) -> Vec<String> {
+        let mut conn = ctx.db_connection.lock().await;
+        let entries = OutboxEntry::get_by_status(OutboxStatus::Pending, &mut conn)
+            .await
+            .unwrap();
+        for entry in &entries {
+            OutboxEntry::delete(entry.id.unwrap(), &mut conn)
+                .await
+                .unwrap();
+        }
+        entries.into_iter().map(|entry| entry.event).collect()
+    }
+
+    async fn intervention(ctx: &AppContext, id: i64) -> Intervention {
+        let mut conn = ctx.db_connection.lock().await;
+        Intervention::by_id(id, &mut conn).await.unwrap().unwrap()
+    }
+
+    /// Plan a maintenance of a new service, through the same path as the administrators.
+    async fn plan(
+        ctx: &AppContext,
+        start_date: NaiveDateTime,
+        estimated_duration: i64,
+        auto_resolve: bool,
+    ) -> i64 {
+        let service_id = testing::insert_service(ctx, "Forge", "https://forge.example.org").await;
+        let mut conn = ctx.db_connection.lock().await;
+        interventions::create(
+            ctx,
+            &mut conn,
+            &Actor::system(),
+            &Intervention {
+                id: None,
+                start_date,
+                estimated_duration: Some(estimated_duration),
+                end_date: None,
+                status: Status::Planned,
+                severity: Severity::PartialOutage,
+                is_planned: true,
+                auto_resolve,
+                title: "Forge upgrade".to_owned(),
+                description: None,
+            },
+            &[service_id],
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn planned_interventions_start_and_end() {
+        let app = testing::app(testing::config()).await;
+        let ctx = &app.ctx;
+        insert_webhook(ctx).await;
+
+        // Dates are stored to the second.
+        let now = NaiveDateTime::from_timestamp_opt(chrono::Utc::now().timestamp(), 0).unwrap();
+        let start_date = now + ChronoDuration::hours(1);
+        let end_date = start_date + ChronoDuration::minutes(30);
+        let id = plan(ctx, start_date, 30, true).await;
+        let manual_id = plan(ctx, start_date, 30, false).await;
+        assert_eq!(
+            take_queued_events(ctx).await,
+            ["intervention.created", "intervention.created"]
+        );
+
+        // Nothing happens before the start date, which is the next transition.
+        for date in [now, start_date - ChronoDuration::seconds(1)] {
+            let next_date = run_due_transitions(ctx, date).await.unwrap();
+            assert_eq!(next_date, Some(start_date));
+            assert_eq!(intervention(ctx, id).await.status, Status::Planned);
+        }
+        assert!(take_queued_events(ctx).await.is_empty());
+
+        // At the start date, the maintenances start; the end of the one resolved automatically is
+        // the next transition.
+        let next_date = run_due_transitions(ctx, start_date).await.unwrap();
+        assert_eq!(next_date, Some(end_date));
+        for id in [id, manual_id] {
+            let started = intervention(ctx, id).await;
+            assert_eq!(started.status, Status::Ongoing);
+            assert_eq!(started.end_date, None);
+        }
+        assert_eq!(
+            take_queued_events(ctx).await,
+            ["intervention.updated", "intervention.updated"]
+        );
+
+        let next_date = run_due_transitions(ctx, end_date - ChronoDuration::seconds(1))
+            .await
+            .unwrap();
+        assert_eq!(next_date, Some(end_date));
+        assert_eq!(intervention(ctx, id).await.status, Status::Ongoing);
+        assert!(take_queued_events(ctx).await.is_empty());
+
+        // Once its estimated duration elapsed, it's resolved, at its estimated end date even if
+        // the scheduler runs late.
+        let next_date = run_due_transitions(ctx, end_date + ChronoDuration::minutes(5))
+            .await
+            .unwrap();
+        assert_eq!(next_date, None);
+        let resolved = intervention(ctx, id).await;
+        assert_eq!(resolved.status, Status::Resolved);
+        assert_eq!(resolved.end_date, Some(end_date));
+        assert_eq!(take_queued_events(ctx).await, ["intervention.resolved"]);
+
+        // The other one is left to the administrators.
+        assert_eq!(intervention(ctx, manual_id).await.status, Status::Ongoing);
+
+        let mut conn = ctx.db_connection.lock().await;
+        let updates = Comment::by_intervention(id, &mut conn).await.unwrap();
+        let updates: Vec<_> = updates.iter().map(|c| c.description.as_str()).collect();
+        assert_eq!(
+            updates,
+            ["Fin de la maintenance.", "Début de la maintenance."]
+        );
+    }
+
+    async fn pending_reminders(ctx: &AppContext) -> Vec<(i64, NaiveDateTime)> {
+        let mut conn = ctx.db_connection.lock().await;
+        let reminders = Reminder::get_pending(&mut conn).await.unwrap();
+        let mut reminders: Vec<_> = reminders
+            .into_iter()
+            .map(|r| (r.lead_time, r.remind_at))
+            .collect();
+        reminders.sort();
+        reminders
+    }
+
+    fn reminders_config() -> crate::AppConfig {
+        crate::AppConfig {
+            reminder_lead_times: vec![24 * 60, 60],
+            ..testing::config()
+        }
+    }
+
+    #[tokio::test]
+    async fn reminders_follow_the_start_date() {
+        let app = testing::app(reminders_config()).await;
+        let ctx = &app.ctx;
+        let now = NaiveDateTime::from_timestamp_opt(chrono::Utc::now().timestamp(), 0).unwrap();
+        let start_date = now + ChronoDuration::days(2);
+        let id = plan(ctx, start_date, 30, false).await;
+        assert_eq!(
+            pending_reminders(ctx).await,
+            [
+                (60, start_date - ChronoDuration::hours(1)),
+                (24 * 60, start_date - ChronoDuration::days(1)),
+            ]
+        );
+
+        // Postponing the maintenance postpones its reminders…
+        let postponed = Intervention {
+            start_date: start_date + ChronoDuration::days(1),
+            ..intervention(ctx, id).await
+        };
+        {
+            let mut conn = ctx.db_connection.lock().await;
+            interventions::update(ctx, &mut conn, &Actor::system(), id, &postponed, None, None)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            pending_reminders(ctx).await,
+            [
+                (60, postponed.start_date - ChronoDuration::hours(1)),
+                (24 * 60, postponed.start_date - ChronoDuration::days(1)),
+            ]
+        );
+
+        // …and bringing it forward skips those which would already be due.
+        let brought_forward = Intervention {
+            start_date: now + ChronoDuration::hours(2),
+            ..postponed
+        };
+        {
+            let mut conn = ctx.db_connection.lock().await;
+            interventions::update(
+                ctx,
+                &mut conn,
+                &Actor::system(),
+                id,
+                &brought_forward,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(
+            pending_reminders(ctx).await,
+            [(60, brought_forward.start_date - ChronoDuration::hours(1))]
+        );
+    }
+
+    #[tokio::test]
+    async fn reminders_are_sent_once() {
+        let path = std::env::temp_dir().join(format!(
+            "rustatouille-reminders-{}.db",
+            crate::tokens::random_token()
+        ));
+        let config = || crate::AppConfig {
+            db_connection_string: format!("sqlite://{}?mode=rwc", path.display()),
+            ..reminders_config()
+        };
+        let now = NaiveDateTime::from_timestamp_opt(chrono::Utc::now().timestamp(), 0).unwrap();
+        let start_date = now + ChronoDuration::days(2);
+
+        let app = testing::app(config()).await;
+        let ctx = &app.ctx;
+        insert_webhook(ctx).await;
+        let id = plan(ctx, start_date, 30, false).await;
+        take_queued_events(ctx).await;
+
+        let next_date = run_due_transitions(ctx, now).await.unwrap();
+        assert_eq!(next_date, Some(start_date - ChronoDuration::days(1)));
+        assert!(take_queued_events(ctx).await.is_empty());
+
+        // After a downtime, only the latest of the reminders which are due is sent.
+        let late = start_date - ChronoDuration::minutes(30);
+        let next_date = run_due_transitions(ctx, late).await.unwrap();
+        assert_eq!(next_date, Some(start_date));
+        assert_eq!(take_queued_events(ctx).await, ["intervention.reminder"]);
+        assert!(pending_reminders(ctx).await.is_empty());
+        drop(app);
+
+        // Once sent, they aren't sent again, even after a restart.
+        let app = testing::app(config()).await;
+        let ctx = &app.ctx;
+        let next_date = run_due_transitions(ctx, late).await.unwrap();
+        assert_eq!(next_date, Some(start_date));
+        assert!(take_queued_events(ctx).await.is_empty());
+        assert_eq!(intervention(ctx, id).await.status, Status::Planned);
+
+        drop(app);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resolving_drops_the_reminders() {
+        let app = testing::app(reminders_config()).await;
+        let ctx = &app.ctx;
+        insert_webhook(ctx).await;
+        let now = NaiveDateTime::from_timestamp_opt(chrono::Utc::now().timestamp(), 0).unwrap();
+        let start_date = now + ChronoDuration::days(2);
+        let id = plan(ctx, start_date, 30, false).await;
+        assert_eq!(pending_reminders(ctx).await.len(), 2);
+
+        // The maintenance is cancelled.
+        {
+            let mut conn = ctx.db_connection.lock().await;
+            interventions::resolve(
+                ctx,
+                &mut conn,
+                &Actor::system(),
+                id,
+                "Cancelled.".to_owned(),
+            )
+            .await
+            .unwrap();
+        }
+        assert!(pending_reminders(ctx).await.is_empty());
+        take_queued_events(ctx).await;
+
+        let next_date = run_due_transitions(ctx, start_date - ChronoDuration::minutes(30))
+            .await
+            .unwrap();
+        assert_eq!(next_date, None);
+        assert!(take_queued_events(ctx).await.is_empty());
+    }
+}
@@ -0,0 +1,419 @@
+//! Aggregation of the interventions into per-service and page-level statuses.
+//!
+//! Every output displaying a status must go through [`compute`], so that they all agree on what's
+//! going on.
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::AnyConnection;
+use std::cmp::Reverse;
+
+use crate::db::models::{
+    interventions::{Intervention, ServiceId, Severity, Status},
+    services::Service,
+};
+
+/// Where an intervention stands, relatively to a given point in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Phase {
+    /// A planned maintenance that hasn't started yet.
+    Upcoming,
+    /// Currently affecting the services.
+    Active,
+    /// Over and done with.
+    Past,
+}
+
+impl Phase {
+    pub fn of(int: &Intervention, now: NaiveDateTime) -> Self {
+        if int.end_date.is_some_and(|end_date| end_date <= now) {
+            return Self::Past;
+        }
+        match int.status {
+            Status::Resolved => Self::Past,
+            Status::Planned => {
+                if int.start_date > now {
+                    Self::Upcoming
+                } else {
+                    // The maintenance should have started already; better show it as active
+                    // than hide it.
+                    Self::Active
+                }
+            }
+            Status::Ongoing | Status::Identified | Status::UnderSurveillance => Self::Active,
+        }
+    }
+}
+
+/// When a past intervention finished.
+///
+/// Not all the interventions have an end date, e.g. those resolved before end dates were
+/// recorded; use the start date as the best approximation in that case.
+pub(crate) fn finished_at(int: &Intervention) -> NaiveDateTime {
+    int.end_date.unwrap_or(int.start_date)
+}
+
+/// Computed status of a service, or of the whole page.
+///
+/// Variants are declared from the best to the worst, so that comparing two statuses tells which
+/// one is worse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) enum ComputedStatus {
+    /// Nothing to report.
+    Operational,
+    /// Nothing active, but a maintenance will happen in the future.
+    MaintenancePlanned,
+    PerformanceIssue,
+    PartialOutage,
+    FullOutage,
+}
+
+impl From<Severity> for ComputedStatus {
+    fn from(value: Severity) -> Self {
+        match value {
+            Severity::PerformanceIssue => Self::PerformanceIssue,
+            Severity::PartialOutage => Self::PartialOutage,
+            Severity::FullOutage => Self::FullOutage,
+        }
+    }
+}
+
+impl ComputedStatus {
+    /// All the statuses, from the best to the worst.
+    pub const ALL: [Self; 5] = [
+        Self::Operational,
+        Self::MaintenancePlanned,
+        Self::PerformanceIssue,
+        Self::PartialOutage,
+        Self::FullOutage,
+    ];
+
+    /// Machine-friendly name of the status, to be used by the templates and other outputs.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Operational => "operational",
+            Self::MaintenancePlanned => "maintenance-planned",
+            Self::PerformanceIssue => "performance-issue",
+            Self::PartialOutage => "partial-outage",
+            Self::FullOutage => "full-outage",
+        }
+    }
+
+    /// Parse the machine-friendly name of a status.
+    pub fn from_str(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|status| status.as_str() == s)
+    }
+
+    pub fn css_class(self) -> &'static str {
+        match self {
+            Self::Operational => "success",
+            Self::MaintenancePlanned | Self::PerformanceIssue => "warning",
+            Self::PartialOutage | Self::FullOutage => "error",
+        }
+    }
+}
+
+/// An intervention, along with the services it affects.
+pub(crate) struct InterventionWithServices {
+    pub intervention: Intervention,
+    pub service_ids: Vec<ServiceId>,
+}
+
+impl InterventionWithServices {
+    /// Read all the interventions, along with their services.
+    pub async fn get_all(conn: &mut AnyConnection) -> anyhow::Result<Vec<Self>> {
+        let interventions = Intervention::get_all(conn).await?;
+        let mut with_services = Vec::with_capacity(interventions.len());
+        for intervention in interventions {
+            let service_ids = Intervention::get_service_ids(intervention.id.unwrap(), conn).await?;
+            with_services.push(Self {
+                intervention,
+                service_ids,
+            });
+        }
+        Ok(with_services)
+    }
+}
+
+/// Interventions sorted by phase.
+#[derive(Default)]
+pub(crate) struct Classified<'a> {
+    /// Worst severity first, then most recent first.
+    pub active: Vec<&'a InterventionWithServices>,
+    /// Soonest first.
+    pub upcoming: Vec<&'a InterventionWithServices>,
+    /// Most recently finished first.
+    pub past: Vec<&'a InterventionWithServices>,
+}
+
+impl<'a> Classified<'a> {
+    fn new(
+        interventions: impl Iterator<Item = &'a InterventionWithServices>,
+        now: NaiveDateTime,
+    ) -> Self {
+        let mut this = Self::default();
+
+        for int in interventions {
+            match Phase::of(&int.intervention, now) {
+                Phase::Upcoming => this.upcoming.push(int),
+                Phase::Active => this.active.push(int),
+                Phase::Past => this.past.push(int),
+            }
+        }
+
+        this.active.sort_by(|a, b| {
+            let (a, b) = (&a.intervention, &b.intervention);
+            b.severity
+                .cmp(&a.severity)
+                .then_with(|| b.start_date.cmp(&a.start_date))
+        });
+        this.upcoming.sort_by_key(|int| int.intervention.start_date);
+        this.past
+            .sort_by_key(|int| Reverse(finished_at(&int.intervention)));
+
+        this
+    }
+
+    /// Past interventions which finished at the given date, or later.
+    pub fn finished_since(&self, date: NaiveDateTime) -> &[&'a InterventionWithServices] {
+        // Past interventions are sorted by decreasing end date.
+        let num = self
+            .past
+            .partition_point(|int| finished_at(&int.intervention) >= date);
+        &self.past[..num]
+    }
+
+    /// Status implied by the interventions: the worst active severity, if any.
+    fn status(&self) -> ComputedStatus {
+        let worst_active = self
+            .active
+            .iter()
+            .map(|int| int.intervention.severity)
+            .max();
+        match worst_active {
+            Some(severity) => severity.into(),
+            None if !self.upcoming.is_empty() => ComputedStatus::MaintenancePlanned,
+            None => ComputedStatus::Operational,
+        }
+    }
+}
+
+/// Status of a single service.
+pub(crate) struct ServiceStatus<'a> {
+    pub service: &'a Service,
+    pub status: ComputedStatus,
+    pub interventions: Classified<'a>,
+}
+
+/// Status of the whole page.
+pub(crate) struct PageStatus<'a> {
+    /// Worst status among all the services.
+    pub overall: ComputedStatus,
+    pub interventions: Classified<'a>,
+    /// Worst status first; services with the same status keep their relative order.
+    pub services: Vec<ServiceStatus<'a>>,
+}
+
+/// Compute the status of every service and of the whole page, at the given time.
+pub(crate) fn compute<'a>(
+    services: &'a [Service],
+    interventions: &'a [InterventionWithServices],
+    now: NaiveDateTime,
+) -> PageStatus<'a> {
+    let mut services: Vec<_> = services
+        .iter()
+        .map(|service| {
+            let service_id = ServiceId(service.id.unwrap());
+            let interventions = Classified::new(
+                interventions
+                    .iter()
+                    .filter(|int| int.service_ids.contains(&service_id)),
+                now,
+            );
+            ServiceStatus {
+                service,
+                status: interventions.status(),
+                interventions,
+            }
+        })
+        .collect();
+
+    services.sort_by_key(|s| Reverse(s.status));
+
+    let interventions = Classified::new(interventions.iter(), now);
+
+    let overall = services
+        .iter()
+        .map(|s| s.status)
+        .max()
+        .unwrap_or(ComputedStatus::Operational)
+        .max(interventions.status());
+
+    PageStatus {
+        overall,
+        interventions,
+        services,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32, hour: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2024, 5, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn intervention(
+        id: i64,
+        status: Status,
+        severity: Severity,
+        start: NaiveDateTime,
+    ) -> Intervention {
+        Intervention {
+            id: Some(id),
+            start_date: start,
+            estimated_duration: None,
+            end_date: None,
+            status,
+            severity,
+            is_planned: status == Status::Planned,
+            auto_resolve: false,
+            title: format!("intervention {id}"),
+            description: None,
+        }
+    }
+
+    fn service(id: i64) -> Service {
+        Service {
+            id: Some(id),
+            name: format!("service {id}"),
+            url: format!("https://{id}.example.org"),
+            team_id: None,
+        }
+    }
+
+    fn with_services(intervention: Intervention, service_ids: &[i64]) -> InterventionWithServices {
+        InterventionWithServices {
+            intervention,
+            service_ids: service_ids.iter().map(|id| ServiceId(*id)).collect(),
+        }
+    }
+
+    #[test]
+    fn phase_of_resolved_intervention_is_past() {
+        let now = date(10, 12);
+        let int = intervention(1, Status::Resolved, Severity::FullOutage, date(10, 8));
+        assert_eq!(Phase::of(&int, now), Phase::Past);
+    }
+
+    #[test]
+    fn phase_of_planned_intervention_depends_on_start_date() {
+        let now = date(10, 12);
+        let future = intervention(1, Status::Planned, Severity::PerformanceIssue, date(11, 8));
+        assert_eq!(Phase::of(&future, now), Phase::Upcoming);
+        let started = intervention(2, Status::Planned, Severity::PerformanceIssue, date(10, 8));
+        assert_eq!(Phase::of(&started, now), Phase::Active);
+    }
+
+    #[test]
+    fn phase_of_intervention_with_elapsed_end_date_is_past() {
+        let now = date(10, 12);
+        let mut int = intervention(1, Status::Ongoing, Severity::PartialOutage, date(10, 8));
+        assert_eq!(Phase::of(&int, now), Phase::Active);
+        int.end_date = Some(date(10, 10));
+        assert_eq!(Phase::of(&int, now), Phase::Past);
+        int.end_date = Some(date(10, 14));
+        assert_eq!(Phase::of(&int, now), Phase::Active);
+    }
+
+    #[test]
+    fn service_status_is_worst_active_severity() {
+        let now = date(10, 12);
+        let services = [service(1)];
+        let interventions = [
+            with_services(
+                intervention(1, Status::Ongoing, Severity::PerformanceIssue, date(10, 8)),
+                &[1],
+            ),
+            with_services(
+                intervention(2, Status::Identified, Severity::PartialOutage, date(10, 9)),
+                &[1],
+            ),
+            with_services(
+                intervention(3, Status::Resolved, Severity::FullOutage, date(9, 9)),
+                &[1],
+            ),
+        ];
+        let page = compute(&services, &interventions, now);
+        assert_eq!(page.services[0].status, ComputedStatus::PartialOutage);
+        let active: Vec<_> = page.services[0]
+            .interventions
+            .active
+            .iter()
+            .map(|int| int.intervention.id.unwrap())
+            .collect();
+        assert_eq!(active, [2, 1]);
+    }
+
+    #[test]
+    fn upcoming_maintenance_only_marks_planned() {
+        let now = date(10, 12);
+        let services = [service(1)];
+        let interventions = [with_services(
+            intervention(1, Status::Planned, Severity::FullOutage, date(12, 8)),
+            &[1],
+        )];
+        let page = compute(&services, &interventions, now);
+        assert_eq!(page.services[0].status, ComputedStatus::MaintenancePlanned);
+        assert_eq!(page.overall, ComputedStatus::MaintenancePlanned);
+    }
+
+    #[test]
+    fn services_are_sorted_by_worst_status_keeping_their_order() {
+        let now = date(10, 12);
+        let services = [service(1), service(2), service(3), service(4)];
+        let interventions = [
+            with_services(
+                intervention(1, Status::Ongoing, Severity::PerformanceIssue, date(10, 8)),
+                &[2],
+            ),
+            with_services(
+                intervention(2, Status::Ongoing, Severity::FullOutage, date(10, 8)),
+                &[4],
+            ),
+        ];
+        let page = compute(&services, &interventions, now);
+        let order: Vec<_> = page
+            .services
+            .iter()
+            .map(|s| s.service.id.unwrap())
+            .collect();
+        assert_eq!(order, [4, 2, 1, 3]);
+    }
+
+    #[test]
+    fn overall_status_is_worst_service_status() {
+        let now = date(10, 12);
+        let services = [service(1), service(2)];
+
+        let page = compute(&services, &[], now);
+        assert_eq!(page.overall, ComputedStatus::Operational);
+
+        let interventions = [
+            with_services(
+                intervention(1, Status::Ongoing, Severity::PartialOutage, date(10, 8)),
+                &[1],
+            ),
+            with_services(
+                intervention(2, Status::Planned, Severity::FullOutage, date(12, 8)),
+                &[2],
+            ),
+        ];
+        let page = compute(&services, &interventions, now);
+        assert_eq!(page.overall, ComputedStatus::PartialOutage);
+    }
+}
@@ -0,0 +1,451 @@
+//! Helpers for the tests: an application running on an in-memory database, and local servers
+//! standing in for the outside world.
+
+use crate::{
+    db::{
+        self,
+        models::{
+            api_tokens::{ApiToken, Scope},
+            interventions::{Intervention, Severity, Status},
+            outbox::{OutboxEntry, OutboxStatus},
+            services::Service,
+            sessions::Session,
+            users::{Role, User},
+        },
+    },
+    metrics::Metrics,
+    monitoring::history::HistoryRetention,
+    notifications::{self, outbox, Event},
+    tokens, AppConfig, AppContext,
+};
+use chrono::NaiveDateTime;
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex as StdMutex, RwLock},
+    time::Duration,
+};
+use tera::Tera;
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    net::TcpListener,
+    sync::{mpsc, Mutex},
+};
+
+/// Configuration of a test application; tests tweak it before calling [`app`].
+pub(crate) fn config() -> AppConfig {
+    AppConfig {
+        port: 3000,
+        interface_ipv4: [127, 0, 0, 1].into(),
+        cache_dir: std::env::temp_dir().join("rustatouille-tests"),
+        template_dir: PathBuf::from("./templates"),
+        db_connection_string: "sqlite::memory:".to_owned(),
+        dev_server: false,
+        bootstrap_admin: None,
+        resolved_retention: chrono::Duration::days(7),
+        public_url: "https://status.example.org".to_owned(),
+        smtp: None,
+        mastodon: None,
+        matrix: None,
+        secret_key: "secret".to_owned(),
+        reminder_lead_times: Vec::new(),
+        history_retention: HistoryRetention {
+            raw: chrono::Duration::days(7),
+            hourly: chrono::Duration::days(90),
+            daily: chrono::Duration::days(730),
+        },
+        alertmanager: None,
+        alerts_token: None,
+        alerts_reopen_window: chrono::Duration::minutes(60),
+        metrics_token: None,
+        session_idle_timeout: chrono::Duration::minutes(60),
+        session_lifetime: chrono::Duration::hours(12),
+    }
+}
+
+/// An application whose background workers aren't running.
+///
+/// The receiving ends of the wake-up channels are kept, so that waking up the workers doesn't
+/// fail.
+pub(crate) struct TestApp {
+    pub ctx: Arc<AppContext>,
+    _receivers: Vec<mpsc::Receiver<()>>,
+}
+
+pub(crate) async fn app(config: AppConfig) -> TestApp {
+    let conn = db::open(&config.db_connection_string).await.unwrap();
+
+    let templates = Tera::new(
+        &config
+            .template_dir
+            .join("**/*.{html,txt,json}")
+            .to_string_lossy(),
+    )
+    .unwrap();
+
+    let mailer = config
+        .smtp
+        .as_ref()
+        .map(crate::notifications::email::Mailer::new)
+        .transpose()
+        .unwrap();
+
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap();
+
+    let (sender, regenerate_pages) = mpsc::channel(128);
+    let (outbox_sender, wake_outbox) = mpsc::channel(128);
+    let (scheduler_sender, wake_scheduler) = mpsc::channel(128);
+    let (prober_sender, wake_prober) = mpsc::channel(128);
+    let (heartbeats_sender, wake_heartbeats) = mpsc::channel(128);
+
+    let ctx = Arc::new(AppContext {
+        config,
+        db_connection: Mutex::new(conn),
+        templates: RwLock::new(templates),
+        toast: RwLock::new(None),
+        regenerate_pages: sender,
+        wake_outbox: outbox_sender,
+        mailer,
+        http_client,
+        wake_scheduler: scheduler_sender,
+        wake_prober: prober_sender,
+        wake_heartbeats: heartbeats_sender,
+        metrics: Metrics::default(),
+    });
+
+    TestApp {
+        ctx,
+        _receivers: vec![
+            regenerate_pages,
+            wake_outbox,
+            wake_scheduler,
+            wake_prober,
+            wake_heartbeats,
+        ],
+    }
+}
+
+/// Serve the router on a local port, standing in for a remote HTTP server; returns its base URL.
+pub(crate) fn serve(router: axum::Router) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(router.into_make_service());
+    tokio::spawn(server);
+    format!("http://{addr}")
+}
+
+/// Insert a new service, and return its id.
+pub(crate) async fn insert_service(ctx: &AppContext, name: &str, url: &str) -> i64 {
+    let mut conn = ctx.db_connection.lock().await;
+    Service::insert(
+        &mut conn,
+        &Service {
+            id: None,
+            name: name.to_owned(),
+            url: url.to_owned(),
+            team_id: None,
+        },
+    )
+    .await
+    .unwrap()
+}
+
+/// Insert an ongoing outage of a new service, and return its id.
+pub(crate) async fn insert_intervention(ctx: &AppContext, title: &str) -> i64 {
+    let service_id = insert_service(ctx, "Forge", "https://forge.example.org").await;
+    let mut conn = ctx.db_connection.lock().await;
+    let id = Intervention::insert(
+        &mut conn,
+        &Intervention {
+            id: None,
+            start_date: chrono::Utc::now().naive_utc(),
+            estimated_duration: None,
+            end_date: None,
+            status: Status::Ongoing,
+            severity: Severity::FullOutage,
+            is_planned: false,
+            auto_resolve: false,
+            title: title.to_owned(),
+            description: None,
+        },
+    )
+    .await
+    .unwrap();
+    Intervention::add_service(id, service_id, &mut conn)
+        .await
+        .unwrap();
+    id
+}
+
+/// Queue notifications about the event, which must go to a single destination, and deliver them;
+/// returns the idempotency key of the queued entry.
+pub(crate) async fn notify(ctx: &AppContext, event: Event) -> String {
+    let key = {
+        let mut conn = ctx.db_connection.lock().await;
+        notifications::enqueue(ctx, &mut conn, event).await.unwrap();
+        let entries = OutboxEntry::get_by_status(OutboxStatus::Pending, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        entries[0].idempotency_key()
+    };
+    outbox::deliver_due(ctx).await.unwrap();
+
+    // Delivered, or skipped, but not waiting for a retry.
+    let mut conn = ctx.db_connection.lock().await;
+    let pending = OutboxEntry::get_by_status(OutboxStatus::Pending, &mut conn)
+        .await
+        .unwrap();
+    assert!(pending.is_empty());
+    key
+}
+
+/// Serve the administration on a local port; returns its base URL, ending with `/admin`.
+pub(crate) fn serve_admin(ctx: &Arc<AppContext>) -> String {
+    let router = axum::Router::new()
+        .nest("/admin", crate::admin_router())
+        .layer(axum::Extension(ctx.clone()));
+    format!("{}/admin", serve(router))
+}
+
+/// HTTP client which doesn't follow the redirections, so that tests can check them.
+pub(crate) fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+/// Password of the administrators inserted by [`insert_user`].
+pub(crate) const PASSWORD: &str = "correct horse battery staple";
+
+/// Insert an administrator, and return its id.
+pub(crate) async fn insert_user(
+    ctx: &AppContext,
+    username: &str,
+    role: Role,
+    team_id: Option<i64>,
+) -> i64 {
+    let password_hash = tokens::hash_password(PASSWORD).unwrap();
+    let mut conn = ctx.db_connection.lock().await;
+    User::insert(
+        &mut conn,
+        &User {
+            id: None,
+            username: username.to_owned(),
+            password_hash,
+            created_at: chrono::Utc::now().naive_utc(),
+            role,
+            team_id,
+        },
+    )
+    .await
+    .unwrap()
+}
+
+/// A session of an administrator, opened without going through the login page.
+pub(crate) struct AdminSession {
+    pub token: String,
+    pub csrf_token: String,
+}
+
+impl AdminSession {
+    /// Value of the `Cookie` header carrying the session.
+    pub fn cookie(&self) -> String {
+        format!("rustatouille_session={}", self.token)
+    }
+}
+
+/// Open a session for the administrator, created and last used at the given dates.
+pub(crate) async fn open_session_at(
+    ctx: &AppContext,
+    user_id: i64,
+    created_at: NaiveDateTime,
+    last_seen_at: NaiveDateTime,
+) -> AdminSession {
+    let token = tokens::random_token();
+    let csrf_token = tokens::random_token();
+    let mut conn = ctx.db_connection.lock().await;
+    Session::insert(
+        &mut conn,
+        &Session {
+            id: None,
+            token_hash: tokens::hash_token(&token),
+            user_id,
+            csrf_token: csrf_token.clone(),
+            created_at,
+            last_seen_at,
+        },
+    )
+    .await
+    .unwrap();
+    AdminSession { token, csrf_token }
+}
+
+/// Open a session for the administrator, right now.
+pub(crate) async fn open_session(ctx: &AppContext, user_id: i64) -> AdminSession {
+    let now = chrono::Utc::now().naive_utc();
+    open_session_at(ctx, user_id, now, now).await
+}
+
+/// Insert an API token of the administrator, and return its id and the token itself.
+pub(crate) async fn insert_api_token(
+    ctx: &AppContext,
+    user_id: i64,
+    scopes: &[Scope],
+    expires_at: Option<NaiveDateTime>,
+) -> (i64, String) {
+    let token = tokens::random_token();
+    let mut conn = ctx.db_connection.lock().await;
+    let id = ApiToken::insert(
+        &mut conn,
+        &ApiToken {
+            id: None,
+            name: "script".to_owned(),
+            token_hash: tokens::hash_token(&token),
+            scopes: scopes.to_vec(),
+            user_id,
+            created_at: chrono::Utc::now().naive_utc(),
+            expires_at,
+            last_used_at: None,
+        },
+    )
+    .await
+    .unwrap();
+    (id, token)
+}
+
+/// Fields of the form creating an ongoing outage of the service.
+pub(crate) fn intervention_form(service_id: i64) -> Vec<(&'static str, String)> {
+    let start_date = chrono::Utc::now().naive_utc().format("%Y-%m-%dT%H:%M");
+    vec![
+        ("title", "Forge down".to_owned()),
+        ("description", "The forge doesn't answer.".to_owned()),
+        ("start-date", start_date.to_string()),
+        ("severity", "full-outage".to_owned()),
+        ("status", "ongoing".to_owned()),
+        ("services", service_id.to_string()),
+    ]
+}
+
+/// An email received by the [`SmtpSink`].
+#[derive(Clone, Debug)]
+pub(crate) struct ReceivedMail {
+    pub recipients: Vec<String>,
+    /// Headers and body, as sent.
+    pub data: String,
+}
+
+impl ReceivedMail {
+    /// Value of a header, if present.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let (headers, _) = self.data.split_once("\r\n\r\n")?;
+        headers.split("\r\n").find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    /// Body of the email, decoded if it was sent as quoted-printable.
+    pub fn body(&self) -> String {
+        let (_, body) = self.data.split_once("\r\n\r\n").unwrap_or_default();
+        if self.header("Content-Transfer-Encoding") != Some("quoted-printable") {
+            return body.to_owned();
+        }
+
+        let body = body.replace("=\r\n", "");
+        let mut bytes = Vec::with_capacity(body.len());
+        let mut rest = body.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            match (byte, tail.get(..2)) {
+                (b'=', Some(hex)) => {
+                    let hex = std::str::from_utf8(hex).unwrap();
+                    bytes.push(u8::from_str_radix(hex, 16).unwrap());
+                    rest = &tail[2..];
+                }
+                _ => {
+                    bytes.push(byte);
+                    rest = tail;
+                }
+            }
+        }
+        String::from_utf8(bytes).unwrap()
+    }
+}
+
+/// A local SMTP server, which accepts every email and keeps it.
+pub(crate) struct SmtpSink {
+    pub port: u16,
+    mails: Arc<StdMutex<Vec<ReceivedMail>>>,
+}
+
+impl SmtpSink {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mails = Arc::new(StdMutex::new(Vec::new()));
+
+        let sink_mails = mails.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(Self::serve(stream, sink_mails.clone()));
+            }
+        });
+
+        Self { port, mails }
+    }
+
+    /// Handle a single SMTP session.
+    async fn serve(stream: tokio::net::TcpStream, mails: Arc<StdMutex<Vec<ReceivedMail>>>) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+
+        let mut recipients = Vec::new();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let command = line.to_ascii_uppercase();
+            let reply: &[u8] = if command.starts_with("RCPT TO:") {
+                let address = line["RCPT TO:".len()..].trim();
+                recipients.push(address.trim_matches(['<', '>']).to_owned());
+                b"250 OK\r\n"
+            } else if command.starts_with("MAIL FROM:") || command.starts_with("RSET") {
+                recipients.clear();
+                b"250 OK\r\n"
+            } else if command.starts_with("DATA") {
+                writer.write_all(b"354 Go ahead\r\n").await.unwrap();
+                let mut data = String::new();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line == "." {
+                        break;
+                    }
+                    // Undo the dot-stuffing.
+                    data.push_str(line.strip_prefix('.').unwrap_or(&line));
+                    data.push_str("\r\n");
+                }
+                mails.lock().unwrap().push(ReceivedMail {
+                    recipients: std::mem::take(&mut recipients),
+                    data,
+                });
+                b"250 OK\r\n"
+            } else if command.starts_with("QUIT") {
+                writer.write_all(b"221 Bye\r\n").await.unwrap();
+                break;
+            } else {
+                // EHLO, NOOP, etc.
+                b"250 localhost\r\n"
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+    }
+
+    /// Take all the emails received so far.
+    pub fn take(&self) -> Vec<ReceivedMail> {
+        std::mem::take(&mut self.mails.lock().unwrap())
+    }
+}
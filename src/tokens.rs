@@ -0,0 +1,102 @@
+//! Generation and verification of secrets.
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier as _, SaltString},
+    Argon2,
+};
+use hmac::{Hmac, Mac as _};
+use rand::{distributions::Alphanumeric, Rng as _};
+use sha2::Sha256;
+
+/// Length of the generated tokens; 32 alphanumeric characters is a bit more than 190 bits of
+/// entropy.
+const TOKEN_LENGTH: usize = 32;
+
+/// Generate a random alphanumeric token, suitable for secret links or shared secrets.
+pub(crate) fn random_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+fn mac(key: &str, data: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac
+}
+
+/// Sign the data with HMAC-SHA256; returns the hex-encoded signature.
+pub(crate) fn sign(key: &str, data: &[u8]) -> String {
+    hex::encode(mac(key, data).finalize().into_bytes())
+}
+
+/// Check, in constant time, that the hex-encoded signature matches the data.
+pub(crate) fn verify(key: &str, data: &[u8], signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    mac(key, data).verify_slice(&signature).is_ok()
+}
+
+/// Compare two secrets in constant time, at least as long as they have the same length.
+pub(crate) fn secure_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Hash a password with Argon2id, in the PHC string format.
+pub(crate) fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+        .map_err(|err| anyhow::anyhow!("generating a salt: {err}"))?;
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|err| anyhow::anyhow!("hashing a password: {err}"))?;
+    Ok(hash.to_string())
+}
+
+/// Hash of a random password, with the parameters of [`hash_password`]: checking a password
+/// against it takes as long as checking it against the hash of an actual user.
+pub(crate) const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$weYXJazlme55HOaCInBL9g$//f+eHlA845b9QZIlTBbo18m1ocdwKMjAvbHnivHMnA";
+
+/// Check a password against a hash produced by [`hash_password`].
+///
+/// This is slow on purpose; call it from a blocking task.
+pub(crate) fn verify_password(password: &str, hash: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(hash) else {
+        return false;
+    };
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
+
+/// Hash a random token with SHA-256, to store it without giving it away; returns the hex digest.
+///
+/// Unlike passwords, random tokens have enough entropy not to need a slow hash.
+pub(crate) fn hash_token(token: &str) -> String {
+    use sha2::Digest as _;
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dummy_hash_has_the_parameters_of_real_ones() {
+        let dummy = PasswordHash::new(DUMMY_PASSWORD_HASH).unwrap();
+        let hash = hash_password("hunter2").unwrap();
+        let real = PasswordHash::new(&hash).unwrap();
+        assert_eq!(dummy.algorithm, real.algorithm);
+        assert_eq!(dummy.version, real.version);
+        assert_eq!(dummy.params, real.params);
+        assert!(!verify_password("hunter2", DUMMY_PASSWORD_HASH));
+    }
+}